@@ -0,0 +1,8 @@
+#!/usr/bin/env bash
+# Regenerates the backend candid interface from the canister code
+
+set -e
+
+cargo build --target wasm32-unknown-unknown --release --package ea_backend
+candid-extractor target/wasm32-unknown-unknown/release/ea_backend.wasm > src/ea_backend/ea_backend.did
+echo "Wrote src/ea_backend/ea_backend.did"
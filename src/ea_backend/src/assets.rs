@@ -0,0 +1,1134 @@
+use crate::certificate;
+use crate::config;
+use crate::domain::{self, AssetScope, Context, LegacyProfileResume, ProfileStore, Rng};
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::memory::{self, Memory};
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk_macros::*;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Blob store for resumes, course media, review images and certificate artwork.
+// Uploads are written chunk by chunk straight into stable memory, so assets
+// survive upgrades without going through the upgrade snapshot. Profiles,
+// courses, reviews and certificates only keep asset ids, the bytes are served
+// by `http_request` under `/assets/<id>`, with support for range requests.
+// Assets referenced as resumes, verification documents or message attachments
+// are private and only served with a short-lived link from `get_asset_link`.
+
+// Every chunk but the last one of an upload has exactly this size
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+// Upper bound for the body of a single `http_request` response
+const MAX_RESPONSE_BYTES: u64 = 2 * 1024 * 1024;
+// Content types accepted for uploads, entries ending in `/` match a whole family
+const ALLOWED_CONTENT_TYPES: &[&str] = &["application/pdf", "image/", "video/", "audio/"];
+// Images able to run scripts on the origin of the canister
+const SCRIPTED_CONTENT_TYPES: &[&str] = &["image/svg+xml"];
+// Asset ids are the hex encoded sha256 issued by `Rng::next_id`
+const ID_LEN: usize = 64;
+// Lifetime of a link to a private asset, in nanoseconds
+const LINK_TTL: u64 = 10 * 60 * 1_000_000_000;
+// Uploads left unfinished for this long are deleted with their chunks
+const STALE_UPLOAD_AGE: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    static ASSETS: RefCell<AssetStore> = RefCell::new(AssetStore::init(
+        memory::get(memory::ASSETS),
+        memory::get(memory::ASSET_CHUNKS),
+        memory::get(memory::ASSET_USAGE),
+        memory::get(memory::ASSET_UPLOADS),
+    ));
+    // Key signing the links to private assets, drawn on first use and kept in
+    // stable memory so links stay valid across upgrades. Empty until drawn.
+    static LINK_KEY: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(memory::get(memory::ASSET_LINK_KEY), Vec::new())
+            .expect("Failed to init the link key")
+    );
+}
+
+pub fn with<T, F: FnOnce(&AssetStore) -> T>(f: F) -> T {
+    ASSETS.with(|store| f(&store.borrow()))
+}
+
+pub fn with_mut<T, F: FnOnce(&mut AssetStore) -> T>(f: F) -> T {
+    ASSETS.with(|store| f(&mut store.borrow_mut()))
+}
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum AssetStatus {
+    Uploading,
+    Committed,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Asset {
+    pub id: String,
+    pub owner: Principal,
+    pub content_type: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    // Hex encoded sha256 of the content, known once the upload is committed
+    pub sha256: Option<String>,
+    pub status: AssetStatus,
+    pub created_at: u64,
+    // Records keeping the asset private, public assets have none
+    #[serde(default)]
+    pub private_to: Vec<AssetScope>,
+}
+
+// Path of a private asset under the HTTP gateway, with the token granting access
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct AssetLink {
+    pub path: String,
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum AssetError {
+    AssetNotFound,
+    Unauthorized,
+    UnsupportedContentType,
+    EmptyAsset,
+    TooLarge(u64),
+    InvalidChunk,
+    IncompleteUpload,
+    HashMismatch,
+    AlreadyCommitted,
+    ProfileNotFound,
+    // The uploads of the owner would exceed the quota, in bytes
+    QuotaExceeded(u64),
+    // Certificates keep showing their artwork, it cannot be deleted
+    CertificateArtwork,
+    // The management canister did not hand out the randomness for the id or link
+    RandomnessUnavailable(String),
+}
+
+impl Asset {
+    fn chunk_count(&self) -> u32 {
+        self.size.div_ceil(self.chunk_size) as u32
+    }
+
+    fn chunk_len(&self, index: u32) -> u64 {
+        let start = index as u64 * self.chunk_size;
+        self.chunk_size.min(self.size.saturating_sub(start))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct AssetKey([u8; ID_LEN]);
+
+impl AssetKey {
+    fn new(id: &str) -> Option<Self> {
+        id.as_bytes().try_into().ok().map(AssetKey)
+    }
+}
+
+impl Storable for AssetKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        AssetKey(bytes.as_ref().try_into().expect("Invalid asset key"))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: ID_LEN as u32,
+        is_fixed_size: true,
+    };
+}
+
+// Chunks of an asset are stored next to each other, in upload order
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ChunkKey {
+    asset: AssetKey,
+    index: u32,
+}
+
+impl Storable for ChunkKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.asset.0.to_vec();
+        bytes.extend(self.index.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (asset, index) = bytes.split_at(ID_LEN);
+        ChunkKey {
+            asset: AssetKey(asset.try_into().expect("Invalid asset key")),
+            index: u32::from_be_bytes(index.try_into().expect("Invalid chunk index")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: ID_LEN as u32 + 4,
+        is_fixed_size: true,
+    };
+}
+
+fn owner_key(owner: &Principal) -> Blob<29> {
+    Blob::try_from(owner.as_slice()).expect("Principals are at most 29 bytes long")
+}
+
+// Uploads in progress, oldest first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct UploadKey {
+    created_at: u64,
+    asset: AssetKey,
+}
+
+impl Storable for UploadKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.created_at.to_be_bytes().to_vec();
+        bytes.extend(self.asset.0);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (created_at, asset) = bytes.split_at(8);
+        UploadKey {
+            created_at: u64::from_be_bytes(created_at.try_into().expect("Invalid upload time")),
+            asset: AssetKey(asset.try_into().expect("Invalid asset key")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + ID_LEN as u32,
+        is_fixed_size: true,
+    };
+}
+
+impl Storable for Asset {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_cbor::to_vec(self).expect("Failed to serialize asset"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_cbor::from_slice(&bytes).expect("Failed to deserialize asset")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ==================================================================================================
+// Asset store
+// ==================================================================================================
+
+pub struct AssetStore {
+    assets: StableBTreeMap<AssetKey, Asset, Memory>,
+    chunks: StableBTreeMap<ChunkKey, Vec<u8>, Memory>,
+    // Bytes taken by the assets of each owner
+    usage: StableBTreeMap<Blob<29>, u64, Memory>,
+    uploads: StableBTreeMap<UploadKey, (), Memory>,
+}
+
+impl AssetStore {
+    pub fn init(assets: Memory, chunks: Memory, usage: Memory, uploads: Memory) -> Self {
+        let mut store = AssetStore {
+            assets: StableBTreeMap::init(assets),
+            chunks: StableBTreeMap::init(chunks),
+            usage: StableBTreeMap::init(usage),
+            uploads: StableBTreeMap::init(uploads),
+        };
+        // Assets stored before the usage and the uploads were kept aside
+        if store.usage.is_empty() && !store.assets.is_empty() {
+            let assets: Vec<Asset> = store.assets.iter().map(|(_, asset)| asset).collect();
+            for asset in &assets {
+                store.index(asset);
+            }
+        }
+        store
+    }
+
+    // Metadata of an asset, uploads in progress included
+    pub fn asset(&self, id: &str) -> Option<Asset> {
+        self.assets.get(&AssetKey::new(id)?)
+    }
+
+    // Whether `id` names a committed asset of `owner`, for the records referencing it
+    pub fn is_owned_by(&self, id: &str, owner: &Principal) -> bool {
+        self.asset(id)
+            .is_some_and(|asset| asset.status == AssetStatus::Committed && asset.owner == *owner)
+    }
+
+    // Every asset uploaded by `owner`, for data exports
+    pub fn owned_by(&self, owner: &Principal) -> Vec<Asset> {
+        self.assets
+            .iter()
+            .map(|(_, asset)| asset)
+            .filter(|asset| asset.owner == *owner)
+            .collect()
+    }
+
+    pub fn is_committed(&self, id: &str) -> bool {
+        self.asset(id)
+            .is_some_and(|asset| asset.status == AssetStatus::Committed)
+    }
+
+    // Bytes taken by the assets of `owner`, uploads in progress included
+    pub fn usage(&self, owner: &Principal) -> u64 {
+        self.usage.get(&owner_key(owner)).unwrap_or_default()
+    }
+
+    // Starting an upload of `size` bytes, sent afterwards in chunks of `CHUNK_SIZE`.
+    // The assets of the caller, this one included, have to fit in `quota` bytes.
+    pub fn begin_upload(
+        &mut self,
+        ctx: &Context,
+        content_type: String,
+        size: u64,
+        max_size: u64,
+        quota: u64,
+    ) -> Result<Asset, AssetError> {
+        let allowed = ALLOWED_CONTENT_TYPES.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                content_type.starts_with(allowed)
+            } else {
+                content_type == *allowed
+            }
+        });
+        if !allowed || is_scripted(&content_type) {
+            return Err(AssetError::UnsupportedContentType);
+        }
+        if size == 0 {
+            return Err(AssetError::EmptyAsset);
+        }
+        if size > max_size {
+            return Err(AssetError::TooLarge(max_size));
+        }
+        if self.usage(&ctx.caller).saturating_add(size) > quota {
+            return Err(AssetError::QuotaExceeded(quota));
+        }
+
+        let asset = Asset {
+            id: ctx.rng.next_id(),
+            owner: ctx.caller,
+            content_type,
+            size,
+            chunk_size: CHUNK_SIZE,
+            sha256: None,
+            status: AssetStatus::Uploading,
+            created_at: ctx.now,
+            private_to: vec![],
+        };
+        self.assets
+            .insert(AssetKey::new(&asset.id).unwrap(), asset.clone());
+        self.index(&asset);
+        Ok(asset)
+    }
+
+    // Chunks can be sent in any order, and sent again until the upload is committed
+    pub fn put_chunk(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        index: u32,
+        bytes: Vec<u8>,
+    ) -> Result<(), AssetError> {
+        let asset = self.uploading(ctx, id)?;
+        if index >= asset.chunk_count() || bytes.len() as u64 != asset.chunk_len(index) {
+            return Err(AssetError::InvalidChunk);
+        }
+        let key = ChunkKey {
+            asset: AssetKey::new(id).unwrap(),
+            index,
+        };
+        self.chunks.insert(key, bytes);
+        Ok(())
+    }
+
+    // Checking that every chunk arrived and, when the uploader sent one, that
+    // the content matches the expected hash
+    pub fn commit_upload(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        sha256: Option<String>,
+    ) -> Result<Asset, AssetError> {
+        let mut asset = self.uploading(ctx, id)?;
+        let key = AssetKey::new(id).unwrap();
+
+        let mut hasher = Sha256::new();
+        for index in 0..asset.chunk_count() {
+            let chunk = self
+                .chunks
+                .get(&ChunkKey { asset: key, index })
+                .ok_or(AssetError::IncompleteUpload)?;
+            hasher.update(chunk);
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        if sha256.is_some_and(|expected| expected.to_lowercase() != hash) {
+            return Err(AssetError::HashMismatch);
+        }
+
+        self.uploads.remove(&UploadKey {
+            created_at: asset.created_at,
+            asset: key,
+        });
+        asset.sha256 = Some(hash);
+        asset.status = AssetStatus::Committed;
+        self.assets.insert(key, asset.clone());
+        Ok(asset)
+    }
+
+    // Only the owner can delete an asset
+    pub fn delete(&mut self, ctx: &Context, id: &str) -> Result<(), AssetError> {
+        let asset = self.asset(id).ok_or(AssetError::AssetNotFound)?;
+        if asset.owner != ctx.caller {
+            return Err(AssetError::Unauthorized);
+        }
+        self.remove(&asset);
+        Ok(())
+    }
+
+    // Deleting every asset of the caller but the ones `keep` holds on to,
+    // whose ids are returned
+    pub fn delete_owned_by(&mut self, ctx: &Context, keep: impl Fn(&Asset) -> bool) -> Vec<String> {
+        let mut kept = vec![];
+        for asset in self.owned_by(&ctx.caller) {
+            if keep(&asset) {
+                kept.push(asset.id);
+            } else {
+                self.remove(&asset);
+            }
+        }
+        kept
+    }
+
+    // Noting a record that keeps the asset private
+    pub fn make_private(&mut self, id: &str, scope: AssetScope) {
+        let Some(key) = AssetKey::new(id) else {
+            return;
+        };
+        let Some(mut asset) = self.assets.get(&key) else {
+            return;
+        };
+        if !asset.private_to.contains(&scope) {
+            asset.private_to.push(scope);
+            self.assets.insert(key, asset);
+        }
+    }
+
+    // Deleting the uploads started `STALE_UPLOAD_AGE` ago and never committed
+    pub fn purge_stale_uploads(&mut self, now: u64) -> usize {
+        let stale: Vec<Asset> = self
+            .uploads
+            .iter()
+            .take_while(|(key, _)| key.created_at.saturating_add(STALE_UPLOAD_AGE) <= now)
+            .filter_map(|(key, _)| self.assets.get(&key.asset))
+            .collect();
+        for asset in &stale {
+            self.remove(asset);
+        }
+        stale.len()
+    }
+
+    // Counting an asset in the usage of its owner, and among the uploads
+    // while it is not committed
+    fn index(&mut self, asset: &Asset) {
+        let usage = self.usage(&asset.owner).saturating_add(asset.size);
+        self.usage.insert(owner_key(&asset.owner), usage);
+        if asset.status == AssetStatus::Uploading {
+            let key = UploadKey {
+                created_at: asset.created_at,
+                asset: AssetKey::new(&asset.id).unwrap(),
+            };
+            self.uploads.insert(key, ());
+        }
+    }
+
+    fn remove(&mut self, asset: &Asset) {
+        let key = AssetKey::new(&asset.id).unwrap();
+        for index in 0..asset.chunk_count() {
+            self.chunks.remove(&ChunkKey { asset: key, index });
+        }
+        self.assets.remove(&key);
+        self.uploads.remove(&UploadKey {
+            created_at: asset.created_at,
+            asset: key,
+        });
+        match self.usage(&asset.owner).saturating_sub(asset.size) {
+            0 => self.usage.remove(&owner_key(&asset.owner)),
+            usage => self.usage.insert(owner_key(&asset.owner), usage),
+        };
+    }
+
+    // Storing content that is already in memory, for migrations
+    pub(crate) fn store(&mut self, ctx: &Context, content_type: &str, bytes: &[u8]) -> Asset {
+        let id = ctx.rng.next_id();
+        let key = AssetKey::new(&id).unwrap();
+        for (index, chunk) in bytes.chunks(CHUNK_SIZE as usize).enumerate() {
+            let chunk_key = ChunkKey {
+                asset: key,
+                index: index as u32,
+            };
+            self.chunks.insert(chunk_key, chunk.to_vec());
+        }
+        let asset = Asset {
+            id,
+            owner: ctx.caller,
+            content_type: content_type.to_string(),
+            size: bytes.len() as u64,
+            chunk_size: CHUNK_SIZE,
+            sha256: Some(format!("{:x}", Sha256::digest(bytes))),
+            status: AssetStatus::Committed,
+            created_at: ctx.now,
+            private_to: vec![],
+        };
+        self.assets.insert(key, asset.clone());
+        self.index(&asset);
+        asset
+    }
+
+    // Bytes `start..=end` of an asset
+    pub fn read(&self, asset: &Asset, start: u64, end: u64) -> Vec<u8> {
+        let key = AssetKey::new(&asset.id).unwrap();
+        let mut bytes = Vec::with_capacity((end + 1 - start) as usize);
+        for index in (start / asset.chunk_size)..=(end / asset.chunk_size) {
+            let chunk_start = index * asset.chunk_size;
+            let chunk = self
+                .chunks
+                .get(&ChunkKey {
+                    asset: key,
+                    index: index as u32,
+                })
+                .unwrap_or_default();
+            let from = start.saturating_sub(chunk_start) as usize;
+            let to = ((end - chunk_start + 1) as usize).min(chunk.len());
+            bytes.extend_from_slice(&chunk[from.min(to)..to]);
+        }
+        bytes
+    }
+
+    fn uploading(&self, ctx: &Context, id: &str) -> Result<Asset, AssetError> {
+        let asset = self.asset(id).ok_or(AssetError::AssetNotFound)?;
+        if asset.owner != ctx.caller {
+            return Err(AssetError::Unauthorized);
+        }
+        if asset.status == AssetStatus::Committed {
+            return Err(AssetError::AlreadyCommitted);
+        }
+        Ok(asset)
+    }
+}
+
+// Resumes used to be stored as bytes in the profile, they are moved to the
+// asset store once on upgrade
+pub fn migrate_profile_resumes(
+    legacy: BTreeMap<Principal, LegacyProfileResume>,
+    profiles: &mut ProfileStore,
+    now: u64,
+) {
+    for (principal, LegacyProfileResume { resume }) in legacy {
+        let Some(profile) = profiles.get_mut(&principal) else {
+            continue;
+        };
+        if resume.is_empty() || profile.resume_id.is_some() {
+            continue;
+        }
+        let content_type = if resume.starts_with(b"%PDF") {
+            "application/pdf"
+        } else {
+            "application/octet-stream"
+        };
+        let ctx = Context::new(principal, now, Rng::from_seed(principal.as_slice()));
+        let asset = with_mut(|store| store.store(&ctx, content_type, &resume));
+        profile.resume_id = Some(asset.id);
+    }
+}
+
+// ==================================================================================================
+// HTTP
+// ==================================================================================================
+
+// Parsing a single `bytes=` range into inclusive bounds, `Ok(None)` when the
+// whole content is requested
+fn parse_range(header: Option<&str>, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(header) = header else {
+        return Ok(None);
+    };
+    let spec = header.trim().strip_prefix("bytes=").ok_or(())?;
+    if spec.contains(',') {
+        return Err(());
+    }
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| ())?;
+            if suffix == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (start, "") => (start.parse().map_err(|_| ())?, size - 1),
+        (start, end) => {
+            let end: u64 = end.parse().map_err(|_| ())?;
+            (start.parse().map_err(|_| ())?, end.min(size - 1))
+        }
+    };
+    if start > end || start >= size {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+// Parameters and case don't change the media type
+fn is_scripted(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    SCRIPTED_CONTENT_TYPES
+        .iter()
+        .any(|scripted| media_type.eq_ignore_ascii_case(scripted))
+}
+
+// Key signing the links, `None` until the first link is issued
+fn link_key() -> Option<Vec<u8>> {
+    LINK_KEY.with(|key| Some(key.borrow().get().clone()).filter(|key| !key.is_empty()))
+}
+
+// Token of a link to `id`, the expiry followed by the HMAC-SHA256 of both
+fn link_token(key: &[u8], id: &str, expires_at: u64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(id.as_bytes());
+    mac.update(&expires_at.to_be_bytes());
+    format!("{}.{:x}", expires_at, mac.finalize().into_bytes())
+}
+
+fn is_valid_link(key: &[u8], id: &str, token: &str, now: u64) -> bool {
+    let Some(expires_at) = token
+        .split_once('.')
+        .and_then(|(expires_at, _)| expires_at.parse::<u64>().ok())
+    else {
+        return false;
+    };
+    now < expires_at && link_token(key, id, expires_at) == token
+}
+
+// Serving an asset requested over HTTP, private assets need the token of a link
+pub(crate) fn serve_request(id: &str, request: &HttpRequest, now: u64) -> HttpResponse {
+    let private = with(|store| store.asset(id)).is_some_and(|asset| !asset.private_to.is_empty());
+    if private {
+        let token = http::query_param(&request.url, "token").unwrap_or_default();
+        let valid = link_key().is_some_and(|key| is_valid_link(&key, id, token, now));
+        if !valid {
+            return http::response(403, vec![], b"Forbidden".to_vec());
+        }
+    }
+    with(|store| serve(store, id, request, private))
+}
+
+// Serving a committed asset, assets are not certified so they have to be
+// fetched from the `raw` domain of the canister
+pub(crate) fn serve(
+    store: &AssetStore,
+    id: &str,
+    request: &HttpRequest,
+    private: bool,
+) -> HttpResponse {
+    let asset = store
+        .asset(id)
+        .filter(|asset| asset.status == AssetStatus::Committed);
+    let Some(asset) = asset else {
+        return http::not_found();
+    };
+
+    let range = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("range"))
+        .map(|(_, value)| value.as_str());
+    let Ok(range) = parse_range(range, asset.size) else {
+        let content_range = format!("bytes */{}", asset.size);
+        return http::response(416, vec![("Content-Range", content_range)], vec![]);
+    };
+
+    // Larger assets than a response can hold are served in parts, clients
+    // continue with range requests
+    let (start, end) = range.unwrap_or((0, asset.size - 1));
+    let end = end.min(start + MAX_RESPONSE_BYTES - 1);
+    let partial = range.is_some() || end + 1 < asset.size;
+
+    // Shared caches must not keep what a link gave access to
+    let cache_control = if private {
+        "private, no-store"
+    } else {
+        "public, max-age=31536000, immutable"
+    };
+    let mut headers = vec![
+        ("Content-Type", asset.content_type.clone()),
+        ("Content-Length", (end + 1 - start).to_string()),
+        ("Accept-Ranges", "bytes".into()),
+        ("Cache-Control", cache_control.into()),
+    ];
+    // Uploaded before such images were rejected, they are only downloaded
+    if is_scripted(&asset.content_type) {
+        headers.push(("Content-Disposition", "attachment".into()));
+    }
+    if let Some(sha256) = &asset.sha256 {
+        headers.push(("ETag", format!("\"{}\"", sha256)));
+    }
+    if partial {
+        let content_range = format!("bytes {}-{}/{}", start, end, asset.size);
+        headers.push(("Content-Range", content_range));
+    }
+    let status_code = if partial { 206 } else { 200 };
+    http::response(status_code, headers, store.read(&asset, start, end))
+}
+
+// ==================================================================================================
+// Assets
+// ==================================================================================================
+
+fn can_read(caller: &Principal, asset: &Asset) -> bool {
+    let admin = config::is_admin().is_ok();
+    asset.owner == *caller
+        || asset.private_to.is_empty()
+        || domain::with(|state| state.can_read_asset(caller, &asset.id, &asset.private_to, admin))
+}
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+// Metadata of an asset, private assets only for the owner and the audience
+// of the records keeping them private
+#[query]
+fn get_asset(id: String) -> Option<Asset> {
+    with(|store| store.asset(&id)).filter(|asset| can_read(&ic_cdk::api::caller(), asset))
+}
+
+// // ======================
+// //      UPDATE CALLS
+// // ======================
+
+// Uploads are open to registered users, and to admins for platform artwork.
+// Uploads abandoned by anyone are cleaned up on the way.
+#[update]
+async fn begin_asset_upload(content_type: String, size: u64) -> Result<Asset, AssetError> {
+    let caller = ic_cdk::api::caller();
+    let registered = domain::with(|state| state.profiles.contains_key(&caller));
+    if !registered && config::is_admin().is_err() {
+        return Err(AssetError::ProfileNotFound);
+    }
+    let ctx = Context::try_with_randomness()
+        .await
+        .map_err(AssetError::RandomnessUnavailable)?;
+    let settings = config::settings();
+    with_mut(|store| {
+        store.purge_stale_uploads(ctx.now);
+        store.begin_upload(
+            &ctx,
+            content_type,
+            size,
+            settings.max_asset_size,
+            settings.max_owner_assets_size,
+        )
+    })
+}
+
+#[update]
+fn put_asset_chunk(id: String, index: u32, bytes: Vec<u8>) -> Result<(), AssetError> {
+    let ctx = Context::current();
+    with_mut(|store| store.put_chunk(&ctx, &id, index, bytes))
+}
+
+#[update]
+fn commit_asset_upload(id: String, sha256: Option<String>) -> Result<Asset, AssetError> {
+    let ctx = Context::current();
+    with_mut(|store| store.commit_upload(&ctx, &id, sha256))
+}
+
+// Records referencing the asset are left without it, certificates keep their artwork
+#[update]
+fn delete_asset(id: String) -> Result<(), AssetError> {
+    let ctx = Context::current();
+    if certificate::ledger::with(|ledger| ledger.references_asset(&id)) {
+        return Err(AssetError::CertificateArtwork);
+    }
+    with_mut(|store| store.delete(&ctx, &id))?;
+    domain::with_mut(|state| state.forget_assets(&BTreeSet::from([id])));
+    Ok(())
+}
+
+// A link to a committed asset, for the owner and the audience of the records
+// keeping it private. Public assets are served without the token as well.
+#[update]
+async fn get_asset_link(id: String) -> Result<AssetLink, AssetError> {
+    let asset = with(|store| store.asset(&id))
+        .filter(|asset| asset.status == AssetStatus::Committed)
+        .ok_or(AssetError::AssetNotFound)?;
+    if !can_read(&ic_cdk::api::caller(), &asset) {
+        return Err(AssetError::Unauthorized);
+    }
+
+    let key = match link_key() {
+        Some(key) => key,
+        None => {
+            let (key,) = raw_rand().await.map_err(|(code, message)| {
+                AssetError::RandomnessUnavailable(format!("{:?}: {}", code, message))
+            })?;
+            // Another call may have drawn the key while this one was waiting
+            link_key().unwrap_or_else(|| {
+                LINK_KEY.with(|link_key| {
+                    link_key
+                        .borrow_mut()
+                        .set(key.clone())
+                        .expect("Failed to save the link key")
+                });
+                key
+            })
+        }
+    };
+    let expires_at = ic_cdk::api::time() + LINK_TTL;
+    let token = link_token(&key, &id, expires_at);
+    Ok(AssetLink {
+        path: format!("/assets/{}?token={}", id, token),
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::testing::{ctx, principal, NOW};
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn store() -> AssetStore {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        AssetStore::init(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(2)),
+            manager.get(MemoryId::new(3)),
+        )
+    }
+
+    // A committed asset of `size` bytes owned by principal 1
+    fn uploaded(store: &mut AssetStore, size: u64) -> (Asset, Vec<u8>) {
+        let owner = ctx(principal(1));
+        let content: Vec<u8> = (0..size).map(|byte| byte as u8).collect();
+        let asset = store
+            .begin_upload(
+                &owner,
+                "application/pdf".into(),
+                size,
+                10 * CHUNK_SIZE,
+                10 * CHUNK_SIZE,
+            )
+            .unwrap();
+        // Out of order on purpose
+        for (index, chunk) in content.chunks(CHUNK_SIZE as usize).enumerate().rev() {
+            store
+                .put_chunk(&owner, &asset.id, index as u32, chunk.to_vec())
+                .unwrap();
+        }
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let asset = store.commit_upload(&owner, &asset.id, Some(hash)).unwrap();
+        (asset, content)
+    }
+
+    fn get(store: &AssetStore, id: &str, range: Option<&str>) -> HttpResponse {
+        let headers = range
+            .map(|range| vec![("Range".to_string(), range.to_string())])
+            .unwrap_or_default();
+        let request = HttpRequest {
+            method: "GET".into(),
+            url: format!("/assets/{}?download", id),
+            headers,
+            body: vec![],
+        };
+        serve(store, id, &request, false)
+    }
+
+    #[test]
+    fn uploads_are_validated() {
+        let mut store = store();
+        let owner = ctx(principal(1));
+        assert_eq!(
+            store
+                .begin_upload(&owner, "text/html".into(), 10, 100, 1_000)
+                .unwrap_err(),
+            AssetError::UnsupportedContentType
+        );
+        for scripted in ["image/svg+xml", "Image/SVG+XML; charset=utf-8"] {
+            assert_eq!(
+                store
+                    .begin_upload(&owner, scripted.into(), 10, 100, 1_000)
+                    .unwrap_err(),
+                AssetError::UnsupportedContentType
+            );
+        }
+        assert_eq!(
+            store
+                .begin_upload(&owner, "image/png".into(), 101, 100, 1_000)
+                .unwrap_err(),
+            AssetError::TooLarge(100)
+        );
+
+        let asset = store
+            .begin_upload(&owner, "image/png".into(), 10, 100, 1_000)
+            .unwrap();
+        assert_eq!(
+            store.put_chunk(&ctx(principal(2)), &asset.id, 0, vec![0; 10]),
+            Err(AssetError::Unauthorized)
+        );
+        assert_eq!(
+            store.put_chunk(&owner, &asset.id, 0, vec![0; 9]),
+            Err(AssetError::InvalidChunk)
+        );
+        assert_eq!(
+            store.commit_upload(&owner, &asset.id, None).unwrap_err(),
+            AssetError::IncompleteUpload
+        );
+        store.put_chunk(&owner, &asset.id, 0, vec![0; 10]).unwrap();
+        assert_eq!(
+            store
+                .commit_upload(&owner, &asset.id, Some("00".into()))
+                .unwrap_err(),
+            AssetError::HashMismatch
+        );
+        assert!(!store.is_committed(&asset.id));
+        store.commit_upload(&owner, &asset.id, None).unwrap();
+        assert!(store.is_owned_by(&asset.id, &principal(1)));
+        assert!(!store.is_owned_by(&asset.id, &principal(2)));
+    }
+
+    #[test]
+    fn assets_are_served_whole_or_in_ranges() {
+        let mut store = store();
+        let (small, content) = uploaded(&mut store, 1_000);
+        let response = get(&store, &small.id, None);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, content);
+
+        let (large, content) = uploaded(&mut store, 3 * CHUNK_SIZE + 7);
+        let response = get(&store, &large.id, None);
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body.len() as u64, MAX_RESPONSE_BYTES);
+
+        // Across a chunk boundary
+        let start = CHUNK_SIZE - 5;
+        let range = format!("bytes={}-{}", start, start + 9);
+        let response = get(&store, &large.id, Some(&range));
+        assert_eq!(response.status_code, 206);
+        assert_eq!(
+            response.body,
+            content[start as usize..start as usize + 10].to_vec()
+        );
+
+        let response = get(&store, &large.id, Some("bytes=-7"));
+        assert_eq!(response.body, content[content.len() - 7..].to_vec());
+        let response = get(&store, &large.id, Some("bytes=99999999-"));
+        assert_eq!(response.status_code, 416);
+        assert_eq!(get(&store, "missing", None).status_code, 404);
+    }
+
+    #[test]
+    fn records_keeping_an_asset_private_are_noted_once() {
+        let mut store = store();
+        let (asset, _) = uploaded(&mut store, 10);
+        assert!(asset.private_to.is_empty());
+
+        let scope = AssetScope::Resume(principal(1));
+        store.make_private(&asset.id, scope.clone());
+        store.make_private(&asset.id, scope.clone());
+        store.make_private("missing", scope.clone());
+        assert_eq!(store.asset(&asset.id).unwrap().private_to, vec![scope]);
+    }
+
+    #[test]
+    fn only_owners_delete_assets() {
+        let mut store = store();
+        let (asset, _) = uploaded(&mut store, 10);
+        assert_eq!(
+            store.delete(&ctx(principal(2)), &asset.id),
+            Err(AssetError::Unauthorized)
+        );
+        store.delete(&ctx(principal(1)), &asset.id).unwrap();
+        assert!(store.asset(&asset.id).is_none());
+        assert_eq!(get(&store, &asset.id, None).status_code, 404);
+    }
+
+    #[test]
+    fn deleted_accounts_keep_only_the_assets_held_on_to() {
+        let mut store = store();
+        let (artwork, _) = uploaded(&mut store, 10);
+        let (resume, _) = uploaded(&mut store, 10);
+        let other = store
+            .begin_upload(&ctx(principal(2)), "image/png".into(), 10, 100, 1_000)
+            .unwrap();
+
+        let kept = store.delete_owned_by(&ctx(principal(1)), |asset| asset.id == artwork.id);
+        assert_eq!(kept, vec![artwork.id.clone()]);
+        assert!(store.is_committed(&artwork.id));
+        assert!(store.asset(&resume.id).is_none());
+        assert!(store.asset(&other.id).is_some());
+    }
+
+    #[test]
+    fn uploads_fit_in_the_quota_of_the_owner() {
+        let mut store = store();
+        let owner = ctx(principal(1));
+        uploaded(&mut store, 600);
+        assert_eq!(store.usage(&principal(1)), 600);
+
+        assert_eq!(
+            store
+                .begin_upload(&owner, "image/png".into(), 500, 1_000, 1_000)
+                .unwrap_err(),
+            AssetError::QuotaExceeded(1_000)
+        );
+        // Other users have a quota of their own
+        store
+            .begin_upload(&ctx(principal(2)), "image/png".into(), 500, 1_000, 1_000)
+            .unwrap();
+        store
+            .begin_upload(&owner, "image/png".into(), 400, 1_000, 1_000)
+            .unwrap();
+        assert_eq!(store.usage(&principal(1)), 1_000);
+    }
+
+    #[test]
+    fn stale_uploads_are_purged() {
+        let mut store = store();
+        let (committed, _) = uploaded(&mut store, 10);
+        let owner = ctx(principal(1));
+        let abandoned = store
+            .begin_upload(&owner, "image/png".into(), 10, 100, 1_000)
+            .unwrap();
+        store
+            .put_chunk(&owner, &abandoned.id, 0, vec![0; 10])
+            .unwrap();
+
+        assert_eq!(store.purge_stale_uploads(NOW + STALE_UPLOAD_AGE - 1), 0);
+        assert_eq!(store.purge_stale_uploads(NOW + STALE_UPLOAD_AGE), 1);
+        assert!(store.asset(&abandoned.id).is_none());
+        assert!(store.is_committed(&committed.id));
+        assert_eq!(store.usage(&principal(1)), 10);
+    }
+
+    #[test]
+    fn usage_and_uploads_are_indexed_for_assets_stored_before_them() {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        let memory = |id| manager.get(MemoryId::new(id));
+        let mut store = AssetStore::init(memory(0), memory(1), memory(2), memory(3));
+        let asset = Asset {
+            id: "a".repeat(ID_LEN),
+            owner: principal(1),
+            content_type: "image/png".into(),
+            size: 10,
+            chunk_size: CHUNK_SIZE,
+            sha256: None,
+            status: AssetStatus::Uploading,
+            created_at: NOW,
+            private_to: vec![],
+        };
+        store
+            .assets
+            .insert(AssetKey::new(&asset.id).unwrap(), asset);
+        assert_eq!(store.usage(&principal(1)), 0);
+
+        let mut store = AssetStore::init(memory(0), memory(1), memory(2), memory(3));
+        assert_eq!(store.usage(&principal(1)), 10);
+        assert_eq!(store.purge_stale_uploads(NOW + STALE_UPLOAD_AGE), 1);
+        assert_eq!(store.usage(&principal(1)), 0);
+    }
+
+    #[test]
+    fn links_expire_and_name_a_single_asset() {
+        let key = b"key";
+        let token = link_token(key, "asset", NOW + LINK_TTL);
+        assert!(is_valid_link(key, "asset", &token, NOW));
+        assert!(!is_valid_link(key, "asset", &token, NOW + LINK_TTL));
+        assert!(!is_valid_link(key, "other", &token, NOW));
+        assert!(!is_valid_link(b"other key", "asset", &token, NOW));
+        let forged = token.replacen(&(NOW + LINK_TTL).to_string(), &u64::MAX.to_string(), 1);
+        assert!(!is_valid_link(key, "asset", &forged, NOW));
+        assert!(!is_valid_link(key, "asset", "", NOW));
+
+        let request = HttpRequest {
+            method: "GET".into(),
+            url: format!("/assets/asset?download&token={}", token),
+            headers: vec![],
+            body: vec![],
+        };
+        assert_eq!(
+            http::query_param(&request.url, "token"),
+            Some(token.as_str())
+        );
+        assert_eq!(http::query_param(&request.url, "missing"), None);
+    }
+
+    #[test]
+    fn private_assets_are_not_cached_by_shared_caches() {
+        let mut store = store();
+        let (asset, _) = uploaded(&mut store, 10);
+        let request = HttpRequest {
+            method: "GET".into(),
+            url: format!("/assets/{}", asset.id),
+            headers: vec![],
+            body: vec![],
+        };
+        let response = serve(&store, &asset.id, &request, true);
+        assert!(response
+            .headers
+            .contains(&("Cache-Control".to_string(), "private, no-store".to_string())));
+    }
+
+    #[test]
+    fn served_assets_are_neither_sniffed_nor_scripted() {
+        let mut store = store();
+        let (mut asset, _) = uploaded(&mut store, 10);
+        let header = |response: &HttpResponse, name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.clone())
+        };
+        let response = get(&store, &asset.id, None);
+        assert_eq!(
+            header(&response, "X-Content-Type-Options").as_deref(),
+            Some("nosniff")
+        );
+        assert_eq!(
+            header(&response, "Content-Security-Policy").as_deref(),
+            Some("sandbox")
+        );
+        assert_eq!(header(&response, "Content-Disposition"), None);
+
+        // Uploaded before such images were rejected
+        asset.content_type = "image/svg+xml".into();
+        store
+            .assets
+            .insert(AssetKey::new(&asset.id).unwrap(), asset.clone());
+        let response = get(&store, &asset.id, None);
+        assert_eq!(
+            header(&response, "Content-Disposition").as_deref(),
+            Some("attachment")
+        );
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(parse_range(None, 10), Ok(None));
+        assert_eq!(parse_range(Some("bytes=2-4"), 10), Ok(Some((2, 4))));
+        assert_eq!(parse_range(Some("bytes=2-"), 10), Ok(Some((2, 9))));
+        assert_eq!(parse_range(Some("bytes=-3"), 10), Ok(Some((7, 9))));
+        assert_eq!(parse_range(Some("bytes=5-100"), 10), Ok(Some((5, 9))));
+        assert!(parse_range(Some("bytes=5-2"), 10).is_err());
+        assert!(parse_range(Some("bytes=0-1,4-5"), 10).is_err());
+        assert!(parse_range(Some("items=0-1"), 10).is_err());
+    }
+}
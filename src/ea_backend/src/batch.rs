@@ -0,0 +1,483 @@
+use crate::certificate::is_canister_custodian;
+use crate::certificate::types::NftError;
+use crate::config;
+use crate::domain::{self, Context, Course};
+use crate::fees;
+use crate::mint_course_certificate;
+use candid::{CandidType, Nat, Principal};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+
+pub(crate) type BatchStore = BTreeMap<String, BatchMintJob>;
+
+thread_local! {
+    pub(crate) static BATCH_STORE: RefCell<BatchStore> = RefCell::default();
+}
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum BatchStatus {
+    InProgress,
+    Completed,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub enum RecipientStatus {
+    Pending,
+    Minted(Nat),
+    AlreadyCertified,
+    Failed(NftError),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct BatchRecipient {
+    pub to: Principal,
+    pub grade: String,
+    pub status: RecipientStatus,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct BatchMintJob {
+    pub id: String,
+    pub course_id: String,
+    pub creator: Principal,
+    pub description: String,
+    pub tag: String,
+    pub certificate: String,
+    pub recipients: Vec<BatchRecipient>,
+    pub next_index: u64,
+    pub status: BatchStatus,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub enum BatchMintError {
+    CourseNotFound,
+    Unauthorized,
+    EmptyBatch,
+    InvalidRecipients(Vec<Principal>),
+    JobNotFound,
+    BatchMintingDisabled,
+    TooManyRecipients(u64),
+    UnverifiedIssuer,
+    // The certificate fee could not be taken from the creator
+    FeeNotPaid(String),
+    // The management canister did not hand out the randomness for the job id
+    RandomnessUnavailable(String),
+}
+
+// ==================================================================================================
+// Batch minting
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+// Get the progress and per-recipient results of a batch
+#[query]
+fn get_certificates_batch(job_id: String) -> Option<BatchMintJob> {
+    let job = BATCH_STORE.with(|store| store.borrow().get(&job_id).cloned())?;
+    let caller = ic_cdk::api::caller();
+    let allowed = can_view_batch(&job, &caller) || is_canister_custodian().is_ok();
+    allowed.then_some(job)
+}
+
+// // ======================
+// //      UPDATE CALLS
+// // ======================
+
+// Minting certificates to a whole course cohort
+#[update]
+async fn mint_certificates_batch(
+    course_id: String,
+    recipients: Vec<(Principal, String)>,
+    description: String,
+    tag: String,
+    certificate: String,
+) -> Result<BatchMintJob, BatchMintError> {
+    let ctx = Context::try_with_randomness()
+        .await
+        .map_err(BatchMintError::RandomnessUnavailable)?;
+    let course = check_minting(&course_id, &ctx.caller)?;
+    check_recipients(
+        &course,
+        &recipients,
+        config::settings().max_batch_recipients,
+    )?;
+    // The whole batch is paid for upfront, except the recipients already certified
+    if is_canister_custodian().is_err() {
+        let count = recipients
+            .iter()
+            .filter(|(to, _)| !is_certified(to, &course_id))
+            .count();
+        fees::charge_certificates(ctx.caller, count as u64)
+            .await
+            .map_err(BatchMintError::FeeNotPaid)?;
+    }
+
+    let job_id = ctx.rng.next_id();
+    let job = BatchMintJob {
+        id: job_id.clone(),
+        course_id,
+        creator: ctx.caller,
+        description,
+        tag,
+        certificate,
+        recipients: recipients
+            .into_iter()
+            .map(|(to, grade)| BatchRecipient {
+                to,
+                grade,
+                status: RecipientStatus::Pending,
+            })
+            .collect(),
+        next_index: 0,
+        status: BatchStatus::InProgress,
+        created_at: ctx.now,
+    };
+    BATCH_STORE.with(|store| store.borrow_mut().insert(job_id.clone(), job));
+
+    run_batch(job_id).await
+}
+
+// Awaited by `run_batch` between chunks, does nothing and only the canister calls it
+#[update(guard = "is_self")]
+fn yield_batch() {}
+
+fn is_self() -> Result<(), String> {
+    if ic_cdk::api::caller() == ic_cdk::api::id() {
+        Ok(())
+    } else {
+        Err("Only the canister yields between batch chunks".to_string())
+    }
+}
+
+// Resuming a batch that was interrupted before all recipients were processed
+#[update]
+async fn resume_certificates_batch(job_id: String) -> Result<BatchMintJob, BatchMintError> {
+    let caller = ic_cdk::api::caller();
+
+    let (creator, course_id) = BATCH_STORE
+        .with(|store| {
+            let store = store.borrow();
+            let job = store.get(&job_id)?;
+            Some((job.creator, job.course_id.clone()))
+        })
+        .ok_or(BatchMintError::JobNotFound)?;
+    if creator != caller && is_canister_custodian().is_err() {
+        return Err(BatchMintError::Unauthorized);
+    }
+    // The creator may have left the course, or batches been disabled, since
+    check_minting(&course_id, &caller)?;
+
+    run_batch(job_id).await
+}
+
+// Batches are started, and resumed, while batch minting is enabled by the
+// managers of the course or the custodians
+fn check_minting(course_id: &str, caller: &Principal) -> Result<Course, BatchMintError> {
+    if !config::features().batch_minting {
+        return Err(BatchMintError::BatchMintingDisabled);
+    }
+
+    let course = domain::with(|state| state.courses.get(course_id).cloned())
+        .ok_or(BatchMintError::CourseNotFound)?;
+    let manager = domain::with(|state| state.manages_course(&course, caller));
+    if !manager && is_canister_custodian().is_err() {
+        return Err(BatchMintError::Unauthorized);
+    }
+    if config::features().verified_issuers_only
+        && !domain::with(|state| state.is_verified_issuer(course_id))
+    {
+        return Err(BatchMintError::UnverifiedIssuer);
+    }
+    Ok(course)
+}
+
+// Every recipient has to be enrolled in the course and appear only once
+fn check_recipients(
+    course: &Course,
+    recipients: &[(Principal, String)],
+    max_recipients: u64,
+) -> Result<(), BatchMintError> {
+    if recipients.is_empty() {
+        return Err(BatchMintError::EmptyBatch);
+    }
+    if recipients.len() as u64 > max_recipients {
+        return Err(BatchMintError::TooManyRecipients(max_recipients));
+    }
+
+    let mut seen = HashSet::new();
+    let invalid: Vec<Principal> = recipients
+        .iter()
+        .filter(|(to, _)| !course.applicants.contains(to) || !seen.insert(*to))
+        .map(|(to, _)| *to)
+        .collect();
+    if !invalid.is_empty() {
+        return Err(BatchMintError::InvalidRecipients(invalid));
+    }
+    Ok(())
+}
+
+// Batches list their recipients and grades, besides the custodians only the
+// creator and the managers of the course see them
+fn can_view_batch(job: &BatchMintJob, caller: &Principal) -> bool {
+    job.creator == *caller
+        || domain::with(|state| {
+            state
+                .courses
+                .get(&job.course_id)
+                .is_some_and(|course| state.manages_course(course, caller))
+        })
+}
+
+fn is_certified(to: &Principal, course_id: &str) -> bool {
+    domain::with(|state| {
+        state
+            .profiles
+            .get(to)
+            .is_some_and(|profile| profile.token_ids.iter().any(|(_, id)| id == course_id))
+    })
+}
+
+// Processing the batch chunk by chunk until every recipient has a result
+async fn run_batch(job_id: String) -> Result<BatchMintJob, BatchMintError> {
+    while !mint_next_chunk(&job_id, mint_recipient)? {
+        // Awaiting a call commits the minted chunk and continues in a new message
+        // with a fresh instruction budget. If the call fails the job stays
+        // resumable through `resume_certificates_batch`.
+        let yielded: ic_cdk::api::call::CallResult<()> =
+            ic_cdk::call(ic_cdk::api::id(), "yield_batch", ()).await;
+        if yielded.is_err() {
+            break;
+        }
+    }
+
+    BATCH_STORE
+        .with(|store| store.borrow().get(&job_id).cloned())
+        .ok_or(BatchMintError::JobNotFound)
+}
+
+fn mint_recipient(job: &BatchMintJob, recipient: &BatchRecipient) -> Result<Nat, NftError> {
+    mint_course_certificate(
+        recipient.to,
+        job.description.clone(),
+        job.tag.clone(),
+        job.course_id.clone(),
+        job.certificate.clone(),
+        Some(recipient.grade.clone()),
+    )
+}
+
+// Minting the next chunk of the batch with `mint`, returns true once the batch
+// is completed
+fn mint_next_chunk(
+    job_id: &str,
+    mint: impl Fn(&BatchMintJob, &BatchRecipient) -> Result<Nat, NftError>,
+) -> Result<bool, BatchMintError> {
+    let chunk_size = config::settings().batch_chunk_size.max(1) as usize;
+
+    BATCH_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        let job = store.get_mut(job_id).ok_or(BatchMintError::JobNotFound)?;
+
+        let start = job.next_index as usize;
+        let end = (start + chunk_size).min(job.recipients.len());
+        for index in start..end {
+            let recipient = &job.recipients[index];
+            job.recipients[index].status = if is_certified(&recipient.to, &job.course_id) {
+                RecipientStatus::AlreadyCertified
+            } else {
+                match mint(job, recipient) {
+                    Ok(token_identifier) => RecipientStatus::Minted(token_identifier),
+                    Err(e) => RecipientStatus::Failed(e),
+                }
+            };
+        }
+
+        job.next_index = end as u64;
+        if end == job.recipients.len() {
+            job.status = BatchStatus::Completed;
+        }
+
+        Ok(job.status == BatchStatus::Completed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::testing::{ctx, principal, state};
+
+    // A job for the trainer, the trainee and the employer of the test state,
+    // all of them already certified so no certificate gets minted
+    fn certified_job(chunk_size: u64) -> String {
+        domain::with_mut(|s| {
+            *s = state();
+            for n in 1..=3 {
+                let profile = s.profiles.get_mut(&principal(n)).unwrap();
+                profile
+                    .token_ids
+                    .push((Nat::from(n - 1), "rust".to_string()));
+            }
+        });
+        config::CONFIG.with(|config| config.borrow_mut().settings.batch_chunk_size = chunk_size);
+
+        let job = BatchMintJob {
+            id: "job".to_string(),
+            course_id: "rust".to_string(),
+            creator: principal(1),
+            description: "Completed the course".to_string(),
+            tag: "certificate".to_string(),
+            certificate: "https://example.com/certificate.png".to_string(),
+            recipients: (1..=3)
+                .map(|n| BatchRecipient {
+                    to: principal(n),
+                    grade: "A".to_string(),
+                    status: RecipientStatus::Pending,
+                })
+                .collect(),
+            next_index: 0,
+            status: BatchStatus::InProgress,
+            created_at: 0,
+        };
+        BATCH_STORE.with(|store| store.borrow_mut().insert(job.id.clone(), job.clone()));
+        job.id
+    }
+
+    fn job(id: &str) -> BatchMintJob {
+        BATCH_STORE.with(|store| store.borrow().get(id).cloned().unwrap())
+    }
+
+    #[test]
+    fn batches_resume_after_the_last_minted_chunk() {
+        let id = certified_job(2);
+
+        assert!(!mint_next_chunk(&id, mint_recipient).unwrap());
+        let stored = job(&id);
+        assert_eq!(stored.next_index, 2);
+        assert_eq!(stored.status, BatchStatus::InProgress);
+        assert!(matches!(
+            stored.recipients[1].status,
+            RecipientStatus::AlreadyCertified
+        ));
+        assert!(matches!(
+            stored.recipients[2].status,
+            RecipientStatus::Pending
+        ));
+
+        // The next chunk starts at the first recipient without a result
+        assert!(mint_next_chunk(&id, mint_recipient).unwrap());
+        let stored = job(&id);
+        assert_eq!(stored.next_index, 3);
+        assert_eq!(stored.status, BatchStatus::Completed);
+        assert!(stored
+            .recipients
+            .iter()
+            .all(|recipient| matches!(recipient.status, RecipientStatus::AlreadyCertified)));
+
+        // Resuming a completed batch leaves it as it is
+        assert!(mint_next_chunk(&id, mint_recipient).unwrap());
+        assert_eq!(job(&id).next_index, 3);
+    }
+
+    #[test]
+    fn an_empty_chunk_size_still_makes_progress() {
+        let id = certified_job(0);
+
+        assert!(!mint_next_chunk(&id, mint_recipient).unwrap());
+        assert_eq!(job(&id).next_index, 1);
+    }
+
+    #[test]
+    fn recipients_get_a_certificate_or_the_error_minting_it() {
+        let id = certified_job(3);
+        domain::with_mut(|s| {
+            for n in 2..=3 {
+                s.profiles.get_mut(&principal(n)).unwrap().token_ids.clear();
+            }
+        });
+
+        let mint = |_: &BatchMintJob, recipient: &BatchRecipient| {
+            if recipient.to == principal(2) {
+                Ok(Nat::from(7u64))
+            } else {
+                Err(NftError::UnverifiedIssuer)
+            }
+        };
+        assert!(mint_next_chunk(&id, mint).unwrap());
+        let stored = job(&id);
+        assert!(matches!(
+            stored.recipients[0].status,
+            RecipientStatus::AlreadyCertified
+        ));
+        assert!(matches!(
+            &stored.recipients[1].status,
+            RecipientStatus::Minted(token) if *token == 7u64
+        ));
+        assert!(matches!(
+            stored.recipients[2].status,
+            RecipientStatus::Failed(NftError::UnverifiedIssuer)
+        ));
+    }
+
+    #[test]
+    fn recipients_are_enrolled_once() {
+        let mut s = state();
+        let course = s.create_course(&ctx(principal(1)), "Rust".into()).unwrap();
+        let course = s.apply_course(&ctx(principal(2)), &course.id).unwrap();
+        let recipient = |n| (principal(n), "A".to_string());
+
+        assert!(check_recipients(&course, &[recipient(2)], 1).is_ok());
+        assert!(matches!(
+            check_recipients(&course, &[], 1),
+            Err(BatchMintError::EmptyBatch)
+        ));
+        assert!(matches!(
+            check_recipients(&course, &[recipient(2), recipient(2)], 1),
+            Err(BatchMintError::TooManyRecipients(1))
+        ));
+        assert!(matches!(
+            check_recipients(&course, &[recipient(2), recipient(3), recipient(2)], 3),
+            Err(BatchMintError::InvalidRecipients(invalid)) if invalid == vec![principal(3), principal(2)]
+        ));
+    }
+
+    #[test]
+    fn batches_are_not_started_while_batch_minting_is_disabled() {
+        config::CONFIG.with(|config| config.borrow_mut().features.batch_minting = false);
+        assert!(matches!(
+            check_minting("rust", &principal(1)),
+            Err(BatchMintError::BatchMintingDisabled)
+        ));
+        config::CONFIG.with(|config| config.borrow_mut().features.batch_minting = true);
+    }
+
+    #[test]
+    fn batches_are_shown_to_their_creator_and_the_course_managers() {
+        let id = certified_job(1);
+        let course =
+            domain::with_mut(|s| s.create_course(&ctx(principal(1)), "Rust".into()).unwrap());
+        let mut stored = job(&id);
+        stored.creator = principal(3);
+        assert!(can_view_batch(&stored, &principal(3)));
+        assert!(!can_view_batch(&stored, &principal(1)));
+        stored.course_id = course.id;
+        assert!(can_view_batch(&stored, &principal(1)));
+        assert!(!can_view_batch(&stored, &principal(2)));
+    }
+
+    #[test]
+    fn unknown_batches_are_not_resumed() {
+        assert!(matches!(
+            mint_next_chunk("missing", mint_recipient),
+            Err(BatchMintError::JobNotFound)
+        ));
+    }
+}
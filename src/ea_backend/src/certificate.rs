@@ -1,18 +1,16 @@
-use cap_sdk::handshake;
-
 use ic_cdk::api::call::ManualReply;
 use ic_cdk::api::{caller, canister_balance128, time};
-use ic_cdk_macros::{init, query, update};
+use ic_cdk_macros::{query, update};
 
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::ops::Not;
 
-use candid::{candid_method, CandidType, Deserialize, Int, Nat, Principal};
+use candid::{CandidType, Int, Nat, Principal};
 use compile_time_run::run_command_str;
 use types::*;
 
-pub(crate) mod types {
+pub mod types {
     use super::*;
     use serde::{Serialize, Deserialize};
     #[derive(CandidType, Deserialize)]
@@ -23,6 +21,13 @@ pub(crate) mod types {
         pub custodians: Option<HashSet<Principal>>,
         pub cap: Option<Principal>,
     }
+    #[derive(CandidType, Deserialize)]
+    pub struct UpgradeArgs {
+        pub name: Option<String>,
+        pub logo: Option<String>,
+        pub symbol: Option<String>,
+        pub cap: Option<Principal>,
+    }
     #[derive(CandidType, Default,Serialize, Deserialize, Clone)]
     pub struct Metadata {
         pub name: Option<String>,
@@ -82,7 +87,7 @@ pub(crate) mod types {
         Mint,
         Burn,
     }
-    #[derive(CandidType)]
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub enum NftError {
         UnauthorizedOwner,
         UnauthorizedOperator,
@@ -92,6 +97,54 @@ pub(crate) mod types {
         ExistedNFT,
         SelfApprove,
         SelfTransfer,
+        TxNotFound,
+        TransferDisabled,
+        UnverifiedIssuer,
+        Unauthorized,
+        CourseNotFound,
+        InvalidPrincipal,
+        // The recipient of a certificate is not enrolled in the course
+        NotEnrolled,
+        // The certificate fee could not be taken from the minter
+        FeeNotPaid(String),
+    }
+    #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub enum TxOperation {
+        Mint,
+        Transfer,
+        TransferFrom,
+        Approve,
+        ApproveAll,
+        Burn,
+        Revoke,
+    }
+    #[derive(CandidType, Serialize, Deserialize, Clone)]
+    pub struct TxEvent {
+        pub tx_id: Nat,
+        pub operation: TxOperation,
+        pub caller: Principal,
+        pub from: Option<Principal>,
+        pub to: Option<Principal>,
+        pub token_identifier: Option<TokenIdentifier>,
+        pub timestamp: u64,
+        // ICRC-7 and ICRC-37 transactions name the subaccounts of the accounts
+        // involved, and approvals their expiry. DIP721 transactions have none.
+        #[serde(default)]
+        pub from_subaccount: Option<Vec<u8>>,
+        #[serde(default)]
+        pub to_subaccount: Option<Vec<u8>>,
+        #[serde(default)]
+        pub spender_subaccount: Option<Vec<u8>>,
+        #[serde(default)]
+        pub expires_at: Option<u64>,
+    }
+    // The part of a transaction only ICRC-7 and ICRC-37 calls fill in
+    #[derive(Clone, Default)]
+    pub struct TxDetails {
+        pub from_subaccount: Option<Vec<u8>>,
+        pub to_subaccount: Option<Vec<u8>>,
+        pub spender_subaccount: Option<Vec<u8>>,
+        pub expires_at: Option<u64>,
     }
 }
 
@@ -111,17 +164,39 @@ pub mod ledger {
         LEDGER.with(|ledger| f(&mut ledger.borrow_mut()))
     }
 
+    // Upper bound for a single page of transaction history
+    pub const MAX_TX_PAGE_SIZE: u64 = 100;
+
     #[derive(CandidType, Default, Serialize, Deserialize, Clone)]
     pub struct Ledger {
         pub metadata: Metadata,
         pub tokens: HashMap<TokenIdentifier, TokenMetadata>, // recommend to have sequential id
         pub owners: HashMap<Principal, HashSet<TokenIdentifier>>, // quick lookup
         pub operators: HashMap<Principal, HashSet<TokenIdentifier>>, // quick lookup
-        pub tx_count: Nat,
+        #[serde(default)]
+        pub token_subaccounts: HashMap<TokenIdentifier, Vec<u8>>, // non-default ICRC-7 subaccounts only
+        #[serde(default)]
+        pub approval_expiries: HashMap<TokenIdentifier, u64>, // ICRC-37 approval expiry
+        #[serde(default)]
+        pub cap: Option<Principal>, // optional CAP-compatible history bucket
+        #[serde(default)]
+        pub collection_approvals: HashMap<Principal, Vec<crate::icrc7::ApprovalInfo>>, // ICRC-37, one per owner subaccount
+        #[serde(default)]
+        pub recent_txs: HashMap<[u8; 32], (Nat, u64)>, // ICRC-7 deduplication, tx id and created_at_time
+    }
+
+    // Transactions used to be kept on the heap as well, they are read once on
+    // upgrade to move the ones missing from the block log into it
+    #[derive(Default, Deserialize)]
+    pub struct LegacyLedgerHistory {
+        #[serde(default)]
+        pub tx_log: Vec<TxEvent>,
     }
 
     impl Ledger {
         pub fn init_metadata(&mut self, default_custodian: Principal, args: Option<InitArgs>) {
+            // History is always kept locally, CAP is only used when explicitly configured
+            self.cap = args.as_ref().and_then(|args| args.cap);
             let metadata = self.metadata_mut();
             metadata.custodians.insert(default_custodian);
             if let Some(args) = args {
@@ -133,14 +208,31 @@ pub mod ledger {
                         metadata.custodians.insert(custodians);
                     }
                 }
-            } else {
-                // default to mainnet cap canister if no args are specified
-                handshake(1_000_000_000_000, None);
             }
             metadata.created_at = time();
             metadata.upgraded_at = time();
         }
 
+        // Only the values present in the upgrade arguments are replaced
+        pub fn upgrade_metadata(&mut self, args: Option<UpgradeArgs>) {
+            if let Some(args) = args {
+                if args.cap.is_some() {
+                    self.cap = args.cap;
+                }
+                let metadata = self.metadata_mut();
+                if args.name.is_some() {
+                    metadata.name = args.name;
+                }
+                if args.logo.is_some() {
+                    metadata.logo = args.logo;
+                }
+                if args.symbol.is_some() {
+                    metadata.symbol = args.symbol;
+                }
+            }
+            self.metadata_mut().upgraded_at = time();
+        }
+
         pub fn metadata(&self) -> &Metadata {
             &self.metadata
         }
@@ -153,8 +245,9 @@ pub mod ledger {
             self.tokens.len()
         }
 
+        // The transactions are kept in the ICRC-3 block log, one block per transaction
         pub fn tx_count(&self) -> Nat {
-            self.tx_count.clone()
+            Nat::from(crate::icrc3::log_length())
         }
 
         pub fn is_token_existed(&self, token_identifier: &TokenIdentifier) -> bool {
@@ -214,23 +307,57 @@ pub mod ledger {
             new_owner: Option<Principal>,
         ) {
             if let Some(old_owner) = old_owner {
-                let old_owner_token_identifiers = self
-                    .owners
-                    .get_mut(&old_owner)
-                    .expect("couldn't find owner");
-                old_owner_token_identifiers.remove(token_identifier);
-                if old_owner_token_identifiers.is_empty() {
-                    self.owners.remove(&old_owner);
+                if let Some(old_owner_token_identifiers) = self.owners.get_mut(&old_owner) {
+                    old_owner_token_identifiers.remove(token_identifier);
+                    if old_owner_token_identifiers.is_empty() {
+                        self.owners.remove(&old_owner);
+                    }
                 }
             }
             if let Some(new_owner) = new_owner {
                 self.owners
                     .entry(new_owner)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(token_identifier.clone());
             }
         }
 
+        // Rebuilds the owner lookup from token metadata, tokens minted before
+        // the cache was maintained on mint are missing from it
+        pub fn rebuild_owner_cache(&mut self) {
+            let mut owners: HashMap<Principal, HashSet<TokenIdentifier>> = HashMap::new();
+            for (token_identifier, token_metadata) in self.tokens.iter() {
+                if let Some(owner) = token_metadata.owner {
+                    owners
+                        .entry(owner)
+                        .or_default()
+                        .insert(token_identifier.clone());
+                }
+            }
+            self.owners = owners;
+        }
+
+        pub fn owner_subaccount(&self, token_identifier: &TokenIdentifier) -> Option<&Vec<u8>> {
+            self.token_subaccounts.get(token_identifier)
+        }
+
+        pub fn set_owner_subaccount(
+            &mut self,
+            token_identifier: &TokenIdentifier,
+            subaccount: Option<Vec<u8>>,
+        ) {
+            // An all-zero subaccount is the default one and is not stored
+            match subaccount.filter(|subaccount| subaccount.iter().any(|byte| *byte != 0)) {
+                Some(subaccount) => {
+                    self.token_subaccounts
+                        .insert(token_identifier.clone(), subaccount);
+                }
+                None => {
+                    self.token_subaccounts.remove(token_identifier);
+                }
+            }
+        }
+
         pub fn operator_token_identifiers(
             &self,
             operator: &Principal,
@@ -277,7 +404,7 @@ pub mod ledger {
             if let Some(new_operator) = new_operator {
                 self.operators
                     .entry(new_operator)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(token_identifier.clone());
             }
         }
@@ -295,6 +422,37 @@ pub mod ledger {
             token_metadata.operator = new_operator;
             token_metadata.approved_by = Some(approved_by);
             token_metadata.approved_at = Some(time());
+            self.approval_expiries.remove(token_identifier);
+        }
+
+        pub fn set_approval_expiry(
+            &mut self,
+            token_identifier: &TokenIdentifier,
+            expires_at: Option<u64>,
+        ) {
+            match expires_at {
+                Some(expires_at) => {
+                    self.approval_expiries
+                        .insert(token_identifier.clone(), expires_at);
+                }
+                None => {
+                    self.approval_expiries.remove(token_identifier);
+                }
+            }
+        }
+
+        pub fn is_approved(
+            &self,
+            token_identifier: &TokenIdentifier,
+            spender: &Principal,
+            now: u64,
+        ) -> bool {
+            self.operator_of(token_identifier)
+                .is_ok_and(|operator| operator.as_ref() == Some(spender))
+                && self
+                    .approval_expiries
+                    .get(token_identifier)
+                    .is_none_or(|expires_at| *expires_at > now)
         }
 
         pub fn transfer(
@@ -311,6 +469,8 @@ pub mod ledger {
             token_metadata.transferred_by = Some(transferred_by);
             token_metadata.transferred_at = Some(time());
             token_metadata.operator = None;
+            self.token_subaccounts.remove(token_identifier);
+            self.approval_expiries.remove(token_identifier);
         }
 
         pub fn burn(&mut self, burned_by: Principal, token_identifier: &TokenIdentifier) {
@@ -323,18 +483,109 @@ pub mod ledger {
             token_metadata.is_burned = true;
             token_metadata.burned_by = Some(burned_by);
             token_metadata.burned_at = Some(time());
+            self.token_subaccounts.remove(token_identifier);
+            self.approval_expiries.remove(token_identifier);
         }
 
-        pub fn inc_tx(&mut self) -> Nat {
-            self.tx_count.clone()
+        pub fn record_tx(
+            &mut self,
+            caller: Principal,
+            operation: TxOperation,
+            from: Option<Principal>,
+            to: Option<Principal>,
+            token_identifier: Option<TokenIdentifier>,
+        ) -> Nat {
+            self.record_tx_with_details(
+                caller,
+                operation,
+                from,
+                to,
+                token_identifier,
+                TxDetails::default(),
+            )
+        }
+
+        pub fn record_tx_with_details(
+            &mut self,
+            caller: Principal,
+            operation: TxOperation,
+            from: Option<Principal>,
+            to: Option<Principal>,
+            token_identifier: Option<TokenIdentifier>,
+            details: TxDetails,
+        ) -> Nat {
+            // The block log sink appends the event as the next block
+            let event = TxEvent {
+                tx_id: Nat::from(crate::icrc3::log_length()),
+                operation,
+                caller,
+                from,
+                to,
+                token_identifier,
+                timestamp: time(),
+                from_subaccount: details.from_subaccount,
+                to_subaccount: details.to_subaccount,
+                spender_subaccount: details.spender_subaccount,
+                expires_at: details.expires_at,
+            };
+            crate::history::publish(&event);
+
+            event.tx_id
+        }
+
+        pub fn transaction(&self, tx_id: &Nat) -> Result<TxEvent, NftError> {
+            u64::try_from(&tx_id.0)
+                .ok()
+                .and_then(|id| self.read_tx(id))
+                .ok_or(NftError::TxNotFound)
+        }
+
+        pub fn token_transactions(
+            &self,
+            token_identifier: &TokenIdentifier,
+            offset: u64,
+            limit: u64,
+        ) -> Vec<TxEvent> {
+            let ids = crate::icrc3::with(|log| {
+                log.token_blocks(token_identifier, offset, limit.min(MAX_TX_PAGE_SIZE))
+            });
+            ids.into_iter().filter_map(|id| self.read_tx(id)).collect()
         }
-    }
-}
 
-#[init]
-#[candid_method(init)]
-fn init(args: Option<InitArgs>) {
-    ledger::with_mut(|ledger| ledger.init_metadata(caller(), args));
+        pub fn principal_transactions(
+            &self,
+            principal: &Principal,
+            offset: u64,
+            limit: u64,
+        ) -> Vec<TxEvent> {
+            let ids = crate::icrc3::with(|log| {
+                log.principal_blocks(principal, offset, limit.min(MAX_TX_PAGE_SIZE))
+            });
+            ids.into_iter().filter_map(|id| self.read_tx(id)).collect()
+        }
+
+        // Reading a transaction back from its block, mint blocks don't name the minter
+        fn read_tx(&self, id: u64) -> Option<TxEvent> {
+            let block = crate::icrc3::with(|log| log.get(id))?;
+            crate::icrc3::tx_from_block(id, &block, |token_identifier| self.minter(token_identifier))
+        }
+
+        pub fn minter(&self, token_identifier: &TokenIdentifier) -> Option<Principal> {
+            self.tokens
+                .get(token_identifier)
+                .map(|token_metadata| token_metadata.minted_by)
+        }
+
+        // Whether a certificate shows the asset as its artwork
+        pub fn references_asset(&self, asset_id: &str) -> bool {
+            self.tokens.values().any(|token_metadata| {
+                token_metadata.properties.iter().any(|(key, value)| {
+                    key == "certificate_asset"
+                        && matches!(value, GenericValue::TextContent(id) if id == asset_id)
+                })
+            })
+        }
+    }
 }
 
 pub fn is_canister_custodian() -> Result<(), String> {
@@ -348,80 +599,77 @@ pub fn is_canister_custodian() -> Result<(), String> {
     })
 }
 
+pub fn ensure_transfers_enabled() -> Result<(), NftError> {
+    crate::config::features()
+        .certificate_transfers
+        .then_some(())
+        .ok_or(NftError::TransferDisabled)
+}
+
 // ==================================================================================================
 // cover metadata
 // ==================================================================================================
 #[query()]
-#[candid_method(query)]
 fn git_commit_hash() -> &'static str {
-    run_command_str!("git", "rev-parse", "HEAD")
+    // Set by the build script when the canister is built from a git checkout
+    option_env!("GIT_COMMIT_HASH").unwrap_or("unknown")
 }
 
 #[query()]
-#[candid_method(query)]
 fn rust_toolchain_info() -> &'static str {
     run_command_str!("rustup", "show")
 }
 
 #[query()]
-#[candid_method(query)]
 fn dfx_info() -> &'static str {
-    run_command_str!("dfx", "--version")
+    // dfx exports its version to the builds it runs, plain cargo builds have no dfx
+    option_env!("DFX_VERSION").unwrap_or("unknown")
 }
 
 // ==================================================================================================
 // metadata
 // ==================================================================================================
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_name() -> ManualReply<Option<String>> {
     ledger::with(|ledger| ManualReply::one(ledger.metadata().name.as_ref()))
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_logo() -> ManualReply<Option<String>> {
     ledger::with(|ledger| ManualReply::one(ledger.metadata().logo.as_ref()))
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_symbol() -> ManualReply<Option<String>> {
     ledger::with(|ledger| ManualReply::one(ledger.metadata().symbol.as_ref()))
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_custodians() -> ManualReply<HashSet<Principal>> {
     ledger::with(|ledger| ManualReply::one(&ledger.metadata().custodians))
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_metadata() -> ManualReply<Metadata> {
     ledger::with(|ledger| ManualReply::one(ledger.metadata()))
 }
 
 #[update(guard = "is_canister_custodian")]
-#[candid_method(update)]
 fn dip721_set_name(name: String) {
     ledger::with_mut(|ledger| ledger.metadata_mut().name = Some(name));
 }
 
 #[update(guard = "is_canister_custodian")]
-#[candid_method(update)]
 fn dip721_set_logo(logo: String) {
     ledger::with_mut(|ledger| ledger.metadata_mut().logo = Some(logo));
 }
 
 #[update(guard = "is_canister_custodian")]
-#[candid_method(update)]
 fn dip721_set_symbol(symbol: String) {
     ledger::with_mut(|ledger| ledger.metadata_mut().symbol = Some(symbol));
 }
 
 #[update(guard = "is_canister_custodian")]
-#[candid_method(update)]
 fn dip721_set_custodians(custodians: HashSet<Principal>) {
     ledger::with_mut(|ledger| ledger.metadata_mut().custodians = custodians);
 }
@@ -432,31 +680,47 @@ fn dip721_set_custodians(custodians: HashSet<Principal>) {
 /// Returns the total current supply of NFT tokens.
 /// NFTs that are minted and later burned explicitly or sent to the zero address should also count towards totalSupply.
 #[query()]
-#[candid_method(query)]
 fn dip721_total_supply() -> Nat {
     ledger::with(|ledger| Nat::from(ledger.tokens_count()))
 }
 
 #[query()]
-#[candid_method(query)]
 fn dip721_total_transactions() -> Nat {
     ledger::with(|ledger| ledger.tx_count())
 }
 
+#[query(manual_reply = true)]
+fn dip721_transaction(tx_id: Nat) -> ManualReply<Result<TxEvent, NftError>> {
+    ledger::with(|ledger| ManualReply::one(ledger.transaction(&tx_id)))
+}
+
+/// Returns the transactions touching a token, oldest first.
+#[query()]
+fn dip721_token_transactions(
+    token_identifier: TokenIdentifier,
+    offset: u64,
+    limit: u64,
+) -> Vec<TxEvent> {
+    ledger::with(|ledger| ledger.token_transactions(&token_identifier, offset, limit))
+}
+
+/// Returns the transactions a principal took part in as caller, sender or receiver, oldest first.
+#[query()]
+fn dip721_principal_transactions(principal: Principal, offset: u64, limit: u64) -> Vec<TxEvent> {
+    ledger::with(|ledger| ledger.principal_transactions(&principal, offset, limit))
+}
+
 #[query()]
-#[candid_method(query)]
 fn dip721_cycles() -> Nat {
     Nat::from(canister_balance128())
 }
 
 #[query()]
-#[candid_method(query)]
 fn dip721_total_unique_holders() -> Nat {
     ledger::with(|ledger| Nat::from(ledger.owners_count()))
 }
 
 #[query()]
-#[candid_method(query)]
 fn dip721_stats() -> Stats {
     Stats {
         total_transactions: dip721_total_transactions(),
@@ -470,7 +734,6 @@ fn dip721_stats() -> Stats {
 // balance
 // ==================================================================================================
 #[query()]
-#[candid_method(query)]
 fn dip721_balance_of(owner: Principal) -> Result<Nat, NftError> {
     ledger::with(|ledger| {
         ledger
@@ -483,19 +746,22 @@ fn dip721_balance_of(owner: Principal) -> Result<Nat, NftError> {
 // token ownership
 // ==================================================================================================
 #[query()]
-#[candid_method(query)]
 fn dip721_owner_of(token_identifier: TokenIdentifier) -> Result<Option<Principal>, NftError> {
     ledger::with(|ledger| ledger.owner_of(&token_identifier))
 }
 
 #[query()]
-#[candid_method(query)]
 fn dip721_operator_of(token_identifier: TokenIdentifier) -> Result<Option<Principal>, NftError> {
-    ledger::with(|ledger| ledger.operator_of(&token_identifier))
+    let now = time();
+    ledger::with(|ledger| {
+        // An expired ICRC-37 approval no longer names an operator
+        Ok(ledger
+            .operator_of(&token_identifier)?
+            .filter(|operator| ledger.is_approved(&token_identifier, operator, now)))
+    })
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_owner_token_metadata(
     owner: Principal,
 ) -> ManualReply<Result<Vec<TokenMetadata>, NftError>> {
@@ -503,7 +769,6 @@ fn dip721_owner_token_metadata(
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_operator_token_metadata(
     operator: Principal,
 ) -> ManualReply<Result<Vec<TokenMetadata>, NftError>> {
@@ -511,7 +776,6 @@ fn dip721_operator_token_metadata(
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_owner_token_identifiers(
     owner: Principal,
 ) -> ManualReply<Result<Vec<TokenIdentifier>, NftError>> {
@@ -519,7 +783,6 @@ fn dip721_owner_token_identifiers(
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 fn dip721_operator_token_identifiers(
     operator: Principal,
 ) -> ManualReply<Result<Vec<TokenIdentifier>, NftError>> {
@@ -527,7 +790,6 @@ fn dip721_operator_token_identifiers(
 }
 
 #[query(manual_reply = true)]
-#[candid_method(query)]
 pub fn dip721_token_metadata(
     token_identifier: TokenIdentifier,
 ) -> ManualReply<Result<TokenMetadata, NftError>> {
@@ -535,21 +797,20 @@ pub fn dip721_token_metadata(
 }
 
 #[query()]
-#[candid_method(query)]
 fn dip721_is_approved_for_all(owner: Principal, operator: Principal) -> Result<bool, NftError> {
+    let now = time();
     ledger::with(|ledger| {
         ledger
             .owner_token_metadata(&owner)
             .map(|owner_token_metadata| {
-                owner_token_metadata
-                    .iter()
-                    .all(|token_metadata| token_metadata.operator.eq(&Some(operator)))
+                owner_token_metadata.iter().all(|token_metadata| {
+                    ledger.is_approved(&token_metadata.token_identifier, &operator, now)
+                })
             })
     })
 }
 
 #[update]
-#[candid_method(update)]
 fn dip721_approve(operator: Principal, token_identifier: TokenIdentifier) -> Result<Nat, NftError> {
     ledger::with_mut(|ledger| {
         let caller = ic_cdk::api::caller();
@@ -570,12 +831,17 @@ fn dip721_approve(operator: Principal, token_identifier: TokenIdentifier) -> Res
         );
         ledger.approve(caller, &token_identifier, Some(operator));
 
-        Ok(ledger.inc_tx())
+        Ok(ledger.record_tx(
+            caller,
+            TxOperation::Approve,
+            Some(caller),
+            Some(operator),
+            Some(token_identifier),
+        ))
     })
 }
 
 #[update]
-#[candid_method(update)]
 fn dip721_set_approval_for_all(operator: Principal, is_approved: bool) -> Result<Nat, NftError> {
     ledger::with_mut(|ledger| {
         let caller = caller();
@@ -591,13 +857,18 @@ fn dip721_set_approval_for_all(operator: Principal, is_approved: bool) -> Result
             ledger.approve(caller, &token_identifier, new_operator);
         }
 
-        Ok(ledger.inc_tx())
+        let operation = if is_approved {
+            TxOperation::ApproveAll
+        } else {
+            TxOperation::Revoke
+        };
+        Ok(ledger.record_tx(caller, operation, Some(caller), Some(operator), None))
     })
 }
 
 #[update]
-#[candid_method(update)]
 fn dip721_transfer(to: Principal, token_identifier: TokenIdentifier) -> Result<Nat, NftError> {
+    ensure_transfers_enabled()?;
     ledger::with_mut(|ledger| {
         let caller = caller();
         to.ne(&caller).then_some(()).ok_or(NftError::SelfTransfer)?;
@@ -611,40 +882,69 @@ fn dip721_transfer(to: Principal, token_identifier: TokenIdentifier) -> Result<N
         ledger.update_operator_cache(&token_identifier, old_operator, None);
         ledger.transfer(caller, &token_identifier, Some(to));
 
-        Ok(ledger.inc_tx())
+        Ok(ledger.record_tx(
+            caller,
+            TxOperation::Transfer,
+            Some(caller),
+            Some(to),
+            Some(token_identifier),
+        ))
     })
 }
 
+// The caller must be approved like an ICRC-37 spender, so approvals that expired
+// or were revoked through ICRC-37 no longer move the token, and collection
+// approvals of the owner are honoured
+fn check_transfer_from(
+    ledger: &ledger::Ledger,
+    caller: Principal,
+    now: u64,
+    owner: Principal,
+    to: Principal,
+    token_identifier: &TokenIdentifier,
+) -> Result<(), NftError> {
+    owner.ne(&to).then_some(()).ok_or(NftError::SelfTransfer)?;
+    ledger
+        .owner_of(token_identifier)?
+        .eq(&Some(owner))
+        .then_some(())
+        .ok_or(NftError::UnauthorizedOwner)?;
+    let spender = crate::icrc7::Account {
+        owner: caller,
+        subaccount: None,
+    };
+    crate::icrc7::is_approved_spender(ledger, token_identifier, &spender, now)
+        .then_some(())
+        .ok_or(NftError::UnauthorizedOperator)
+}
+
 #[update]
-#[candid_method(update)]
 fn dip721_transfer_from(
     owner: Principal,
     to: Principal,
     token_identifier: TokenIdentifier,
 ) -> Result<Nat, NftError> {
+    ensure_transfers_enabled()?;
     ledger::with_mut(|ledger| {
         let caller = caller();
-        owner.ne(&to).then_some(()).ok_or(NftError::SelfTransfer)?;
+        check_transfer_from(ledger, caller, time(), owner, to, &token_identifier)?;
         let old_owner = ledger.owner_of(&token_identifier)?;
         let old_operator = ledger.operator_of(&token_identifier)?;
-        old_owner
-            .eq(&Some(owner))
-            .then_some(())
-            .ok_or(NftError::UnauthorizedOwner)?;
-        old_operator
-            .eq(&Some(caller))
-            .then_some(())
-            .ok_or(NftError::UnauthorizedOperator)?;
         ledger.update_owner_cache(&token_identifier, old_owner, Some(to));
         ledger.update_operator_cache(&token_identifier, old_operator, None);
         ledger.transfer(caller, &token_identifier, Some(to));
 
-        Ok(ledger.inc_tx())
+        Ok(ledger.record_tx(
+            caller,
+            TxOperation::TransferFrom,
+            Some(owner),
+            Some(to),
+            Some(token_identifier),
+        ))
     })
 }
 
 #[update(guard = "is_canister_custodian")]
-#[candid_method(update)]
 fn dip721_mint(
     to: Principal,
     token_identifier: TokenIdentifier,
@@ -675,13 +975,19 @@ fn dip721_mint(
                 burned_by: None,
             },
         );
-
-        Ok(ledger.inc_tx())
+        ledger.update_owner_cache(&token_identifier, None, Some(to));
+
+        Ok(ledger.record_tx(
+            caller,
+            TxOperation::Mint,
+            None,
+            Some(to),
+            Some(token_identifier),
+        ))
     })
 }
 
 #[update]
-#[candid_method(update)]
 fn dip721_burn(token_identifier: TokenIdentifier) -> Result<Nat, NftError> {
     ledger::with_mut(|ledger| {
         let caller = caller();
@@ -695,20 +1001,17 @@ fn dip721_burn(token_identifier: TokenIdentifier) -> Result<Nat, NftError> {
         ledger.update_operator_cache(&token_identifier, old_operator, None);
         ledger.burn(caller, &token_identifier);
 
-        Ok(ledger.inc_tx())
+        Ok(ledger.record_tx(
+            caller,
+            TxOperation::Burn,
+            old_owner,
+            None,
+            Some(token_identifier),
+        ))
     })
 }
 
-#[cfg(any(target_arch = "wasm32", test))]
-fn main() {}
-
-#[cfg(not(any(target_arch = "wasm32", test)))]
-fn main() {
-    std::print!("{}", export_candid());
-}
-
 #[update(name = "mint", guard = "is_canister_custodian")]
-#[candid_method(update, rename = "mint")]
 pub fn mint(
     to: Principal,
     token_identifier: TokenIdentifier,
@@ -717,31 +1020,261 @@ pub fn mint(
     dip721_mint(to, token_identifier, properties)
 }
 #[update(name = "setName", guard = "is_canister_custodian")]
-#[candid_method(update, rename = "setName")]
 fn set_name(name: String) {
     dip721_set_name(name)
 }
 
 #[update(name = "setLogo", guard = "is_canister_custodian")]
-#[candid_method(update, rename = "setLogo")]
 fn set_logo(logo: String) {
     dip721_set_logo(logo)
 }
 
 #[update(name = "setSymbol", guard = "is_canister_custodian")]
-#[candid_method(update, rename = "setSymbol")]
 fn set_symbol(symbol: String) {
     dip721_set_symbol(symbol)
 }
 
 #[update(name = "setCustodians", guard = "is_canister_custodian")]
-#[candid_method(update, rename = "setCustodians")]
 fn set_custodians(custodians: HashSet<Principal>) {
     dip721_set_custodians(custodians)
 }
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::types::TokenMetadata;
+    use crate::domain::testing::NOW;
+    use candid::{Nat, Principal};
+
+    // A live certificate without properties or approval
+    pub fn token(id: u64, owner: Principal, minted_by: Principal) -> TokenMetadata {
+        TokenMetadata {
+            token_identifier: Nat::from(id),
+            owner: Some(owner),
+            operator: None,
+            is_burned: false,
+            properties: vec![],
+            minted_at: NOW,
+            minted_by,
+            transferred_at: None,
+            transferred_by: None,
+            approved_at: None,
+            approved_by: None,
+            burned_at: None,
+            burned_by: None,
+        }
+    }
+}
 
-#[query()]
-fn export_candid() -> String {
-    candid::export_service!();
-    __export_service()
+#[cfg(test)]
+mod tests {
+    use super::ledger::{Ledger, MAX_TX_PAGE_SIZE};
+    use super::testing::token;
+    use super::types::*;
+    use crate::domain::testing::{principal, NOW};
+    use candid::{Nat, Principal};
+
+    // Appending straight to the block log, `record_tx` also certifies the tip
+    // which is only possible inside a canister
+    fn append(
+        operation: TxOperation,
+        caller: Principal,
+        from: Option<Principal>,
+        to: Option<Principal>,
+        token_identifier: Option<u64>,
+    ) {
+        let event = TxEvent {
+            tx_id: Nat::from(0u64),
+            operation,
+            caller,
+            from,
+            to,
+            token_identifier: token_identifier.map(Nat::from),
+            timestamp: NOW,
+            from_subaccount: None,
+            to_subaccount: None,
+            spender_subaccount: None,
+            expires_at: None,
+        };
+        crate::icrc3::with_mut(|log| log.append(&event));
+    }
+
+    fn ids(txs: Vec<TxEvent>) -> Vec<Nat> {
+        txs.into_iter().map(|tx| tx.tx_id).collect()
+    }
+
+    // The custodian (9) mints a certificate to Alice (1), who hands it to Bob
+    // (2), who approves Carol (3) for it. Bob then gets a second certificate.
+    fn ledger_with_history() -> Ledger {
+        let (custodian, alice, bob, carol) =
+            (principal(9), principal(1), principal(2), principal(3));
+        let mut ledger = Ledger::default();
+        ledger
+            .tokens
+            .insert(Nat::from(0u64), token(0, bob, custodian));
+        ledger
+            .tokens
+            .insert(Nat::from(1u64), token(1, bob, custodian));
+
+        append(TxOperation::Mint, custodian, None, Some(alice), Some(0));
+        append(
+            TxOperation::Transfer,
+            alice,
+            Some(alice),
+            Some(bob),
+            Some(0),
+        );
+        append(TxOperation::Approve, bob, Some(bob), Some(carol), Some(0));
+        append(TxOperation::Mint, custodian, None, Some(bob), Some(1));
+        ledger
+    }
+
+    #[test]
+    fn transactions_are_read_back_from_the_block_log() {
+        let ledger = ledger_with_history();
+        assert_eq!(ledger.tx_count(), Nat::from(4u64));
+
+        let mint = ledger.transaction(&Nat::from(0u64)).unwrap();
+        assert_eq!(mint.operation, TxOperation::Mint);
+        assert_eq!(mint.caller, principal(9));
+        assert_eq!(mint.to, Some(principal(1)));
+        assert_eq!(mint.timestamp, NOW);
+
+        let transfer = ledger.transaction(&Nat::from(1u64)).unwrap();
+        assert_eq!(transfer.tx_id, Nat::from(1u64));
+        assert_eq!(transfer.operation, TxOperation::Transfer);
+        assert_eq!(transfer.caller, principal(1));
+        assert_eq!(transfer.from, Some(principal(1)));
+        assert_eq!(transfer.to, Some(principal(2)));
+        assert_eq!(transfer.token_identifier, Some(Nat::from(0u64)));
+
+        let approval = ledger.transaction(&Nat::from(2u64)).unwrap();
+        assert_eq!(approval.caller, principal(2));
+        assert_eq!(approval.to, Some(principal(3)));
+
+        assert!(matches!(
+            ledger.transaction(&Nat::from(4u64)),
+            Err(NftError::TxNotFound)
+        ));
+    }
+
+    #[test]
+    fn history_is_listed_per_token_and_per_principal() {
+        let ledger = ledger_with_history();
+        let token = Nat::from(0u64);
+
+        assert_eq!(
+            ids(ledger.token_transactions(&token, 0, 10)),
+            vec![0u64, 1, 2]
+        );
+        assert_eq!(ids(ledger.token_transactions(&token, 1, 1)), vec![1u64]);
+        assert_eq!(
+            ids(ledger.token_transactions(&token, 3, 10)),
+            Vec::<Nat>::new()
+        );
+        assert_eq!(
+            ids(ledger.token_transactions(&Nat::from(1u64), 0, 10)),
+            vec![3u64]
+        );
+
+        // Callers, senders and receivers all see the transaction once
+        assert_eq!(
+            ids(ledger.principal_transactions(&principal(2), 0, 10)),
+            vec![1u64, 2, 3]
+        );
+        assert_eq!(
+            ids(ledger.principal_transactions(&principal(2), 2, 10)),
+            vec![3u64]
+        );
+        assert_eq!(
+            ids(ledger.principal_transactions(&principal(3), 0, 10)),
+            vec![2u64]
+        );
+        assert_eq!(
+            ids(ledger.principal_transactions(&principal(9), 0, 10)),
+            vec![0u64, 3]
+        );
+        assert!(ledger
+            .principal_transactions(&principal(4), 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn history_pages_are_capped() {
+        let (alice, bob) = (principal(1), principal(2));
+        let mut ledger = Ledger::default();
+        ledger
+            .tokens
+            .insert(Nat::from(0u64), token(0, alice, alice));
+        for n in 0..MAX_TX_PAGE_SIZE + 5 {
+            let (from, to) = if n % 2 == 0 {
+                (alice, bob)
+            } else {
+                (bob, alice)
+            };
+            append(TxOperation::Transfer, from, Some(from), Some(to), Some(0));
+        }
+
+        let page = ledger.token_transactions(&Nat::from(0u64), 0, u64::MAX);
+        assert_eq!(page.len() as u64, MAX_TX_PAGE_SIZE);
+        let rest = ledger.principal_transactions(&alice, MAX_TX_PAGE_SIZE, u64::MAX);
+        let expected: Vec<Nat> = (MAX_TX_PAGE_SIZE..MAX_TX_PAGE_SIZE + 5)
+            .map(Nat::from)
+            .collect();
+        assert_eq!(ids(rest), expected);
+    }
+
+    #[test]
+    fn certificate_artwork_is_found_by_asset_id() {
+        let alice = principal(1);
+        let mut ledger = Ledger::default();
+        let mut certificate = token(0, alice, alice);
+        certificate.properties.push((
+            "certificate_asset".to_string(),
+            GenericValue::TextContent("artwork".to_string()),
+        ));
+        ledger.tokens.insert(Nat::from(0u64), certificate);
+
+        assert!(ledger.references_asset("artwork"));
+        assert!(!ledger.references_asset("resume"));
+    }
+
+    #[test]
+    fn dip721_transfer_from_honours_icrc37_approvals() {
+        let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+        let mut ledger = Ledger::default();
+        let token_id = Nat::from(0u64);
+        let mut certificate = token(0, alice, alice);
+        certificate.operator = Some(bob);
+        ledger.tokens.insert(token_id.clone(), certificate);
+        ledger.approval_expiries.insert(token_id.clone(), NOW + 10);
+        let check = |ledger: &Ledger, caller: Principal, now: u64| {
+            super::check_transfer_from(ledger, caller, now, alice, carol, &token_id)
+        };
+
+        assert_eq!(check(&ledger, bob, NOW), Ok(()));
+        assert_eq!(
+            check(&ledger, bob, NOW + 10),
+            Err(NftError::UnauthorizedOperator)
+        );
+        assert_eq!(check(&ledger, carol, NOW), Err(NftError::UnauthorizedOperator));
+        assert_eq!(
+            super::check_transfer_from(&ledger, bob, NOW, carol, alice, &token_id),
+            Err(NftError::UnauthorizedOwner)
+        );
+
+        // A collection approval of the owner covers the token as well
+        ledger.collection_approvals.insert(
+            alice,
+            vec![crate::icrc7::ApprovalInfo {
+                spender: crate::icrc7::Account {
+                    owner: carol,
+                    subaccount: None,
+                },
+                from_subaccount: None,
+                expires_at: None,
+                memo: None,
+                created_at_time: NOW,
+            }],
+        );
+        assert_eq!(check(&ledger, carol, NOW + 10), Ok(()));
+    }
 }
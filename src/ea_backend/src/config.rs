@@ -0,0 +1,197 @@
+use crate::certificate::types::{InitArgs, UpgradeArgs};
+use crate::outbound::OutboundChannel;
+use candid::{CandidType, Principal};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+// Platform wide configuration, set from `BackendInitArgs` on install and
+// adjustable through `BackendUpgradeArgs` on every upgrade.
+
+thread_local! {
+    pub(crate) static CONFIG: RefCell<PlatformConfig> = RefCell::default();
+}
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct PlatformSettings {
+    // Fee charged to the minter per certificate, in e8s of the fee ledger
+    pub certificate_fee_e8s: u64,
+    // ICRC-2 ledger the certificate fee is paid on
+    #[serde(default = "default_fee_ledger")]
+    pub fee_ledger: Principal,
+    // Upper bound for the recipients of a single batch mint
+    pub max_batch_recipients: u64,
+    // Number of certificates minted in a single message before yielding
+    pub batch_chunk_size: u64,
+    // Independent reports after which a review is hidden until an admin decides
+    #[serde(default = "default_review_report_threshold")]
+    pub review_report_threshold: u64,
+    // Upper bound for the size of an uploaded asset, in bytes
+    #[serde(default = "default_max_asset_size")]
+    pub max_asset_size: u64,
+    // Upper bound for the total size of the assets of a single user, in bytes
+    #[serde(default = "default_max_owner_assets_size")]
+    pub max_owner_assets_size: u64,
+}
+
+fn default_fee_ledger() -> Principal {
+    crate::fees::ICP_LEDGER
+}
+
+fn default_review_report_threshold() -> u64 {
+    3
+}
+
+fn default_max_asset_size() -> u64 {
+    20 * 1024 * 1024
+}
+
+fn default_max_owner_assets_size() -> u64 {
+    200 * 1024 * 1024
+}
+
+impl Default for PlatformSettings {
+    fn default() -> Self {
+        PlatformSettings {
+            certificate_fee_e8s: 0,
+            fee_ledger: default_fee_ledger(),
+            max_batch_recipients: 500,
+            batch_chunk_size: 50,
+            review_report_threshold: default_review_report_threshold(),
+            max_asset_size: default_max_asset_size(),
+            max_owner_assets_size: default_max_owner_assets_size(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct FeatureFlags {
+    pub batch_minting: bool,
+    // Certificates stay with the recipient when disabled
+    pub certificate_transfers: bool,
+    // Only verified organizations and trainers can have certificates minted
+    #[serde(default)]
+    pub verified_issuers_only: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        FeatureFlags {
+            batch_minting: true,
+            certificate_transfers: true,
+            verified_issuers_only: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct PlatformConfig {
+    pub admins: BTreeSet<Principal>,
+    #[serde(default)]
+    pub settings: PlatformSettings,
+    #[serde(default)]
+    pub features: FeatureFlags,
+    // Where messages to users, such as verification codes, are sent
+    #[serde(default)]
+    pub outbound: OutboundChannel,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct BackendInitArgs {
+    pub collection: Option<InitArgs>,
+    pub admins: Option<Vec<Principal>>,
+    pub settings: Option<PlatformSettings>,
+    pub features: Option<FeatureFlags>,
+    pub outbound: Option<OutboundChannel>,
+}
+
+// Every field left empty keeps the value from before the upgrade
+#[derive(CandidType, Deserialize, Default)]
+pub struct BackendUpgradeArgs {
+    pub collection: Option<UpgradeArgs>,
+    pub admins: Option<Vec<Principal>>,
+    pub settings: Option<PlatformSettings>,
+    pub features: Option<FeatureFlags>,
+    pub outbound: Option<OutboundChannel>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum BackendArgs {
+    Init(BackendInitArgs),
+    Upgrade(Option<BackendUpgradeArgs>),
+}
+
+impl PlatformConfig {
+    // The installer is always an admin, so the platform can't be locked out
+    pub fn apply_init(&mut self, installer: Principal, args: &BackendInitArgs) {
+        self.admins.insert(installer);
+        if let Some(admins) = &args.admins {
+            self.admins.extend(admins.iter().copied());
+        }
+        self.settings = args.settings.clone().unwrap_or_default();
+        self.features = args.features.clone().unwrap_or_default();
+        self.outbound = args.outbound.clone().unwrap_or_default();
+    }
+
+    // The upgrader stays an admin as well, an empty list can't lock everyone out
+    pub fn apply_upgrade(&mut self, upgrader: Principal, args: &BackendUpgradeArgs) {
+        if let Some(admins) = &args.admins {
+            self.admins = admins.iter().copied().collect();
+            self.admins.insert(upgrader);
+        }
+        if let Some(settings) = &args.settings {
+            self.settings = settings.clone();
+        }
+        if let Some(features) = &args.features {
+            self.features = features.clone();
+        }
+        if let Some(outbound) = &args.outbound {
+            self.outbound = outbound.clone();
+        }
+    }
+}
+
+pub fn with<T, F: FnOnce(&PlatformConfig) -> T>(f: F) -> T {
+    CONFIG.with(|config| f(&config.borrow()))
+}
+
+pub fn settings() -> PlatformSettings {
+    with(|config| config.settings.clone())
+}
+
+pub fn features() -> FeatureFlags {
+    with(|config| config.features.clone())
+}
+
+pub fn is_admin() -> Result<(), String> {
+    with(|config| {
+        config
+            .admins
+            .contains(&ic_cdk::api::caller())
+            .then_some(())
+            .ok_or_else(|| "Caller is not an admin of the platform".into())
+    })
+}
+
+// ==================================================================================================
+// Config
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+// Get the platform settings and enabled features, only admins see the relay URL
+#[query]
+fn get_platform_config() -> PlatformConfig {
+    let mut config = with(|config| config.clone());
+    if is_admin().is_err() {
+        config.outbound = config.outbound.redacted();
+    }
+    config
+}
@@ -0,0 +1,493 @@
+use crate::domain::{self, CvDate, CvDocument, LanguageProficiency};
+use crate::http::{self, HttpResponse};
+use candid::Principal;
+
+// Rendering CVs for download, as an HTML page or as a plain text PDF. Both
+// formats show the sections written by the owner followed by the courses and
+// jobs the platform knows about.
+
+// Characters per line and lines per page of the PDF, for 10pt Helvetica on A4
+const PDF_LINE_WIDTH: usize = 95;
+const PDF_LINES_PER_PAGE: usize = 55;
+
+// ==================================================================================================
+// Outline
+// ==================================================================================================
+
+// A CV as a list of sections, each one a list of entries with a heading line
+// and detail lines
+struct Section {
+    title: &'static str,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    heading: String,
+    details: Vec<String>,
+    link: Option<String>,
+}
+
+fn entry(heading: String, details: Vec<String>) -> Entry {
+    Entry {
+        heading,
+        details: details
+            .into_iter()
+            .filter(|detail| !detail.is_empty())
+            .collect(),
+        link: None,
+    }
+}
+
+fn month(date: &CvDate) -> String {
+    format!("{:04}-{:02}", date.year, date.month)
+}
+
+fn period(start: &CvDate, end: &Option<CvDate>) -> String {
+    let end = end.as_ref().map_or("present".to_string(), month);
+    format!("{} to {}", month(start), end)
+}
+
+fn proficiency(proficiency: &LanguageProficiency) -> &'static str {
+    match proficiency {
+        LanguageProficiency::Elementary => "Elementary",
+        LanguageProficiency::Intermediate => "Intermediate",
+        LanguageProficiency::Professional => "Professional",
+        LanguageProficiency::Fluent => "Fluent",
+        LanguageProficiency::Native => "Native",
+    }
+}
+
+fn outline(document: &CvDocument) -> Vec<Section> {
+    let cv = &document.cv;
+    let mut sections = vec![
+        Section {
+            title: "Experience",
+            entries: cv
+                .experience
+                .iter()
+                .map(|experience| {
+                    entry(
+                        format!("{}, {}", experience.title, experience.employer),
+                        vec![
+                            period(&experience.start, &experience.end),
+                            experience.description.clone(),
+                        ],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Education",
+            entries: cv
+                .education
+                .iter()
+                .map(|education| {
+                    let degree = [education.degree.as_str(), &education.field_of_study]
+                        .iter()
+                        .filter(|part| !part.is_empty())
+                        .copied()
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    entry(
+                        education.institution.clone(),
+                        vec![
+                            degree,
+                            period(&education.start, &education.end),
+                            education.description.clone(),
+                        ],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Courses completed on Eakazi",
+            entries: document
+                .completed_courses
+                .iter()
+                .map(|course| {
+                    entry(
+                        course.title.clone(),
+                        vec![format!(
+                            "Taught by {}, certificate #{}",
+                            course.trainer, course.token_id
+                        )],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Jobs through Eakazi",
+            entries: document
+                .platform_jobs
+                .iter()
+                .map(|job| {
+                    entry(
+                        job.title.clone(),
+                        vec![format!("Hired by {}", job.employer)],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Projects",
+            entries: cv
+                .projects
+                .iter()
+                .map(|project| Entry {
+                    link: project.url.clone(),
+                    ..entry(
+                        project.name.clone(),
+                        vec![project.description.clone(), project.skills.join(", ")],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Languages",
+            entries: cv
+                .languages
+                .iter()
+                .map(|language| {
+                    entry(
+                        format!(
+                            "{}: {}",
+                            language.language,
+                            proficiency(&language.proficiency)
+                        ),
+                        vec![],
+                    )
+                })
+                .collect(),
+        },
+        Section {
+            title: "Links",
+            entries: cv
+                .links
+                .iter()
+                .map(|link| Entry {
+                    link: Some(link.url.clone()),
+                    ..entry(link.label.clone(), vec![])
+                })
+                .collect(),
+        },
+    ];
+    if !document.skills.is_empty() {
+        sections.insert(
+            0,
+            Section {
+                title: "Skills",
+                entries: vec![entry(document.skills.join(", "), vec![])],
+            },
+        );
+    }
+    sections.retain(|section| !section.entries.is_empty());
+    sections
+}
+
+fn subtitle(document: &CvDocument) -> String {
+    [document.occupation.as_str(), &document.location]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+// ==================================================================================================
+// HTML
+// ==================================================================================================
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn render_html(document: &CvDocument) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{name}</title>\n</head>\n<body>\n<h1>{name}</h1>\n<p>{subtitle}</p>\n<p>{description}</p>\n",
+        name = escape_html(&document.fullname),
+        subtitle = escape_html(&subtitle(document)),
+        description = escape_html(&document.description),
+    );
+    for section in outline(document) {
+        html.push_str(&format!("<h2>{}</h2>\n", escape_html(section.title)));
+        for entry in section.entries {
+            let heading = escape_html(&entry.heading);
+            let heading = match &entry.link {
+                // Urls are checked to be http(s) when the CV is saved
+                Some(link) => format!("<a href=\"{}\">{}</a>", escape_html(link), heading),
+                None => heading,
+            };
+            html.push_str(&format!("<h3>{}</h3>\n", heading));
+            for detail in entry.details {
+                html.push_str(&format!("<p>{}</p>\n", escape_html(&detail)));
+            }
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// ==================================================================================================
+// PDF
+// ==================================================================================================
+
+// Lines are printed with the standard Helvetica fonts, which only cover
+// Latin-1. Other characters are replaced.
+fn escape_pdf(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Lines of the PDF with their font size, in points
+fn pdf_lines(document: &CvDocument) -> Vec<(String, u8)> {
+    let mut lines = vec![(document.fullname.clone(), 18)];
+    for text in [subtitle(document), document.description.clone()] {
+        lines.extend(
+            wrap(&text, PDF_LINE_WIDTH)
+                .into_iter()
+                .map(|line| (line, 10)),
+        );
+    }
+    for section in outline(document) {
+        lines.push((String::new(), 10));
+        lines.push((section.title.to_string(), 14));
+        for entry in section.entries {
+            let heading = match entry.link {
+                Some(link) => format!("{} ({})", entry.heading, link),
+                None => entry.heading,
+            };
+            lines.extend(
+                wrap(&heading, PDF_LINE_WIDTH)
+                    .into_iter()
+                    .map(|line| (line, 11)),
+            );
+            for detail in entry.details {
+                lines.extend(
+                    wrap(&detail, PDF_LINE_WIDTH)
+                        .into_iter()
+                        .map(|line| (line, 10)),
+                );
+            }
+        }
+    }
+    lines
+}
+
+pub fn render_pdf(document: &CvDocument) -> Vec<u8> {
+    let pages: Vec<String> = pdf_lines(document)
+        .chunks(PDF_LINES_PER_PAGE)
+        .map(|lines| {
+            let mut stream = String::from("BT\n50 800 Td\n");
+            for (line, size) in lines {
+                let font = if *size > 10 { "F2" } else { "F1" };
+                stream.push_str(&format!(
+                    "/{} {} Tf\n({}) Tj\n0 -{} Td\n",
+                    font,
+                    size,
+                    escape_pdf(line),
+                    *size as u32 + 3
+                ));
+            }
+            stream.push_str("ET\n");
+            stream
+        })
+        .collect();
+
+    // Objects 1 to 4 are the catalog, the page tree and the fonts, every page
+    // adds a page object followed by its content stream
+    let kids: Vec<String> = (0..pages.len())
+        .map(|page| format!("{} 0 R", 5 + 2 * page))
+        .collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold >>".to_string(),
+    ];
+    for (page, stream) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            6 + 2 * page
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            stream.len(),
+            stream
+        ));
+    }
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", index + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    pdf.into_bytes()
+}
+
+// ==================================================================================================
+// HTTP
+// ==================================================================================================
+
+// Serving `<profile id>.html` or `<profile id>.pdf`, HTTP requests come
+// without a caller so only public CVs are served
+pub(crate) fn serve(file: &str) -> HttpResponse {
+    let Some((profile_id, extension)) = file.rsplit_once('.') else {
+        return http::not_found();
+    };
+    let viewer = Principal::anonymous();
+    let Some(document) = domain::with(|state| state.cv_document(&viewer, profile_id)) else {
+        return http::not_found();
+    };
+    let (content_type, body) = match extension {
+        "html" => (
+            "text/html; charset=utf-8",
+            render_html(&document).into_bytes(),
+        ),
+        "pdf" => ("application/pdf", render_pdf(&document)),
+        _ => return http::not_found(),
+    };
+    let disposition = format!("attachment; filename=\"cv.{}\"", extension);
+    http::response(
+        200,
+        vec![
+            ("Content-Type", content_type.to_string()),
+            ("Content-Length", body.len().to_string()),
+            ("Content-Disposition", disposition),
+            ("Cache-Control", "no-cache".to_string()),
+        ],
+        body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Cv, PortfolioLink, WorkExperience};
+
+    fn document() -> CvDocument {
+        CvDocument {
+            profile_id: "bob".into(),
+            fullname: "Bob <script>".into(),
+            occupation: "Developer".into(),
+            location: "Nairobi".into(),
+            description: "Builds canisters (mostly in Rust)".into(),
+            skills: vec!["Rust".into()],
+            cv: Cv {
+                experience: vec![WorkExperience {
+                    employer: "Acme".into(),
+                    title: "Engineer".into(),
+                    start: CvDate {
+                        year: 2021,
+                        month: 3,
+                    },
+                    end: None,
+                    description: "Backend work".into(),
+                }],
+                links: vec![PortfolioLink {
+                    label: "GitHub".into(),
+                    url: "https://github.com/bob".into(),
+                }],
+                ..Default::default()
+            },
+            completed_courses: vec![],
+            platform_jobs: vec![],
+        }
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let html = render_html(&document());
+        assert!(html.contains("<h1>Bob &lt;script&gt;</h1>"));
+        assert!(html.contains("2021-03 to present"));
+        assert!(html.contains("<a href=\"https://github.com/bob\">GitHub</a>"));
+        // Empty sections are left out
+        assert!(!html.contains("Education"));
+    }
+
+    #[test]
+    fn pdfs_have_a_valid_cross_reference_table() {
+        let pdf = String::from_utf8(render_pdf(&document())).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("(Builds canisters \\(mostly in Rust\\)) Tj"));
+
+        let startxref: usize = pdf
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[startxref..].starts_with("xref"));
+        let first_object: usize = pdf[startxref..].lines().nth(3).unwrap()[..10]
+            .parse()
+            .unwrap();
+        assert!(pdf[first_object..].starts_with("1 0 obj"));
+    }
+
+    #[test]
+    fn long_lines_are_wrapped() {
+        let text = "word ".repeat(100);
+        let lines = wrap(&text, 20);
+        assert!(lines.iter().all(|line| line.len() <= 20));
+        assert_eq!(lines.join(" "), text.trim());
+    }
+}
@@ -0,0 +1,403 @@
+use super::{
+    Context, Course, CourseReview, Jobs, Notification, Organization, Profile, ReviewReport,
+    Reviews, State, Thread, VerificationRequest, VerificationSubject,
+};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// Shown instead of the name of the author of reviews whose account was deleted
+pub const DELETED_USER_NAME: &str = "Deleted user";
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum AccountError {
+    ProfileNotFound,
+    AlreadyDeactivated,
+    NotDeactivated,
+    // The caller is the only owner of an organization, which would be left
+    // without anyone to manage it
+    LastOwner,
+}
+
+// A report filed by the user, with the review it is about
+#[derive(Clone, Debug, Serialize)]
+pub struct FiledReport {
+    pub review_id: String,
+    pub report: ReviewReport,
+}
+
+// Everything the platform holds about a user, hidden reviews included
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountExport {
+    pub principal: Principal,
+    pub profile: Option<Profile>,
+    pub courses_created: Vec<Course>,
+    pub courses_enrolled: Vec<Course>,
+    pub jobs_created: Vec<Jobs>,
+    pub jobs_applied: Vec<Jobs>,
+    pub reviews_written: Vec<Reviews>,
+    pub reviews_received: Vec<Reviews>,
+    pub course_reviews: Vec<CourseReview>,
+    pub reports: Vec<FiledReport>,
+    pub organizations: Vec<Organization>,
+    pub verification_requests: Vec<VerificationRequest>,
+    pub notifications: Vec<Notification>,
+    pub threads: Vec<Thread>,
+}
+
+// ==================================================================================================
+// Account workflows
+// ==================================================================================================
+
+impl State {
+    pub fn deactivate_account(&mut self, ctx: &Context) -> Result<(), AccountError> {
+        let profile = self
+            .profiles
+            .get_mut(&ctx.caller)
+            .ok_or(AccountError::ProfileNotFound)?;
+        if !profile.is_active() {
+            return Err(AccountError::AlreadyDeactivated);
+        }
+        profile.deactivated_at = Some(ctx.now);
+        Ok(())
+    }
+
+    pub fn reactivate_account(&mut self, ctx: &Context) -> Result<(), AccountError> {
+        let profile = self
+            .profiles
+            .get_mut(&ctx.caller)
+            .ok_or(AccountError::ProfileNotFound)?;
+        if profile.is_active() {
+            return Err(AccountError::NotDeactivated);
+        }
+        profile.deactivated_at = None;
+        Ok(())
+    }
+
+    // Removing the caller from the platform, returns the removed profile.
+    // Reviews written by the caller stay, without their author, so the ratings
+    // of others do not change, reviews of the caller go with the profile. The
+    // trainer verification of the caller goes too, requests for organizations
+    // stay without their submitter. Courses and jobs created by the caller stay as
+    // well, certificates and CVs of others refer to them. Conversations about
+    // the caller's applications and enrollments go, in the others the caller's
+    // messages stay without their sender. The only owner of an organization
+    // has to hand it over first.
+    pub fn delete_account(&mut self, ctx: &Context) -> Result<Profile, AccountError> {
+        let caller = ctx.caller;
+        if !self.profiles.contains_key(&caller) {
+            return Err(AccountError::ProfileNotFound);
+        }
+        let last_owner = self
+            .organizations
+            .values()
+            .any(|organization| organization.is_last_owner(&caller));
+        if last_owner {
+            return Err(AccountError::LastOwner);
+        }
+        let profile = self.profiles.remove(&caller).unwrap();
+        self.ids.remove(&profile.id);
+        self.email_codes.remove(&caller);
+        self.notifications.remove(&caller);
+        self.threads.retain(|_, thread| !thread.is_about(&caller));
+        for thread in self.threads.values_mut() {
+            for message in thread.messages.iter_mut() {
+                if message.sender == caller {
+                    message.sender = Principal::anonymous();
+                }
+            }
+            thread.receipts.retain(|receipt| receipt.reader != caller);
+        }
+
+        for course in self.courses.values_mut() {
+            course.applicants.retain(|applicant| *applicant != caller);
+        }
+        for job in self.jobs.values_mut() {
+            job.applicants.retain(|applicant| *applicant != caller);
+            job.hired.retain(|hired| *hired != caller);
+        }
+        for organization in self.organizations.values_mut() {
+            organization
+                .members
+                .retain(|member| member.principal != caller);
+        }
+
+        for review_id in self.reviews_by_reviewee.remove(&caller).unwrap_or_default() {
+            self.remove_review(&review_id);
+        }
+        for review_id in self.reviews_by_reviewer.remove(&caller).unwrap_or_default() {
+            if let Some(review) = self.reviews.get_mut(&review_id) {
+                review.reviewer = DELETED_USER_NAME.to_string();
+                review.reviewer_principal = None;
+            }
+        }
+        // Course reviews are keyed by their author, each one gets a principal
+        // of its own that cannot be traced back
+        for reviews in self.course_reviews.values_mut() {
+            if let Some(mut review) = reviews.remove(&caller) {
+                review.reviewer = Principal::self_authenticating(ctx.rng.next_id());
+                review.reviewer_name = DELETED_USER_NAME.to_string();
+                reviews.insert(review.reviewer, review);
+            }
+        }
+        for case in self.moderation.values_mut() {
+            for report in case.reports.iter_mut() {
+                if report.reporter == caller {
+                    report.reporter = Principal::anonymous();
+                }
+            }
+        }
+
+        let trainer = VerificationSubject::Trainer(caller);
+        self.verifications
+            .retain(|_, request| request.subject != trainer);
+        for request in self.verifications.values_mut() {
+            if request.submitted_by == caller {
+                request.submitted_by = Principal::anonymous();
+            }
+        }
+        self.refresh_verified_badges();
+        Ok(profile)
+    }
+
+    pub fn export_account(&self, ctx: &Context) -> AccountExport {
+        let caller = ctx.caller;
+        let written = |review: &&Reviews| review.reviewer_principal == Some(caller);
+        AccountExport {
+            principal: caller,
+            profile: self.profiles.get(&caller).cloned(),
+            courses_created: self
+                .courses
+                .values()
+                .filter(|course| course.creator == caller)
+                .cloned()
+                .collect(),
+            courses_enrolled: self
+                .courses
+                .values()
+                .filter(|course| course.applicants.contains(&caller))
+                .cloned()
+                .collect(),
+            jobs_created: self
+                .jobs
+                .values()
+                .filter(|job| job.creator == caller)
+                .cloned()
+                .collect(),
+            jobs_applied: self
+                .jobs
+                .values()
+                .filter(|job| job.applicants.contains(&caller))
+                .cloned()
+                .collect(),
+            reviews_written: self.reviews.values().filter(written).cloned().collect(),
+            reviews_received: self
+                .reviews
+                .values()
+                .filter(|review| review.reviewee == caller)
+                .cloned()
+                .collect(),
+            course_reviews: self
+                .course_reviews
+                .values()
+                .filter_map(|reviews| reviews.get(&caller).cloned())
+                .collect(),
+            reports: self
+                .moderation
+                .values()
+                .flat_map(|case| {
+                    case.reports
+                        .iter()
+                        .filter(|report| report.reporter == caller)
+                        .map(|report| FiledReport {
+                            review_id: case.review_id.clone(),
+                            report: report.clone(),
+                        })
+                })
+                .collect(),
+            organizations: self.organizations_of(&caller),
+            verification_requests: self.verification_requests_of(&caller),
+            notifications: self
+                .notifications
+                .get(&caller)
+                .map(|inbox| inbox.notifications.clone())
+                .unwrap_or_default(),
+            threads: self.threads_with(&caller),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::{
+        CourseRatings, Engagement, MemberRole, OrganizationDetails, OrganizationKind,
+        VerificationStatus,
+    };
+    use super::*;
+
+    // Bob was hired by Carol and enrolled in a course of Alice, and reviewed both
+    fn engaged() -> (State, String) {
+        let mut state = state();
+        let (alice, bob, carol) = (ctx(principal(1)), ctx(principal(2)), ctx(principal(3)));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        state.apply_job(&bob, &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+        state.apply_course(&bob, &course.id);
+
+        let carol_id = state.own_profile(&carol).id;
+        state
+            .add_review(
+                &bob,
+                4.0,
+                "Great".into(),
+                "Clear brief".into(),
+                String::new(),
+                &carol_id,
+                Engagement::Job(job.id),
+                None,
+            )
+            .unwrap();
+        state
+            .rate_course(
+                &bob,
+                &course.id,
+                5.0,
+                CourseRatings {
+                    content_quality: 5.0,
+                    pacing: 4.0,
+                },
+                "Loved it".into(),
+                String::new(),
+            )
+            .unwrap();
+        (state, course.id)
+    }
+
+    #[test]
+    fn deactivated_profiles_are_hidden_until_reactivated() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let bob_id = state.own_profile(&bob).id;
+
+        state.deactivate_account(&bob).unwrap();
+        assert_eq!(
+            state.deactivate_account(&bob),
+            Err(AccountError::AlreadyDeactivated)
+        );
+        assert!(state.search("bob").is_none());
+        assert_eq!(state.profile_by_id(&ctx(principal(1)), &bob_id).id, "");
+        assert_eq!(state.profile_by_id(&bob, &bob_id).id, bob_id);
+
+        state.reactivate_account(&bob).unwrap();
+        assert_eq!(state.search("bob").unwrap().id, bob_id);
+        assert_eq!(
+            state.reactivate_account(&bob),
+            Err(AccountError::NotDeactivated)
+        );
+    }
+
+    #[test]
+    fn deleted_accounts_leave_anonymous_reviews() {
+        let (mut state, course_id) = engaged();
+        let bob = ctx(principal(2));
+        let bob_id = state.own_profile(&bob).id;
+        let job_id = state.jobs.keys().next().unwrap().clone();
+        let received = state
+            .add_review(
+                &ctx(principal(3)),
+                5.0,
+                "Great".into(),
+                "Fast".into(),
+                String::new(),
+                &bob_id,
+                Engagement::Job(job_id),
+                None,
+            )
+            .unwrap();
+        let request = VerificationRequest {
+            id: "request".into(),
+            subject: VerificationSubject::Trainer(principal(2)),
+            documents: vec![],
+            submitted_by: principal(2),
+            submitted_at: NOW,
+            status: VerificationStatus::Pending,
+            decision: None,
+        };
+        state.verifications.insert(request.id.clone(), request);
+        let profile = state.delete_account(&bob).unwrap();
+
+        assert!(!state.profiles.contains_key(&principal(2)));
+        assert!(!state.ids.contains_key(&profile.id));
+        assert!(!state.is_registered(&bob));
+        assert!(state.courses[&course_id].applicants.is_empty());
+        assert!(state.jobs.values().all(|job| job.hired.is_empty()));
+
+        let review = &state.reviews_of(&principal(3))[0];
+        assert_eq!(review.reviewer, DELETED_USER_NAME);
+        assert_eq!(review.reviewer_principal, None);
+        assert_eq!(state.profiles[&principal(3)].rating.count, 1);
+
+        let course_review = &state.course_reviews(&course_id)[0];
+        assert_eq!(course_review.reviewer_name, DELETED_USER_NAME);
+        assert_ne!(course_review.reviewer, principal(2));
+        assert_eq!(state.courses[&course_id].rating.overall.count, 1);
+
+        // Reviews of the deleted account go with it
+        assert!(!state.reviews.contains_key(&received.id));
+        assert!(!state.reviews_by_reviewee.contains_key(&principal(2)));
+        assert!(state.reviews_written_by(&principal(3)).is_empty());
+        assert!(state.verifications.is_empty());
+
+        assert_eq!(
+            state.delete_account(&bob).unwrap_err(),
+            AccountError::ProfileNotFound
+        );
+    }
+
+    #[test]
+    fn sole_owners_hand_their_organizations_over_first() {
+        let mut state = state();
+        let (bob, carol) = (ctx(principal(2)), ctx(principal(3)));
+        let details = OrganizationDetails {
+            name: "Acme".into(),
+            kind: OrganizationKind::Company,
+            description: String::new(),
+            website: None,
+            location: "Nairobi".into(),
+            logo_id: None,
+        };
+        let acme = state.create_organization(&carol, details).unwrap().id;
+        assert_eq!(
+            state.delete_account(&carol).unwrap_err(),
+            AccountError::LastOwner
+        );
+
+        state
+            .set_organization_member(&carol, &acme, principal(2), MemberRole::Owner)
+            .unwrap();
+        state.delete_account(&carol).unwrap();
+        assert!(state.organizations[&acme].allows(&principal(2), MemberRole::Owner));
+        assert_eq!(
+            state.delete_account(&bob).unwrap_err(),
+            AccountError::LastOwner
+        );
+    }
+
+    #[test]
+    fn exports_cover_what_the_platform_holds() {
+        let (state, course_id) = engaged();
+        let export = state.export_account(&ctx(principal(2)));
+
+        assert_eq!(export.profile.unwrap().fullname, "Bob");
+        assert_eq!(export.courses_enrolled[0].id, course_id);
+        assert_eq!(export.jobs_applied.len(), 1);
+        assert_eq!(export.reviews_written.len(), 1);
+        assert_eq!(export.course_reviews.len(), 1);
+        assert!(export.courses_created.is_empty());
+    }
+}
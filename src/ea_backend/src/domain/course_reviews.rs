@@ -0,0 +1,311 @@
+use super::ratings::is_whole_star;
+use super::{Context, RatingSummary, ReviewError, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// One review per learner and course, keyed by course id then learner
+pub type CourseReviewStore = BTreeMap<String, BTreeMap<Principal, CourseReview>>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// Sub-ratings of a course, whole stars like the overall rating
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct CourseRatings {
+    pub content_quality: f64,
+    pub pacing: f64,
+}
+
+impl CourseRatings {
+    fn accumulate(&mut self, other: &CourseRatings, sign: f64) {
+        self.content_quality += sign * other.content_quality;
+        self.pacing += sign * other.pacing;
+    }
+
+    fn divided_by(&self, count: u64) -> CourseRatings {
+        if count == 0 {
+            return CourseRatings::default();
+        }
+        let count = count as f64;
+        CourseRatings {
+            content_quality: self.content_quality / count,
+            pacing: self.pacing / count,
+        }
+    }
+}
+
+// Aggregates of the reviews of a course, or of every course of a trainer.
+// Summaries stored before course reviews existed are dropped on upgrade and
+// rebuilt from the review store.
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CourseRatingSummary {
+    pub overall: RatingSummary,
+    pub totals: CourseRatings,
+    pub means: CourseRatings,
+}
+
+impl CourseRatingSummary {
+    fn add(&mut self, review: &CourseReview) {
+        self.overall.add_rating(review.ratings, None);
+        self.totals.accumulate(&review.course_ratings, 1.0);
+        self.means = self.totals.divided_by(self.overall.count);
+    }
+
+    fn remove(&mut self, review: &CourseReview) {
+        self.overall.remove_rating(review.ratings, None);
+        self.totals.accumulate(&review.course_ratings, -1.0);
+        self.means = self.totals.divided_by(self.overall.count);
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct CourseReview {
+    pub course_id: String,
+    pub reviewer: Principal,
+    pub reviewer_name: String,
+    pub ratings: f64,
+    pub course_ratings: CourseRatings,
+    pub title: String,
+    pub review: String,
+    pub review_time: i64,
+    pub edited_at: Option<i64>,
+}
+
+// ==================================================================================================
+// Course review workflows
+// ==================================================================================================
+
+impl State {
+    // Learners enrolled in a course rate it once, rating it again replaces
+    // their review. The trainer can't rate their own course.
+    pub fn rate_course(
+        &mut self,
+        ctx: &Context,
+        course_id: &str,
+        ratings: f64,
+        course_ratings: CourseRatings,
+        title: String,
+        review: String,
+    ) -> Result<CourseReview, ReviewError> {
+        let reviewer = self
+            .profiles
+            .get(&ctx.caller)
+            .ok_or(ReviewError::ReviewerNotFound)?;
+        let course = self
+            .courses
+            .get(course_id)
+            .ok_or(ReviewError::CourseNotFound)?;
+        if course.creator == ctx.caller {
+            return Err(ReviewError::SelfReview);
+        }
+        if !course.applicants.contains(&ctx.caller) {
+            return Err(ReviewError::NotEngaged);
+        }
+        let valid = [
+            ratings,
+            course_ratings.content_quality,
+            course_ratings.pacing,
+        ]
+        .iter()
+        .all(|rating| is_whole_star(*rating));
+        if !valid {
+            return Err(ReviewError::InvalidRating);
+        }
+
+        let mut course_review = CourseReview {
+            course_id: course_id.to_string(),
+            reviewer: ctx.caller,
+            reviewer_name: reviewer.fullname.clone(),
+            ratings,
+            course_ratings,
+            title,
+            review,
+            review_time: ctx.now as i64,
+            edited_at: None,
+        };
+        if let Some(previous) = self.remove_course_review(course_id, &ctx.caller) {
+            course_review.review_time = previous.review_time;
+            course_review.edited_at = Some(ctx.now as i64);
+        }
+        self.record_course_rating(&course_review);
+        self.course_reviews
+            .entry(course_id.to_string())
+            .or_default()
+            .insert(ctx.caller, course_review.clone());
+        Ok(course_review)
+    }
+
+    pub fn delete_course_review(
+        &mut self,
+        ctx: &Context,
+        course_id: &str,
+    ) -> Result<(), ReviewError> {
+        self.remove_course_review(course_id, &ctx.caller)
+            .map(|_| ())
+            .ok_or(ReviewError::ReviewNotFound)
+    }
+
+    // The reviews of a course, newest first
+    pub fn course_reviews(&self, course_id: &str) -> Vec<CourseReview> {
+        let mut reviews: Vec<CourseReview> = self
+            .course_reviews
+            .get(course_id)
+            .map(|reviews| reviews.values().cloned().collect())
+            .unwrap_or_default();
+        reviews.sort_by_key(|review| std::cmp::Reverse(review.review_time));
+        reviews
+    }
+
+    // Counting a course review for the course and the rollup of its trainer
+    pub(crate) fn record_course_rating(&mut self, review: &CourseReview) {
+        let Some(course) = self.courses.get_mut(&review.course_id) else {
+            return;
+        };
+        course.rating.add(review);
+        if let Some(trainer) = self.profiles.get_mut(&course.creator) {
+            trainer.course_rating.add(review);
+        }
+    }
+
+    fn remove_course_review(
+        &mut self,
+        course_id: &str,
+        reviewer: &Principal,
+    ) -> Option<CourseReview> {
+        let reviews = self.course_reviews.get_mut(course_id)?;
+        let review = reviews.remove(reviewer)?;
+        if reviews.is_empty() {
+            self.course_reviews.remove(course_id);
+        }
+        if let Some(course) = self.courses.get_mut(course_id) {
+            course.rating.remove(&review);
+            if let Some(trainer) = self.profiles.get_mut(&course.creator) {
+                trainer.course_rating.remove(&review);
+            }
+        }
+        Some(review)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    fn course_ratings(content_quality: f64, pacing: f64) -> CourseRatings {
+        CourseRatings {
+            content_quality,
+            pacing,
+        }
+    }
+
+    // Alice teaches a course Bob enrolled in
+    fn enrolled() -> (State, String) {
+        let mut state = state();
+        let course = state
+            .create_course(&ctx(principal(1)), "Rust".into())
+            .unwrap();
+        state.apply_course(&ctx(principal(2)), &course.id).unwrap();
+        (state, course.id)
+    }
+
+    fn rate(
+        state: &mut State,
+        learner: u8,
+        course_id: &str,
+        ratings: f64,
+    ) -> Result<CourseReview, ReviewError> {
+        state.rate_course(
+            &ctx(principal(learner)),
+            course_id,
+            ratings,
+            course_ratings(ratings, 3.0),
+            "Course".into(),
+            "Well paced".into(),
+        )
+    }
+
+    #[test]
+    fn only_enrolled_learners_rate_courses() {
+        let (mut state, course_id) = enrolled();
+
+        assert_eq!(
+            rate(&mut state, 1, &course_id, 5.0).unwrap_err(),
+            ReviewError::SelfReview
+        );
+        assert_eq!(
+            rate(&mut state, 3, &course_id, 5.0).unwrap_err(),
+            ReviewError::NotEngaged
+        );
+        assert_eq!(
+            rate(&mut state, 2, "missing", 5.0).unwrap_err(),
+            ReviewError::CourseNotFound
+        );
+        assert_eq!(
+            rate(&mut state, 2, &course_id, 4.5).unwrap_err(),
+            ReviewError::InvalidRating
+        );
+        assert!(state.course_reviews(&course_id).is_empty());
+    }
+
+    #[test]
+    fn course_and_trainer_aggregates_follow_reviews() {
+        let (mut state, course_id) = enrolled();
+        let other = state
+            .create_course(&ctx(principal(1)), "Go".into())
+            .unwrap();
+        state.apply_course(&ctx(principal(3)), &other.id).unwrap();
+
+        rate(&mut state, 2, &course_id, 4.0).unwrap();
+        rate(&mut state, 3, &other.id, 2.0).unwrap();
+        let rating = state.course(&course_id).unwrap().rating;
+        assert_eq!(rating.overall.count, 1);
+        assert_eq!(rating.means, course_ratings(4.0, 3.0));
+
+        let trainer = state.own_profile(&ctx(principal(1)));
+        assert_eq!(trainer.course_rating.overall.count, 2);
+        assert_eq!(trainer.course_rating.overall.mean, 3.0);
+        // Course reviews don't count as reviews of the trainer
+        assert_eq!(trainer.rating.count, 0);
+
+        // Rating again replaces the review
+        let edited = rate(&mut state, 2, &course_id, 2.0).unwrap();
+        assert!(edited.edited_at.is_some());
+        assert_eq!(state.course_reviews(&course_id).len(), 1);
+        assert_eq!(state.course(&course_id).unwrap().rating.overall.mean, 2.0);
+
+        let aggregated = state.clone();
+        state.rebuild_ratings();
+        assert_eq!(
+            state.course(&course_id).unwrap().rating,
+            aggregated.course(&course_id).unwrap().rating
+        );
+        assert_eq!(
+            state.own_profile(&ctx(principal(1))).course_rating,
+            aggregated.own_profile(&ctx(principal(1))).course_rating
+        );
+
+        state
+            .delete_course_review(&ctx(principal(2)), &course_id)
+            .unwrap();
+        assert_eq!(
+            state.delete_course_review(&ctx(principal(2)), &course_id),
+            Err(ReviewError::ReviewNotFound)
+        );
+        assert_eq!(
+            state.course(&course_id).unwrap().rating,
+            CourseRatingSummary::default()
+        );
+        assert_eq!(
+            state
+                .own_profile(&ctx(principal(1)))
+                .course_rating
+                .overall
+                .count,
+            1
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use super::{Context, CourseRatingSummary, Profile, Roles, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Course {
+    pub id: String,
+    pub title: String,
+    pub creator: Principal,
+    pub creator_fullname: String,
+    pub applicants: Vec<Principal>,
+    // Asset ids of the course media, in display order
+    #[serde(default)]
+    pub media: Vec<String>,
+    // Ratings of the course by its enrolled learners
+    #[serde(default)]
+    pub rating: CourseRatingSummary,
+    // Courses of an organization are managed by its owners and instructors
+    #[serde(default)]
+    pub organization_id: Option<String>,
+    // Whether the organization, or the trainer without one, is verified
+    #[serde(default)]
+    pub verified: bool,
+}
+
+// ==================================================================================================
+// Course workflows
+// ==================================================================================================
+
+impl State {
+    // Only trainers can create courses
+    pub fn create_course(&mut self, ctx: &Context, title: String) -> Option<Course> {
+        let creator = self.profiles.get(&ctx.caller)?;
+        if !creator.has_role(&Roles::TRAINER) {
+            return None;
+        }
+        Some(self.insert_course(ctx, title, None))
+    }
+
+    pub(super) fn insert_course(
+        &mut self,
+        ctx: &Context,
+        title: String,
+        organization_id: Option<String>,
+    ) -> Course {
+        let creator_fullname = self
+            .profiles
+            .get(&ctx.caller)
+            .map(|creator| creator.fullname.clone())
+            .unwrap_or_default();
+        let verified = self.is_verified_owner(&organization_id, &ctx.caller);
+        let course = Course {
+            id: ctx.rng.next_id(),
+            title,
+            creator: ctx.caller,
+            creator_fullname,
+            applicants: vec![],
+            media: vec![],
+            rating: Default::default(),
+            organization_id,
+            verified,
+        };
+        self.courses.insert(course.id.clone(), course.clone());
+        course
+    }
+
+    // In reverse id order, as the listings always were. Ids are random, so this
+    // is not the order the courses were created in.
+    pub fn courses_by_creator(&self, ctx: &Context) -> Vec<Course> {
+        self.courses
+            .values()
+            .rev()
+            .filter(|course| course.creator == ctx.caller)
+            .cloned()
+            .collect()
+    }
+
+    pub fn course(&self, id: &str) -> Option<Course> {
+        self.courses.get(id).cloned()
+    }
+
+    pub fn all_courses(&self) -> Vec<Course> {
+        self.courses.values().rev().cloned().collect()
+    }
+
+    pub fn apply_course(&mut self, ctx: &Context, id: &str) -> Option<Course> {
+        let course = self.courses.get_mut(id)?;
+        if course.applicants.contains(&ctx.caller) {
+            return Some(course.clone());
+        }
+        course.applicants.push(ctx.caller);
+        let course = course.clone();
+        self.notify_course_enrollment(ctx, &course);
+        Some(course)
+    }
+
+    // Only the managers of a course can change its media
+    pub fn set_course_media(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        media: Vec<String>,
+    ) -> Option<Course> {
+        let managed = self
+            .courses
+            .get(id)
+            .is_some_and(|course| self.manages_course(course, &ctx.caller));
+        let course = self.courses.get_mut(id).filter(|_| managed)?;
+        course.media = media;
+        Some(course.clone())
+    }
+
+    pub fn has_applied_course(&self, ctx: &Context, course_id: &str) -> bool {
+        self.courses
+            .get(course_id)
+            .is_some_and(|course| course.applicants.contains(&ctx.caller))
+    }
+
+    pub fn courses_registered_by(&self, ctx: &Context) -> Vec<Course> {
+        self.courses
+            .values()
+            .rev()
+            .filter(|course| course.applicants.contains(&ctx.caller))
+            .cloned()
+            .collect()
+    }
+
+    pub fn course_applicants(&self, course_id: &str) -> Vec<Profile> {
+        self.courses
+            .get(course_id)
+            .map(|course| {
+                course
+                    .applicants
+                    .iter()
+                    .filter_map(|applicant| self.profiles.get(applicant).cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn trainers_create_courses_students_apply() {
+        let mut state = state();
+        let alice = ctx(principal(1));
+        let bob = ctx(principal(2));
+
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+        assert_eq!(course.creator_fullname, "Alice");
+        assert!(!state.has_applied_course(&bob, &course.id));
+
+        let applied = state.apply_course(&bob, &course.id).unwrap();
+        assert_eq!(applied.applicants, vec![principal(2)]);
+        assert!(state.has_applied_course(&bob, &course.id));
+        assert_eq!(state.courses_registered_by(&bob).len(), 1);
+        assert_eq!(state.courses_by_creator(&alice).len(), 1);
+        assert_eq!(state.course_applicants(&course.id)[0].fullname, "Bob");
+        assert!(state.apply_course(&bob, "missing").is_none());
+    }
+
+    #[test]
+    fn only_creators_set_course_media() {
+        let mut state = state();
+        let alice = ctx(principal(1));
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+
+        let media = vec!["intro".to_string()];
+        assert!(state
+            .set_course_media(&ctx(principal(2)), &course.id, media.clone())
+            .is_none());
+        let updated = state.set_course_media(&alice, &course.id, media).unwrap();
+        assert_eq!(state.course(&course.id).unwrap().media, updated.media);
+    }
+
+    #[test]
+    fn only_trainers_create_courses() {
+        let mut state = state();
+        assert!(state
+            .create_course(&ctx(principal(3)), "Rust".into())
+            .is_none());
+        assert!(state
+            .create_course(&ctx(principal(9)), "Rust".into())
+            .is_none());
+        assert!(state.courses.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn applying_is_idempotent(applications in prop::collection::vec(1u8..=3, 1..20)) {
+            let mut state = state();
+            let course = state.create_course(&ctx(principal(1)), "Rust".into()).unwrap();
+
+            for applicant in &applications {
+                state.apply_course(&ctx(principal(*applicant)), &course.id);
+            }
+
+            let mut expected: Vec<u8> = Vec::new();
+            for applicant in applications {
+                if !expected.contains(&applicant) {
+                    expected.push(applicant);
+                }
+            }
+            let expected: Vec<Principal> = expected.into_iter().map(principal).collect();
+            prop_assert_eq!(&state.course(&course.id).unwrap().applicants, &expected);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use super::{Context, Profile, State};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+// Upper bound for the entries of every section of a CV
+pub const MAX_CV_ENTRIES: usize = 50;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// Month precision is enough for a CV
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CvDate {
+    pub year: u16,
+    pub month: u8,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct WorkExperience {
+    pub employer: String,
+    pub title: String,
+    pub start: CvDate,
+    // Current positions have no end
+    pub end: Option<CvDate>,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Education {
+    pub institution: String,
+    pub degree: String,
+    pub field_of_study: String,
+    pub start: CvDate,
+    pub end: Option<CvDate>,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum LanguageProficiency {
+    Elementary,
+    Intermediate,
+    Professional,
+    Fluent,
+    Native,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Language {
+    pub language: String,
+    pub proficiency: LanguageProficiency,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct PortfolioLink {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Project {
+    pub name: String,
+    pub description: String,
+    pub url: Option<String>,
+    pub skills: Vec<String>,
+}
+
+// The sections of a CV written by its owner
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Cv {
+    pub experience: Vec<WorkExperience>,
+    pub education: Vec<Education>,
+    pub languages: Vec<Language>,
+    pub links: Vec<PortfolioLink>,
+    pub projects: Vec<Project>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum CvError {
+    ProfileNotFound,
+    InvalidDate,
+    EmptyField(String),
+    InvalidUrl(String),
+    TooManyEntries(u64),
+}
+
+// A course the owner was certified for on the platform
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct CompletedCourse {
+    pub course_id: String,
+    pub title: String,
+    pub trainer: String,
+    pub token_id: Nat,
+}
+
+// A job the owner was hired for on the platform
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct PlatformJob {
+    pub job_id: String,
+    pub title: String,
+    pub employer: String,
+}
+
+// A CV as it is shown and rendered, the written sections followed by what the
+// platform knows about its owner
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct CvDocument {
+    pub profile_id: String,
+    pub fullname: String,
+    pub occupation: String,
+    pub location: String,
+    pub description: String,
+    pub skills: Vec<String>,
+    pub cv: Cv,
+    pub completed_courses: Vec<CompletedCourse>,
+    pub platform_jobs: Vec<PlatformJob>,
+}
+
+impl CvDate {
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month) && self.year >= 1900
+    }
+}
+
+fn check_period(start: &CvDate, end: &Option<CvDate>) -> Result<(), CvError> {
+    let end_valid = end
+        .as_ref()
+        .is_none_or(|end| end.is_valid() && end >= start);
+    if !start.is_valid() || !end_valid {
+        return Err(CvError::InvalidDate);
+    }
+    Ok(())
+}
+
+fn check_filled(name: &str, value: &str) -> Result<(), CvError> {
+    if value.trim().is_empty() {
+        return Err(CvError::EmptyField(name.to_string()));
+    }
+    Ok(())
+}
+
+// Only links a browser can open safely end up in a rendered CV
+fn check_url(url: &str) -> Result<(), CvError> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(CvError::InvalidUrl(url.to_string()));
+    }
+    Ok(())
+}
+
+impl Cv {
+    pub fn validate(&self) -> Result<(), CvError> {
+        let counts = [
+            self.experience.len(),
+            self.education.len(),
+            self.languages.len(),
+            self.links.len(),
+            self.projects.len(),
+        ];
+        if counts.iter().any(|count| *count > MAX_CV_ENTRIES) {
+            return Err(CvError::TooManyEntries(MAX_CV_ENTRIES as u64));
+        }
+        for experience in &self.experience {
+            check_filled("employer", &experience.employer)?;
+            check_filled("title", &experience.title)?;
+            check_period(&experience.start, &experience.end)?;
+        }
+        for education in &self.education {
+            check_filled("institution", &education.institution)?;
+            check_period(&education.start, &education.end)?;
+        }
+        for language in &self.languages {
+            check_filled("language", &language.language)?;
+        }
+        for link in &self.links {
+            check_filled("label", &link.label)?;
+            check_url(&link.url)?;
+        }
+        for project in &self.projects {
+            check_filled("name", &project.name)?;
+            if let Some(url) = &project.url {
+                check_url(url)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ==================================================================================================
+// CV workflows
+// ==================================================================================================
+
+impl State {
+    // Replacing the CV of the caller, positions and studies are kept newest first
+    pub fn update_cv(&mut self, ctx: &Context, mut cv: Cv) -> Result<Cv, CvError> {
+        cv.validate()?;
+        let profile = self
+            .profiles
+            .get_mut(&ctx.caller)
+            .ok_or(CvError::ProfileNotFound)?;
+        cv.experience.sort_by(|a, b| b.start.cmp(&a.start));
+        cv.education.sort_by(|a, b| b.start.cmp(&a.start));
+        profile.cv = cv.clone();
+        Ok(cv)
+    }
+
+    // Nothing is returned when the privacy settings hide the CV from the viewer,
+    // or when the profile is deactivated. A hidden location is left empty.
+    pub fn cv_document(&self, viewer: &Principal, profile_id: &str) -> Option<CvDocument> {
+        let principal = self.ids.get(profile_id)?;
+        let profile = self.profiles.get(principal)?;
+        let hidden = !profile.is_active() && profile.principal_id != *viewer;
+        if hidden || !self.can_view_cv(viewer, profile) {
+            return None;
+        }
+        Some(CvDocument {
+            profile_id: profile.id.clone(),
+            fullname: profile.fullname.clone(),
+            occupation: profile.occupation.clone(),
+            location: if self.can_view_location(viewer, profile) {
+                profile.location.clone()
+            } else {
+                String::new()
+            },
+            description: profile.description.clone(),
+            skills: profile.skills.clone(),
+            cv: profile.cv.clone(),
+            completed_courses: self.completed_courses(profile),
+            platform_jobs: self.platform_jobs(profile),
+        })
+    }
+
+    // Courses the profile holds a certificate for
+    fn completed_courses(&self, profile: &Profile) -> Vec<CompletedCourse> {
+        profile
+            .token_ids
+            .iter()
+            .filter_map(|(token_id, course_id)| {
+                let course = self.courses.get(course_id)?;
+                Some(CompletedCourse {
+                    course_id: course_id.clone(),
+                    title: course.title.clone(),
+                    trainer: self.display_owner(&course.organization_id, &course.creator_fullname),
+                    token_id: token_id.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn platform_jobs(&self, profile: &Profile) -> Vec<PlatformJob> {
+        self.jobs
+            .values()
+            .filter(|job| job.hired.contains(&profile.principal_id))
+            .map(|job| PlatformJob {
+                job_id: job.id.clone(),
+                title: job.title.clone(),
+                employer: self.display_owner(&job.organization_id, &job.creator_fullname),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::Visibility;
+    use super::*;
+
+    fn date(year: u16, month: u8) -> CvDate {
+        CvDate { year, month }
+    }
+
+    fn experience(title: &str, start: CvDate, end: Option<CvDate>) -> WorkExperience {
+        WorkExperience {
+            employer: "Acme".into(),
+            title: title.into(),
+            start,
+            end,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn cvs_are_validated() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+
+        let backwards = Cv {
+            experience: vec![experience("Dev", date(2020, 5), Some(date(2019, 1)))],
+            ..Default::default()
+        };
+        assert_eq!(
+            state.update_cv(&bob, backwards).unwrap_err(),
+            CvError::InvalidDate
+        );
+
+        let unsafe_link = Cv {
+            links: vec![PortfolioLink {
+                label: "Site".into(),
+                url: "javascript:alert(1)".into(),
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            state.update_cv(&bob, unsafe_link),
+            Err(CvError::InvalidUrl(_))
+        ));
+        assert_eq!(
+            state.update_cv(&ctx(principal(9)), Cv::default()),
+            Err(CvError::ProfileNotFound)
+        );
+    }
+
+    #[test]
+    fn cv_documents_list_platform_courses_and_jobs() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let cv = Cv {
+            experience: vec![
+                experience("Intern", date(2019, 1), Some(date(2019, 6))),
+                experience("Developer", date(2021, 3), None),
+            ],
+            ..Default::default()
+        };
+        state.update_cv(&bob, cv).unwrap();
+
+        let course = state
+            .create_course(&ctx(principal(1)), "Rust".into())
+            .unwrap();
+        state
+            .profiles
+            .get_mut(&principal(2))
+            .unwrap()
+            .token_ids
+            .push((Nat::from(7u64), course.id.clone()));
+        let carol = ctx(principal(3));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        state.apply_job(&bob, &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+
+        let bob_id = state.own_profile(&bob).id;
+        let document = state.cv_document(&principal(1), &bob_id).unwrap();
+        assert_eq!(document.cv.experience[0].title, "Developer");
+        assert_eq!(document.completed_courses[0].title, "Rust");
+        assert_eq!(document.completed_courses[0].trainer, "Alice");
+        assert_eq!(document.platform_jobs[0].employer, "Carol");
+        assert!(state.cv_document(&principal(1), "missing").is_none());
+
+        state.profiles.get_mut(&principal(2)).unwrap().privacy.cv = Visibility::Employers;
+        assert!(state.cv_document(&principal(1), &bob_id).is_none());
+        assert!(state.cv_document(&principal(3), &bob_id).is_some());
+    }
+
+    #[test]
+    fn cv_documents_leave_a_hidden_location_empty() {
+        let mut state = state();
+        verified_company(&mut state, 3);
+        let bob = state.profiles.get_mut(&principal(2)).unwrap();
+        bob.location = "Nairobi".into();
+        bob.privacy.location = Visibility::Employers;
+        let bob_id = bob.id.clone();
+
+        let document = state.cv_document(&principal(1), &bob_id).unwrap();
+        assert_eq!(document.location, "");
+        let document = state.cv_document(&principal(3), &bob_id).unwrap();
+        assert_eq!(document.location, "Nairobi");
+        let document = state.cv_document(&principal(2), &bob_id).unwrap();
+        assert_eq!(document.location, "Nairobi");
+    }
+}
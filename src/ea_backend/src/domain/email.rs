@@ -0,0 +1,307 @@
+use super::{Context, Profile, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type EmailCodeStore = BTreeMap<Principal, EmailCode>;
+
+// A code can be confirmed for 15 minutes, with a handful of guesses
+pub const EMAIL_CODE_TTL: u64 = 15 * 60 * 1_000_000_000;
+pub const MAX_EMAIL_CODE_ATTEMPTS: u32 = 5;
+// Every code sent costs an outcall, users wait a minute before asking again
+pub const EMAIL_CODE_INTERVAL: u64 = 60 * 1_000_000_000;
+// Asking for new codes doesn't buy more guesses, failures add up over codes
+// until the user is locked out for a day
+pub const MAX_EMAIL_CODE_FAILURES: u32 = 3 * MAX_EMAIL_CODE_ATTEMPTS;
+pub const EMAIL_LOCKOUT: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// The code last sent to a user, it is never returned by the canister
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailCode {
+    pub email: String,
+    pub code: String,
+    pub sent_at: u64,
+    pub expires_at: u64,
+    pub attempts: u32,
+    // Failed guesses over every code sent since the last lockout
+    #[serde(default)]
+    pub failures: u32,
+    #[serde(default)]
+    pub locked_until: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum EmailError {
+    ProfileNotFound,
+    InvalidEmail,
+    AlreadyVerified,
+    RequestedTooSoon,
+    NoPendingCode,
+    CodeExpired,
+    TooManyAttempts,
+    InvalidCode,
+    DeliveryFailed(String),
+    LockedOut(u64),
+}
+
+// Enough to catch typos, the code sent is what proves the address
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+// ==================================================================================================
+// Email workflows
+// ==================================================================================================
+
+impl State {
+    // Issuing a code for the email of the caller's profile, replacing any earlier
+    // one. Returns the code, for the endpoint to send it.
+    pub fn issue_email_code(&mut self, ctx: &Context) -> Result<EmailCode, EmailError> {
+        let profile = self
+            .profiles
+            .get(&ctx.caller)
+            .ok_or(EmailError::ProfileNotFound)?;
+        if profile.email_verified {
+            return Err(EmailError::AlreadyVerified);
+        }
+        if !is_valid_email(&profile.email) {
+            return Err(EmailError::InvalidEmail);
+        }
+        let failures = match self.email_codes.get(&ctx.caller) {
+            Some(previous) => {
+                if let Some(until) = previous.locked_until.filter(|until| ctx.now < *until) {
+                    return Err(EmailError::LockedOut(until));
+                }
+                if ctx.now < previous.sent_at + EMAIL_CODE_INTERVAL {
+                    return Err(EmailError::RequestedTooSoon);
+                }
+                // A lockout that ran out starts the count over
+                if previous.locked_until.is_some() {
+                    0
+                } else {
+                    previous.failures
+                }
+            }
+            None => 0,
+        };
+
+        let code = EmailCode {
+            email: profile.email.clone(),
+            code: ctx.rng.next_code(),
+            sent_at: ctx.now,
+            expires_at: ctx.now + EMAIL_CODE_TTL,
+            attempts: 0,
+            failures,
+            locked_until: None,
+        };
+        self.email_codes.insert(ctx.caller, code.clone());
+        Ok(code)
+    }
+
+    // Withdrawing a code that could not be delivered, the failures are kept
+    pub fn discard_email_code(&mut self, caller: &Principal, code: &str) {
+        if let Some(pending) = self.email_codes.get_mut(caller) {
+            if pending.code == code {
+                pending.code.clear();
+            }
+        }
+    }
+
+    // Marking the email verified when the code matches. Codes are only valid
+    // for the address they were sent to.
+    pub fn confirm_email(&mut self, ctx: &Context, code: &str) -> Result<Profile, EmailError> {
+        let profile = self
+            .profiles
+            .get_mut(&ctx.caller)
+            .ok_or(EmailError::ProfileNotFound)?;
+        let pending = self
+            .email_codes
+            .get_mut(&ctx.caller)
+            .ok_or(EmailError::NoPendingCode)?;
+        if let Some(until) = pending.locked_until.filter(|until| ctx.now < *until) {
+            return Err(EmailError::LockedOut(until));
+        }
+        if pending.locked_until.is_some()
+            || pending.code.is_empty()
+            || pending.email != profile.email
+        {
+            return Err(EmailError::NoPendingCode);
+        }
+        if ctx.now >= pending.expires_at {
+            return Err(EmailError::CodeExpired);
+        }
+        if pending.attempts >= MAX_EMAIL_CODE_ATTEMPTS {
+            return Err(EmailError::TooManyAttempts);
+        }
+        if pending.code != code.trim() {
+            pending.attempts += 1;
+            pending.failures += 1;
+            if pending.failures >= MAX_EMAIL_CODE_FAILURES {
+                let until = ctx.now + EMAIL_LOCKOUT;
+                pending.locked_until = Some(until);
+                return Err(EmailError::LockedOut(until));
+            }
+            return Err(EmailError::InvalidCode);
+        }
+
+        profile.email_verified = true;
+        let profile = profile.clone();
+        self.email_codes.remove(&ctx.caller);
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::Rng;
+    use super::*;
+
+    fn at(caller: u8, now: u64) -> Context {
+        Context::new(principal(caller), now, Rng::from_seed(&now.to_le_bytes()))
+    }
+
+    #[test]
+    fn emails_are_verified_with_the_code_sent() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let code = state.issue_email_code(&bob).unwrap();
+        assert_eq!(code.email, "bob@ea.dev");
+        assert_eq!(code.code.len(), 6);
+        assert_eq!(
+            state.issue_email_code(&bob).unwrap_err(),
+            EmailError::RequestedTooSoon
+        );
+
+        assert_eq!(
+            state.confirm_email(&bob, "wrong").unwrap_err(),
+            EmailError::InvalidCode
+        );
+        let profile = state.confirm_email(&bob, &code.code).unwrap();
+        assert!(profile.email_verified);
+        assert_eq!(
+            state.confirm_email(&bob, &code.code).unwrap_err(),
+            EmailError::NoPendingCode
+        );
+        assert_eq!(
+            state.issue_email_code(&bob).unwrap_err(),
+            EmailError::AlreadyVerified
+        );
+
+        // Changing the address takes the verification away
+        let mut changed = profile.clone();
+        changed.email = "bob@elsewhere.dev".into();
+        changed.email_verified = true;
+        state.update_profile(&bob, changed);
+        assert!(!state.own_profile(&bob).email_verified);
+    }
+
+    #[test]
+    fn codes_expire_and_allow_few_attempts() {
+        let mut state = state();
+        let code = state.issue_email_code(&at(2, NOW)).unwrap();
+        for _ in 0..MAX_EMAIL_CODE_ATTEMPTS {
+            assert_eq!(
+                state.confirm_email(&at(2, NOW), "000000x").unwrap_err(),
+                EmailError::InvalidCode
+            );
+        }
+        assert_eq!(
+            state.confirm_email(&at(2, NOW), &code.code).unwrap_err(),
+            EmailError::TooManyAttempts
+        );
+
+        let later = NOW + EMAIL_CODE_INTERVAL;
+        let code = state.issue_email_code(&at(2, later)).unwrap();
+        assert_eq!(
+            state
+                .confirm_email(&at(2, later + EMAIL_CODE_TTL), &code.code)
+                .unwrap_err(),
+            EmailError::CodeExpired
+        );
+    }
+
+    #[test]
+    fn failures_add_up_over_codes_until_the_user_is_locked_out() {
+        let mut state = state();
+        let mut now = NOW;
+        let mut last = Err(EmailError::NoPendingCode);
+        for _ in 0..MAX_EMAIL_CODE_FAILURES / MAX_EMAIL_CODE_ATTEMPTS {
+            state.issue_email_code(&at(2, now)).unwrap();
+            for _ in 0..MAX_EMAIL_CODE_ATTEMPTS {
+                last = state.confirm_email(&at(2, now), "000000x");
+            }
+            now += EMAIL_CODE_INTERVAL;
+        }
+        let until = now - EMAIL_CODE_INTERVAL + EMAIL_LOCKOUT;
+        assert_eq!(last.unwrap_err(), EmailError::LockedOut(until));
+
+        // Neither a new code nor the right guess gets through until the lockout ends
+        assert_eq!(
+            state.issue_email_code(&at(2, now)).unwrap_err(),
+            EmailError::LockedOut(until)
+        );
+        let code = state.email_codes[&principal(2)].code.clone();
+        assert_eq!(
+            state.confirm_email(&at(2, now), &code).unwrap_err(),
+            EmailError::LockedOut(until)
+        );
+
+        let code = state.issue_email_code(&at(2, until)).unwrap();
+        assert_eq!(code.failures, 0);
+        assert!(state.confirm_email(&at(2, until), &code.code).is_ok());
+    }
+
+    #[test]
+    fn undelivered_codes_are_withdrawn() {
+        let mut state = state();
+        let code = state.issue_email_code(&at(2, NOW)).unwrap();
+        state.confirm_email(&at(2, NOW), "000000x").unwrap_err();
+        state.discard_email_code(&principal(2), &code.code);
+        assert_eq!(
+            state.confirm_email(&at(2, NOW), &code.code).unwrap_err(),
+            EmailError::NoPendingCode
+        );
+
+        // The next code keeps counting the failures
+        let later = NOW + EMAIL_CODE_INTERVAL;
+        let code = state.issue_email_code(&at(2, later)).unwrap();
+        assert_eq!(code.failures, 1);
+        state.discard_email_code(&principal(2), "another code");
+        assert!(state.confirm_email(&at(2, later), &code.code).is_ok());
+    }
+
+    #[test]
+    fn codes_are_tied_to_the_address_they_were_sent_to() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let code = state.issue_email_code(&bob).unwrap();
+        state.profiles.get_mut(&principal(2)).unwrap().email = "bob@elsewhere.dev".into();
+        assert_eq!(
+            state.confirm_email(&bob, &code.code).unwrap_err(),
+            EmailError::NoPendingCode
+        );
+
+        state.profiles.get_mut(&principal(2)).unwrap().email = "not an email".into();
+        assert_eq!(
+            state
+                .issue_email_code(&at(2, NOW + EMAIL_CODE_INTERVAL))
+                .unwrap_err(),
+            EmailError::InvalidEmail
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use super::{Context, Profile, Roles, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Jobs {
+    pub id: String,
+    pub title: String,
+    pub creator: Principal,
+    pub creator_fullname: String,
+    pub applicants: Vec<Principal>,
+    // Applicants the creator took on, jobs from before hiring have none
+    #[serde(default)]
+    pub hired: Vec<Principal>,
+    // Jobs of an organization are managed by its owners and recruiters
+    #[serde(default)]
+    pub organization_id: Option<String>,
+    // Whether the organization, or the creator without one, is verified
+    #[serde(default)]
+    pub verified: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum JobError {
+    JobNotFound,
+    Unauthorized,
+    NotAnApplicant,
+}
+
+// ==================================================================================================
+// Job workflows
+// ==================================================================================================
+
+impl State {
+    pub fn apply_job(&mut self, ctx: &Context, id: &str) -> Option<Jobs> {
+        let job = self.jobs.get_mut(id)?;
+        if job.applicants.contains(&ctx.caller) {
+            return Some(job.clone());
+        }
+        job.applicants.push(ctx.caller);
+        let job = job.clone();
+        self.notify_job_application(ctx, &job);
+        Some(job)
+    }
+
+    // Only employers can create jobs
+    pub fn create_job(&mut self, ctx: &Context, title: String) -> Option<Jobs> {
+        let creator = self.profiles.get(&ctx.caller)?;
+        if !creator.has_role(&Roles::EMPLOYER) {
+            return None;
+        }
+        Some(self.insert_job(ctx, title, None))
+    }
+
+    pub(super) fn insert_job(
+        &mut self,
+        ctx: &Context,
+        title: String,
+        organization_id: Option<String>,
+    ) -> Jobs {
+        let creator_fullname = self
+            .profiles
+            .get(&ctx.caller)
+            .map(|creator| creator.fullname.clone())
+            .unwrap_or_default();
+        let verified = self.is_verified_owner(&organization_id, &ctx.caller);
+        let job = Jobs {
+            id: ctx.rng.next_id(),
+            title,
+            creator: ctx.caller,
+            creator_fullname,
+            applicants: vec![],
+            hired: vec![],
+            organization_id,
+            verified,
+        };
+        self.jobs.insert(job.id.clone(), job.clone());
+        job
+    }
+
+    // Only the managers of a job hire, and only among the applicants
+    pub fn hire(
+        &mut self,
+        ctx: &Context,
+        job_id: &str,
+        applicant: Principal,
+    ) -> Result<Jobs, JobError> {
+        let job = self.jobs.get(job_id).ok_or(JobError::JobNotFound)?;
+        if !self.manages_job(job, &ctx.caller) {
+            return Err(JobError::Unauthorized);
+        }
+        let job = self.jobs.get_mut(job_id).ok_or(JobError::JobNotFound)?;
+        if !job.applicants.contains(&applicant) {
+            return Err(JobError::NotAnApplicant);
+        }
+        if job.hired.contains(&applicant) {
+            return Ok(job.clone());
+        }
+        job.hired.push(applicant);
+        let job = job.clone();
+        self.notify_hire(ctx, &job, applicant);
+        Ok(job)
+    }
+
+    // In reverse id order, as the listings always were. Ids are random, so this
+    // is not the order the jobs were created in.
+    pub fn all_jobs(&self) -> Vec<Jobs> {
+        self.jobs.values().rev().cloned().collect()
+    }
+
+    pub fn jobs_by_creator(&self, ctx: &Context) -> Vec<Jobs> {
+        self.jobs
+            .values()
+            .rev()
+            .filter(|job| job.creator == ctx.caller)
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_applied_job(&self, ctx: &Context, job_id: &str) -> bool {
+        self.jobs
+            .get(job_id)
+            .is_some_and(|job| job.applicants.contains(&ctx.caller))
+    }
+
+    pub fn jobs_applied_count(&self, ctx: &Context) -> u32 {
+        self.jobs
+            .values()
+            .filter(|job| job.applicants.contains(&ctx.caller))
+            .count() as u32
+    }
+
+    pub fn job_applicants(&self, job_id: &str) -> Vec<Profile> {
+        self.jobs
+            .get(job_id)
+            .map(|job| {
+                job.applicants
+                    .iter()
+                    .filter_map(|applicant| self.profiles.get(applicant).cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    #[test]
+    fn employers_create_jobs_freelancers_apply() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let carol = ctx(principal(3));
+
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        assert_eq!(job.creator_fullname, "Carol");
+        assert_eq!(state.jobs_applied_count(&bob), 0);
+
+        state.apply_job(&bob, &job.id).unwrap();
+        state.apply_job(&bob, &job.id).unwrap();
+        assert!(state.has_applied_job(&bob, &job.id));
+        assert_eq!(state.jobs_applied_count(&bob), 1);
+        assert_eq!(state.jobs_by_creator(&carol).len(), 1);
+        assert_eq!(state.job_applicants(&job.id)[0].fullname, "Bob");
+        assert_eq!(state.all_jobs().len(), 1);
+    }
+
+    #[test]
+    fn creators_hire_among_applicants() {
+        let mut state = state();
+        let carol = ctx(principal(3));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+
+        assert_eq!(
+            state.hire(&carol, &job.id, principal(2)).unwrap_err(),
+            JobError::NotAnApplicant
+        );
+        state.apply_job(&ctx(principal(2)), &job.id).unwrap();
+        assert_eq!(
+            state
+                .hire(&ctx(principal(2)), &job.id, principal(2))
+                .unwrap_err(),
+            JobError::Unauthorized
+        );
+        assert_eq!(
+            state.hire(&carol, "missing", principal(2)).unwrap_err(),
+            JobError::JobNotFound
+        );
+
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+        let hired = state.hire(&carol, &job.id, principal(2)).unwrap();
+        assert_eq!(hired.hired, vec![principal(2)]);
+    }
+
+    #[test]
+    fn only_employers_create_jobs() {
+        let mut state = state();
+        assert!(state
+            .create_job(&ctx(principal(1)), "Designer".into())
+            .is_none());
+        assert!(state
+            .create_job(&ctx(principal(9)), "Designer".into())
+            .is_none());
+        assert!(state.jobs.is_empty());
+    }
+}
@@ -0,0 +1,412 @@
+use super::{Context, MemberRole, NotificationKind, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type ThreadStore = BTreeMap<String, Thread>;
+
+// Upper bound for the length of a message, in characters
+pub const MAX_MESSAGE_LENGTH: usize = 4_000;
+pub const MAX_MESSAGE_ATTACHMENTS: usize = 5;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// What a conversation is about. Its participants are the applicant or learner
+// and whoever manages the job or course at the time.
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum ThreadScope {
+    JobApplication {
+        job_id: String,
+        applicant: Principal,
+    },
+    CourseEnrollment {
+        course_id: String,
+        learner: Principal,
+    },
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Message {
+    // Position of the message in its thread
+    pub id: u64,
+    pub sender: Principal,
+    pub body: String,
+    // Asset ids of the attached files
+    pub attachments: Vec<String>,
+    pub sent_at: u64,
+}
+
+// How far a participant read the thread
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct ReadReceipt {
+    pub reader: Principal,
+    // Id of the last message read
+    pub last_read: u64,
+    pub read_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Thread {
+    pub id: String,
+    pub scope: ThreadScope,
+    pub messages: Vec<Message>,
+    pub receipts: Vec<ReadReceipt>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    pub id: String,
+    pub scope: ThreadScope,
+    pub last_message: Option<Message>,
+    // Messages of the other participants the caller has not read yet
+    pub unread: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum MessagingError {
+    JobNotFound,
+    CourseNotFound,
+    NotAnApplicant,
+    NotEnrolled,
+    ThreadNotFound,
+    Unauthorized,
+    EmptyMessage,
+    MessageTooLong,
+    TooManyAttachments,
+    InvalidAsset,
+}
+
+impl Thread {
+    fn unread_by(&self, reader: &Principal) -> u64 {
+        let last_read = self
+            .receipts
+            .iter()
+            .find(|receipt| receipt.reader == *reader)
+            .map(|receipt| receipt.last_read);
+        self.messages
+            .iter()
+            .filter(|message| message.sender != *reader)
+            .filter(|message| last_read.is_none_or(|last_read| message.id > last_read))
+            .count() as u64
+    }
+
+    fn mark_read(&mut self, reader: Principal, now: u64) {
+        let Some(last) = self.messages.last() else {
+            return;
+        };
+        let receipt = ReadReceipt {
+            reader,
+            last_read: last.id,
+            read_at: now,
+        };
+        match self
+            .receipts
+            .iter_mut()
+            .find(|receipt| receipt.reader == reader)
+        {
+            Some(existing) => *existing = receipt,
+            None => self.receipts.push(receipt),
+        }
+    }
+
+    // Whether the thread is about an application or enrollment of the user
+    pub(super) fn is_about(&self, principal: &Principal) -> bool {
+        match &self.scope {
+            ThreadScope::JobApplication { applicant, .. } => applicant == principal,
+            ThreadScope::CourseEnrollment { learner, .. } => learner == principal,
+        }
+    }
+
+    fn summary(&self, reader: &Principal) -> ThreadSummary {
+        ThreadSummary {
+            id: self.id.clone(),
+            scope: self.scope.clone(),
+            last_message: self.messages.last().cloned(),
+            unread: self.unread_by(reader),
+        }
+    }
+}
+
+// ==================================================================================================
+// Messaging workflows
+// ==================================================================================================
+
+impl State {
+    fn participants(&self, scope: &ThreadScope) -> Result<Vec<Principal>, MessagingError> {
+        match scope {
+            ThreadScope::JobApplication { job_id, applicant } => {
+                let job = self.jobs.get(job_id).ok_or(MessagingError::JobNotFound)?;
+                if !job.applicants.contains(applicant) {
+                    return Err(MessagingError::NotAnApplicant);
+                }
+                let mut participants =
+                    self.managers(&job.organization_id, job.creator, MemberRole::Recruiter);
+                participants.push(*applicant);
+                Ok(participants)
+            }
+            ThreadScope::CourseEnrollment { course_id, learner } => {
+                let course = self
+                    .courses
+                    .get(course_id)
+                    .ok_or(MessagingError::CourseNotFound)?;
+                if !course.applicants.contains(learner) {
+                    return Err(MessagingError::NotEnrolled);
+                }
+                let mut participants = self.managers(
+                    &course.organization_id,
+                    course.creator,
+                    MemberRole::Instructor,
+                );
+                participants.push(*learner);
+                Ok(participants)
+            }
+        }
+    }
+
+    pub(super) fn is_participant(&self, thread: &Thread, principal: &Principal) -> bool {
+        self.participants(&thread.scope)
+            .is_ok_and(|participants| participants.contains(principal))
+    }
+
+    // The thread of an application or enrollment, started on first use
+    pub fn open_thread(
+        &mut self,
+        ctx: &Context,
+        scope: ThreadScope,
+    ) -> Result<Thread, MessagingError> {
+        if !self.participants(&scope)?.contains(&ctx.caller) {
+            return Err(MessagingError::Unauthorized);
+        }
+        if let Some(thread) = self.threads.values().find(|thread| thread.scope == scope) {
+            return Ok(thread.clone());
+        }
+        let thread = Thread {
+            id: ctx.rng.next_id(),
+            scope,
+            messages: vec![],
+            receipts: vec![],
+            created_at: ctx.now,
+        };
+        self.threads.insert(thread.id.clone(), thread.clone());
+        Ok(thread)
+    }
+
+    // Only participants post, the other participants are notified
+    pub fn send_message(
+        &mut self,
+        ctx: &Context,
+        thread_id: &str,
+        body: String,
+        attachments: Vec<String>,
+    ) -> Result<Message, MessagingError> {
+        if body.trim().is_empty() && attachments.is_empty() {
+            return Err(MessagingError::EmptyMessage);
+        }
+        if body.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(MessagingError::MessageTooLong);
+        }
+        if attachments.len() > MAX_MESSAGE_ATTACHMENTS {
+            return Err(MessagingError::TooManyAttachments);
+        }
+        let thread = self
+            .threads
+            .get(thread_id)
+            .ok_or(MessagingError::ThreadNotFound)?;
+        if !self.is_participant(thread, &ctx.caller) {
+            return Err(MessagingError::Unauthorized);
+        }
+        let recipients = self.participants(&thread.scope)?;
+
+        let thread = self.threads.get_mut(thread_id).unwrap();
+        let message = Message {
+            id: thread.messages.len() as u64,
+            sender: ctx.caller,
+            body,
+            attachments,
+            sent_at: ctx.now,
+        };
+        thread.messages.push(message.clone());
+        // Senders have read everything up to their own message
+        thread.mark_read(ctx.caller, ctx.now);
+
+        let notice = format!("New message from {}", self.fullname(&ctx.caller));
+        for recipient in recipients {
+            self.notify(
+                recipient,
+                NotificationKind::NewMessage,
+                Some(ctx.caller),
+                thread_id.to_string(),
+                notice.clone(),
+                ctx.now,
+            );
+        }
+        Ok(message)
+    }
+
+    // Participants read their threads, admins read any thread for moderation
+    pub fn thread(
+        &self,
+        ctx: &Context,
+        thread_id: &str,
+        admin: bool,
+    ) -> Result<Thread, MessagingError> {
+        let thread = self
+            .threads
+            .get(thread_id)
+            .ok_or(MessagingError::ThreadNotFound)?;
+        if !admin && !self.is_participant(thread, &ctx.caller) {
+            return Err(MessagingError::Unauthorized);
+        }
+        Ok(thread.clone())
+    }
+
+    pub fn mark_thread_read(
+        &mut self,
+        ctx: &Context,
+        thread_id: &str,
+    ) -> Result<Thread, MessagingError> {
+        self.thread(ctx, thread_id, false)?;
+        let thread = self.threads.get_mut(thread_id).unwrap();
+        thread.mark_read(ctx.caller, ctx.now);
+        Ok(thread.clone())
+    }
+
+    // Threads the caller takes part in, latest activity first
+    pub fn threads_of(&self, principal: &Principal) -> Vec<ThreadSummary> {
+        let mut summaries: Vec<(u64, ThreadSummary)> = self
+            .threads
+            .values()
+            .filter(|thread| self.is_participant(thread, principal))
+            .map(|thread| {
+                let active_at = thread
+                    .messages
+                    .last()
+                    .map_or(thread.created_at, |message| message.sent_at);
+                (active_at, thread.summary(principal))
+            })
+            .collect();
+        summaries.sort_by_key(|(active_at, _)| std::cmp::Reverse(*active_at));
+        summaries.into_iter().map(|(_, summary)| summary).collect()
+    }
+
+    // Whole threads the user takes part in, for data exports
+    pub fn threads_with(&self, principal: &Principal) -> Vec<Thread> {
+        self.threads
+            .values()
+            .filter(|thread| self.is_participant(thread, principal))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    // Bob applied to a job of Carol and enrolled in a course of Alice
+    fn engaged() -> (State, ThreadScope, ThreadScope) {
+        let mut state = state();
+        let job = state
+            .create_job(&ctx(principal(3)), "Designer".into())
+            .unwrap();
+        state.apply_job(&ctx(principal(2)), &job.id).unwrap();
+        let course = state
+            .create_course(&ctx(principal(1)), "Rust".into())
+            .unwrap();
+        state.apply_course(&ctx(principal(2)), &course.id);
+        let application = ThreadScope::JobApplication {
+            job_id: job.id,
+            applicant: principal(2),
+        };
+        let enrollment = ThreadScope::CourseEnrollment {
+            course_id: course.id,
+            learner: principal(2),
+        };
+        (state, application, enrollment)
+    }
+
+    #[test]
+    fn threads_are_scoped_to_applications_and_enrollments() {
+        let (mut state, application, enrollment) = engaged();
+        let (alice, bob, carol) = (ctx(principal(1)), ctx(principal(2)), ctx(principal(3)));
+
+        let thread = state.open_thread(&carol, application.clone()).unwrap();
+        assert_eq!(
+            state.open_thread(&bob, application.clone()).unwrap().id,
+            thread.id
+        );
+        assert_eq!(
+            state.open_thread(&alice, application).unwrap_err(),
+            MessagingError::Unauthorized
+        );
+        assert_eq!(
+            state
+                .open_thread(
+                    &carol,
+                    ThreadScope::JobApplication {
+                        job_id: "missing".into(),
+                        applicant: principal(2),
+                    }
+                )
+                .unwrap_err(),
+            MessagingError::JobNotFound
+        );
+        let lessons = state.open_thread(&alice, enrollment).unwrap();
+        assert_ne!(lessons.id, thread.id);
+
+        assert_eq!(
+            state
+                .send_message(&alice, &thread.id, "Hi".into(), vec![])
+                .unwrap_err(),
+            MessagingError::Unauthorized
+        );
+        assert_eq!(
+            state.thread(&alice, &thread.id, false).unwrap_err(),
+            MessagingError::Unauthorized
+        );
+        // Admins read threads they don't take part in
+        assert!(state.thread(&ctx(principal(9)), &thread.id, true).is_ok());
+    }
+
+    #[test]
+    fn messages_are_read_and_notified() {
+        let (mut state, application, _) = engaged();
+        let (bob, carol) = (ctx(principal(2)), ctx(principal(3)));
+        let thread = state.open_thread(&carol, application).unwrap();
+
+        assert_eq!(
+            state
+                .send_message(&carol, &thread.id, "  ".into(), vec![])
+                .unwrap_err(),
+            MessagingError::EmptyMessage
+        );
+        state
+            .send_message(&carol, &thread.id, "When can you start?".into(), vec![])
+            .unwrap();
+        state
+            .send_message(&carol, &thread.id, "Portfolio?".into(), vec![])
+            .unwrap();
+
+        assert_eq!(state.threads_of(&principal(2))[0].unread, 2);
+        assert_eq!(state.threads_of(&principal(3))[0].unread, 0);
+        assert_eq!(
+            state.notifications_page(&bob, 0, 10).notifications[0].kind,
+            NotificationKind::NewMessage
+        );
+
+        let read = state.mark_thread_read(&bob, &thread.id).unwrap();
+        assert_eq!(read.receipts[1].reader, principal(2));
+        assert_eq!(read.receipts[1].last_read, 1);
+        assert_eq!(state.threads_of(&principal(2))[0].unread, 0);
+
+        let reply = state
+            .send_message(&bob, &thread.id, "Monday".into(), vec!["cv".into()])
+            .unwrap();
+        assert_eq!(reply.id, 2);
+        assert_eq!(state.threads_of(&principal(3))[0].unread, 1);
+    }
+}
@@ -0,0 +1,269 @@
+mod account;
+mod course_reviews;
+mod courses;
+mod cv;
+mod email;
+mod jobs;
+mod messaging;
+mod moderation;
+mod notifications;
+mod organizations;
+mod privacy;
+mod ratings;
+mod reviews;
+mod roles;
+mod users;
+mod verification;
+
+pub use account::{AccountError, AccountExport, FiledReport, DELETED_USER_NAME};
+pub use course_reviews::{CourseRatingSummary, CourseRatings, CourseReview, CourseReviewStore};
+pub use courses::Course;
+pub use cv::{
+    CompletedCourse, Cv, CvDate, CvDocument, CvError, Education, Language, LanguageProficiency,
+    PlatformJob, PortfolioLink, Project, WorkExperience,
+};
+pub use email::{EmailCode, EmailCodeStore, EmailError, EMAIL_CODE_TTL};
+pub use jobs::{JobError, Jobs};
+pub use messaging::{
+    Message, MessagingError, ReadReceipt, Thread, ThreadScope, ThreadStore, ThreadSummary,
+};
+pub use moderation::{
+    ModerationAction, ModerationCase, ModerationNote, ModerationQueueEntry, ModerationStore,
+    ReviewReport,
+};
+pub use notifications::{
+    Inbox, Notification, NotificationError, NotificationKind, NotificationPage,
+    NotificationPreferences, NotificationStore,
+};
+pub use organizations::{
+    Member, MemberRole, Organization, OrganizationDetails, OrganizationError, OrganizationKind,
+    OrganizationStore,
+};
+pub use privacy::{AssetScope, PrivacySettings, PublicProfile, Visibility};
+pub use ratings::{CategoryRatings, RatingSummary};
+pub use reviews::{
+    Engagement, LegacyProfileReviews, ReviewError, ReviewIndex, ReviewReply, ReviewRevision,
+    Reviews,
+};
+pub use roles::{
+    Availability, EmployerProfile, FreelancerProfile, RoleError, RoleProfile, TrainerProfile,
+};
+pub use users::{LegacyProfileResume, Profile, RegistrationError, Roles};
+pub use verification::{
+    VerificationDecision, VerificationError, VerificationNote, VerificationRequest,
+    VerificationStatus, VerificationStore, VerificationSubject,
+};
+
+use candid::Principal;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+// Platform workflows, independent of the IC system API. Endpoints capture the
+// caller, time and randomness of the call in a `Context` and run the workflow
+// against the `State`, so every workflow can be exercised natively in tests.
+
+pub type IdStore = BTreeMap<String, Principal>;
+pub type ProfileStore = BTreeMap<Principal, Profile>;
+pub type CourseStore = BTreeMap<String, Course>;
+pub type JobStore = BTreeMap<String, Jobs>;
+pub type ReviewStore = BTreeMap<String, Reviews>;
+
+thread_local! {
+    pub(crate) static STATE: RefCell<State> = RefCell::default();
+}
+
+pub fn with<T, F: FnOnce(&State) -> T>(f: F) -> T {
+    STATE.with(|state| f(&state.borrow()))
+}
+
+pub fn with_mut<T, F: FnOnce(&mut State) -> T>(f: F) -> T {
+    STATE.with(|state| f(&mut state.borrow_mut()))
+}
+
+#[derive(Clone, Default)]
+pub struct State {
+    pub profiles: ProfileStore,
+    pub ids: IdStore,
+    pub courses: CourseStore,
+    pub jobs: JobStore,
+    pub reviews: ReviewStore,
+    pub moderation: ModerationStore,
+    pub course_reviews: CourseReviewStore,
+    pub organizations: OrganizationStore,
+    pub verifications: VerificationStore,
+    pub email_codes: EmailCodeStore,
+    pub notifications: NotificationStore,
+    pub threads: ThreadStore,
+    // Derived from the review store, not persisted
+    pub reviews_by_reviewee: ReviewIndex,
+    pub reviews_by_reviewer: ReviewIndex,
+}
+
+// ==================================================================================================
+// Context
+// ==================================================================================================
+
+pub struct Context {
+    pub caller: Principal,
+    pub now: u64,
+    pub rng: Rng,
+}
+
+impl Context {
+    pub fn new(caller: Principal, now: u64, rng: Rng) -> Self {
+        Context { caller, now, rng }
+    }
+
+    // Context of the current call, for workflows that don't issue new ids
+    pub fn current() -> Self {
+        Context::new(ic_cdk::api::caller(), ic_cdk::api::time(), Rng::unseeded())
+    }
+
+    // Context of the current call, seeded from the management canister
+    pub async fn with_randomness() -> Self {
+        Context::try_with_randomness()
+            .await
+            .expect("Failed to get randomness")
+    }
+
+    // The same, handing the rejection of the management canister back to the caller
+    pub async fn try_with_randomness() -> Result<Self, String> {
+        let caller = ic_cdk::api::caller();
+        let (seed,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(code, message)| format!("{:?}: {}", code, message))?;
+        Ok(Context::new(
+            caller,
+            ic_cdk::api::time(),
+            Rng::from_seed(&seed),
+        ))
+    }
+}
+
+// Issues ids derived from a per call seed, as many as a workflow needs
+pub struct Rng {
+    seed: Option<Vec<u8>>,
+    counter: Cell<u64>,
+}
+
+impl Rng {
+    pub fn from_seed(seed: &[u8]) -> Self {
+        Rng {
+            seed: Some(seed.to_vec()),
+            counter: Cell::new(0),
+        }
+    }
+
+    // For calls that don't issue ids, every call would issue the same ones
+    pub fn unseeded() -> Self {
+        Rng {
+            seed: None,
+            counter: Cell::new(0),
+        }
+    }
+
+    // Hex encoded sha256, the same shape as the ids issued so far
+    pub fn next_id(&self) -> String {
+        let seed = self
+            .seed
+            .as_ref()
+            .expect("Ids are only issued in a context seeded with randomness");
+        let counter = self.counter.get();
+        self.counter.set(counter + 1);
+
+        let mut hasher = Sha256::new();
+        hasher.update(seed);
+        hasher.update(counter.to_le_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    // Six decimal digits, for codes users type in
+    pub fn next_code(&self) -> String {
+        let id = self.next_id();
+        let value = u64::from_str_radix(&id[..15], 16).unwrap();
+        format!("{:06}", value % 1_000_000)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    pub const NOW: u64 = 1_700_000_000_000_000_000;
+
+    thread_local! {
+        static SEEDS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    pub fn principal(n: u8) -> Principal {
+        Principal::self_authenticating([n])
+    }
+
+    // Every context gets its own seed, like every call does on the IC
+    pub fn ctx(caller: Principal) -> Context {
+        let seed = SEEDS.with(|seeds| {
+            let seed = seeds.get();
+            seeds.set(seed + 1);
+            seed
+        });
+        Context::new(caller, NOW, Rng::from_seed(&seed.to_le_bytes()))
+    }
+
+    // A company owned by `owner`, verified by the admins
+    pub fn verified_company(state: &mut State, owner: u8) -> String {
+        let details = OrganizationDetails {
+            name: "Acme".into(),
+            kind: OrganizationKind::Company,
+            description: String::new(),
+            website: None,
+            location: "Nairobi".into(),
+            logo_id: None,
+        };
+        let company = state
+            .create_organization(&ctx(principal(owner)), details)
+            .unwrap();
+        let company = state.organizations.get_mut(&company.id).unwrap();
+        company.verification = VerificationStatus::Verified;
+        company.id.clone()
+    }
+
+    // A state with a trainer (1), a trainee (2) and an employer (3)
+    pub fn state() -> State {
+        let mut state = State::default();
+        for (n, name, role) in [
+            (1, "Alice", "trainer"),
+            (2, "Bob", "trainee"),
+            (3, "Carol", "employer"),
+        ] {
+            let email = format!("{}@ea.dev", name.to_lowercase());
+            state
+                .create_user(&ctx(principal(n)), name.into(), email, role.into())
+                .unwrap();
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    proptest! {
+        #[test]
+        fn ids_are_unique_per_seed(seed in any::<[u8; 32]>(), count in 1usize..200) {
+            let rng = Rng::from_seed(&seed);
+            let ids: HashSet<String> = (0..count).map(|_| rng.next_id()).collect();
+            prop_assert_eq!(ids.len(), count);
+            prop_assert!(ids.iter().all(|id| id.len() == 64));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "seeded with randomness")]
+    fn unseeded_contexts_issue_no_ids() {
+        Rng::unseeded().next_id();
+    }
+}
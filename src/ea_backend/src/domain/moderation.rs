@@ -0,0 +1,345 @@
+use super::{Context, ReviewError, Reviews, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type ModerationStore = BTreeMap<String, ModerationCase>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ReviewReport {
+    pub reporter: Principal,
+    pub reason: String,
+    pub reported_at: i64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum ModerationAction {
+    Hide,
+    Restore,
+}
+
+// An entry of the audit log, automatic actions have no moderator
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ModerationNote {
+    pub moderator: Option<Principal>,
+    pub action: ModerationAction,
+    pub note: String,
+    pub at: i64,
+}
+
+// Reports and moderation history of a review. A case is open, and listed in
+// the moderation queue, from its first report until an admin decides on it.
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ModerationCase {
+    pub review_id: String,
+    pub reports: Vec<ReviewReport>,
+    pub audit: Vec<ModerationNote>,
+    pub open: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ModerationQueueEntry {
+    pub case: ModerationCase,
+    pub review: Reviews,
+}
+
+impl ModerationCase {
+    fn new(review_id: &str) -> Self {
+        ModerationCase {
+            review_id: review_id.to_string(),
+            reports: vec![],
+            audit: vec![],
+            open: false,
+        }
+    }
+
+    // Reports made after the last decision of an admin
+    fn pending_reports(&self) -> usize {
+        let decided_at = self
+            .audit
+            .iter()
+            .filter(|note| note.moderator.is_some())
+            .map(|note| note.at)
+            .max();
+        self.reports
+            .iter()
+            .filter(|report| decided_at.is_none_or(|decided_at| report.reported_at > decided_at))
+            .count()
+    }
+}
+
+// ==================================================================================================
+// Moderation workflows
+// ==================================================================================================
+
+impl State {
+    // Any registered user but the author can report a review once. The review is
+    // hidden once `threshold` users reported it since the last admin decision.
+    pub fn report_review(
+        &mut self,
+        ctx: &Context,
+        review_id: &str,
+        reason: String,
+        threshold: u64,
+    ) -> Result<(), ReviewError> {
+        if !self.profiles.contains_key(&ctx.caller) {
+            return Err(ReviewError::ReviewerNotFound);
+        }
+        let review = self
+            .reviews
+            .get(review_id)
+            .ok_or(ReviewError::ReviewNotFound)?;
+        if review.reviewer_principal == Some(ctx.caller) {
+            return Err(ReviewError::Unauthorized);
+        }
+        let case = self
+            .moderation
+            .entry(review_id.to_string())
+            .or_insert_with(|| ModerationCase::new(review_id));
+        if case
+            .reports
+            .iter()
+            .any(|report| report.reporter == ctx.caller)
+        {
+            return Err(ReviewError::AlreadyReported);
+        }
+
+        case.reports.push(ReviewReport {
+            reporter: ctx.caller,
+            reason,
+            reported_at: ctx.now as i64,
+        });
+        case.open = true;
+        if !review.hidden && case.pending_reports() as u64 >= threshold.max(1) {
+            case.audit.push(ModerationNote {
+                moderator: None,
+                action: ModerationAction::Hide,
+                note: format!("Hidden after {} reports", threshold),
+                at: ctx.now as i64,
+            });
+            self.set_hidden(review_id, true);
+        }
+        Ok(())
+    }
+
+    // Open cases, oldest report first
+    pub fn moderation_queue(&self) -> Vec<ModerationQueueEntry> {
+        let mut queue: Vec<ModerationQueueEntry> = self
+            .moderation
+            .values()
+            .filter(|case| case.open)
+            .filter_map(|case| {
+                Some(ModerationQueueEntry {
+                    case: case.clone(),
+                    review: self.reviews.get(&case.review_id)?.clone(),
+                })
+            })
+            .collect();
+        queue.sort_by_key(|entry| entry.case.reports.first().map(|report| report.reported_at));
+        queue
+    }
+
+    // Hiding or restoring a review closes its case, the caller must be an admin
+    pub fn moderate_review(
+        &mut self,
+        ctx: &Context,
+        review_id: &str,
+        action: ModerationAction,
+        note: String,
+    ) -> Result<ModerationCase, ReviewError> {
+        if !self.reviews.contains_key(review_id) {
+            return Err(ReviewError::ReviewNotFound);
+        }
+        self.set_hidden(review_id, action == ModerationAction::Hide);
+
+        let case = self
+            .moderation
+            .entry(review_id.to_string())
+            .or_insert_with(|| ModerationCase::new(review_id));
+        case.audit.push(ModerationNote {
+            moderator: Some(ctx.caller),
+            action,
+            note,
+            at: ctx.now as i64,
+        });
+        case.open = false;
+        Ok(case.clone())
+    }
+
+    // Hidden reviews don't count in the rating aggregates
+    fn set_hidden(&mut self, review_id: &str, hidden: bool) {
+        let Some(review) = self.reviews.get(review_id).cloned() else {
+            return;
+        };
+        if review.hidden == hidden {
+            return;
+        }
+        if hidden {
+            self.forget_rating(&review.reviewee, &review);
+        }
+        let review = self.reviews.get_mut(review_id).unwrap();
+        review.hidden = hidden;
+        let review = review.clone();
+        if !hidden {
+            self.record_rating(&review.reviewee, &review);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::{Engagement, Rng};
+    use super::*;
+
+    // Carol reviewed Bob after hiring him
+    fn reviewed() -> (State, String) {
+        let mut state = state();
+        let carol = ctx(principal(3));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        state.apply_job(&ctx(principal(2)), &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+        let bob = state.own_profile(&ctx(principal(2))).id;
+        let review = state
+            .add_review(
+                &carol,
+                1.0,
+                "Awful".into(),
+                "Rude".into(),
+                String::new(),
+                &bob,
+                Engagement::Job(job.id),
+                None,
+            )
+            .unwrap();
+        for n in 4..=6 {
+            state
+                .create_user(
+                    &ctx(principal(n)),
+                    format!("User {}", n),
+                    String::new(),
+                    "trainee".into(),
+                )
+                .unwrap();
+        }
+        (state, review.id)
+    }
+
+    fn report(state: &mut State, reporter: u8, review_id: &str) -> Result<(), ReviewError> {
+        state.report_review(&ctx(principal(reporter)), review_id, "Abusive".into(), 3)
+    }
+
+    #[test]
+    fn reviews_are_hidden_after_enough_reports() {
+        let (mut state, review_id) = reviewed();
+
+        report(&mut state, 2, &review_id).unwrap();
+        assert_eq!(
+            report(&mut state, 2, &review_id).unwrap_err(),
+            ReviewError::AlreadyReported
+        );
+        assert_eq!(
+            report(&mut state, 3, &review_id).unwrap_err(),
+            ReviewError::Unauthorized
+        );
+        report(&mut state, 4, &review_id).unwrap();
+        assert_eq!(state.reviews_of(&principal(2)).len(), 1);
+        assert_eq!(state.moderation_queue().len(), 1);
+
+        report(&mut state, 5, &review_id).unwrap();
+        assert!(state.reviews_of(&principal(2)).is_empty());
+        assert!(state.review(&review_id).is_none());
+        assert_eq!(state.own_profile(&ctx(principal(2))).rating.count, 0);
+        // Still waiting for an admin
+        assert_eq!(state.moderation_queue().len(), 1);
+    }
+
+    #[test]
+    fn admins_hide_and_restore_with_notes() {
+        let (mut state, review_id) = reviewed();
+        let admin = ctx(principal(9));
+        report(&mut state, 2, &review_id).unwrap();
+
+        let case = state
+            .moderate_review(&admin, &review_id, ModerationAction::Hide, "Insults".into())
+            .unwrap();
+        assert!(!case.open);
+        assert!(state.moderation_queue().is_empty());
+        assert!(state.reviews_of(&principal(2)).is_empty());
+
+        let case = state
+            .moderate_review(
+                &admin,
+                &review_id,
+                ModerationAction::Restore,
+                "Appeal".into(),
+            )
+            .unwrap();
+        assert_eq!(case.audit.len(), 2);
+        assert_eq!(case.audit[1].moderator, Some(principal(9)));
+        assert_eq!(state.reviews_of(&principal(2)).len(), 1);
+        assert_eq!(state.own_profile(&ctx(principal(2))).rating.count, 1);
+    }
+
+    #[test]
+    fn reviews_under_moderation_stay_as_they_are() {
+        let (mut state, review_id) = reviewed();
+        let carol = ctx(principal(3));
+        report(&mut state, 2, &review_id).unwrap();
+
+        let edited = state.edit_review(
+            &carol,
+            &review_id,
+            5.0,
+            "Great".into(),
+            "Lovely".into(),
+            String::new(),
+            None,
+        );
+        assert_eq!(edited.unwrap_err(), ReviewError::UnderModeration);
+        assert_eq!(
+            state.delete_review(&carol, &review_id),
+            Err(ReviewError::UnderModeration)
+        );
+
+        let admin = ctx(principal(9));
+        state
+            .moderate_review(&admin, &review_id, ModerationAction::Hide, "Rude".into())
+            .unwrap();
+        assert_eq!(
+            state.delete_review(&carol, &review_id),
+            Err(ReviewError::UnderModeration)
+        );
+        state
+            .moderate_review(&admin, &review_id, ModerationAction::Restore, "Ok".into())
+            .unwrap();
+        state.delete_review(&carol, &review_id).unwrap();
+
+        let case = &state.moderation[&review_id];
+        assert!(!case.open);
+        assert_eq!(case.reports.len(), 1);
+        assert_eq!(case.audit.len(), 2);
+    }
+
+    #[test]
+    fn restored_reviews_need_new_reports_to_be_hidden_again() {
+        let (mut state, review_id) = reviewed();
+        for reporter in [2, 4, 5] {
+            report(&mut state, reporter, &review_id).unwrap();
+        }
+        let later = Context::new(principal(9), NOW + 1, Rng::unseeded());
+        state
+            .moderate_review(&later, &review_id, ModerationAction::Restore, "Fine".into())
+            .unwrap();
+
+        let reporter = Context::new(principal(6), NOW + 2, Rng::unseeded());
+        state
+            .report_review(&reporter, &review_id, "Abusive".into(), 3)
+            .unwrap();
+        assert_eq!(state.reviews_of(&principal(2)).len(), 1);
+    }
+}
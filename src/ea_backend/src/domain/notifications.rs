@@ -0,0 +1,422 @@
+use super::{Context, Course, Jobs, MemberRole, Reviews, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type NotificationStore = BTreeMap<Principal, Inbox>;
+
+// Notifications kept per user, older ones are dropped
+pub const MAX_NOTIFICATIONS: usize = 200;
+// Upper bound for the notifications returned in a single page
+pub const MAX_NOTIFICATION_PAGE: u64 = 50;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum NotificationKind {
+    // Someone applied to a job the user manages
+    JobApplication,
+    // The user was hired for a job they applied to
+    Hired,
+    // Someone enrolled in a course the user manages
+    CourseEnrollment,
+    CertificateMinted,
+    ReviewReceived,
+    // A participant posted in a conversation of the user
+    NewMessage,
+}
+
+// Which kinds of notifications the user wants, all of them by default
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct NotificationPreferences {
+    pub job_applications: bool,
+    pub hires: bool,
+    pub course_enrollments: bool,
+    pub certificates: bool,
+    pub reviews: bool,
+    pub messages: bool,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            job_applications: true,
+            hires: true,
+            course_enrollments: true,
+            certificates: true,
+            reviews: true,
+            messages: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Notification {
+    // Increasing per user
+    pub id: u64,
+    pub kind: NotificationKind,
+    // Whose action the notification is about, none for the platform
+    pub actor: Option<Principal>,
+    // Id of the job, course, certificate token, review or conversation thread
+    pub reference: String,
+    pub message: String,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Inbox {
+    #[serde(default)]
+    pub preferences: NotificationPreferences,
+    pub next_id: u64,
+    // Oldest first
+    pub notifications: Vec<Notification>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct NotificationPage {
+    // Newest first
+    pub notifications: Vec<Notification>,
+    pub total: u64,
+    pub unread: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum NotificationError {
+    ProfileNotFound,
+}
+
+impl NotificationPreferences {
+    fn allows(&self, kind: &NotificationKind) -> bool {
+        match kind {
+            NotificationKind::JobApplication => self.job_applications,
+            NotificationKind::Hired => self.hires,
+            NotificationKind::CourseEnrollment => self.course_enrollments,
+            NotificationKind::CertificateMinted => self.certificates,
+            NotificationKind::ReviewReceived => self.reviews,
+            NotificationKind::NewMessage => self.messages,
+        }
+    }
+}
+
+impl Inbox {
+    fn unread(&self) -> u64 {
+        self.notifications
+            .iter()
+            .filter(|notification| !notification.read)
+            .count() as u64
+    }
+}
+
+// ==================================================================================================
+// Notification workflows
+// ==================================================================================================
+
+impl State {
+    // Adding a notification to the inbox of a registered user, unless they
+    // turned its kind off or are the actor themselves
+    pub(super) fn notify(
+        &mut self,
+        recipient: Principal,
+        kind: NotificationKind,
+        actor: Option<Principal>,
+        reference: String,
+        message: String,
+        now: u64,
+    ) {
+        if actor == Some(recipient) || !self.profiles.contains_key(&recipient) {
+            return;
+        }
+        let inbox = self.notifications.entry(recipient).or_default();
+        if !inbox.preferences.allows(&kind) {
+            return;
+        }
+        inbox.notifications.push(Notification {
+            id: inbox.next_id,
+            kind,
+            actor,
+            reference,
+            message,
+            created_at: now,
+            read: false,
+        });
+        inbox.next_id += 1;
+        if inbox.notifications.len() > MAX_NOTIFICATIONS {
+            inbox.notifications.remove(0);
+        }
+    }
+
+    pub(super) fn fullname(&self, principal: &Principal) -> String {
+        self.profiles
+            .get(principal)
+            .map_or_else(String::new, |profile| profile.fullname.clone())
+    }
+
+    pub(super) fn notify_job_application(&mut self, ctx: &Context, job: &Jobs) {
+        let message = format!("{} applied to {}", self.fullname(&ctx.caller), job.title);
+        let managers = self.managers(&job.organization_id, job.creator, MemberRole::Recruiter);
+        for manager in managers {
+            self.notify(
+                manager,
+                NotificationKind::JobApplication,
+                Some(ctx.caller),
+                job.id.clone(),
+                message.clone(),
+                ctx.now,
+            );
+        }
+    }
+
+    pub(super) fn notify_hire(&mut self, ctx: &Context, job: &Jobs, applicant: Principal) {
+        self.notify(
+            applicant,
+            NotificationKind::Hired,
+            Some(ctx.caller),
+            job.id.clone(),
+            format!("You were hired for {}", job.title),
+            ctx.now,
+        );
+    }
+
+    pub(super) fn notify_course_enrollment(&mut self, ctx: &Context, course: &Course) {
+        let message = format!(
+            "{} enrolled in {}",
+            self.fullname(&ctx.caller),
+            course.title
+        );
+        let managers = self.managers(
+            &course.organization_id,
+            course.creator,
+            MemberRole::Instructor,
+        );
+        for manager in managers {
+            self.notify(
+                manager,
+                NotificationKind::CourseEnrollment,
+                Some(ctx.caller),
+                course.id.clone(),
+                message.clone(),
+                ctx.now,
+            );
+        }
+    }
+
+    pub(super) fn notify_review(&mut self, ctx: &Context, review: &Reviews) {
+        self.notify(
+            review.reviewee,
+            NotificationKind::ReviewReceived,
+            Some(ctx.caller),
+            review.id.clone(),
+            format!("{} reviewed you", review.reviewer),
+            ctx.now,
+        );
+    }
+
+    // Certificates are minted by the platform, on behalf of the issuer of the course
+    pub fn notify_certificate(
+        &mut self,
+        recipient: Principal,
+        token_id: String,
+        course_id: &str,
+        now: u64,
+    ) {
+        let title = self
+            .courses
+            .get(course_id)
+            .map_or_else(|| course_id.to_string(), |course| course.title.clone());
+        self.notify(
+            recipient,
+            NotificationKind::CertificateMinted,
+            None,
+            token_id,
+            format!("You received a certificate for {}", title),
+            now,
+        );
+    }
+
+    // A page of the caller's notifications, newest first
+    pub fn notifications_page(&self, ctx: &Context, offset: u64, limit: u64) -> NotificationPage {
+        let Some(inbox) = self.notifications.get(&ctx.caller) else {
+            return NotificationPage {
+                notifications: vec![],
+                total: 0,
+                unread: 0,
+            };
+        };
+        NotificationPage {
+            notifications: inbox
+                .notifications
+                .iter()
+                .rev()
+                .skip(offset as usize)
+                .take(limit.min(MAX_NOTIFICATION_PAGE) as usize)
+                .cloned()
+                .collect(),
+            total: inbox.notifications.len() as u64,
+            unread: inbox.unread(),
+        }
+    }
+
+    pub fn unread_notifications(&self, ctx: &Context) -> u64 {
+        self.notifications
+            .get(&ctx.caller)
+            .map_or(0, |inbox| inbox.unread())
+    }
+
+    // Marking notifications of the caller as read, returns how many are left unread
+    pub fn mark_notifications_read(&mut self, ctx: &Context, ids: &[u64]) -> u64 {
+        let Some(inbox) = self.notifications.get_mut(&ctx.caller) else {
+            return 0;
+        };
+        for notification in inbox.notifications.iter_mut() {
+            if ids.contains(&notification.id) {
+                notification.read = true;
+            }
+        }
+        inbox.unread()
+    }
+
+    pub fn mark_all_notifications_read(&mut self, ctx: &Context) {
+        if let Some(inbox) = self.notifications.get_mut(&ctx.caller) {
+            for notification in inbox.notifications.iter_mut() {
+                notification.read = true;
+            }
+        }
+    }
+
+    pub fn notification_preferences(&self, ctx: &Context) -> NotificationPreferences {
+        self.notifications
+            .get(&ctx.caller)
+            .map(|inbox| inbox.preferences.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_notification_preferences(
+        &mut self,
+        ctx: &Context,
+        preferences: NotificationPreferences,
+    ) -> Result<NotificationPreferences, NotificationError> {
+        if !self.profiles.contains_key(&ctx.caller) {
+            return Err(NotificationError::ProfileNotFound);
+        }
+        self.notifications
+            .entry(ctx.caller)
+            .or_default()
+            .preferences = preferences.clone();
+        Ok(preferences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::Engagement;
+    use super::*;
+
+    #[test]
+    fn existing_flows_notify_the_people_concerned() {
+        let mut state = state();
+        let (alice, bob, carol) = (ctx(principal(1)), ctx(principal(2)), ctx(principal(3)));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        state.apply_job(&bob, &job.id).unwrap();
+        // Applying again is not news
+        state.apply_job(&bob, &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+        state.apply_course(&bob, &course.id);
+        let bob_id = state.own_profile(&bob).id;
+        state
+            .add_review(
+                &carol,
+                5.0,
+                "Great".into(),
+                "Fast".into(),
+                String::new(),
+                &bob_id,
+                Engagement::Job(job.id.clone()),
+                None,
+            )
+            .unwrap();
+        state.notify_certificate(principal(2), "0".into(), &course.id, NOW);
+
+        let page = state.notifications_page(&carol, 0, 10);
+        assert_eq!(page.total, 1);
+        assert_eq!(page.notifications[0].kind, NotificationKind::JobApplication);
+        assert_eq!(page.notifications[0].message, "Bob applied to Designer");
+        assert_eq!(
+            state.notifications_page(&alice, 0, 10).notifications[0].kind,
+            NotificationKind::CourseEnrollment
+        );
+
+        let kinds: Vec<NotificationKind> = state
+            .notifications_page(&bob, 0, 10)
+            .notifications
+            .into_iter()
+            .map(|notification| notification.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NotificationKind::CertificateMinted,
+                NotificationKind::ReviewReceived,
+                NotificationKind::Hired,
+            ]
+        );
+    }
+
+    #[test]
+    fn notifications_are_paged_and_marked_read() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        for n in 0..5 {
+            state.notify_certificate(principal(2), n.to_string(), "course", NOW);
+        }
+
+        let page = state.notifications_page(&bob, 1, 2);
+        assert_eq!(page.total, 5);
+        assert_eq!(page.unread, 5);
+        let ids: Vec<u64> = page.notifications.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+
+        assert_eq!(state.mark_notifications_read(&bob, &ids), 3);
+        assert_eq!(state.unread_notifications(&bob), 3);
+        state.mark_all_notifications_read(&bob);
+        assert_eq!(state.unread_notifications(&bob), 0);
+    }
+
+    #[test]
+    fn preferences_turn_kinds_off() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let preferences = NotificationPreferences {
+            certificates: false,
+            ..Default::default()
+        };
+        state
+            .set_notification_preferences(&bob, preferences.clone())
+            .unwrap();
+        assert_eq!(state.notification_preferences(&bob), preferences);
+
+        state.notify_certificate(principal(2), "0".into(), "course", NOW);
+        assert_eq!(state.notifications_page(&bob, 0, 10).total, 0);
+        assert_eq!(
+            state.set_notification_preferences(&ctx(principal(9)), preferences),
+            Err(NotificationError::ProfileNotFound)
+        );
+    }
+
+    #[test]
+    fn inboxes_keep_the_latest_notifications() {
+        let mut state = state();
+        for n in 0..MAX_NOTIFICATIONS + 1 {
+            state.notify_certificate(principal(2), n.to_string(), "course", NOW);
+        }
+        let page = state.notifications_page(&ctx(principal(2)), 0, 1000);
+        assert_eq!(page.total, MAX_NOTIFICATIONS as u64);
+        assert_eq!(page.notifications.len() as u64, MAX_NOTIFICATION_PAGE);
+        assert_eq!(page.notifications[0].id, MAX_NOTIFICATIONS as u64);
+    }
+}
@@ -0,0 +1,469 @@
+use super::{Context, Course, Jobs, State, VerificationStatus};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type OrganizationStore = BTreeMap<String, Organization>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum OrganizationKind {
+    Company,
+    TrainingProvider,
+}
+
+// Owners manage the organization and everything it owns, recruiters its jobs
+// and instructors its courses
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum MemberRole {
+    Owner,
+    Recruiter,
+    Instructor,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Member {
+    pub principal: Principal,
+    pub role: MemberRole,
+    pub added_at: u64,
+}
+
+// The profile of an organization, as written by its owners
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct OrganizationDetails {
+    pub name: String,
+    pub kind: OrganizationKind,
+    pub description: String,
+    pub website: Option<String>,
+    pub location: String,
+    // Asset id of the logo
+    pub logo_id: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct Organization {
+    pub id: String,
+    pub details: OrganizationDetails,
+    // Set by admins through verification requests
+    pub verification: VerificationStatus,
+    pub members: Vec<Member>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum OrganizationError {
+    ProfileNotFound,
+    OrganizationNotFound,
+    Unauthorized,
+    EmptyName,
+    InvalidAsset,
+    MemberNotFound,
+    LastOwner,
+}
+
+impl Organization {
+    fn role_of(&self, principal: &Principal) -> Option<&MemberRole> {
+        self.members
+            .iter()
+            .find(|member| member.principal == *principal)
+            .map(|member| &member.role)
+    }
+
+    // Whether the principal is an owner or has the given role
+    pub fn allows(&self, principal: &Principal, role: MemberRole) -> bool {
+        self.role_of(principal)
+            .is_some_and(|held| *held == MemberRole::Owner || *held == role)
+    }
+
+    fn owner_count(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|member| member.role == MemberRole::Owner)
+            .count()
+    }
+
+    // Whether the principal is the only owner, who cannot leave the organization
+    pub(super) fn is_last_owner(&self, principal: &Principal) -> bool {
+        self.owner_count() == 1 && self.role_of(principal) == Some(&MemberRole::Owner)
+    }
+}
+
+fn check_details(details: &OrganizationDetails) -> Result<(), OrganizationError> {
+    if details.name.trim().is_empty() {
+        return Err(OrganizationError::EmptyName);
+    }
+    Ok(())
+}
+
+// ==================================================================================================
+// Organization workflows
+// ==================================================================================================
+
+impl State {
+    // Registered users create organizations and become their first owner
+    pub fn create_organization(
+        &mut self,
+        ctx: &Context,
+        details: OrganizationDetails,
+    ) -> Result<Organization, OrganizationError> {
+        if !self.profiles.contains_key(&ctx.caller) {
+            return Err(OrganizationError::ProfileNotFound);
+        }
+        check_details(&details)?;
+        let organization = Organization {
+            id: ctx.rng.next_id(),
+            details,
+            verification: VerificationStatus::Unverified,
+            members: vec![Member {
+                principal: ctx.caller,
+                role: MemberRole::Owner,
+                added_at: ctx.now,
+            }],
+            created_at: ctx.now,
+        };
+        self.organizations
+            .insert(organization.id.clone(), organization.clone());
+        Ok(organization)
+    }
+
+    // Renaming a verified organization, or changing its kind, takes the
+    // verification away until an admin reviews it again
+    pub fn update_organization(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        details: OrganizationDetails,
+    ) -> Result<Organization, OrganizationError> {
+        check_details(&details)?;
+        let organization = self.owned_organization(ctx, id)?;
+        let reviewed =
+            organization.details.name == details.name && organization.details.kind == details.kind;
+        let unverified = !reviewed && organization.verification == VerificationStatus::Verified;
+        if unverified {
+            organization.verification = VerificationStatus::Unverified;
+        }
+        organization.details = details;
+        let organization = organization.clone();
+
+        if unverified {
+            self.refresh_verified_badges();
+        }
+        Ok(organization)
+    }
+
+    // Adding a registered user, or changing the role of a member
+    pub fn set_organization_member(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        principal: Principal,
+        role: MemberRole,
+    ) -> Result<Organization, OrganizationError> {
+        if !self.profiles.contains_key(&principal) {
+            return Err(OrganizationError::ProfileNotFound);
+        }
+        let organization = self.owned_organization(ctx, id)?;
+        if organization.is_last_owner(&principal) && role != MemberRole::Owner {
+            return Err(OrganizationError::LastOwner);
+        }
+        match organization
+            .members
+            .iter_mut()
+            .find(|member| member.principal == principal)
+        {
+            Some(member) => member.role = role,
+            None => organization.members.push(Member {
+                principal,
+                role,
+                added_at: ctx.now,
+            }),
+        }
+        Ok(organization.clone())
+    }
+
+    // Owners remove members, and every member can leave
+    pub fn remove_organization_member(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+        principal: Principal,
+    ) -> Result<Organization, OrganizationError> {
+        let organization = self
+            .organizations
+            .get_mut(id)
+            .ok_or(OrganizationError::OrganizationNotFound)?;
+        if principal != ctx.caller && !organization.allows(&ctx.caller, MemberRole::Owner) {
+            return Err(OrganizationError::Unauthorized);
+        }
+        let role = organization
+            .role_of(&principal)
+            .ok_or(OrganizationError::MemberNotFound)?;
+        if *role == MemberRole::Owner && organization.owner_count() == 1 {
+            return Err(OrganizationError::LastOwner);
+        }
+        organization
+            .members
+            .retain(|member| member.principal != principal);
+        Ok(organization.clone())
+    }
+
+    fn owned_organization(
+        &mut self,
+        ctx: &Context,
+        id: &str,
+    ) -> Result<&mut Organization, OrganizationError> {
+        let organization = self
+            .organizations
+            .get_mut(id)
+            .ok_or(OrganizationError::OrganizationNotFound)?;
+        if !organization.allows(&ctx.caller, MemberRole::Owner) {
+            return Err(OrganizationError::Unauthorized);
+        }
+        Ok(organization)
+    }
+
+    pub fn organization(&self, id: &str) -> Option<Organization> {
+        self.organizations.get(id).cloned()
+    }
+
+    pub fn organizations_of(&self, principal: &Principal) -> Vec<Organization> {
+        self.organizations
+            .values()
+            .filter(|organization| organization.role_of(principal).is_some())
+            .cloned()
+            .collect()
+    }
+
+    // Jobs of an organization are posted by its recruiters, courses by its instructors
+    pub fn create_organization_job(
+        &mut self,
+        ctx: &Context,
+        organization_id: &str,
+        title: String,
+    ) -> Result<Jobs, OrganizationError> {
+        self.member_of(ctx, organization_id, MemberRole::Recruiter)?;
+        Ok(self.insert_job(ctx, title, Some(organization_id.to_string())))
+    }
+
+    pub fn create_organization_course(
+        &mut self,
+        ctx: &Context,
+        organization_id: &str,
+        title: String,
+    ) -> Result<Course, OrganizationError> {
+        self.member_of(ctx, organization_id, MemberRole::Instructor)?;
+        Ok(self.insert_course(ctx, title, Some(organization_id.to_string())))
+    }
+
+    fn member_of(
+        &self,
+        ctx: &Context,
+        organization_id: &str,
+        role: MemberRole,
+    ) -> Result<(), OrganizationError> {
+        let organization = self
+            .organizations
+            .get(organization_id)
+            .ok_or(OrganizationError::OrganizationNotFound)?;
+        if !organization.allows(&ctx.caller, role) {
+            return Err(OrganizationError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    pub fn organization_jobs(&self, organization_id: &str) -> Vec<Jobs> {
+        self.jobs
+            .values()
+            .rev()
+            .filter(|job| job.organization_id.as_deref() == Some(organization_id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn organization_courses(&self, organization_id: &str) -> Vec<Course> {
+        self.courses
+            .values()
+            .rev()
+            .filter(|course| course.organization_id.as_deref() == Some(organization_id))
+            .cloned()
+            .collect()
+    }
+
+    // The owners and recruiters of the organization of a job, or its creator
+    // when it has none. Members who left lose the jobs they posted.
+    pub fn manages_job(&self, job: &Jobs, principal: &Principal) -> bool {
+        self.managers(&job.organization_id, job.creator, MemberRole::Recruiter)
+            .contains(principal)
+    }
+
+    // The owners and instructors of the organization of a course, or its creator
+    // when it has none
+    pub fn manages_course(&self, course: &Course, principal: &Principal) -> bool {
+        self.managers(
+            &course.organization_id,
+            course.creator,
+            MemberRole::Instructor,
+        )
+        .contains(principal)
+    }
+
+    // The members of the organization of a job or course who manage it, or its
+    // creator when it has no organization
+    pub(super) fn managers(
+        &self,
+        organization_id: &Option<String>,
+        creator: Principal,
+        role: MemberRole,
+    ) -> Vec<Principal> {
+        let Some(id) = organization_id else {
+            return vec![creator];
+        };
+        self.organizations
+            .get(id)
+            .map(|organization| {
+                organization
+                    .members
+                    .iter()
+                    .map(|member| member.principal)
+                    .filter(|principal| organization.allows(principal, role.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Certificates are issued in the name of the organization of the course,
+    // or of its trainer
+    pub fn certificate_issuer(&self, course_id: &str) -> Option<String> {
+        let course = self.courses.get(course_id)?;
+        Some(self.display_owner(&course.organization_id, &course.creator_fullname))
+    }
+
+    // Name of the organization owning a job or course, or of its creator
+    pub(super) fn display_owner(
+        &self,
+        organization_id: &Option<String>,
+        creator_fullname: &str,
+    ) -> String {
+        organization_id
+            .as_ref()
+            .and_then(|id| self.organizations.get(id))
+            .map_or(creator_fullname.to_string(), |organization| {
+                organization.details.name.clone()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::JobError;
+    use super::*;
+
+    fn details(name: &str) -> OrganizationDetails {
+        OrganizationDetails {
+            name: name.into(),
+            kind: OrganizationKind::Company,
+            description: String::new(),
+            website: None,
+            location: "Nairobi".into(),
+            logo_id: None,
+        }
+    }
+
+    // Carol owns Acme, where Alice is an instructor and Bob a recruiter
+    fn acme() -> (State, String) {
+        let mut state = state();
+        let carol = ctx(principal(3));
+        let acme = state.create_organization(&carol, details("Acme")).unwrap();
+        for (n, role) in [(1, MemberRole::Instructor), (2, MemberRole::Recruiter)] {
+            state
+                .set_organization_member(&carol, &acme.id, principal(n), role)
+                .unwrap();
+        }
+        (state, acme.id)
+    }
+
+    #[test]
+    fn owners_manage_members() {
+        let (mut state, acme) = acme();
+        let (bob, carol) = (ctx(principal(2)), ctx(principal(3)));
+
+        assert_eq!(
+            state.create_organization(&ctx(principal(9)), details("Nobody")),
+            Err(OrganizationError::ProfileNotFound)
+        );
+        assert_eq!(
+            state.update_organization(&bob, &acme, details("Bob Inc")),
+            Err(OrganizationError::Unauthorized)
+        );
+        assert_eq!(
+            state.remove_organization_member(&carol, &acme, principal(3)),
+            Err(OrganizationError::LastOwner)
+        );
+        assert_eq!(state.organizations_of(&principal(2)).len(), 1);
+
+        state
+            .remove_organization_member(&bob, &acme, principal(2))
+            .unwrap();
+        assert!(state.organizations_of(&principal(2)).is_empty());
+    }
+
+    #[test]
+    fn members_post_jobs_and_courses_for_the_organization() {
+        let (mut state, acme) = acme();
+        let (alice, bob, carol) = (ctx(principal(1)), ctx(principal(2)), ctx(principal(3)));
+
+        // Bob is a trainee, the organization role is what lets him post jobs
+        let job = state
+            .create_organization_job(&bob, &acme, "Designer".into())
+            .unwrap();
+        assert_eq!(
+            state
+                .create_organization_course(&bob, &acme, "Rust".into())
+                .unwrap_err(),
+            OrganizationError::Unauthorized
+        );
+        let course = state
+            .create_organization_course(&alice, &acme, "Rust".into())
+            .unwrap();
+
+        assert!(state.manages_job(&job, &principal(3)));
+        assert!(!state.manages_job(&job, &principal(1)));
+        assert!(state.manages_course(&course, &principal(3)));
+        assert_eq!(state.organization_jobs(&acme)[0].id, job.id);
+        assert_eq!(state.organization_courses(&acme)[0].id, course.id);
+
+        state.apply_job(&ctx(principal(1)), &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(1)).unwrap();
+        assert_eq!(state.certificate_issuer(&course.id).unwrap(), "Acme");
+    }
+
+    #[test]
+    fn members_who_leave_lose_what_they_posted() {
+        let (mut state, acme) = acme();
+        let (alice, bob, carol) = (ctx(principal(1)), ctx(principal(2)), ctx(principal(3)));
+        let job = state
+            .create_organization_job(&bob, &acme, "Designer".into())
+            .unwrap();
+        let course = state
+            .create_organization_course(&alice, &acme, "Rust".into())
+            .unwrap();
+        for n in [1, 2] {
+            state
+                .remove_organization_member(&carol, &acme, principal(n))
+                .unwrap();
+        }
+
+        assert!(!state.manages_job(&job, &principal(2)));
+        assert!(!state.manages_course(&course, &principal(1)));
+        assert!(state.manages_job(&job, &principal(3)));
+        state.apply_job(&ctx(principal(1)), &job.id).unwrap();
+        assert_eq!(
+            state.hire(&bob, &job.id, principal(1)).unwrap_err(),
+            JobError::Unauthorized
+        );
+    }
+}
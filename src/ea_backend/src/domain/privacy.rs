@@ -0,0 +1,479 @@
+use super::{
+    Context, CourseRatingSummary, Cv, EmployerProfile, FreelancerProfile, MemberRole,
+    OrganizationKind, Profile, RatingSummary, Roles, State, TrainerProfile, VerificationStatus,
+    VerificationSubject,
+};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// Who can see a field of a profile, besides its owner
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum Visibility {
+    Public,
+    // Owners and recruiters of a verified company. Anyone can add the employer
+    // role to their profile, the verification is granted by admins.
+    Employers,
+    // Employers who posted a job the owner applied to
+    AppliedEmployers,
+    Private,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PrivacySettings {
+    pub email: Visibility,
+    pub location: Visibility,
+    pub organization: Visibility,
+    pub resume: Visibility,
+    pub cv: Visibility,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings {
+            email: Visibility::AppliedEmployers,
+            location: Visibility::Public,
+            organization: Visibility::Public,
+            resume: Visibility::AppliedEmployers,
+            cv: Visibility::Public,
+        }
+    }
+}
+
+// A record keeping an asset private, noted on the asset when the record is
+// written so serving the asset looks up that record only
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum AssetScope {
+    // The resume of the profile of the principal
+    Resume(Principal),
+    // The documents of a verification request
+    Verification(String),
+    // The attachments of the messages of a thread
+    Thread(String),
+}
+
+// A profile as seen by someone else, fields hidden from the viewer are left empty
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct PublicProfile {
+    pub id: String,
+    pub principal_id: Principal,
+    pub fullname: String,
+    pub occupation: String,
+    pub role: Roles,
+    pub roles: Vec<Roles>,
+    pub freelancer: Option<FreelancerProfile>,
+    pub employer: Option<EmployerProfile>,
+    pub trainer: Option<TrainerProfile>,
+    pub description: String,
+    pub keywords: Vec<String>,
+    pub skills: Vec<String>,
+    pub token_ids: Vec<(Nat, String)>,
+    pub rating: RatingSummary,
+    pub course_rating: CourseRatingSummary,
+    pub verification: VerificationStatus,
+    pub email: Option<String>,
+    pub location: Option<String>,
+    pub organization: Option<String>,
+    pub resume_id: Option<String>,
+}
+
+// How close the viewer of a profile is to its owner, from farthest to closest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Audience {
+    Anyone,
+    Employer,
+    AppliedEmployer,
+    Owner,
+}
+
+impl Visibility {
+    fn allows(&self, audience: Audience) -> bool {
+        let required = match self {
+            Visibility::Public => Audience::Anyone,
+            Visibility::Employers => Audience::Employer,
+            Visibility::AppliedEmployers => Audience::AppliedEmployer,
+            Visibility::Private => Audience::Owner,
+        };
+        audience >= required
+    }
+}
+
+// ==================================================================================================
+// Privacy workflows
+// ==================================================================================================
+
+// What the viewer of profiles is to their owners, worked out once for all the
+// profiles of a call
+struct Viewer {
+    principal: Principal,
+    // Applicants to the jobs the viewer manages
+    applicants: BTreeSet<Principal>,
+    // Owner or recruiter of a verified company
+    employer: bool,
+}
+
+impl Viewer {
+    fn audience(&self, profile: &Profile) -> Audience {
+        if self.principal == profile.principal_id {
+            Audience::Owner
+        } else if self.applicants.contains(&profile.principal_id) {
+            Audience::AppliedEmployer
+        } else if self.employer {
+            Audience::Employer
+        } else {
+            Audience::Anyone
+        }
+    }
+}
+
+impl State {
+    fn viewer(&self, viewer: &Principal) -> Viewer {
+        let applicants = self
+            .jobs
+            .values()
+            .filter(|job| self.manages_job(job, viewer))
+            .flat_map(|job| job.applicants.iter().copied())
+            .collect();
+        let employer = self.organizations.values().any(|organization| {
+            organization.details.kind == OrganizationKind::Company
+                && organization.verification == VerificationStatus::Verified
+                && organization.allows(viewer, MemberRole::Recruiter)
+        });
+        Viewer {
+            principal: *viewer,
+            applicants,
+            employer,
+        }
+    }
+
+    fn audience(&self, viewer: &Principal, profile: &Profile) -> Audience {
+        self.viewer(viewer).audience(profile)
+    }
+
+    pub fn public_profile(&self, viewer: &Principal, profile: &Profile) -> PublicProfile {
+        profile_for(self.audience(viewer, profile), profile)
+    }
+
+    pub fn public_profiles(&self, ctx: &Context, profiles: Vec<Profile>) -> Vec<PublicProfile> {
+        let viewer = self.viewer(&ctx.caller);
+        profiles
+            .iter()
+            .map(|profile| profile_for(viewer.audience(profile), profile))
+            .collect()
+    }
+
+    // The whole record, only for its owner and the employers they applied to.
+    // Fields the owner keeps private are left empty for the employers as well.
+    pub fn full_profile(&self, ctx: &Context, uid: &str) -> Option<Profile> {
+        let profile = self.profiles.get(self.ids.get(uid)?)?;
+        let audience = self.audience(&ctx.caller, profile);
+        if audience < Audience::AppliedEmployer {
+            return None;
+        }
+        let mut profile = profile.clone();
+        let privacy = profile.privacy.clone();
+        for (visibility, value) in [
+            (&privacy.email, &mut profile.email),
+            (&privacy.location, &mut profile.location),
+            (&privacy.organization, &mut profile.organization),
+        ] {
+            if !visibility.allows(audience) {
+                value.clear();
+            }
+        }
+        if !privacy.resume.allows(audience) {
+            profile.resume_id = None;
+        }
+        if !privacy.cv.allows(audience) {
+            profile.cv = Cv::default();
+        }
+        Some(profile)
+    }
+
+    pub fn can_view_cv(&self, viewer: &Principal, profile: &Profile) -> bool {
+        profile.privacy.cv.allows(self.audience(viewer, profile))
+    }
+
+    pub fn can_view_location(&self, viewer: &Principal, profile: &Profile) -> bool {
+        profile
+            .privacy
+            .location
+            .allows(self.audience(viewer, profile))
+    }
+}
+
+// The fields of a profile the audience is allowed to see
+fn profile_for(audience: Audience, profile: &Profile) -> PublicProfile {
+    let privacy = &profile.privacy;
+    let visible = |visibility: &Visibility, value: &str| {
+        visibility.allows(audience).then(|| value.to_string())
+    };
+    PublicProfile {
+        id: profile.id.clone(),
+        principal_id: profile.principal_id,
+        fullname: profile.fullname.clone(),
+        occupation: profile.occupation.clone(),
+        role: profile.role.clone(),
+        roles: profile.roles.clone(),
+        freelancer: profile.freelancer.clone(),
+        employer: profile.employer.clone(),
+        trainer: profile.trainer.clone(),
+        description: profile.description.clone(),
+        keywords: profile.keywords.clone(),
+        skills: profile.skills.clone(),
+        token_ids: profile.token_ids.clone(),
+        rating: profile.rating.clone(),
+        course_rating: profile.course_rating.clone(),
+        verification: profile.verification.clone(),
+        email: visible(&privacy.email, &profile.email),
+        location: visible(&privacy.location, &profile.location),
+        organization: visible(&privacy.organization, &profile.organization),
+        resume_id: profile
+            .resume_id
+            .clone()
+            .filter(|_| privacy.resume.allows(audience)),
+    }
+}
+
+// ==================================================================================================
+// Asset access
+// ==================================================================================================
+
+impl State {
+    // The records keeping each asset private, for the assets referenced before
+    // the scopes were noted on them
+    pub fn asset_scopes(&self) -> Vec<(String, AssetScope)> {
+        let resumes = self.profiles.values().filter_map(|profile| {
+            let id = profile.resume_id.clone()?;
+            Some((id, AssetScope::Resume(profile.principal_id)))
+        });
+        let documents = self.verifications.values().flat_map(|request| {
+            request
+                .documents
+                .iter()
+                .map(|id| (id.clone(), AssetScope::Verification(request.id.clone())))
+        });
+        let attachments = self.threads.values().flat_map(|thread| {
+            thread.messages.iter().flat_map(|message| {
+                message
+                    .attachments
+                    .iter()
+                    .map(|id| (id.clone(), AssetScope::Thread(thread.id.clone())))
+            })
+        });
+        resumes.chain(documents).chain(attachments).collect()
+    }
+
+    // Whether one of the records keeping an asset private lets the viewer read
+    // it. Admins read verification documents and attachments to moderate them.
+    pub fn can_read_asset(
+        &self,
+        viewer: &Principal,
+        id: &str,
+        scopes: &[AssetScope],
+        admin: bool,
+    ) -> bool {
+        scopes.iter().any(|scope| match scope {
+            AssetScope::Resume(owner) => self.profiles.get(owner).is_some_and(|profile| {
+                profile.resume_id.as_deref() == Some(id)
+                    && profile
+                        .privacy
+                        .resume
+                        .allows(self.audience(viewer, profile))
+            }),
+            AssetScope::Verification(request_id) => {
+                self.verifications.get(request_id).is_some_and(|request| {
+                    let submitter = match &request.subject {
+                        VerificationSubject::Organization(organization) => self
+                            .organizations
+                            .get(organization)
+                            .is_some_and(|organization| {
+                                organization.allows(viewer, MemberRole::Owner)
+                            }),
+                        VerificationSubject::Trainer(trainer) => trainer == viewer,
+                    };
+                    request.documents.iter().any(|document| document == id)
+                        && (admin || submitter || request.submitted_by == *viewer)
+                })
+            }
+            AssetScope::Thread(thread_id) => self.threads.get(thread_id).is_some_and(|thread| {
+                let attached = thread.messages.iter().any(|message| {
+                    message
+                        .attachments
+                        .iter()
+                        .any(|attachment| attachment == id)
+                });
+                attached && (admin || self.is_participant(thread, viewer))
+            }),
+        })
+    }
+
+    // Dropping deleted assets from every record referencing them
+    pub fn forget_assets(&mut self, deleted: &BTreeSet<String>) {
+        let forget = |ids: &mut Vec<String>| ids.retain(|id| !deleted.contains(id));
+        let clear = |asset: &mut Option<String>| {
+            if asset.as_ref().is_some_and(|id| deleted.contains(id)) {
+                *asset = None;
+            }
+        };
+        for profile in self.profiles.values_mut() {
+            clear(&mut profile.resume_id);
+        }
+        for course in self.courses.values_mut() {
+            forget(&mut course.media);
+        }
+        for organization in self.organizations.values_mut() {
+            clear(&mut organization.details.logo_id);
+        }
+        for review in self.reviews.values_mut() {
+            clear(&mut review.image);
+            for revision in &mut review.history {
+                clear(&mut revision.image);
+            }
+        }
+        for request in self.verifications.values_mut() {
+            forget(&mut request.documents);
+        }
+        for thread in self.threads.values_mut() {
+            for message in &mut thread.messages {
+                forget(&mut message.attachments);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    // Bob applied to a job of Carol, Dan is another employer
+    fn applied() -> (State, Profile) {
+        let mut state = state();
+        let job = state
+            .create_job(&ctx(principal(3)), "Designer".into())
+            .unwrap();
+        state.apply_job(&ctx(principal(2)), &job.id).unwrap();
+        state
+            .create_user(
+                &ctx(principal(4)),
+                "Dan".into(),
+                "dan@ea.dev".into(),
+                "employer".into(),
+            )
+            .unwrap();
+        verified_company(&mut state, 4);
+        let bob = state.profiles.get_mut(&principal(2)).unwrap();
+        bob.location = "Nairobi".into();
+        bob.resume_id = Some("resume".into());
+        bob.privacy.location = Visibility::Employers;
+        let bob = bob.clone();
+        (state, bob)
+    }
+
+    #[test]
+    fn fields_are_shown_to_the_audience_they_allow() {
+        let (mut state, bob) = applied();
+
+        let stranger = state.public_profile(&principal(1), &bob);
+        assert_eq!(stranger.email, None);
+        assert_eq!(stranger.location, None);
+        assert_eq!(stranger.resume_id, None);
+
+        let employer = state.public_profile(&principal(4), &bob);
+        assert_eq!(employer.email, None);
+        assert_eq!(employer.location.as_deref(), Some("Nairobi"));
+
+        // The employer role alone is not enough to see what employers see
+        let eve = ctx(principal(5));
+        state
+            .create_user(&eve, "Eve".into(), "eve@ea.dev".into(), "employer".into())
+            .unwrap();
+        assert_eq!(state.public_profile(&principal(5), &bob).location, None);
+
+        let applied_to = state.public_profile(&principal(3), &bob);
+        assert_eq!(applied_to.email.as_deref(), Some("bob@ea.dev"));
+        assert_eq!(applied_to.resume_id.as_deref(), Some("resume"));
+
+        // Listings work the audience out once for all their profiles
+        let profiles: Vec<Profile> = state.profiles.values().cloned().collect();
+        let listed = state.public_profiles(&ctx(principal(3)), profiles.clone());
+        for (listed, profile) in listed.iter().zip(&profiles) {
+            let shown = state.public_profile(&principal(3), profile);
+            assert_eq!(listed.email, shown.email);
+            assert_eq!(listed.location, shown.location);
+            assert_eq!(listed.resume_id, shown.resume_id);
+        }
+        assert!(listed
+            .iter()
+            .any(|profile| profile.email.as_deref() == Some("bob@ea.dev")));
+    }
+
+    #[test]
+    fn full_profiles_are_limited_to_the_owner_and_applied_employers() {
+        let (mut state, bob) = applied();
+        assert!(state.full_profile(&ctx(principal(2)), &bob.id).is_some());
+        assert!(state.full_profile(&ctx(principal(3)), &bob.id).is_some());
+        assert!(state.full_profile(&ctx(principal(4)), &bob.id).is_none());
+
+        state.profiles.get_mut(&principal(2)).unwrap().privacy.email = Visibility::Private;
+        let bob = state.profiles[&principal(2)].clone();
+        assert_eq!(state.public_profile(&principal(3), &bob).email, None);
+        // Applying to a job doesn't share what the owner keeps private
+        let full = state.full_profile(&ctx(principal(3)), &bob.id).unwrap();
+        assert_eq!(full.email, "");
+        assert_eq!(full.resume_id.as_deref(), Some("resume"));
+        let own = state.full_profile(&ctx(principal(2)), &bob.id).unwrap();
+        assert_eq!(own.email, "bob@ea.dev");
+    }
+
+    #[test]
+    fn private_assets_are_read_by_the_audience_of_their_records() {
+        let (mut state, _) = applied();
+        let alice = ctx(principal(1));
+        state
+            .submit_verification(
+                &alice,
+                VerificationSubject::Trainer(principal(1)),
+                vec!["licence".into()],
+            )
+            .unwrap();
+
+        let scopes = |state: &State, id: &str| -> Vec<AssetScope> {
+            state
+                .asset_scopes()
+                .into_iter()
+                .filter(|(asset, _)| asset == id)
+                .map(|(_, scope)| scope)
+                .collect()
+        };
+        let resume = scopes(&state, "resume");
+        let licence = scopes(&state, "licence");
+        assert_eq!(resume, vec![AssetScope::Resume(principal(2))]);
+        assert_eq!(licence.len(), 1);
+        assert!(scopes(&state, "course-media").is_empty());
+
+        assert!(state.can_read_asset(&principal(3), "resume", &resume, false));
+        assert!(!state.can_read_asset(&principal(4), "resume", &resume, false));
+        assert!(!state.can_read_asset(&principal(1), "resume", &resume, true));
+        // A scope only covers the assets its record still references
+        assert!(!state.can_read_asset(&principal(3), "other", &resume, false));
+
+        assert!(state.can_read_asset(&principal(1), "licence", &licence, false));
+        assert!(!state.can_read_asset(&principal(3), "licence", &licence, false));
+        assert!(state.can_read_asset(&principal(9), "licence", &licence, true));
+
+        state.forget_assets(&BTreeSet::from(["resume".into(), "licence".into()]));
+        assert!(state.asset_scopes().is_empty());
+        assert_eq!(state.profiles[&principal(2)].resume_id, None);
+        assert!(state
+            .verifications
+            .values()
+            .all(|request| request.documents.is_empty()));
+    }
+}
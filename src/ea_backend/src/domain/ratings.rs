@@ -0,0 +1,345 @@
+use super::course_reviews::{CourseRatingSummary, CourseReview};
+use super::reviews::{MAX_RATING, MIN_RATING};
+use super::{Course, Profile, Reviews, Roles, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// Bayesian smoothing pulls the score of rarely reviewed profiles and courses
+// towards the middle of the scale, so a single five star review doesn't
+// outrank a long track record.
+pub const PRIOR_MEAN: f64 = 3.0;
+pub const PRIOR_WEIGHT: f64 = 5.0;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// Optional sub-ratings of a review, whole stars like the overall rating
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct CategoryRatings {
+    pub communication: f64,
+    pub quality: f64,
+    pub timeliness: f64,
+}
+
+impl CategoryRatings {
+    pub fn is_valid(&self) -> bool {
+        [self.communication, self.quality, self.timeliness]
+            .iter()
+            .all(|rating| is_whole_star(*rating))
+    }
+
+    fn is_finite(&self) -> bool {
+        [self.communication, self.quality, self.timeliness]
+            .iter()
+            .all(|rating| rating.is_finite())
+    }
+
+    fn accumulate(&mut self, other: &CategoryRatings, sign: f64) {
+        self.communication += sign * other.communication;
+        self.quality += sign * other.quality;
+        self.timeliness += sign * other.timeliness;
+    }
+
+    fn divided_by(&self, count: u64) -> CategoryRatings {
+        if count == 0 {
+            return CategoryRatings::default();
+        }
+        let count = count as f64;
+        CategoryRatings {
+            communication: self.communication / count,
+            quality: self.quality / count,
+            timeliness: self.timeliness / count,
+        }
+    }
+}
+
+// Aggregates of the reviews of a profile or course, kept up to date as reviews
+// come and go. `stars` counts the reviews per star, from one to five.
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct RatingSummary {
+    pub count: u64,
+    pub total: f64,
+    pub mean: f64,
+    pub score: f64,
+    pub stars: Vec<u64>,
+    pub category_count: u64,
+    pub category_totals: CategoryRatings,
+    pub categories: CategoryRatings,
+}
+
+impl Default for RatingSummary {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total: 0.0,
+            mean: 0.0,
+            score: PRIOR_MEAN,
+            stars: vec![0; MAX_RATING as usize],
+            category_count: 0,
+            category_totals: CategoryRatings::default(),
+            categories: CategoryRatings::default(),
+        }
+    }
+}
+
+pub fn is_whole_star(rating: f64) -> bool {
+    (MIN_RATING..=MAX_RATING).contains(&rating) && rating.fract() == 0.0
+}
+
+// A NaN or infinite rating would poison the totals for good, such reviews are
+// left out of the aggregates
+fn is_countable(review: &Reviews) -> bool {
+    !review.hidden
+        && review.ratings.is_finite()
+        && review
+            .categories
+            .as_ref()
+            .is_none_or(CategoryRatings::is_finite)
+}
+
+// Pulling a legacy rating that isn't a number back onto the scale
+pub fn sanitize_rating(rating: f64) -> f64 {
+    if rating.is_nan() {
+        PRIOR_MEAN
+    } else {
+        rating.clamp(MIN_RATING, MAX_RATING)
+    }
+}
+
+// Reviews written before ratings were validated can hold any value
+fn star_index(rating: f64) -> usize {
+    rating.round().clamp(MIN_RATING, MAX_RATING) as usize - 1
+}
+
+impl RatingSummary {
+    pub fn add(&mut self, review: &Reviews) {
+        self.add_rating(review.ratings, review.categories.as_ref());
+    }
+
+    pub fn remove(&mut self, review: &Reviews) {
+        self.remove_rating(review.ratings, review.categories.as_ref());
+    }
+
+    pub fn add_rating(&mut self, ratings: f64, categories: Option<&CategoryRatings>) {
+        self.count += 1;
+        self.total += ratings;
+        self.stars[star_index(ratings)] += 1;
+        if let Some(categories) = categories {
+            self.category_count += 1;
+            self.category_totals.accumulate(categories, 1.0);
+        }
+        self.refresh();
+    }
+
+    pub fn remove_rating(&mut self, ratings: f64, categories: Option<&CategoryRatings>) {
+        self.count = self.count.saturating_sub(1);
+        self.total -= ratings;
+        let stars = &mut self.stars[star_index(ratings)];
+        *stars = stars.saturating_sub(1);
+        if let Some(categories) = categories {
+            self.category_count = self.category_count.saturating_sub(1);
+            self.category_totals.accumulate(categories, -1.0);
+        }
+        self.refresh();
+    }
+
+    fn refresh(&mut self) {
+        self.mean = if self.count == 0 {
+            0.0
+        } else {
+            self.total / self.count as f64
+        };
+        self.score = (PRIOR_WEIGHT * PRIOR_MEAN + self.total) / (PRIOR_WEIGHT + self.count as f64);
+        self.categories = self.category_totals.divided_by(self.category_count);
+    }
+}
+
+// ==================================================================================================
+// Rating workflows
+// ==================================================================================================
+
+impl State {
+    // Counting a new review in the aggregates of the reviewed profile, hidden
+    // reviews are never counted
+    pub(crate) fn record_rating(&mut self, reviewee: &Principal, review: &Reviews) {
+        if !is_countable(review) {
+            return;
+        }
+        if let Some(profile) = self.profiles.get_mut(reviewee) {
+            profile.rating.add(review);
+        }
+    }
+
+    pub(crate) fn forget_rating(&mut self, reviewee: &Principal, review: &Reviews) {
+        if !is_countable(review) {
+            return;
+        }
+        if let Some(profile) = self.profiles.get_mut(reviewee) {
+            profile.rating.remove(review);
+        }
+    }
+
+    // Recomputing every aggregate from the stored reviews, for state restored
+    // from releases that didn't keep them
+    pub fn rebuild_ratings(&mut self) {
+        for course in self.courses.values_mut() {
+            course.rating = CourseRatingSummary::default();
+        }
+        for profile in self.profiles.values_mut() {
+            profile.rating = RatingSummary::default();
+            profile.course_rating = CourseRatingSummary::default();
+        }
+        let reviews: Vec<Reviews> = self
+            .reviews
+            .values()
+            .filter(|review| is_countable(review))
+            .cloned()
+            .collect();
+        for review in &reviews {
+            self.record_rating(&review.reviewee, review);
+        }
+        let course_reviews: Vec<CourseReview> = self
+            .course_reviews
+            .values()
+            .flat_map(|reviews| reviews.values().cloned())
+            .collect();
+        for review in &course_reviews {
+            self.record_course_rating(review);
+        }
+    }
+
+    // Best rated freelancers first
+    pub fn top_rated_freelancers(&self, limit: usize) -> Vec<Profile> {
+        let mut freelancers: Vec<Profile> = self
+            .profiles
+            .values()
+            .filter(|profile| profile.has_role(&Roles::FREELANCER) && profile.is_active())
+            .cloned()
+            .collect();
+        freelancers.sort_by(|a, b| b.rating.score.total_cmp(&a.rating.score));
+        freelancers.truncate(limit);
+        freelancers
+    }
+
+    // Best rated courses first
+    pub fn top_rated_courses(&self, limit: usize) -> Vec<Course> {
+        let mut courses: Vec<Course> = self.courses.values().cloned().collect();
+        courses.sort_by(|a, b| b.rating.overall.score.total_cmp(&a.rating.overall.score));
+        courses.truncate(limit);
+        courses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::Engagement;
+    use super::*;
+    use proptest::prelude::*;
+
+    fn rated(ratings: f64, categories: Option<CategoryRatings>) -> Reviews {
+        Reviews {
+            ratings,
+            categories,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summaries_follow_added_and_removed_reviews() {
+        let mut summary = RatingSummary::default();
+        let categories = CategoryRatings {
+            communication: 5.0,
+            quality: 4.0,
+            timeliness: 3.0,
+        };
+
+        summary.add(&rated(5.0, Some(categories.clone())));
+        summary.add(&rated(3.0, None));
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.mean, 4.0);
+        assert_eq!(summary.stars, vec![0, 0, 1, 0, 1]);
+        assert_eq!(summary.score, (5.0 * 3.0 + 8.0) / 7.0);
+        assert_eq!(summary.category_count, 1);
+        assert_eq!(summary.categories, categories);
+
+        summary.remove(&rated(5.0, Some(categories)));
+        summary.remove(&rated(3.0, None));
+        assert_eq!(summary, RatingSummary::default());
+    }
+
+    #[test]
+    fn ratings_that_are_not_numbers_are_not_counted() {
+        let mut state = state();
+        let reviewee = principal(2);
+        state.own_profile(&ctx(reviewee));
+        state.reviews.insert("nan".into(), rated(f64::NAN, None));
+        state
+            .reviews
+            .insert("infinite".into(), rated(f64::NEG_INFINITY, None));
+        for review in state.reviews.values_mut() {
+            review.reviewee = reviewee;
+        }
+
+        state.rebuild_ratings();
+        assert_eq!(
+            state.own_profile(&ctx(reviewee)).rating,
+            RatingSummary::default()
+        );
+
+        state.record_rating(&reviewee, &rated(f64::NAN, None));
+        assert_eq!(
+            state.own_profile(&ctx(reviewee)).rating,
+            RatingSummary::default()
+        );
+    }
+
+    #[test]
+    fn rebuilt_aggregates_match_the_incremental_ones() {
+        let mut state = state();
+        let course = state
+            .create_course(&ctx(principal(1)), "Rust".into())
+            .unwrap();
+        state.apply_course(&ctx(principal(2)), &course.id).unwrap();
+        let alice = state.own_profile(&ctx(principal(1))).id;
+
+        state
+            .add_review(
+                &ctx(principal(2)),
+                4.0,
+                "Great".into(),
+                "Clear".into(),
+                String::new(),
+                &alice,
+                Engagement::Course(course.id.clone()),
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(state.own_profile(&ctx(principal(1))).rating.mean, 4.0);
+
+        let aggregated = state.clone();
+        state.rebuild_ratings();
+        assert_eq!(
+            state.own_profile(&ctx(principal(1))).rating,
+            aggregated.own_profile(&ctx(principal(1))).rating
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn incremental_summaries_match_a_recount(stars in prop::collection::vec(1u8..=5, 0..30)) {
+            let mut summary = RatingSummary::default();
+            for star in &stars {
+                summary.add(&rated(*star as f64, None));
+            }
+
+            let total: f64 = stars.iter().map(|star| *star as f64).sum();
+            prop_assert_eq!(summary.count, stars.len() as u64);
+            prop_assert_eq!(summary.total, total);
+            prop_assert_eq!(summary.stars.iter().sum::<u64>(), stars.len() as u64);
+            prop_assert!(summary.score >= MIN_RATING && summary.score <= MAX_RATING);
+        }
+    }
+}
@@ -0,0 +1,758 @@
+use super::ratings::{is_whole_star, sanitize_rating, CategoryRatings};
+use super::{Context, ReviewStore, Rng, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const MIN_RATING: f64 = 1.0;
+pub const MAX_RATING: f64 = 5.0;
+// Authors can edit or delete their review for two weeks
+pub const REVIEW_EDIT_WINDOW: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+
+pub type ReviewIndex = BTreeMap<Principal, Vec<String>>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Reviews {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default = "Principal::anonymous")]
+    pub reviewee: Principal,
+    pub ratings: f64,
+    pub title: String,
+    pub review: String,
+    pub image: Option<String>,
+    pub reviewer: String,
+    pub review_time: i64,
+    pub reviewer_principal: Option<Principal>,
+    // Reviews written before engagements were required have none
+    #[serde(default)]
+    pub engagement: Option<Engagement>,
+    #[serde(default)]
+    pub categories: Option<CategoryRatings>,
+    #[serde(default)]
+    pub edited_at: Option<i64>,
+    // Earlier versions of an edited review, oldest first
+    #[serde(default)]
+    pub history: Vec<ReviewRevision>,
+    #[serde(default)]
+    pub reply: Option<ReviewReply>,
+    // Hidden by moderation, left out of listings and aggregates
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl Default for Reviews {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            reviewee: Principal::anonymous(),
+            ratings: Default::default(),
+            title: Default::default(),
+            review: Default::default(),
+            image: Default::default(),
+            reviewer: Default::default(),
+            review_time: Default::default(),
+            reviewer_principal: Default::default(),
+            engagement: Default::default(),
+            categories: Default::default(),
+            edited_at: Default::default(),
+            history: Default::default(),
+            reply: Default::default(),
+            hidden: Default::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct ReviewRevision {
+    pub ratings: f64,
+    pub title: String,
+    pub review: String,
+    pub image: Option<String>,
+    pub categories: Option<CategoryRatings>,
+    pub written_at: i64,
+}
+
+// The public answer of the reviewed user, one per review
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct ReviewReply {
+    pub text: String,
+    pub replied_at: i64,
+}
+
+// The interaction a review is about, both sides of it can review each other once
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Engagement {
+    // Between the job creator and a hired applicant
+    Job(String),
+    // Between the course creator and an enrolled student
+    Course(String),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum ReviewError {
+    ReviewerNotFound,
+    RevieweeNotFound,
+    SelfReview,
+    InvalidRating,
+    EngagementNotFound,
+    NotEngaged,
+    AlreadyReviewed,
+    ReviewNotFound,
+    Unauthorized,
+    EditWindowClosed,
+    AlreadyReplied,
+    AlreadyReported,
+    CourseNotFound,
+    InvalidAsset,
+    // Reported or hidden, the review stays as it is until an admin decides
+    UnderModeration,
+}
+
+// Reviews used to be copied into the profile of the reviewed user, the copies
+// are read once on upgrade to move them into the review store
+#[derive(Default, Deserialize)]
+pub struct LegacyProfileReviews {
+    #[serde(default)]
+    pub reviews: Vec<Reviews>,
+}
+
+fn validate_ratings(ratings: f64, categories: &Option<CategoryRatings>) -> Result<(), ReviewError> {
+    // Whole stars only
+    let categories_valid = categories.as_ref().is_none_or(CategoryRatings::is_valid);
+    if !is_whole_star(ratings) || !categories_valid {
+        return Err(ReviewError::InvalidRating);
+    }
+    Ok(())
+}
+
+// ==================================================================================================
+// Review workflows
+// ==================================================================================================
+
+impl State {
+    // Whether the caller is the given principal
+    pub fn confirm_reviewer(&self, ctx: &Context, reviewee: &str) -> bool {
+        reviewee == ctx.caller.to_text()
+    }
+
+    // Whether the caller has already reviewed the profile
+    pub fn confirm_reviewed(&self, ctx: &Context, reviewee: &Principal) -> bool {
+        self.reviews_by(&ctx.caller)
+            .any(|review| review.reviewee == *reviewee)
+    }
+
+    pub fn review(&self, review_id: &str) -> Option<Reviews> {
+        self.reviews
+            .get(review_id)
+            .filter(|review| !review.hidden)
+            .cloned()
+    }
+
+    // Reviews of the principal, oldest first
+    pub fn reviews_of(&self, principal: &Principal) -> Vec<Reviews> {
+        Self::indexed(&self.reviews_by_reviewee, &self.reviews, principal)
+            .filter(|review| !review.hidden)
+            .cloned()
+            .collect()
+    }
+
+    // Reviews written by the principal, oldest first
+    pub fn reviews_written_by(&self, principal: &Principal) -> Vec<Reviews> {
+        self.reviews_by(principal)
+            .filter(|review| !review.hidden)
+            .cloned()
+            .collect()
+    }
+
+    fn reviews_by<'a>(&'a self, principal: &Principal) -> impl Iterator<Item = &'a Reviews> {
+        Self::indexed(&self.reviews_by_reviewer, &self.reviews, principal)
+    }
+
+    fn indexed<'a>(
+        index: &'a ReviewIndex,
+        reviews: &'a ReviewStore,
+        principal: &Principal,
+    ) -> impl Iterator<Item = &'a Reviews> {
+        index
+            .get(principal)
+            .into_iter()
+            .flatten()
+            .filter_map(|review_id| reviews.get(review_id))
+    }
+
+    // Whether the two principals are on opposite sides of the engagement, where
+    // the managing side is whoever currently manages the job or course
+    fn engaged(
+        &self,
+        engagement: &Engagement,
+        reviewer: &Principal,
+        reviewee: &Principal,
+    ) -> Result<bool, ReviewError> {
+        let (reviewer_manages, reviewee_manages, participants) = match engagement {
+            Engagement::Job(id) => {
+                let job = self.jobs.get(id).ok_or(ReviewError::EngagementNotFound)?;
+                (
+                    self.manages_job(job, reviewer),
+                    self.manages_job(job, reviewee),
+                    &job.hired,
+                )
+            }
+            Engagement::Course(id) => {
+                let course = self
+                    .courses
+                    .get(id)
+                    .ok_or(ReviewError::EngagementNotFound)?;
+                (
+                    self.manages_course(course, reviewer),
+                    self.manages_course(course, reviewee),
+                    &course.applicants,
+                )
+            }
+        };
+        Ok((reviewer_manages && participants.contains(reviewee))
+            || (reviewee_manages && participants.contains(reviewer)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_review(
+        &mut self,
+        ctx: &Context,
+        ratings: f64,
+        title: String,
+        review: String,
+        image: String,
+        profile_reviewed: &str,
+        engagement: Engagement,
+        categories: Option<CategoryRatings>,
+    ) -> Result<Reviews, ReviewError> {
+        let reviewer = self
+            .profiles
+            .get(&ctx.caller)
+            .ok_or(ReviewError::ReviewerNotFound)?;
+        let reviewee = *self
+            .ids
+            .get(profile_reviewed)
+            .ok_or(ReviewError::RevieweeNotFound)?;
+        if reviewee == ctx.caller {
+            return Err(ReviewError::SelfReview);
+        }
+        validate_ratings(ratings, &categories)?;
+        if !self.engaged(&engagement, &ctx.caller, &reviewee)? {
+            return Err(ReviewError::NotEngaged);
+        }
+        let already_reviewed = self.reviews_by(&ctx.caller).any(|review| {
+            review.reviewee == reviewee && review.engagement.as_ref() == Some(&engagement)
+        });
+        if already_reviewed {
+            return Err(ReviewError::AlreadyReviewed);
+        }
+
+        let new_review = Reviews {
+            id: ctx.rng.next_id(),
+            reviewee,
+            ratings,
+            title,
+            review,
+            image: (!image.is_empty()).then_some(image),
+            reviewer: reviewer.fullname.clone(),
+            review_time: ctx.now as i64,
+            reviewer_principal: Some(ctx.caller),
+            engagement: Some(engagement),
+            categories,
+            ..Default::default()
+        };
+        self.insert_review(new_review.clone());
+        self.notify_review(ctx, &new_review);
+
+        Ok(new_review)
+    }
+
+    // The author can change a review within the edit window, the previous
+    // version is kept in its history
+    #[allow(clippy::too_many_arguments)]
+    pub fn edit_review(
+        &mut self,
+        ctx: &Context,
+        review_id: &str,
+        ratings: f64,
+        title: String,
+        review: String,
+        image: String,
+        categories: Option<CategoryRatings>,
+    ) -> Result<Reviews, ReviewError> {
+        self.editable_review(ctx, review_id)?;
+        validate_ratings(ratings, &categories)?;
+
+        let previous = self.reviews[review_id].clone();
+        self.forget_rating(&previous.reviewee, &previous);
+        let mut edited = previous.clone();
+        edited.history.push(ReviewRevision {
+            ratings: previous.ratings,
+            title: previous.title,
+            review: previous.review,
+            image: previous.image,
+            categories: previous.categories,
+            written_at: previous.edited_at.unwrap_or(previous.review_time),
+        });
+        edited.ratings = ratings;
+        edited.title = title;
+        edited.review = review;
+        edited.image = (!image.is_empty()).then_some(image);
+        edited.categories = categories;
+        edited.edited_at = Some(ctx.now as i64);
+        self.record_rating(&edited.reviewee, &edited);
+        self.reviews.insert(edited.id.clone(), edited.clone());
+
+        Ok(edited)
+    }
+
+    pub fn delete_review(&mut self, ctx: &Context, review_id: &str) -> Result<(), ReviewError> {
+        self.editable_review(ctx, review_id)?;
+        self.remove_review(review_id);
+        Ok(())
+    }
+
+    // The reviewed user can answer a review once
+    pub fn reply_to_review(
+        &mut self,
+        ctx: &Context,
+        review_id: &str,
+        text: String,
+    ) -> Result<Reviews, ReviewError> {
+        let review = self
+            .reviews
+            .get_mut(review_id)
+            .ok_or(ReviewError::ReviewNotFound)?;
+        if review.reviewee != ctx.caller {
+            return Err(ReviewError::Unauthorized);
+        }
+        if review.reply.is_some() {
+            return Err(ReviewError::AlreadyReplied);
+        }
+        review.reply = Some(ReviewReply {
+            text,
+            replied_at: ctx.now as i64,
+        });
+        Ok(review.clone())
+    }
+
+    fn editable_review(&self, ctx: &Context, review_id: &str) -> Result<(), ReviewError> {
+        let review = self
+            .reviews
+            .get(review_id)
+            .ok_or(ReviewError::ReviewNotFound)?;
+        if review.reviewer_principal != Some(ctx.caller) {
+            return Err(ReviewError::Unauthorized);
+        }
+        let open_case = self.moderation.get(review_id).is_some_and(|case| case.open);
+        if review.hidden || open_case {
+            return Err(ReviewError::UnderModeration);
+        }
+        let written_at = u64::try_from(review.review_time).unwrap_or_default();
+        if ctx.now > written_at.saturating_add(REVIEW_EDIT_WINDOW) {
+            return Err(ReviewError::EditWindowClosed);
+        }
+        Ok(())
+    }
+
+    // Storing a review with its index entries and counting it in the aggregates
+    fn insert_review(&mut self, review: Reviews) {
+        self.index_review(&review);
+        self.record_rating(&review.reviewee, &review);
+        self.reviews.insert(review.id.clone(), review);
+    }
+
+    pub(super) fn remove_review(&mut self, review_id: &str) -> Option<Reviews> {
+        let review = self.reviews.remove(review_id)?;
+        for (index, principal) in [
+            (&mut self.reviews_by_reviewee, Some(review.reviewee)),
+            (&mut self.reviews_by_reviewer, review.reviewer_principal),
+        ] {
+            if let Some(ids) = principal.and_then(|principal| index.get_mut(&principal)) {
+                ids.retain(|id| id != review_id);
+            }
+        }
+        self.forget_rating(&review.reviewee, &review);
+        // The reports and the audit notes are kept with the case
+        if let Some(case) = self.moderation.get_mut(review_id) {
+            case.open = false;
+        }
+        Some(review)
+    }
+
+    fn index_review(&mut self, review: &Reviews) {
+        self.reviews_by_reviewee
+            .entry(review.reviewee)
+            .or_default()
+            .push(review.id.clone());
+        if let Some(reviewer) = review.reviewer_principal {
+            self.reviews_by_reviewer
+                .entry(reviewer)
+                .or_default()
+                .push(review.id.clone());
+        }
+    }
+
+    // The indexes are derived from the store and rebuilt after an upgrade
+    pub fn rebuild_review_indexes(&mut self) {
+        self.reviews_by_reviewee.clear();
+        self.reviews_by_reviewer.clear();
+
+        let mut reviews: Vec<Reviews> = self.reviews.values().cloned().collect();
+        reviews.sort_by_key(|review| review.review_time);
+        for review in &reviews {
+            self.index_review(review);
+        }
+    }
+
+    // Moving the review copies of older releases into the store. Entries of the
+    // old store matching a copy keep their key, the others were never attached
+    // to a profile and are dropped.
+    pub fn migrate_profile_reviews(&mut self, legacy: BTreeMap<Principal, LegacyProfileReviews>) {
+        let (current, mut unattached): (ReviewStore, ReviewStore) =
+            std::mem::take(&mut self.reviews)
+                .into_iter()
+                .partition(|(_, review)| !review.id.is_empty());
+        self.reviews = current;
+
+        for (reviewee, profile) in legacy {
+            let ids = Rng::from_seed(reviewee.as_slice());
+            for mut review in profile.reviews {
+                let stored = unattached.iter().find(|(_, stored)| {
+                    stored.reviewer_principal == review.reviewer_principal
+                        && stored.review_time == review.review_time
+                        && stored.title == review.title
+                        && stored.review == review.review
+                });
+                review.id = match stored.map(|(key, _)| key.clone()) {
+                    Some(key) => {
+                        unattached.remove(&key);
+                        key
+                    }
+                    None => ids.next_id(),
+                };
+                review.reviewee = reviewee;
+                if !review.ratings.is_finite() {
+                    review.ratings = sanitize_rating(review.ratings);
+                }
+                self.reviews.insert(review.id.clone(), review);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::{MemberRole, OrganizationDetails, OrganizationKind, Rng};
+    use super::*;
+    use proptest::prelude::*;
+
+    // Carol hired Bob on a job
+    fn hired() -> (State, Engagement) {
+        let mut state = state();
+        let carol = ctx(principal(3));
+        let job = state.create_job(&carol, "Designer".into()).unwrap();
+        state.apply_job(&ctx(principal(2)), &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(2)).unwrap();
+        (state, Engagement::Job(job.id))
+    }
+
+    fn review(
+        state: &mut State,
+        reviewer: u8,
+        reviewee: u8,
+        ratings: f64,
+        engagement: &Engagement,
+    ) -> Result<Reviews, ReviewError> {
+        let reviewee = state.own_profile(&ctx(principal(reviewee))).id;
+        state.add_review(
+            &ctx(principal(reviewer)),
+            ratings,
+            "Great".into(),
+            "On time".into(),
+            String::new(),
+            &reviewee,
+            engagement.clone(),
+            None,
+        )
+    }
+
+    #[test]
+    fn both_sides_of_a_hire_review_each_other_once() {
+        let (mut state, engagement) = hired();
+        let carol = ctx(principal(3));
+
+        assert!(!state.confirm_reviewed(&carol, &principal(2)));
+        let written = review(&mut state, 3, 2, 4.0, &engagement).unwrap();
+        assert_eq!(written.reviewer, "Carol");
+        assert_eq!(written.image, None);
+        assert_eq!(written.review_time, NOW as i64);
+        assert_eq!(written.engagement, Some(engagement.clone()));
+        assert_eq!(state.reviews_of(&principal(2)).len(), 1);
+        assert!(state.confirm_reviewed(&carol, &principal(2)));
+
+        assert_eq!(
+            review(&mut state, 3, 2, 5.0, &engagement).unwrap_err(),
+            ReviewError::AlreadyReviewed
+        );
+        review(&mut state, 2, 3, 5.0, &engagement).unwrap();
+        assert_eq!(state.reviews.len(), 2);
+    }
+
+    #[test]
+    fn reviews_need_an_engagement() {
+        let (mut state, engagement) = hired();
+
+        // Alice took no part in the job
+        assert_eq!(
+            review(&mut state, 1, 2, 4.0, &engagement).unwrap_err(),
+            ReviewError::NotEngaged
+        );
+        assert_eq!(
+            review(&mut state, 3, 2, 4.0, &Engagement::Job("missing".into())).unwrap_err(),
+            ReviewError::EngagementNotFound
+        );
+        assert_eq!(
+            review(&mut state, 2, 2, 4.0, &engagement).unwrap_err(),
+            ReviewError::SelfReview
+        );
+
+        // Applying to a job is not enough, the applicant has to be hired
+        let job = state
+            .create_job(&ctx(principal(3)), "Writer".into())
+            .unwrap();
+        state.apply_job(&ctx(principal(1)), &job.id).unwrap();
+        assert_eq!(
+            review(&mut state, 3, 1, 4.0, &Engagement::Job(job.id)).unwrap_err(),
+            ReviewError::NotEngaged
+        );
+    }
+
+    #[test]
+    fn organization_jobs_are_reviewed_by_their_current_managers() {
+        let mut state = state();
+        let (bob, carol) = (ctx(principal(2)), ctx(principal(3)));
+        let details = OrganizationDetails {
+            name: "Acme".into(),
+            kind: OrganizationKind::Company,
+            description: String::new(),
+            website: None,
+            location: "Nairobi".into(),
+            logo_id: None,
+        };
+        let acme = state.create_organization(&carol, details).unwrap();
+        state
+            .set_organization_member(&carol, &acme.id, principal(2), MemberRole::Recruiter)
+            .unwrap();
+        let job = state
+            .create_organization_job(&bob, &acme.id, "Designer".into())
+            .unwrap();
+        state.apply_job(&ctx(principal(1)), &job.id).unwrap();
+        state.hire(&carol, &job.id, principal(1)).unwrap();
+        let engagement = Engagement::Job(job.id);
+
+        // Carol owns the organization without having posted the job
+        review(&mut state, 3, 1, 4.0, &engagement).unwrap();
+        review(&mut state, 1, 3, 4.0, &engagement).unwrap();
+
+        // Bob posted it but left the organization
+        state
+            .remove_organization_member(&carol, &acme.id, principal(2))
+            .unwrap();
+        assert_eq!(
+            review(&mut state, 2, 1, 4.0, &engagement).unwrap_err(),
+            ReviewError::NotEngaged
+        );
+    }
+
+    #[test]
+    fn students_and_trainers_review_each_other() {
+        let mut state = state();
+        let course = state
+            .create_course(&ctx(principal(1)), "Rust".into())
+            .unwrap();
+        let engagement = Engagement::Course(course.id.clone());
+
+        assert_eq!(
+            review(&mut state, 2, 1, 5.0, &engagement).unwrap_err(),
+            ReviewError::NotEngaged
+        );
+        state.apply_course(&ctx(principal(2)), &course.id).unwrap();
+        review(&mut state, 2, 1, 5.0, &engagement).unwrap();
+        review(&mut state, 1, 2, 3.0, &engagement).unwrap();
+    }
+
+    #[test]
+    fn authors_edit_and_delete_within_the_window() {
+        let (mut state, engagement) = hired();
+        let written = review(&mut state, 3, 2, 2.0, &engagement).unwrap();
+        let carol = ctx(principal(3));
+
+        let edited = state
+            .edit_review(
+                &carol,
+                &written.id,
+                4.0,
+                "Better".into(),
+                "Fixed it".into(),
+                String::new(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(edited.history.len(), 1);
+        assert_eq!(edited.history[0].title, "Great");
+        assert_eq!(edited.edited_at, Some(NOW as i64));
+        assert_eq!(state.own_profile(&ctx(principal(2))).rating.mean, 4.0);
+        assert_eq!(state.reviews_written_by(&principal(3))[0].title, "Better");
+
+        let edit = |state: &mut State, caller: &Context| {
+            state.edit_review(
+                caller,
+                &written.id,
+                5.0,
+                "Late".into(),
+                String::new(),
+                String::new(),
+                None,
+            )
+        };
+        assert_eq!(
+            edit(&mut state, &ctx(principal(2))).unwrap_err(),
+            ReviewError::Unauthorized
+        );
+        let later = Context::new(principal(3), NOW + REVIEW_EDIT_WINDOW + 1, Rng::unseeded());
+        assert_eq!(
+            edit(&mut state, &later).unwrap_err(),
+            ReviewError::EditWindowClosed
+        );
+
+        state.delete_review(&carol, &written.id).unwrap();
+        assert!(state.reviews_of(&principal(2)).is_empty());
+        assert!(!state.confirm_reviewed(&carol, &principal(2)));
+        assert_eq!(state.own_profile(&ctx(principal(2))).rating.count, 0);
+    }
+
+    #[test]
+    fn the_reviewee_replies_once() {
+        let (mut state, engagement) = hired();
+        let written = review(&mut state, 3, 2, 4.0, &engagement).unwrap();
+
+        assert_eq!(
+            state
+                .reply_to_review(&ctx(principal(3)), &written.id, "Me".into())
+                .unwrap_err(),
+            ReviewError::Unauthorized
+        );
+        let replied = state
+            .reply_to_review(&ctx(principal(2)), &written.id, "Thanks".into())
+            .unwrap();
+        assert_eq!(replied.reply.unwrap().text, "Thanks");
+        assert_eq!(
+            state
+                .reply_to_review(&ctx(principal(2)), &written.id, "Again".into())
+                .unwrap_err(),
+            ReviewError::AlreadyReplied
+        );
+    }
+
+    #[test]
+    fn profile_copies_move_into_the_store() {
+        let mut state = state();
+        let copy = Reviews {
+            title: "Legacy".into(),
+            reviewer_principal: Some(principal(3)),
+            ratings: 4.0,
+            ..Default::default()
+        };
+        state.reviews.insert("kept".into(), copy.clone());
+        state.reviews.insert("orphan".into(), Default::default());
+
+        let mut legacy = BTreeMap::new();
+        legacy.insert(
+            principal(2),
+            LegacyProfileReviews {
+                reviews: vec![copy],
+            },
+        );
+        state.migrate_profile_reviews(legacy);
+        state.rebuild_review_indexes();
+        state.rebuild_ratings();
+
+        let reviews = state.reviews_of(&principal(2));
+        assert_eq!(state.reviews.len(), 1);
+        assert_eq!(reviews[0].id, "kept");
+        assert_eq!(reviews[0].reviewee, principal(2));
+        assert_eq!(state.own_profile(&ctx(principal(2))).rating.count, 1);
+        assert!(state.confirm_reviewed(&ctx(principal(3)), &principal(2)));
+    }
+
+    #[test]
+    fn legacy_ratings_that_are_not_numbers_are_sanitized() {
+        let mut state = state();
+        let legacy_review = |title: &str, ratings: f64| Reviews {
+            title: title.into(),
+            reviewer_principal: Some(principal(3)),
+            ratings,
+            ..Default::default()
+        };
+
+        let mut legacy = BTreeMap::new();
+        legacy.insert(
+            principal(2),
+            LegacyProfileReviews {
+                reviews: vec![
+                    legacy_review("NaN", f64::NAN),
+                    legacy_review("Infinite", f64::INFINITY),
+                    legacy_review("Fine", 4.0),
+                ],
+            },
+        );
+        state.migrate_profile_reviews(legacy);
+        state.rebuild_review_indexes();
+        state.rebuild_ratings();
+
+        let reviews = state.reviews_of(&principal(2));
+        assert!(reviews.iter().all(|review| review.ratings.is_finite()));
+        let rating = state.own_profile(&ctx(principal(2))).rating;
+        assert_eq!(rating.count, 3);
+        assert_eq!(rating.total, 3.0 + 5.0 + 4.0);
+        assert!(rating.score.is_finite());
+    }
+
+    #[test]
+    fn confirm_reviewer_compares_with_the_caller() {
+        let state = state();
+        let carol = ctx(principal(3));
+
+        assert!(state.confirm_reviewer(&carol, &principal(3).to_text()));
+        assert!(!state.confirm_reviewer(&carol, &principal(2).to_text()));
+    }
+
+    proptest! {
+        #[test]
+        fn only_whole_stars_are_accepted(ratings in -10.0f64..10.0) {
+            let (mut state, engagement) = hired();
+            let valid = (1.0..=5.0).contains(&ratings) && ratings.fract() == 0.0;
+
+            let result = review(&mut state, 3, 2, ratings, &engagement);
+            prop_assert_eq!(result.is_ok(), valid);
+            if !valid {
+                prop_assert_eq!(result.unwrap_err(), ReviewError::InvalidRating);
+            }
+        }
+
+        #[test]
+        fn whole_star_ratings_are_kept(stars in 1u8..=5) {
+            let (mut state, engagement) = hired();
+
+            let written = review(&mut state, 3, 2, stars as f64, &engagement).unwrap();
+            prop_assert_eq!(written.ratings, stars as f64);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use super::{Context, Profile, Roles, State};
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum Availability {
+    #[default]
+    Available,
+    PartTime,
+    Unavailable,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct FreelancerProfile {
+    // In whole US dollars
+    pub hourly_rate: Option<u64>,
+    pub availability: Availability,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct EmployerProfile {
+    pub company_name: String,
+    pub website: Option<String>,
+    pub industry: String,
+    pub company_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct TrainerProfile {
+    pub bio: String,
+    pub specialties: Vec<String>,
+}
+
+// The details of one role of a profile
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum RoleProfile {
+    Freelancer(FreelancerProfile),
+    Employer(EmployerProfile),
+    Trainer(TrainerProfile),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum RoleError {
+    ProfileNotFound,
+    // Admins are appointed through the platform config
+    RoleNotAllowed,
+    RoleNotHeld,
+    LastRole,
+}
+
+impl RoleProfile {
+    fn role(&self) -> Roles {
+        match self {
+            RoleProfile::Freelancer(_) => Roles::FREELANCER,
+            RoleProfile::Employer(_) => Roles::EMPLOYER,
+            RoleProfile::Trainer(_) => Roles::TRAINER,
+        }
+    }
+}
+
+impl Profile {
+    pub fn has_role(&self, role: &Roles) -> bool {
+        self.roles.contains(role)
+    }
+
+    // Sub-profiles only exist for the roles the profile holds
+    fn drop_unheld_role_profiles(&mut self) {
+        if !self.has_role(&Roles::FREELANCER) {
+            self.freelancer = None;
+        }
+        if !self.has_role(&Roles::EMPLOYER) {
+            self.employer = None;
+        }
+        if !self.has_role(&Roles::TRAINER) {
+            self.trainer = None;
+        }
+    }
+}
+
+// ==================================================================================================
+// Role workflows
+// ==================================================================================================
+
+impl State {
+    fn own_profile_mut(&mut self, ctx: &Context) -> Result<&mut Profile, RoleError> {
+        self.profiles
+            .get_mut(&ctx.caller)
+            .ok_or(RoleError::ProfileNotFound)
+    }
+
+    // Adding a role to the caller, adding a held role changes nothing
+    pub fn add_role(&mut self, ctx: &Context, role: Roles) -> Result<Profile, RoleError> {
+        if role == Roles::ADMIN {
+            return Err(RoleError::RoleNotAllowed);
+        }
+        let profile = self.own_profile_mut(ctx)?;
+        if !profile.has_role(&role) {
+            profile.roles.push(role);
+        }
+        Ok(profile.clone())
+    }
+
+    // Removing a role with its sub-profile, the caller keeps at least one role.
+    // When the active role is removed the first remaining one becomes active.
+    pub fn remove_role(&mut self, ctx: &Context, role: Roles) -> Result<Profile, RoleError> {
+        let profile = self.own_profile_mut(ctx)?;
+        if !profile.has_role(&role) {
+            return Err(RoleError::RoleNotHeld);
+        }
+        if profile.roles.len() == 1 {
+            return Err(RoleError::LastRole);
+        }
+        profile.roles.retain(|held| *held != role);
+        if profile.role == role {
+            profile.role = profile.roles[0].clone();
+        }
+        profile.drop_unheld_role_profiles();
+
+        // Only trainers are verified on their own
+        if role == Roles::TRAINER {
+            self.revoke_trainer_verification(&ctx.caller);
+        }
+        self.own_profile_mut(ctx).cloned()
+    }
+
+    // Choosing the role the UI acts in
+    pub fn switch_role(&mut self, ctx: &Context, role: Roles) -> Result<Profile, RoleError> {
+        let profile = self.own_profile_mut(ctx)?;
+        if !profile.has_role(&role) {
+            return Err(RoleError::RoleNotHeld);
+        }
+        profile.role = role;
+        Ok(profile.clone())
+    }
+
+    pub fn update_role_profile(
+        &mut self,
+        ctx: &Context,
+        role_profile: RoleProfile,
+    ) -> Result<Profile, RoleError> {
+        let profile = self.own_profile_mut(ctx)?;
+        if !profile.has_role(&role_profile.role()) {
+            return Err(RoleError::RoleNotHeld);
+        }
+        match role_profile {
+            RoleProfile::Freelancer(freelancer) => profile.freelancer = Some(freelancer),
+            RoleProfile::Employer(employer) => profile.employer = Some(employer),
+            RoleProfile::Trainer(trainer) => profile.trainer = Some(trainer),
+        }
+        Ok(profile.clone())
+    }
+
+    // Profiles stored before users could hold several roles only have their active one
+    pub fn migrate_single_roles(&mut self) {
+        for profile in self.profiles.values_mut() {
+            if profile.roles.is_empty() {
+                profile.roles.push(profile.role.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    #[test]
+    fn roles_are_added_switched_and_removed() {
+        let mut state = state();
+        let alice = ctx(principal(1));
+
+        assert_eq!(
+            state.add_role(&alice, Roles::ADMIN).unwrap_err(),
+            RoleError::RoleNotAllowed
+        );
+        state.add_role(&alice, Roles::EMPLOYER).unwrap();
+        let profile = state.add_role(&alice, Roles::EMPLOYER).unwrap();
+        assert_eq!(profile.roles, vec![Roles::TRAINER, Roles::EMPLOYER]);
+        assert_eq!(profile.role, Roles::TRAINER);
+
+        // Gates check membership, not the active role
+        state
+            .create_job(&alice, "Teaching assistant".into())
+            .unwrap();
+        state.create_course(&alice, "Rust".into()).unwrap();
+
+        let profile = state.switch_role(&alice, Roles::EMPLOYER).unwrap();
+        assert_eq!(profile.role, Roles::EMPLOYER);
+        assert_eq!(
+            state.switch_role(&alice, Roles::FREELANCER).unwrap_err(),
+            RoleError::RoleNotHeld
+        );
+
+        let profile = state.remove_role(&alice, Roles::EMPLOYER).unwrap();
+        assert_eq!(profile.roles, vec![Roles::TRAINER]);
+        assert_eq!(profile.role, Roles::TRAINER);
+        assert_eq!(
+            state.remove_role(&alice, Roles::TRAINER).unwrap_err(),
+            RoleError::LastRole
+        );
+    }
+
+    #[test]
+    fn role_profiles_follow_the_roles_held() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let employer = RoleProfile::Employer(EmployerProfile {
+            company_name: "Bob & Co".into(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            state
+                .update_role_profile(&bob, employer.clone())
+                .unwrap_err(),
+            RoleError::RoleNotHeld
+        );
+        state.add_role(&bob, Roles::EMPLOYER).unwrap();
+        let profile = state.update_role_profile(&bob, employer).unwrap();
+        assert_eq!(profile.employer.unwrap().company_name, "Bob & Co");
+
+        let profile = state.remove_role(&bob, Roles::EMPLOYER).unwrap();
+        assert!(profile.employer.is_none());
+    }
+
+    #[test]
+    fn single_roles_are_migrated() {
+        let mut state = state();
+        for profile in state.profiles.values_mut() {
+            profile.roles.clear();
+        }
+        state.migrate_single_roles();
+        assert_eq!(state.profiles[&principal(3)].roles, vec![Roles::EMPLOYER]);
+    }
+}
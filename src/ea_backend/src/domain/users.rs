@@ -0,0 +1,410 @@
+use super::{
+    Context, CourseRatingSummary, Cv, EmployerProfile, FreelancerProfile, PrivacySettings,
+    RatingSummary, State, TrainerProfile, VerificationStatus,
+};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub principal_id: Principal,
+    pub fullname: String,
+    pub email: String,
+    // Set once the user confirmed a code sent to `email`
+    #[serde(default)]
+    pub email_verified: bool,
+    pub occupation: String,
+    pub organization: String,
+    pub location: String,
+    // Asset id of the uploaded resume
+    #[serde(default)]
+    pub resume_id: Option<String>,
+    #[serde(default)]
+    pub cv: Cv,
+    // The role the user currently acts in, one of `roles`
+    pub role: Roles,
+    #[serde(default)]
+    pub roles: Vec<Roles>,
+    #[serde(default)]
+    pub freelancer: Option<FreelancerProfile>,
+    #[serde(default)]
+    pub employer: Option<EmployerProfile>,
+    #[serde(default)]
+    pub trainer: Option<TrainerProfile>,
+    pub description: String,
+    pub keywords: Vec<String>,
+    pub skills: Vec<String>,
+    pub token_ids: Vec<(Nat, String)>,
+    #[serde(default)]
+    pub rating: RatingSummary,
+    // Rollup of the ratings of every course of a trainer
+    #[serde(default)]
+    pub course_rating: CourseRatingSummary,
+    // Who can see the contact details, resume and CV
+    #[serde(default)]
+    pub privacy: PrivacySettings,
+    // Deactivated profiles are left out of searches and listings
+    #[serde(default)]
+    pub deactivated_at: Option<u64>,
+    // Trainers teaching on their own are verified through verification requests
+    #[serde(default)]
+    pub verification: VerificationStatus,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            principal_id: Principal::anonymous(),
+            fullname: Default::default(),
+            email: Default::default(),
+            email_verified: Default::default(),
+            occupation: Default::default(),
+            organization: Default::default(),
+            location: Default::default(),
+            resume_id: Default::default(),
+            cv: Default::default(),
+            role: Roles::ADMIN,
+            roles: Default::default(),
+            freelancer: Default::default(),
+            employer: Default::default(),
+            trainer: Default::default(),
+            description: Default::default(),
+            keywords: Default::default(),
+            skills: Default::default(),
+            token_ids: Default::default(),
+            rating: Default::default(),
+            course_rating: Default::default(),
+            privacy: Default::default(),
+            deactivated_at: Default::default(),
+            verification: Default::default(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn is_active(&self) -> bool {
+        self.deactivated_at.is_none()
+    }
+}
+
+// Resumes used to be kept as bytes in the profile, they are read once on
+// upgrade to move them into the asset store
+#[derive(Default, Deserialize)]
+pub struct LegacyProfileResume {
+    #[serde(default)]
+    pub resume: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum RegistrationError {
+    AnonymousCaller,
+    AlreadyRegistered,
+    InvalidRole,
+}
+
+// Role enum
+#[derive(CandidType, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub enum Roles {
+    FREELANCER,
+    #[default]
+    EMPLOYER,
+    TRAINER,
+    ADMIN,
+}
+// Roles a user can register with, admins are never picked at registration
+impl FromStr for Roles {
+    type Err = RegistrationError;
+
+    fn from_str(el: &str) -> Result<Roles, RegistrationError> {
+        match el.to_lowercase().as_str() {
+            "trainee" | "freelancer" => Ok(Roles::FREELANCER),
+            "employer" => Ok(Roles::EMPLOYER),
+            "trainer" => Ok(Roles::TRAINER),
+            _ => Err(RegistrationError::InvalidRole),
+        }
+    }
+}
+
+// ==================================================================================================
+// User workflows
+// ==================================================================================================
+
+impl State {
+    // Whether the caller already has a profile
+    pub fn is_registered(&self, ctx: &Context) -> bool {
+        self.profiles.contains_key(&ctx.caller)
+    }
+
+    // Registering the caller once, a second registration would replace the
+    // reviews and certificates of the profile
+    pub fn create_user(
+        &mut self,
+        ctx: &Context,
+        fullname: String,
+        email: String,
+        role: String,
+    ) -> Result<Profile, RegistrationError> {
+        if ctx.caller == Principal::anonymous() {
+            return Err(RegistrationError::AnonymousCaller);
+        }
+        if self.is_registered(ctx) {
+            return Err(RegistrationError::AlreadyRegistered);
+        }
+        let role = role.parse::<Roles>()?;
+        let profile = Profile {
+            id: ctx.rng.next_id(),
+            principal_id: ctx.caller,
+            fullname,
+            email,
+            roles: vec![role.clone()],
+            role,
+            ..Default::default()
+        };
+        self.ids.insert(profile.id.clone(), ctx.caller);
+        self.profiles.insert(ctx.caller, profile.clone());
+        Ok(profile)
+    }
+
+    // Earlier releases let users register again, leaving ids behind that no
+    // longer match the id of their profile
+    pub fn remove_stale_ids(&mut self) {
+        let profiles = &self.profiles;
+        self.ids.retain(|id, principal| {
+            profiles
+                .get(principal)
+                .is_some_and(|profile| profile.id == *id)
+        });
+    }
+
+    // Profile of the caller, an empty one owned by the caller if not registered yet
+    pub fn own_profile(&self, ctx: &Context) -> Profile {
+        self.profiles
+            .get(&ctx.caller)
+            .cloned()
+            .unwrap_or_else(|| Profile {
+                principal_id: ctx.caller,
+                ..Default::default()
+            })
+    }
+
+    // Deactivated profiles are only shown to their owner
+    pub fn profile_by_id(&self, ctx: &Context, uid: &str) -> Profile {
+        self.ids
+            .get(uid)
+            .and_then(|id| self.profiles.get(id))
+            .filter(|profile| profile.is_active() || profile.principal_id == ctx.caller)
+            .cloned()
+            .unwrap_or_else(|| Profile {
+                principal_id: ctx.caller,
+                ..Default::default()
+            })
+    }
+
+    // Ids, certificates, ratings, roles, verifications and the account status
+    // are kept as they are, roles have their own workflows. A changed email has
+    // to be verified again.
+    // Renaming a verified trainer takes the verification away until an admin
+    // reviews it again
+    pub fn update_profile(&mut self, ctx: &Context, profile: Profile) {
+        let renamed_verified = self.profiles.get(&ctx.caller).is_some_and(|existing| {
+            existing.fullname != profile.fullname
+                && existing.verification == VerificationStatus::Verified
+        });
+        if let Some(existing) = self.profiles.get_mut(&ctx.caller) {
+            *existing = Profile {
+                id: existing.id.clone(),
+                principal_id: existing.principal_id,
+                token_ids: existing.token_ids.clone(),
+                rating: existing.rating.clone(),
+                course_rating: existing.course_rating.clone(),
+                cv: existing.cv.clone(),
+                deactivated_at: existing.deactivated_at,
+                verification: existing.verification.clone(),
+                email_verified: existing.email_verified && existing.email == profile.email,
+                role: existing.role.clone(),
+                roles: existing.roles.clone(),
+                freelancer: existing.freelancer.clone(),
+                employer: existing.employer.clone(),
+                trainer: existing.trainer.clone(),
+                ..profile
+            };
+        }
+        if renamed_verified {
+            self.revoke_trainer_verification(&ctx.caller);
+        }
+    }
+
+    // First profile matching the text in the name, description or keywords
+    pub fn search(&self, text: &str) -> Option<&Profile> {
+        let text = text.to_lowercase();
+        self.profiles.values().filter(|p| p.is_active()).find(|p| {
+            p.fullname.to_lowercase().contains(&text)
+                || p.description.to_lowercase().contains(&text)
+                || p.keywords.iter().any(|x| x.to_lowercase() == text)
+        })
+    }
+
+    // Only employers can browse the freelancers
+    pub fn freelancers(&self, ctx: &Context) -> Option<Vec<Profile>> {
+        let caller = self.profiles.get(&ctx.caller)?;
+        if !caller.has_role(&Roles::EMPLOYER) {
+            return None;
+        }
+
+        let freelancers = self
+            .profiles
+            .values()
+            .filter(|profile| profile.has_role(&Roles::FREELANCER) && profile.is_active())
+            .cloned()
+            .collect();
+        Some(freelancers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::*;
+
+    #[test]
+    fn users_register_once() {
+        let mut state = State::default();
+        let alice = ctx(principal(1));
+
+        assert!(!state.is_registered(&alice));
+        let created = state
+            .create_user(&alice, "Alice".into(), "a@ea.dev".into(), "trainer".into())
+            .unwrap();
+        assert!(state.is_registered(&alice));
+        assert_eq!(
+            state
+                .create_user(&alice, "Eve".into(), "e@ea.dev".into(), "admin".into())
+                .unwrap_err(),
+            RegistrationError::AlreadyRegistered
+        );
+        assert_eq!(state.own_profile(&alice).fullname, "Alice");
+        assert_eq!(state.ids.len(), 1);
+
+        let anonymous = ctx(Principal::anonymous());
+        assert_eq!(
+            state
+                .create_user(&anonymous, "Anon".into(), String::new(), "trainee".into())
+                .unwrap_err(),
+            RegistrationError::AnonymousCaller
+        );
+
+        state.ids.insert("stale".into(), principal(1));
+        state.remove_stale_ids();
+        assert_eq!(state.ids.keys().collect::<Vec<_>>(), vec![&created.id]);
+    }
+
+    #[test]
+    fn created_users_are_found_by_principal_and_id() {
+        let mut state = State::default();
+        let alice = ctx(principal(1));
+
+        let created = state
+            .create_user(&alice, "Alice".into(), "a@ea.dev".into(), "Trainer".into())
+            .unwrap();
+        assert_eq!(created.principal_id, principal(1));
+        assert_eq!(created.role, Roles::TRAINER);
+        assert_eq!(created.roles, vec![Roles::TRAINER]);
+        assert_eq!(state.own_profile(&alice).id, created.id);
+        assert_eq!(
+            state
+                .profile_by_id(&ctx(principal(4)), &created.id)
+                .principal_id,
+            principal(1)
+        );
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected_at_registration() {
+        let mut state = State::default();
+        let alice = ctx(principal(1));
+
+        for role in ["admin", "ADMIN", "", "teacher"] {
+            assert_eq!(
+                state
+                    .create_user(&alice, "Alice".into(), "a@ea.dev".into(), role.into())
+                    .unwrap_err(),
+                RegistrationError::InvalidRole
+            );
+        }
+        assert!(!state.is_registered(&alice));
+    }
+
+    #[test]
+    fn unknown_profiles_default_to_the_caller() {
+        let state = State::default();
+        let stranger = ctx(principal(4));
+
+        assert_eq!(state.own_profile(&stranger).principal_id, principal(4));
+        assert_eq!(
+            state.profile_by_id(&stranger, "missing").principal_id,
+            principal(4)
+        );
+    }
+
+    #[test]
+    fn update_only_touches_registered_profiles() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let stranger = ctx(principal(4));
+
+        let mut profile = state.own_profile(&bob);
+        profile.skills = vec!["rust".into()];
+        state.update_profile(&bob, profile.clone());
+        state.update_profile(&stranger, profile);
+
+        assert_eq!(state.own_profile(&bob).skills, vec!["rust".to_string()]);
+
+        let mut forged = state.own_profile(&bob);
+        forged.token_ids.push((Nat::from(7u64), "course".into()));
+        state.update_profile(&bob, forged);
+        assert!(state.own_profile(&bob).token_ids.is_empty());
+        assert!(!state.profiles.contains_key(&principal(4)));
+    }
+
+    #[test]
+    fn search_matches_names_and_keywords() {
+        let mut state = state();
+        let bob = ctx(principal(2));
+        let mut profile = state.own_profile(&bob);
+        profile.keywords = vec!["Motoko".into()];
+        state.update_profile(&bob, profile);
+
+        assert_eq!(state.search("ali").unwrap().fullname, "Alice");
+        assert_eq!(state.search("motoko").unwrap().fullname, "Bob");
+        assert!(state.search("nobody").is_none());
+    }
+
+    #[test]
+    fn employers_browse_active_freelancers() {
+        let mut state = state();
+        let freelancers = state.freelancers(&ctx(principal(3))).unwrap();
+        assert_eq!(freelancers.len(), 1);
+        assert_eq!(freelancers[0].fullname, "Bob");
+
+        state
+            .profiles
+            .get_mut(&principal(2))
+            .unwrap()
+            .deactivated_at = Some(NOW);
+        assert!(state.freelancers(&ctx(principal(3))).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_employers_browse_freelancers() {
+        let state = state();
+        assert!(state.freelancers(&ctx(principal(1))).is_none());
+        assert!(state.freelancers(&ctx(principal(9))).is_none());
+    }
+}
@@ -0,0 +1,481 @@
+use super::{Context, MemberRole, Roles, State};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub type VerificationStore = BTreeMap<String, VerificationRequest>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum VerificationStatus {
+    #[default]
+    Unverified,
+    // Waiting for an admin to review the submitted documents
+    Pending,
+    Verified,
+    Rejected,
+}
+
+// Organizations are verified as a whole, trainers teaching on their own one by one
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum VerificationSubject {
+    Organization(String),
+    Trainer(Principal),
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum VerificationDecision {
+    Approve,
+    Reject,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct VerificationNote {
+    pub admin: Principal,
+    pub decision: VerificationDecision,
+    pub note: String,
+    pub at: u64,
+}
+
+// A request decided by an admin stays as it is, a rejected subject submits a new one
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub struct VerificationRequest {
+    pub id: String,
+    pub subject: VerificationSubject,
+    // Asset ids of the registration documents, licences and the like
+    pub documents: Vec<String>,
+    pub submitted_by: Principal,
+    pub submitted_at: u64,
+    pub status: VerificationStatus,
+    pub decision: Option<VerificationNote>,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize, PartialEq)]
+pub enum VerificationError {
+    ProfileNotFound,
+    OrganizationNotFound,
+    Unauthorized,
+    NoDocuments,
+    InvalidAsset,
+    AlreadyPending,
+    AlreadyVerified,
+    RequestNotFound,
+    AlreadyDecided,
+}
+
+// ==================================================================================================
+// Verification workflows
+// ==================================================================================================
+
+impl State {
+    // Owners submit for their organization, trainers for themselves
+    pub fn submit_verification(
+        &mut self,
+        ctx: &Context,
+        subject: VerificationSubject,
+        documents: Vec<String>,
+    ) -> Result<VerificationRequest, VerificationError> {
+        self.check_subject(ctx, &subject)?;
+        if documents.is_empty() {
+            return Err(VerificationError::NoDocuments);
+        }
+        match self.verification_status(&subject) {
+            Some(VerificationStatus::Pending) => return Err(VerificationError::AlreadyPending),
+            Some(VerificationStatus::Verified) => return Err(VerificationError::AlreadyVerified),
+            _ => {}
+        }
+
+        let request = VerificationRequest {
+            id: ctx.rng.next_id(),
+            subject,
+            documents,
+            submitted_by: ctx.caller,
+            submitted_at: ctx.now,
+            status: VerificationStatus::Pending,
+            decision: None,
+        };
+        self.set_verification_status(&request.subject, VerificationStatus::Pending);
+        self.verifications
+            .insert(request.id.clone(), request.clone());
+        Ok(request)
+    }
+
+    fn check_subject(
+        &self,
+        ctx: &Context,
+        subject: &VerificationSubject,
+    ) -> Result<(), VerificationError> {
+        match subject {
+            VerificationSubject::Organization(id) => {
+                let organization = self
+                    .organizations
+                    .get(id)
+                    .ok_or(VerificationError::OrganizationNotFound)?;
+                if !organization.allows(&ctx.caller, MemberRole::Owner) {
+                    return Err(VerificationError::Unauthorized);
+                }
+            }
+            VerificationSubject::Trainer(principal) => {
+                let profile = self
+                    .profiles
+                    .get(&ctx.caller)
+                    .ok_or(VerificationError::ProfileNotFound)?;
+                if *principal != ctx.caller || !profile.has_role(&Roles::TRAINER) {
+                    return Err(VerificationError::Unauthorized);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verification_status(&self, subject: &VerificationSubject) -> Option<VerificationStatus> {
+        match subject {
+            VerificationSubject::Organization(id) => self
+                .organizations
+                .get(id)
+                .map(|organization| organization.verification.clone()),
+            VerificationSubject::Trainer(principal) => self
+                .profiles
+                .get(principal)
+                .map(|profile| profile.verification.clone()),
+        }
+    }
+
+    fn set_verification_status(
+        &mut self,
+        subject: &VerificationSubject,
+        status: VerificationStatus,
+    ) {
+        match subject {
+            VerificationSubject::Organization(id) => {
+                if let Some(organization) = self.organizations.get_mut(id) {
+                    organization.verification = status;
+                }
+            }
+            VerificationSubject::Trainer(principal) => {
+                if let Some(profile) = self.profiles.get_mut(principal) {
+                    profile.verification = status;
+                }
+            }
+        }
+    }
+
+    // Pending requests, oldest first
+    pub fn verification_queue(&self) -> Vec<VerificationRequest> {
+        let mut queue: Vec<VerificationRequest> = self
+            .verifications
+            .values()
+            .filter(|request| request.status == VerificationStatus::Pending)
+            .cloned()
+            .collect();
+        queue.sort_by_key(|request| request.submitted_at);
+        queue
+    }
+
+    // Requests submitted by the caller, with the notes of the admins
+    pub fn verification_requests_of(&self, principal: &Principal) -> Vec<VerificationRequest> {
+        self.verifications
+            .values()
+            .filter(|request| request.submitted_by == *principal)
+            .cloned()
+            .collect()
+    }
+
+    // Approving or rejecting a pending request, the caller must be an admin
+    pub fn decide_verification(
+        &mut self,
+        ctx: &Context,
+        request_id: &str,
+        decision: VerificationDecision,
+        note: String,
+    ) -> Result<VerificationRequest, VerificationError> {
+        let request = self
+            .verifications
+            .get_mut(request_id)
+            .ok_or(VerificationError::RequestNotFound)?;
+        if request.status != VerificationStatus::Pending {
+            return Err(VerificationError::AlreadyDecided);
+        }
+        request.status = match decision {
+            VerificationDecision::Approve => VerificationStatus::Verified,
+            VerificationDecision::Reject => VerificationStatus::Rejected,
+        };
+        request.decision = Some(VerificationNote {
+            admin: ctx.caller,
+            decision,
+            note,
+            at: ctx.now,
+        });
+        let request = request.clone();
+
+        self.set_verification_status(&request.subject, request.status.clone());
+        self.refresh_verified_badges();
+        Ok(request)
+    }
+
+    // Whether the organization owning a job or course is verified, or its
+    // creator as a trainer when it has no organization
+    pub fn is_verified_owner(&self, organization_id: &Option<String>, creator: &Principal) -> bool {
+        let status = match organization_id {
+            Some(id) => self.verification_status(&VerificationSubject::Organization(id.clone())),
+            None => self.verification_status(&VerificationSubject::Trainer(*creator)),
+        };
+        status == Some(VerificationStatus::Verified)
+    }
+
+    // Certificates of a course are issued in the name of a verified issuer
+    pub fn is_verified_issuer(&self, course_id: &str) -> bool {
+        self.courses
+            .get(course_id)
+            .is_some_and(|course| self.is_verified_owner(&course.organization_id, &course.creator))
+    }
+
+    // Taking the trainer verification away until an admin reviews the trainer
+    // again, requests still waiting for a decision go with it
+    pub(super) fn revoke_trainer_verification(&mut self, principal: &Principal) {
+        let subject = VerificationSubject::Trainer(*principal);
+        self.verifications.retain(|_, request| {
+            request.subject != subject || request.status != VerificationStatus::Pending
+        });
+        self.set_verification_status(&subject, VerificationStatus::Unverified);
+        self.refresh_verified_badges();
+    }
+
+    // Jobs and courses carry the badge of their owner, kept up to date on every decision
+    pub(super) fn refresh_verified_badges(&mut self) {
+        let mut courses = std::mem::take(&mut self.courses);
+        for course in courses.values_mut() {
+            course.verified = self.is_verified_owner(&course.organization_id, &course.creator);
+        }
+        self.courses = courses;
+
+        let mut jobs = std::mem::take(&mut self.jobs);
+        for job in jobs.values_mut() {
+            job.verified = self.is_verified_owner(&job.organization_id, &job.creator);
+        }
+        self.jobs = jobs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::*;
+    use super::super::{OrganizationDetails, OrganizationKind};
+    use super::*;
+
+    fn acme(state: &mut State) -> String {
+        let details = OrganizationDetails {
+            name: "Acme".into(),
+            kind: OrganizationKind::Company,
+            description: String::new(),
+            website: None,
+            location: "Nairobi".into(),
+            logo_id: None,
+        };
+        state
+            .create_organization(&ctx(principal(3)), details)
+            .unwrap()
+            .id
+    }
+
+    fn submit(
+        state: &mut State,
+        caller: u8,
+        subject: VerificationSubject,
+    ) -> Result<VerificationRequest, VerificationError> {
+        state.submit_verification(&ctx(principal(caller)), subject, vec!["licence".into()])
+    }
+
+    #[test]
+    fn only_owners_and_trainers_themselves_submit() {
+        let mut state = state();
+        let acme = acme(&mut state);
+        let organization = VerificationSubject::Organization(acme);
+
+        assert_eq!(
+            submit(&mut state, 1, organization.clone()),
+            Err(VerificationError::Unauthorized)
+        );
+        assert_eq!(
+            submit(&mut state, 2, VerificationSubject::Trainer(principal(2))),
+            Err(VerificationError::Unauthorized)
+        );
+        assert_eq!(
+            state.submit_verification(&ctx(principal(3)), organization.clone(), vec![]),
+            Err(VerificationError::NoDocuments)
+        );
+
+        submit(&mut state, 3, organization.clone()).unwrap();
+        assert_eq!(
+            submit(&mut state, 3, organization),
+            Err(VerificationError::AlreadyPending)
+        );
+        submit(&mut state, 1, VerificationSubject::Trainer(principal(1))).unwrap();
+        assert_eq!(state.verification_queue().len(), 2);
+    }
+
+    #[test]
+    fn decisions_badge_jobs_and_courses() {
+        let mut state = state();
+        let acme = acme(&mut state);
+        let (alice, carol) = (ctx(principal(1)), ctx(principal(3)));
+        let job = state
+            .create_organization_job(&carol, &acme, "Designer".into())
+            .unwrap();
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+        let admin = ctx(principal(9));
+
+        let request = submit(
+            &mut state,
+            3,
+            VerificationSubject::Organization(acme.clone()),
+        )
+        .unwrap();
+        let rejected = state
+            .decide_verification(
+                &admin,
+                &request.id,
+                VerificationDecision::Reject,
+                "Blurry scan".into(),
+            )
+            .unwrap();
+        assert_eq!(rejected.status, VerificationStatus::Rejected);
+        assert_eq!(rejected.decision.unwrap().note, "Blurry scan");
+        assert_eq!(
+            state.decide_verification(
+                &admin,
+                &request.id,
+                VerificationDecision::Approve,
+                String::new()
+            ),
+            Err(VerificationError::AlreadyDecided)
+        );
+        assert!(!state.jobs[&job.id].verified);
+
+        let request = submit(
+            &mut state,
+            3,
+            VerificationSubject::Organization(acme.clone()),
+        )
+        .unwrap();
+        state
+            .decide_verification(
+                &admin,
+                &request.id,
+                VerificationDecision::Approve,
+                String::new(),
+            )
+            .unwrap();
+        assert_eq!(
+            state.organizations[&acme].verification,
+            VerificationStatus::Verified
+        );
+        assert!(state.jobs[&job.id].verified);
+        assert!(!state.courses[&course.id].verified);
+        assert!(!state.is_verified_issuer(&course.id));
+
+        let request = submit(&mut state, 1, VerificationSubject::Trainer(principal(1))).unwrap();
+        state
+            .decide_verification(
+                &admin,
+                &request.id,
+                VerificationDecision::Approve,
+                String::new(),
+            )
+            .unwrap();
+        assert!(state.courses[&course.id].verified);
+        assert!(state.is_verified_issuer(&course.id));
+        // Later courses get the badge from the start
+        assert!(state.create_course(&alice, "Wasm".into()).unwrap().verified);
+        assert_eq!(
+            submit(&mut state, 1, VerificationSubject::Trainer(principal(1))),
+            Err(VerificationError::AlreadyVerified)
+        );
+    }
+
+    #[test]
+    fn renaming_a_verified_organization_takes_the_badge_away() {
+        let mut state = state();
+        let acme = acme(&mut state);
+        let carol = ctx(principal(3));
+        let job = state
+            .create_organization_job(&carol, &acme, "Designer".into())
+            .unwrap();
+        let request = submit(
+            &mut state,
+            3,
+            VerificationSubject::Organization(acme.clone()),
+        )
+        .unwrap();
+        state
+            .decide_verification(
+                &ctx(principal(9)),
+                &request.id,
+                VerificationDecision::Approve,
+                String::new(),
+            )
+            .unwrap();
+
+        let mut details = state.organizations[&acme].details.clone();
+        details.location = "Mombasa".into();
+        let updated = state
+            .update_organization(&carol, &acme, details.clone())
+            .unwrap();
+        assert_eq!(updated.verification, VerificationStatus::Verified);
+        assert!(state.jobs[&job.id].verified);
+
+        details.name = "Acme Holdings".into();
+        let renamed = state.update_organization(&carol, &acme, details).unwrap();
+        assert_eq!(renamed.verification, VerificationStatus::Unverified);
+        assert!(!state.jobs[&job.id].verified);
+        submit(&mut state, 3, VerificationSubject::Organization(acme)).unwrap();
+    }
+
+    #[test]
+    fn renamed_trainers_and_former_trainers_lose_the_badge() {
+        let mut state = state();
+        let alice = ctx(principal(1));
+        let course = state.create_course(&alice, "Rust".into()).unwrap();
+        let approve = |state: &mut State| {
+            let request = submit(state, 1, VerificationSubject::Trainer(principal(1))).unwrap();
+            state
+                .decide_verification(
+                    &ctx(principal(9)),
+                    &request.id,
+                    VerificationDecision::Approve,
+                    String::new(),
+                )
+                .unwrap();
+        };
+        approve(&mut state);
+
+        let mut profile = state.profiles[&principal(1)].clone();
+        profile.description = "Teaching Rust".into();
+        state.update_profile(&alice, profile.clone());
+        assert!(state.courses[&course.id].verified);
+
+        profile.fullname = "Alice Smith".into();
+        state.update_profile(&alice, profile);
+        assert_eq!(
+            state.profiles[&principal(1)].verification,
+            VerificationStatus::Unverified
+        );
+        assert!(!state.courses[&course.id].verified);
+
+        approve(&mut state);
+        assert!(state.courses[&course.id].verified);
+        state.add_role(&alice, Roles::EMPLOYER).unwrap();
+        let profile = state.remove_role(&alice, Roles::TRAINER).unwrap();
+        assert_eq!(profile.verification, VerificationStatus::Unverified);
+        assert!(!state.courses[&course.id].verified);
+
+        // Requests still waiting for a decision go as well
+        state.add_role(&alice, Roles::TRAINER).unwrap();
+        submit(&mut state, 1, VerificationSubject::Trainer(principal(1))).unwrap();
+        state.remove_role(&alice, Roles::TRAINER).unwrap();
+        assert!(state.verification_queue().is_empty());
+    }
+}
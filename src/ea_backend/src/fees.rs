@@ -0,0 +1,131 @@
+use crate::config;
+use crate::icrc7::Account;
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+
+// Certificate fees, taken from the minter on the fee ledger with an ICRC-2
+// `icrc2_transfer_from`. Minters approve the canister on the ledger for the
+// fee beforehand, custodians mint for free.
+
+// The ICP ledger, ryjl3-tyaaa-aaaaa-aaaba-cai
+pub const ICP_LEDGER: Principal = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1]);
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+impl TransferFromError {
+    fn describe(self) -> String {
+        match self {
+            TransferFromError::BadFee { expected_fee } => {
+                format!("The ledger expects a fee of {}", expected_fee)
+            }
+            TransferFromError::BadBurn { min_burn_amount } => {
+                format!("The ledger burns at least {}", min_burn_amount)
+            }
+            TransferFromError::InsufficientFunds { balance } => {
+                format!("The balance of {} does not cover the fee", balance)
+            }
+            TransferFromError::InsufficientAllowance { allowance } => {
+                format!("The allowance of {} does not cover the fee", allowance)
+            }
+            TransferFromError::TooOld => "The transfer is too old".to_string(),
+            TransferFromError::CreatedInFuture { ledger_time } => {
+                format!("The transfer is ahead of the ledger time {}", ledger_time)
+            }
+            TransferFromError::Duplicate { duplicate_of } => {
+                format!("The transfer duplicates {}", duplicate_of)
+            }
+            TransferFromError::TemporarilyUnavailable => {
+                "The ledger is temporarily unavailable".to_string()
+            }
+            TransferFromError::GenericError {
+                error_code,
+                message,
+            } => format!("{}: {}", error_code, message),
+        }
+    }
+}
+
+// ==================================================================================================
+// Charging
+// ==================================================================================================
+
+// The fee for minting `count` certificates, in e8s
+pub fn certificates_fee(fee_e8s: u64, count: u64) -> u64 {
+    fee_e8s.saturating_mul(count)
+}
+
+// Charging `payer` for `count` certificates, nothing is charged while the fee is zero
+pub async fn charge_certificates(payer: Principal, count: u64) -> Result<(), String> {
+    let settings = config::settings();
+    let amount = certificates_fee(settings.certificate_fee_e8s, count);
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: payer,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let result: ic_cdk::api::call::CallResult<(Result<Nat, TransferFromError>,)> =
+        ic_cdk::call(settings.fee_ledger, "icrc2_transfer_from", (args,)).await;
+    match result {
+        Ok((Ok(_),)) => Ok(()),
+        Ok((Err(e),)) => Err(e.describe()),
+        Err((code, reason)) => Err(format!("{:?}: {}", code, reason)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fees_default_to_the_icp_ledger() {
+        assert_eq!(ICP_LEDGER.to_text(), "ryjl3-tyaaa-aaaaa-aaaba-cai");
+        assert_eq!(config::PlatformSettings::default().fee_ledger, ICP_LEDGER);
+    }
+
+    #[test]
+    fn fees_scale_with_the_certificates() {
+        assert_eq!(certificates_fee(0, 500), 0);
+        assert_eq!(certificates_fee(10_000, 3), 30_000);
+        assert_eq!(certificates_fee(u64::MAX, 2), u64::MAX);
+    }
+}
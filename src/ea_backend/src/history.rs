@@ -0,0 +1,237 @@
+use crate::certificate::types::{TxEvent, TxOperation};
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::cell::RefCell;
+
+// Every ledger transaction is published to the configured history sinks. The
+// in-canister ICRC-3 block log is always present, a CAP-compatible bucket can
+// be added through `InitArgs.cap`.
+
+pub trait HistorySink {
+    fn publish(&self, event: &TxEvent);
+}
+
+thread_local! {
+    static SINKS: RefCell<Vec<Box<dyn HistorySink>>> = RefCell::new(vec![Box::new(LocalHistory)]);
+}
+
+// Configuring the sinks from the ledger settings, called on init and upgrade
+pub fn configure(cap: Option<Principal>) {
+    let mut sinks: Vec<Box<dyn HistorySink>> = vec![Box::new(LocalHistory)];
+    if let Some(bucket) = cap {
+        sinks.push(Box::new(CapHistory { bucket }));
+    }
+    replace_sinks(sinks);
+}
+
+// Swapping the sinks out entirely, e.g. for an in-memory sink in tests
+pub fn replace_sinks(sinks: Vec<Box<dyn HistorySink>>) {
+    SINKS.with(|s| *s.borrow_mut() = sinks);
+}
+
+pub fn publish(event: &TxEvent) {
+    SINKS.with(|sinks| {
+        for sink in sinks.borrow().iter() {
+            sink.publish(event);
+        }
+    });
+}
+
+// ==================================================================================================
+// Local sink
+// ==================================================================================================
+
+// Appends to the certified ICRC-3 block log kept by this canister
+pub struct LocalHistory;
+
+impl HistorySink for LocalHistory {
+    fn publish(&self, event: &TxEvent) {
+        crate::icrc3::append_tx(event);
+    }
+}
+
+// ==================================================================================================
+// CAP sink
+// ==================================================================================================
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum DetailValue {
+    True,
+    False,
+    U64(u64),
+    I64(i64),
+    Float(f64),
+    Text(String),
+    Principal(Principal),
+    Slice(Vec<u8>),
+    Vec(Vec<DetailValue>),
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct IndefiniteEvent {
+    pub caller: Principal,
+    pub operation: String,
+    pub details: Vec<(String, DetailValue)>,
+}
+
+impl From<&TxEvent> for IndefiniteEvent {
+    fn from(event: &TxEvent) -> Self {
+        let operation = match event.operation {
+            TxOperation::Mint => "mint",
+            TxOperation::Transfer => "transfer",
+            TxOperation::TransferFrom => "transferFrom",
+            TxOperation::Approve => "approve",
+            TxOperation::Revoke if event.token_identifier.is_some() => "revoke",
+            TxOperation::ApproveAll | TxOperation::Revoke => "setApprovalForAll",
+            TxOperation::Burn => "burn",
+        };
+
+        let mut details = Vec::new();
+        if let Some(token_identifier) = &event.token_identifier {
+            details.push((
+                "token_identifier".to_string(),
+                DetailValue::Text(token_identifier.0.to_string()),
+            ));
+        }
+        if let Some(from) = event.from {
+            details.push(("from".to_string(), DetailValue::Principal(from)));
+        }
+        if let Some(to) = event.to {
+            details.push(("to".to_string(), DetailValue::Principal(to)));
+        }
+        if event.token_identifier.is_none() {
+            match event.operation {
+                TxOperation::ApproveAll => {
+                    details.push(("is_approved".to_string(), DetailValue::True))
+                }
+                TxOperation::Revoke => {
+                    details.push(("is_approved".to_string(), DetailValue::False))
+                }
+                _ => {}
+            }
+        }
+
+        IndefiniteEvent {
+            caller: event.caller,
+            operation: operation.to_string(),
+            details,
+        }
+    }
+}
+
+// Inserts events into a CAP-compatible root bucket. Delivery is best effort and
+// never fails the ledger operation that produced the event.
+pub struct CapHistory {
+    pub bucket: Principal,
+}
+
+impl HistorySink for CapHistory {
+    fn publish(&self, event: &TxEvent) {
+        let bucket = self.bucket;
+        let event = IndefiniteEvent::from(event);
+        ic_cdk::spawn(async move {
+            let result: ic_cdk::api::call::CallResult<(u64,)> =
+                ic_cdk::call(bucket, "insert", (event,)).await;
+            if let Err((code, message)) = result {
+                ic_cdk::api::print(format!(
+                    "Failed to insert event into CAP bucket {}: {:?} {}",
+                    bucket, code, message
+                ));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::testing::{principal, NOW};
+    use candid::Nat;
+    use std::rc::Rc;
+
+    // Keeps every published event instead of sending it anywhere
+    struct RecordingSink(Rc<RefCell<Vec<TxEvent>>>);
+
+    impl HistorySink for RecordingSink {
+        fn publish(&self, event: &TxEvent) {
+            self.0.borrow_mut().push(event.clone());
+        }
+    }
+
+    fn event(operation: TxOperation, token_identifier: Option<u64>) -> TxEvent {
+        TxEvent {
+            tx_id: Nat::from(0u64),
+            operation,
+            caller: principal(1),
+            from: Some(principal(1)),
+            to: Some(principal(2)),
+            token_identifier: token_identifier.map(Nat::from),
+            timestamp: NOW,
+            from_subaccount: None,
+            to_subaccount: None,
+            spender_subaccount: None,
+            expires_at: None,
+        }
+    }
+
+    fn sink_count() -> usize {
+        SINKS.with(|sinks| sinks.borrow().len())
+    }
+
+    #[test]
+    fn events_go_to_the_configured_sinks_only() {
+        // The block log is always there, a CAP bucket comes on top. Both are
+        // only swapped out here, they certify data and spawn calls on the IC.
+        configure(None);
+        assert_eq!(sink_count(), 1);
+        configure(Some(principal(9)));
+        assert_eq!(sink_count(), 2);
+
+        let events = Rc::new(RefCell::new(vec![]));
+        replace_sinks(vec![Box::new(RecordingSink(events.clone()))]);
+        publish(&event(TxOperation::Mint, Some(0)));
+        publish(&event(TxOperation::Transfer, Some(0)));
+
+        let operations: Vec<TxOperation> = events
+            .borrow()
+            .iter()
+            .map(|event| event.operation.clone())
+            .collect();
+        assert_eq!(operations, vec![TxOperation::Mint, TxOperation::Transfer]);
+    }
+
+    #[test]
+    fn events_are_translated_to_cap_operations() {
+        let transfer = IndefiniteEvent::from(&event(TxOperation::Transfer, Some(7)));
+        assert_eq!(transfer.caller, principal(1));
+        assert_eq!(transfer.operation, "transfer");
+        assert_eq!(
+            transfer.details,
+            vec![
+                (
+                    "token_identifier".to_string(),
+                    DetailValue::Text("7".to_string())
+                ),
+                ("from".to_string(), DetailValue::Principal(principal(1))),
+                ("to".to_string(), DetailValue::Principal(principal(2))),
+            ]
+        );
+
+        // Collection wide approvals are reported as `setApprovalForAll`
+        let approve_all = IndefiniteEvent::from(&event(TxOperation::ApproveAll, None));
+        assert_eq!(approve_all.operation, "setApprovalForAll");
+        assert_eq!(
+            approve_all.details.last(),
+            Some(&("is_approved".to_string(), DetailValue::True))
+        );
+        let revoke_all = IndefiniteEvent::from(&event(TxOperation::Revoke, None));
+        assert_eq!(revoke_all.operation, "setApprovalForAll");
+        assert_eq!(
+            revoke_all.details.last(),
+            Some(&("is_approved".to_string(), DetailValue::False))
+        );
+        let revoke = IndefiniteEvent::from(&event(TxOperation::Revoke, Some(7)));
+        assert_eq!(revoke.operation, "revoke");
+        assert!(revoke.details.iter().all(|(key, _)| key != "is_approved"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{assets, cv_export};
+use candid::CandidType;
+use ic_cdk_macros::*;
+use serde::Deserialize;
+
+// HTTP gateway of the canister. Assets are served under `/assets/<id>` and
+// rendered CVs under `/cv/<profile id>.html` or `.pdf`. Responses are not
+// certified, so they have to be fetched from the `raw` domain of the canister.
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+// Uploads and rendered CVs are served from the origin of the canister, browsers
+// must neither guess their type nor run scripts in them
+const SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("X-Content-Type-Options", "nosniff"),
+    ("Content-Security-Policy", "sandbox"),
+];
+
+pub(crate) fn response(
+    status_code: u16,
+    headers: Vec<(&str, String)>,
+    body: Vec<u8>,
+) -> HttpResponse {
+    let security_headers = SECURITY_HEADERS
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()));
+    HttpResponse {
+        status_code,
+        headers: headers
+            .into_iter()
+            .chain(security_headers)
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+        body,
+    }
+}
+
+pub(crate) fn not_found() -> HttpResponse {
+    response(404, vec![], b"Not found".to_vec())
+}
+
+// Value of a parameter in the query string of `url`
+pub(crate) fn query_param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// ==================================================================================================
+// HTTP
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method.to_uppercase() != "GET" {
+        return response(405, vec![("Allow", "GET".into())], vec![]);
+    }
+    let path = request.url.split('?').next().unwrap_or_default();
+    if let Some(id) = path.strip_prefix("/assets/") {
+        assets::serve_request(id, &request, ic_cdk::api::time())
+    } else if let Some(file) = path.strip_prefix("/cv/") {
+        cv_export::serve(file)
+    } else {
+        not_found()
+    }
+}
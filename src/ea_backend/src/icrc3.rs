@@ -0,0 +1,949 @@
+use crate::certificate::types::{TokenIdentifier, TxEvent, TxOperation};
+use crate::icrc7::Value;
+use crate::memory::{self, Memory};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, StableLog, Storable};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+// ICRC-3 block log for the certificate ledger. Every ledger transaction is
+// appended as a block holding the hash of its parent, straight to stable
+// memory, so the log can grow past the heap limit and survives upgrades as it
+// is. The log is the only record of the ledger history, the DIP721 history
+// queries read their transactions back from it through an index of the blocks
+// by token and principal. The tip is certified so clients can verify the chain.
+
+// Upper bound for the number of blocks returned by a single `icrc3_get_blocks`
+const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+
+type Hash = [u8; 32];
+
+thread_local! {
+    static BLOCK_LOG: RefCell<BlockLog> = RefCell::new(BlockLog::init(
+        memory::get(memory::BLOCK_LOG_INDEX),
+        memory::get(memory::BLOCK_LOG_DATA),
+        memory::get(memory::BLOCK_INDEX),
+    ));
+}
+
+pub fn with<T, F: FnOnce(&BlockLog) -> T>(f: F) -> T {
+    BLOCK_LOG.with(|log| f(&log.borrow()))
+}
+
+pub(crate) fn with_mut<T, F: FnOnce(&mut BlockLog) -> T>(f: F) -> T {
+    BLOCK_LOG.with(|log| f(&mut log.borrow_mut()))
+}
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+candid::define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+// ==================================================================================================
+// Hashing
+// ==================================================================================================
+
+// Representation independent hash of a value as defined by ICRC-3
+pub fn hash_value(value: &Value) -> Hash {
+    match value {
+        Value::Blob(bytes) => Sha256::digest(bytes).into(),
+        Value::Text(text) => Sha256::digest(text.as_bytes()).into(),
+        Value::Nat(nat) => {
+            let mut buf = Vec::new();
+            nat.encode(&mut buf).expect("Failed to encode nat");
+            Sha256::digest(&buf).into()
+        }
+        Value::Int(int) => {
+            let mut buf = Vec::new();
+            int.encode(&mut buf).expect("Failed to encode int");
+            Sha256::digest(&buf).into()
+        }
+        Value::Array(values) => {
+            let mut hasher = Sha256::new();
+            for value in values {
+                hasher.update(hash_value(value));
+            }
+            hasher.finalize().into()
+        }
+        Value::Map(entries) => {
+            let mut hashes: Vec<(Hash, Hash)> = entries
+                .iter()
+                .map(|(key, value)| (Sha256::digest(key.as_bytes()).into(), hash_value(value)))
+                .collect();
+            hashes.sort();
+            let mut hasher = Sha256::new();
+            for (key, value) in hashes {
+                hasher.update(key);
+                hasher.update(value);
+            }
+            hasher.finalize().into()
+        }
+    }
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn leb128(index: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    Nat::from(index)
+        .encode(&mut buf)
+        .expect("Failed to encode nat");
+    buf
+}
+
+// Root hash of the tree `{ last_block_hash: hash, last_block_index: leb128(index) }`
+fn tip_tree_root(index: u64, hash: &Hash) -> Hash {
+    let hash_leaf = domain_hash("ic-hashtree-leaf", &[hash]);
+    let index_leaf = domain_hash("ic-hashtree-leaf", &[&leb128(index)]);
+    let hash_node = domain_hash("ic-hashtree-labeled", &[b"last_block_hash", &hash_leaf]);
+    let index_node = domain_hash("ic-hashtree-labeled", &[b"last_block_index", &index_leaf]);
+    domain_hash("ic-hashtree-fork", &[&hash_node, &index_node])
+}
+
+// CBOR encoding of the same tree, as expected in `DataCertificate.hash_tree`
+fn tip_tree_cbor(index: u64, hash: &Hash) -> Vec<u8> {
+    use serde_cbor::Value as Cbor;
+
+    let labeled = |label: &[u8], value: Vec<u8>| {
+        Cbor::Array(vec![
+            Cbor::Integer(2),
+            Cbor::Bytes(label.to_vec()),
+            Cbor::Array(vec![Cbor::Integer(3), Cbor::Bytes(value)]),
+        ])
+    };
+    let tree = Cbor::Array(vec![
+        Cbor::Integer(1),
+        labeled(b"last_block_hash", hash.to_vec()),
+        labeled(b"last_block_index", leb128(index)),
+    ]);
+
+    // Self-describing CBOR tag
+    let mut bytes = vec![0xd9, 0xd9, 0xf7];
+    bytes.extend(serde_cbor::to_vec(&tree).expect("Failed to encode hash tree"));
+    bytes
+}
+
+// ==================================================================================================
+// Block log
+// ==================================================================================================
+
+// Accounts are encoded as `[owner]`, or `[owner, subaccount]` outside the default subaccount
+fn account(owner: Principal, subaccount: Option<&Vec<u8>>) -> Value {
+    let mut parts = vec![Value::Blob(owner.as_slice().to_vec())];
+    if let Some(subaccount) = subaccount {
+        parts.push(Value::Blob(subaccount.clone()));
+    }
+    Value::Array(parts)
+}
+
+fn account_owner(value: &Value) -> Option<Principal> {
+    match value {
+        Value::Array(parts) => match parts.first()? {
+            Value::Blob(owner) => Principal::try_from_slice(owner).ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn account_subaccount(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Array(parts) => match parts.get(1)? {
+            Value::Blob(subaccount) => Some(subaccount.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Building the ICRC-3 block for a ledger transaction
+pub fn block_from_tx(tx: &TxEvent, phash: Option<Hash>) -> Value {
+    let mut fields = Vec::new();
+    if let Some(token_identifier) = &tx.token_identifier {
+        fields.push(("tid".to_string(), Value::Nat(token_identifier.clone())));
+    }
+
+    let btype = match tx.operation {
+        TxOperation::Mint => "7mint",
+        TxOperation::Burn => "7burn",
+        TxOperation::Transfer => "7xfer",
+        TxOperation::TransferFrom => {
+            let spender = account(tx.caller, tx.spender_subaccount.as_ref());
+            fields.push(("spender".to_string(), spender));
+            "37xfer"
+        }
+        TxOperation::Approve => "37approve",
+        TxOperation::ApproveAll => "37approve_coll",
+        TxOperation::Revoke if tx.token_identifier.is_some() => "37revoke",
+        TxOperation::Revoke => "37revoke_coll",
+    };
+    let is_approval = matches!(
+        tx.operation,
+        TxOperation::Approve | TxOperation::ApproveAll | TxOperation::Revoke
+    );
+
+    if let Some(from) = tx.from {
+        let from = account(from, tx.from_subaccount.as_ref());
+        fields.push(("from".to_string(), from));
+    }
+    if let Some(to) = tx.to {
+        let key = if is_approval { "spender" } else { "to" };
+        fields.push((key.to_string(), account(to, tx.to_subaccount.as_ref())));
+    }
+    if let Some(expires_at) = tx.expires_at {
+        fields.push(("exp".to_string(), Value::Nat(Nat::from(expires_at))));
+    }
+
+    let mut block = vec![
+        ("btype".to_string(), Value::Text(btype.to_string())),
+        ("ts".to_string(), Value::Nat(Nat::from(tx.timestamp))),
+        ("tx".to_string(), Value::Map(fields)),
+    ];
+    if let Some(phash) = phash {
+        block.push(("phash".to_string(), Value::Blob(phash.to_vec())));
+    }
+    Value::Map(block)
+}
+
+// Reading a ledger transaction back from its block. Mint blocks don't name the
+// minter, `minter` looks it up from the token.
+pub fn tx_from_block(
+    id: u64,
+    block: &Value,
+    minter: impl Fn(&TokenIdentifier) -> Option<Principal>,
+) -> Option<TxEvent> {
+    let Value::Map(block) = block else {
+        return None;
+    };
+    let field = |fields: &[(String, Value)], key: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+    };
+    let Some(Value::Text(btype)) = field(block, "btype") else {
+        return None;
+    };
+    let Some(Value::Nat(timestamp)) = field(block, "ts") else {
+        return None;
+    };
+    let Some(Value::Map(tx)) = field(block, "tx") else {
+        return None;
+    };
+    let token_identifier = match field(&tx, "tid") {
+        Some(Value::Nat(token_identifier)) => Some(token_identifier),
+        _ => None,
+    };
+    let owner = |key: &str| field(&tx, key).as_ref().and_then(account_owner);
+    let subaccount = |key: &str| field(&tx, key).as_ref().and_then(account_subaccount);
+
+    let operation = match btype.as_str() {
+        "7mint" => TxOperation::Mint,
+        "7burn" => TxOperation::Burn,
+        "7xfer" => TxOperation::Transfer,
+        "37xfer" => TxOperation::TransferFrom,
+        "37approve" => TxOperation::Approve,
+        "37approve_coll" => TxOperation::ApproveAll,
+        "37revoke" | "37revoke_coll" => TxOperation::Revoke,
+        _ => return None,
+    };
+    let from = owner("from");
+    let is_approval = matches!(
+        operation,
+        TxOperation::Approve | TxOperation::ApproveAll | TxOperation::Revoke
+    );
+    let to_key = if is_approval { "spender" } else { "to" };
+    let caller = match operation {
+        TxOperation::TransferFrom => owner("spender"),
+        TxOperation::Mint => token_identifier.as_ref().and_then(&minter),
+        _ => from,
+    };
+    let spender_subaccount = match operation {
+        TxOperation::TransferFrom => subaccount("spender"),
+        _ => None,
+    };
+    let expires_at = match field(&tx, "exp") {
+        Some(Value::Nat(expires_at)) => u64::try_from(&expires_at.0).ok(),
+        _ => None,
+    };
+
+    Some(TxEvent {
+        tx_id: Nat::from(id),
+        operation,
+        caller: caller.unwrap_or_else(Principal::anonymous),
+        from,
+        to: owner(to_key),
+        token_identifier,
+        timestamp: u64::try_from(&timestamp.0).ok()?,
+        from_subaccount: subaccount("from"),
+        to_subaccount: subaccount(to_key),
+        spender_subaccount,
+        expires_at,
+    })
+}
+
+// Key of the block index, the token or principal a block touches followed by
+// the index of the block, so the blocks of a subject are listed in log order
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct IndexKey(Vec<u8>);
+
+// Upper bound for the encoding of a subject, larger token ids are not indexed
+const MAX_SUBJECT_LEN: usize = 56;
+
+fn token_subject(token_identifier: &TokenIdentifier) -> Vec<u8> {
+    // LEB128 is self-delimiting, no token id is a prefix of another one
+    let mut subject = vec![0];
+    token_identifier
+        .encode(&mut subject)
+        .expect("Failed to encode nat");
+    subject
+}
+
+fn principal_subject(principal: &Principal) -> Vec<u8> {
+    let bytes = principal.as_slice();
+    let mut subject = vec![1, bytes.len() as u8];
+    subject.extend_from_slice(bytes);
+    subject
+}
+
+impl IndexKey {
+    fn new(subject: &[u8], block: u64) -> Self {
+        let mut key = subject.to_vec();
+        key.extend(block.to_be_bytes());
+        IndexKey(key)
+    }
+
+    fn block(&self) -> u64 {
+        let (_, block) = self.0.split_at(self.0.len() - 8);
+        u64::from_be_bytes(block.try_into().expect("Invalid index key"))
+    }
+}
+
+impl Storable for IndexKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        IndexKey(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_SUBJECT_LEN as u32 + 8,
+        is_fixed_size: false,
+    };
+}
+
+pub struct BlockLog {
+    blocks: StableLog<Vec<u8>, Memory, Memory>,
+    index: StableBTreeMap<IndexKey, (), Memory>,
+    tip: Option<(u64, Hash)>,
+}
+
+impl BlockLog {
+    // Opening the log kept in stable memory, the tip is the hash of its last block
+    pub fn init(block_index: Memory, block_data: Memory, index: Memory) -> Self {
+        let blocks =
+            StableLog::init(block_index, block_data).expect("Failed to initialize the block log");
+        let mut log = BlockLog {
+            blocks,
+            index: StableBTreeMap::init(index),
+            tip: None,
+        };
+        log.tip = log
+            .len()
+            .checked_sub(1)
+            .and_then(|index| Some((index, hash_value(&log.get(index)?))));
+        log
+    }
+
+    pub fn len(&self) -> u64 {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.len() == 0
+    }
+
+    pub fn tip(&self) -> Option<(u64, Hash)> {
+        self.tip
+    }
+
+    pub fn get(&self, index: u64) -> Option<Value> {
+        self.blocks
+            .get(index)
+            .and_then(|bytes| serde_cbor::from_slice(&bytes).ok())
+    }
+
+    // Appending the block of a transaction, returns its index
+    pub fn append(&mut self, tx: &TxEvent) -> u64 {
+        let block = block_from_tx(tx, self.tip.map(|(_, hash)| hash));
+        let hash = hash_value(&block);
+        let bytes = serde_cbor::to_vec(&block).expect("Failed to serialize block");
+        let index = self.blocks.append(&bytes).expect("Failed to append block");
+        self.index_tx(index, tx);
+        self.tip = Some((index, hash));
+        index
+    }
+
+    fn index_tx(&mut self, index: u64, tx: &TxEvent) {
+        let mut subjects = vec![];
+        subjects.extend(tx.token_identifier.as_ref().map(token_subject));
+        for principal in [Some(tx.caller), tx.from, tx.to].into_iter().flatten() {
+            subjects.push(principal_subject(&principal));
+        }
+        for subject in subjects {
+            if subject.len() <= MAX_SUBJECT_LEN {
+                self.index.insert(IndexKey::new(&subject, index), ());
+            }
+        }
+    }
+
+    // Indexing the blocks appended before the index existed
+    pub fn rebuild_index(&mut self, minter: impl Fn(&TokenIdentifier) -> Option<Principal>) {
+        if !self.index.is_empty() {
+            return;
+        }
+        for index in 0..self.len() {
+            let tx = self
+                .get(index)
+                .and_then(|block| tx_from_block(index, &block, &minter));
+            if let Some(tx) = tx {
+                self.index_tx(index, &tx);
+            }
+        }
+    }
+
+    fn blocks_of(&self, subject: Vec<u8>, offset: u64, limit: u64) -> Vec<u64> {
+        self.index
+            .range(IndexKey::new(&subject, 0)..)
+            .take_while(|(key, _)| key.0.starts_with(&subject))
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(key, _)| key.block())
+            .collect()
+    }
+
+    // Indexes of the blocks touching a token, oldest first
+    pub fn token_blocks(
+        &self,
+        token_identifier: &TokenIdentifier,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<u64> {
+        self.blocks_of(token_subject(token_identifier), offset, limit)
+    }
+
+    // Indexes of the blocks a principal took part in, oldest first
+    pub fn principal_blocks(&self, principal: &Principal, offset: u64, limit: u64) -> Vec<u64> {
+        self.blocks_of(principal_subject(principal), offset, limit)
+    }
+}
+
+pub fn log_length() -> u64 {
+    with(|log| log.len())
+}
+
+// Appending a block for the transaction and certifying the new tip
+pub fn append_tx(tx: &TxEvent) -> u64 {
+    let index = with_mut(|log| log.append(tx));
+    certify_tip();
+    index
+}
+
+pub fn certify_tip() {
+    if let Some((index, hash)) = with(|log| log.tip()) {
+        ic_cdk::api::set_certified_data(&tip_tree_root(index, &hash));
+    }
+}
+
+// Appending blocks for the transactions recorded before the block log existed,
+// and indexing the blocks appended before the index existed
+pub fn post_upgrade(
+    legacy_txs: &[TxEvent],
+    minter: impl Fn(&TokenIdentifier) -> Option<Principal>,
+) {
+    with_mut(|log| {
+        log.rebuild_index(minter);
+        for tx in legacy_txs.iter().skip(log.len() as usize) {
+            log.append(tx);
+        }
+    });
+    certify_tip();
+}
+
+// ==================================================================================================
+// ICRC-3
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+#[query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    let log_length = log_length();
+    let mut remaining = MAX_BLOCKS_PER_RESPONSE;
+    let mut blocks = Vec::new();
+
+    for arg in args {
+        let start = u64::try_from(&arg.start.0).unwrap_or(u64::MAX);
+        let length = u64::try_from(&arg.length.0).unwrap_or(u64::MAX);
+        let end = start.saturating_add(length.min(remaining)).min(log_length);
+        for id in start..end {
+            if let Some(block) = with(|log| log.get(id)) {
+                blocks.push(BlockWithId {
+                    id: Nat::from(id),
+                    block,
+                });
+            }
+        }
+        remaining -= end.saturating_sub(start);
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    // The whole log lives in this canister's stable memory and is served directly
+    GetBlocksResult {
+        log_length: Nat::from(log_length),
+        blocks,
+        archived_blocks: vec![],
+    }
+}
+
+#[query]
+fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    let (index, hash) = with(|log| log.tip())?;
+    Some(DataCertificate {
+        certificate,
+        hash_tree: tip_tree_cbor(index, &hash),
+    })
+}
+
+#[query]
+fn icrc3_get_archives(_args: GetArchivesArgs) -> Vec<ArchiveInfo> {
+    vec![]
+}
+
+#[query]
+fn icrc3_supported_block_types() -> Vec<BlockType> {
+    let icrc7 = "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7";
+    let icrc37 = "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37";
+    [
+        ("7mint", icrc7),
+        ("7burn", icrc7),
+        ("7xfer", icrc7),
+        ("37approve", icrc37),
+        ("37approve_coll", icrc37),
+        ("37revoke", icrc37),
+        ("37revoke_coll", icrc37),
+        ("37xfer", icrc37),
+    ]
+    .into_iter()
+    .map(|(block_type, url)| BlockType {
+        block_type: block_type.to_string(),
+        url: url.to_string(),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::testing::{principal, NOW};
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn hex(hash: Hash) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn open(memory: &DefaultMemoryImpl, index: u8) -> BlockLog {
+        let manager = MemoryManager::init(memory.clone());
+        BlockLog::init(
+            manager.get(MemoryId::new(0)),
+            manager.get(MemoryId::new(1)),
+            manager.get(MemoryId::new(index)),
+        )
+    }
+
+    fn tx(
+        operation: TxOperation,
+        caller: Principal,
+        from: Option<Principal>,
+        to: Option<Principal>,
+        token_identifier: Option<u64>,
+    ) -> TxEvent {
+        TxEvent {
+            tx_id: Nat::from(0u64),
+            operation,
+            caller,
+            from,
+            to,
+            token_identifier: token_identifier.map(Nat::from),
+            timestamp: NOW,
+            from_subaccount: None,
+            to_subaccount: None,
+            spender_subaccount: None,
+            expires_at: None,
+        }
+    }
+
+    // The custodian (9) mints token 0 to Alice (1) who sends it to Bob (2)
+    fn history() -> Vec<TxEvent> {
+        let (custodian, alice, bob) = (principal(9), principal(1), principal(2));
+        vec![
+            tx(TxOperation::Mint, custodian, None, Some(alice), Some(0)),
+            tx(
+                TxOperation::Transfer,
+                alice,
+                Some(alice),
+                Some(bob),
+                Some(0),
+            ),
+            tx(TxOperation::Approve, bob, Some(bob), Some(alice), Some(0)),
+        ]
+    }
+
+    fn phash(block: &Value) -> Option<Vec<u8>> {
+        let Value::Map(fields) = block else {
+            return None;
+        };
+        fields
+            .iter()
+            .find_map(|(key, value)| match (key.as_str(), value) {
+                ("phash", Value::Blob(hash)) => Some(hash.clone()),
+                _ => None,
+            })
+    }
+
+    // Root hash of a CBOR encoded hash tree, computed the way clients verify it
+    fn tree_root(tree: &serde_cbor::Value) -> Hash {
+        use serde_cbor::Value as Cbor;
+
+        let Cbor::Array(node) = tree else {
+            panic!("Unexpected hash tree node");
+        };
+        match &node[..] {
+            [Cbor::Integer(1), left, right] => {
+                domain_hash("ic-hashtree-fork", &[&tree_root(left), &tree_root(right)])
+            }
+            [Cbor::Integer(2), Cbor::Bytes(label), subtree] => {
+                domain_hash("ic-hashtree-labeled", &[label, &tree_root(subtree)])
+            }
+            [Cbor::Integer(3), Cbor::Bytes(value)] => domain_hash("ic-hashtree-leaf", &[value]),
+            _ => panic!("Unexpected hash tree node"),
+        }
+    }
+
+    #[test]
+    fn values_are_hashed_as_in_the_standard() {
+        // Examples from the ICRC-3 specification
+        let cases = [
+            (
+                Value::Nat(Nat::from(42u64)),
+                "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1",
+            ),
+            (
+                Value::Int(candid::Int::from(-42)),
+                "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc",
+            ),
+            (
+                Value::Text("Hello, World!".to_string()),
+                "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f",
+            ),
+            (
+                Value::Blob(vec![1, 2, 3, 4]),
+                "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a",
+            ),
+            (
+                Value::Array(vec![
+                    Value::Nat(Nat::from(3u64)),
+                    Value::Text("foo".to_string()),
+                    Value::Blob(vec![5, 6]),
+                ]),
+                "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6",
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(hex(hash_value(&value)), expected);
+        }
+
+        // Map entries are hashed independently of their order
+        let entries = vec![
+            ("a".to_string(), Value::Nat(Nat::from(1u64))),
+            ("b".to_string(), Value::Text("x".to_string())),
+        ];
+        let reversed = entries.iter().rev().cloned().collect();
+        assert_eq!(
+            hash_value(&Value::Map(entries)),
+            hash_value(&Value::Map(reversed))
+        );
+    }
+
+    #[test]
+    fn blocks_are_chained_to_their_parent() {
+        let mut log = open(&DefaultMemoryImpl::default(), 2);
+        assert!(log.is_empty());
+        for (n, tx) in history().iter().enumerate() {
+            assert_eq!(log.append(tx), n as u64);
+        }
+
+        let blocks: Vec<Value> = (0..log.len()).map(|n| log.get(n).unwrap()).collect();
+        assert_eq!(phash(&blocks[0]), None);
+        for pair in blocks.windows(2) {
+            assert_eq!(phash(&pair[1]), Some(hash_value(&pair[0]).to_vec()));
+        }
+        assert_eq!(log.tip(), Some((2, hash_value(&blocks[2]))));
+        assert!(log.get(3).is_none());
+    }
+
+    #[test]
+    fn the_tip_is_restored_when_the_log_is_reopened() {
+        let memory = DefaultMemoryImpl::default();
+        let mut log = open(&memory, 2);
+        for tx in history() {
+            log.append(&tx);
+        }
+        let tip = log.tip();
+
+        // Like after an upgrade, the log is opened again from stable memory
+        let mut log = open(&memory, 2);
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.tip(), tip);
+        assert_eq!(log.token_blocks(&Nat::from(0u64), 0, 10), vec![0, 1, 2]);
+
+        let next = tx(
+            TxOperation::Burn,
+            principal(2),
+            Some(principal(2)),
+            None,
+            Some(0),
+        );
+        assert_eq!(log.append(&next), 3);
+        assert_eq!(
+            phash(&log.get(3).unwrap()),
+            tip.map(|(_, hash)| hash.to_vec())
+        );
+    }
+
+    #[test]
+    fn blocks_are_indexed_by_token_and_principal() {
+        let mut log = open(&DefaultMemoryImpl::default(), 2);
+        for tx in history() {
+            log.append(&tx);
+        }
+        log.append(&tx(
+            TxOperation::Mint,
+            principal(9),
+            None,
+            Some(principal(2)),
+            Some(1),
+        ));
+
+        assert_eq!(log.token_blocks(&Nat::from(0u64), 0, 10), vec![0, 1, 2]);
+        assert_eq!(log.token_blocks(&Nat::from(0u64), 1, 1), vec![1]);
+        assert_eq!(log.token_blocks(&Nat::from(1u64), 0, 10), vec![3]);
+        assert!(log.token_blocks(&Nat::from(2u64), 0, 10).is_empty());
+        assert_eq!(log.principal_blocks(&principal(2), 0, 10), vec![1, 2, 3]);
+        assert_eq!(log.principal_blocks(&principal(9), 0, 10), vec![0, 3]);
+        assert!(log.principal_blocks(&principal(3), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn a_missing_index_is_rebuilt_from_the_blocks() {
+        let memory = DefaultMemoryImpl::default();
+        let mut log = open(&memory, 2);
+        for tx in history() {
+            log.append(&tx);
+        }
+
+        // A log written before the index existed
+        let mut log = open(&memory, 3);
+        assert!(log.principal_blocks(&principal(1), 0, 10).is_empty());
+        log.rebuild_index(|_| Some(principal(9)));
+        assert_eq!(log.principal_blocks(&principal(1), 0, 10), vec![0, 1, 2]);
+        assert_eq!(log.principal_blocks(&principal(9), 0, 10), vec![0]);
+        assert_eq!(log.token_blocks(&Nat::from(0u64), 0, 10), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn blocks_decode_back_to_their_transactions() {
+        let (custodian, alice, bob) = (principal(9), principal(1), principal(2));
+        let txs = [
+            tx(TxOperation::Mint, custodian, None, Some(alice), Some(0)),
+            tx(
+                TxOperation::Transfer,
+                alice,
+                Some(alice),
+                Some(bob),
+                Some(0),
+            ),
+            tx(
+                TxOperation::TransferFrom,
+                bob,
+                Some(alice),
+                Some(custodian),
+                Some(0),
+            ),
+            tx(TxOperation::Approve, alice, Some(alice), Some(bob), Some(0)),
+            tx(TxOperation::ApproveAll, alice, Some(alice), Some(bob), None),
+            tx(TxOperation::Revoke, alice, Some(alice), Some(bob), Some(0)),
+            tx(TxOperation::Revoke, alice, Some(alice), Some(bob), None),
+            tx(TxOperation::Burn, alice, Some(alice), None, Some(0)),
+        ];
+        for (n, expected) in txs.iter().enumerate() {
+            let block = block_from_tx(expected, None);
+            let decoded = tx_from_block(n as u64, &block, |_| Some(custodian)).unwrap();
+            assert_eq!(decoded.tx_id, Nat::from(n as u64));
+            assert_eq!(decoded.operation, expected.operation);
+            assert_eq!(decoded.caller, expected.caller);
+            assert_eq!(decoded.from, expected.from);
+            assert_eq!(decoded.to, expected.to);
+            assert_eq!(decoded.token_identifier, expected.token_identifier);
+            assert_eq!(decoded.timestamp, expected.timestamp);
+        }
+    }
+
+    #[test]
+    fn blocks_keep_subaccounts_and_approval_expiries() {
+        let (alice, bob) = (principal(1), principal(2));
+        let mut transfer_from = tx(
+            TxOperation::TransferFrom,
+            bob,
+            Some(alice),
+            Some(bob),
+            Some(0),
+        );
+        transfer_from.from_subaccount = Some(vec![1; 32]);
+        transfer_from.to_subaccount = Some(vec![2; 32]);
+        transfer_from.spender_subaccount = Some(vec![3; 32]);
+        let mut approve = tx(TxOperation::Approve, alice, Some(alice), Some(bob), Some(0));
+        approve.from_subaccount = Some(vec![1; 32]);
+        approve.to_subaccount = Some(vec![3; 32]);
+        approve.expires_at = Some(NOW + 60);
+
+        for expected in [transfer_from, approve] {
+            let block = block_from_tx(&expected, None);
+            let decoded = tx_from_block(0, &block, |_| None).unwrap();
+            assert_eq!(decoded.caller, expected.caller);
+            assert_eq!(decoded.from_subaccount, expected.from_subaccount);
+            assert_eq!(decoded.to_subaccount, expected.to_subaccount);
+            assert_eq!(decoded.spender_subaccount, expected.spender_subaccount);
+            assert_eq!(decoded.expires_at, expected.expires_at);
+        }
+    }
+
+    #[test]
+    fn the_certified_tree_hashes_to_the_certified_root() {
+        let hash = hash_value(&Value::Text("tip".to_string()));
+        let cbor = tip_tree_cbor(1234, &hash);
+        assert_eq!(&cbor[..3], &[0xd9, 0xd9, 0xf7]);
+
+        let tree: serde_cbor::Value = serde_cbor::from_slice(&cbor[3..]).unwrap();
+        assert_eq!(tree_root(&tree), tip_tree_root(1234, &hash));
+    }
+
+    #[test]
+    fn blocks_are_served_in_bounded_pages() {
+        with_mut(|log| {
+            for n in 0..MAX_BLOCKS_PER_RESPONSE + 10 {
+                log.append(&tx(
+                    TxOperation::Mint,
+                    principal(9),
+                    None,
+                    Some(principal(1)),
+                    Some(n),
+                ));
+            }
+        });
+        let args = |start: u64, length: u64| GetBlocksArgs {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        };
+        let ids = |result: GetBlocksResult| -> Vec<Nat> {
+            result.blocks.into_iter().map(|block| block.id).collect()
+        };
+
+        let page = icrc3_get_blocks(vec![args(2, 3), args(200, 5)]);
+        assert_eq!(page.log_length, Nat::from(MAX_BLOCKS_PER_RESPONSE + 10));
+        assert_eq!(
+            ids(page),
+            vec![Nat::from(2u64), Nat::from(3u64), Nat::from(4u64)]
+        );
+        assert_eq!(
+            icrc3_get_blocks(vec![args(0, 1_000)]).blocks.len() as u64,
+            MAX_BLOCKS_PER_RESPONSE
+        );
+        assert_eq!(
+            icrc3_get_blocks(vec![args(0, 60), args(60, 60)])
+                .blocks
+                .len() as u64,
+            MAX_BLOCKS_PER_RESPONSE
+        );
+    }
+}
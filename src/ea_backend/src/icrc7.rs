@@ -0,0 +1,1573 @@
+use crate::certificate::ledger::{self, Ledger};
+use crate::certificate::types::{GenericValue, TokenIdentifier, TxDetails, TxOperation};
+use candid::{CandidType, Int, Nat, Principal};
+use ic_cdk::api::{caller, time};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// ICRC-7 and ICRC-37 views over the certificate ledger. Ownership, approvals and
+// history all live in `certificate::ledger`, so DIP721 and ICRC calls observe
+// the same state. Token approvals are the DIP721 operators, collection
+// approvals are kept per owner account and cover every token it holds.
+
+pub const MAX_QUERY_BATCH_SIZE: u64 = 100;
+pub const MAX_UPDATE_BATCH_SIZE: u64 = 20;
+pub const DEFAULT_TAKE_VALUE: u64 = 10;
+pub const MAX_TAKE_VALUE: u64 = 100;
+// The ledger keeps a single operator per token, shared with DIP721, and up to
+// this many collection approvals per account
+pub const MAX_APPROVALS_PER_TOKEN_OR_COLLECTION: u64 = 10;
+pub const MAX_REVOKE_APPROVALS: u64 = 20;
+pub const TX_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+pub const PERMITTED_DRIFT: u64 = 2 * 60 * 1_000_000_000;
+
+pub type Subaccount = Vec<u8>;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SupportedStandard {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type TransferResult = Result<Nat, TransferError>;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApprovalInfo {
+    pub spender: Account,
+    pub from_subaccount: Option<Subaccount>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApproveTokenArg {
+    pub token_id: Nat,
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ApproveTokenError {
+    InvalidSpender,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type ApproveTokenResult = Result<Nat, ApproveTokenError>;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApproveCollectionArg {
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ApproveCollectionError {
+    InvalidSpender,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type ApproveCollectionResult = Result<Nat, ApproveCollectionError>;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RevokeTokenApprovalArg {
+    pub spender: Option<Account>,
+    pub from_subaccount: Option<Subaccount>,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum RevokeTokenApprovalError {
+    ApprovalDoesNotExist,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type RevokeTokenApprovalResult = Result<Nat, RevokeTokenApprovalError>;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RevokeCollectionApprovalArg {
+    pub spender: Option<Account>,
+    pub from_subaccount: Option<Subaccount>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum RevokeCollectionApprovalError {
+    ApprovalDoesNotExist,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type RevokeCollectionApprovalResult = Result<Nat, RevokeCollectionApprovalError>;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct IsApprovedArg {
+    pub spender: Account,
+    pub from_subaccount: Option<Subaccount>,
+    pub token_id: Nat,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TokenApproval {
+    pub token_id: Nat,
+    pub approval_info: ApprovalInfo,
+}
+
+pub type CollectionApproval = ApprovalInfo;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransferFromArg {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    InvalidRecipient,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub type TransferFromResult = Result<Nat, TransferFromError>;
+
+// Outcome of validating `created_at_time` against the transaction window
+enum TimeError {
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+}
+
+impl From<TimeError> for TransferError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => TransferError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                TransferError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+impl From<TimeError> for TransferFromError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => TransferFromError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                TransferFromError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+impl From<TimeError> for ApproveTokenError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => ApproveTokenError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                ApproveTokenError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+impl From<TimeError> for ApproveCollectionError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => ApproveCollectionError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                ApproveCollectionError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+impl From<TimeError> for RevokeTokenApprovalError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => RevokeTokenApprovalError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                RevokeTokenApprovalError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+impl From<TimeError> for RevokeCollectionApprovalError {
+    fn from(e: TimeError) -> Self {
+        match e {
+            TimeError::TooOld => RevokeCollectionApprovalError::TooOld,
+            TimeError::CreatedInFuture { ledger_time } => {
+                RevokeCollectionApprovalError::CreatedInFuture { ledger_time }
+            }
+        }
+    }
+}
+
+// ==================================================================================================
+// Helpers
+// ==================================================================================================
+
+// The default subaccount may be passed as null or as 32 zero bytes
+fn normalize_subaccount(subaccount: Option<&Subaccount>) -> Option<&Subaccount> {
+    subaccount.filter(|subaccount| subaccount.iter().any(|byte| *byte != 0))
+}
+
+// Subaccounts as recorded in the blocks, where the default one is left out
+fn recorded_subaccount(subaccount: Option<&Subaccount>) -> Option<Subaccount> {
+    normalize_subaccount(subaccount).cloned()
+}
+
+fn same_account(a: &Account, b: &Account) -> bool {
+    a.owner == b.owner
+        && normalize_subaccount(a.subaccount.as_ref())
+            == normalize_subaccount(b.subaccount.as_ref())
+}
+
+// `created_at_time` comes from the caller, the sums saturate instead of overflowing
+fn is_outside_window(created_at_time: u64, now: u64) -> bool {
+    created_at_time
+        .saturating_add(TX_WINDOW)
+        .saturating_add(PERMITTED_DRIFT)
+        < now
+}
+
+fn check_created_at_time(created_at_time: Option<u64>, now: u64) -> Result<(), TimeError> {
+    match created_at_time {
+        Some(created_at_time) if is_outside_window(created_at_time, now) => Err(TimeError::TooOld),
+        Some(created_at_time) if created_at_time > now.saturating_add(PERMITTED_DRIFT) => {
+            Err(TimeError::CreatedInFuture { ledger_time: now })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Key of a transaction that opted into deduplication by setting `created_at_time`,
+// the hash covers the method, the caller and the whole argument, memo included
+type TxKey = ([u8; 32], u64);
+
+fn tx_key<T: CandidType>(
+    method: &str,
+    caller: Principal,
+    arg: &T,
+    created_at_time: Option<u64>,
+) -> Option<TxKey> {
+    let created_at_time = created_at_time?;
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(caller.as_slice());
+    hasher.update(candid::encode_one(arg).expect("Failed to encode argument"));
+    Some((hasher.finalize().into(), created_at_time))
+}
+
+// Entries share the `created_at_time` of the argument, so a key that passed
+// the window check never matches an expired entry
+fn duplicate_of(ledger: &Ledger, key: Option<&TxKey>) -> Option<Nat> {
+    let (hash, _) = key?;
+    ledger.recent_txs.get(hash).map(|(tx_id, _)| tx_id.clone())
+}
+
+// Remembering a transaction for the length of the window, dropping the expired ones
+fn remember_tx(ledger: &mut Ledger, key: Option<TxKey>, tx_id: &Nat, now: u64) {
+    ledger
+        .recent_txs
+        .retain(|_, (_, created_at_time)| !is_outside_window(*created_at_time, now));
+    if let Some((hash, created_at_time)) = key {
+        ledger
+            .recent_txs
+            .insert(hash, (tx_id.clone(), created_at_time));
+    }
+}
+
+fn batch_too_large(len: usize) -> Option<String> {
+    (len as u64 > MAX_UPDATE_BATCH_SIZE).then(|| {
+        format!(
+            "batch size exceeds the maximum of {}",
+            MAX_UPDATE_BATCH_SIZE
+        )
+    })
+}
+
+fn transfers_disabled() -> Option<String> {
+    (!crate::config::features().certificate_transfers)
+        .then(|| "certificate transfers are disabled".to_string())
+}
+
+fn nat_to_u64(value: &Nat) -> Option<u64> {
+    u64::try_from(&value.0).ok()
+}
+
+fn is_live_token(ledger: &Ledger, token_id: &TokenIdentifier) -> bool {
+    ledger
+        .token_metadata(token_id)
+        .is_ok_and(|token_metadata| !token_metadata.is_burned)
+}
+
+fn owner_account(ledger: &Ledger, token_id: &TokenIdentifier) -> Option<Account> {
+    ledger
+        .owner_of(token_id)
+        .ok()
+        .flatten()
+        .map(|owner| Account {
+            owner,
+            subaccount: ledger.owner_subaccount(token_id).cloned(),
+        })
+}
+
+fn holds_token(ledger: &Ledger, token_id: &TokenIdentifier, account: &Account) -> bool {
+    owner_account(ledger, token_id).is_some_and(|owner| same_account(&owner, account))
+}
+
+fn same_subaccount(a: Option<&Subaccount>, b: Option<&Subaccount>) -> bool {
+    normalize_subaccount(a) == normalize_subaccount(b)
+}
+
+fn is_expired(approval: &ApprovalInfo, now: u64) -> bool {
+    approval
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+}
+
+// The unexpired collection approvals of an account, one per spender
+fn collection_approvals<'a>(
+    ledger: &'a Ledger,
+    owner: &'a Account,
+    now: u64,
+) -> impl Iterator<Item = &'a ApprovalInfo> {
+    ledger
+        .collection_approvals
+        .get(&owner.owner)
+        .into_iter()
+        .flatten()
+        .filter(move |approval| {
+            same_subaccount(approval.from_subaccount.as_ref(), owner.subaccount.as_ref())
+                && !is_expired(approval, now)
+        })
+}
+
+// A spender may move a token approved to it, or any token of an account that
+// approved it for the whole collection
+pub(crate) fn is_approved_spender(
+    ledger: &Ledger,
+    token_id: &TokenIdentifier,
+    spender: &Account,
+    now: u64,
+) -> bool {
+    ledger.is_approved(token_id, &spender.owner, now)
+        || owner_account(ledger, token_id).is_some_and(|owner| {
+            collection_approvals(ledger, &owner, now)
+                .any(|approval| same_account(&approval.spender, spender))
+        })
+}
+
+fn account_tokens(ledger: &Ledger, account: &Account) -> Vec<TokenIdentifier> {
+    let mut tokens: Vec<TokenIdentifier> = ledger
+        .owner_token_identifiers(&account.owner)
+        .map(|token_identifiers| {
+            token_identifiers
+                .iter()
+                .filter(|token_id| holds_token(ledger, token_id, account))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    tokens.sort();
+    tokens
+}
+
+fn paginate(tokens: Vec<TokenIdentifier>, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let take = take
+        .as_ref()
+        .and_then(nat_to_u64)
+        .unwrap_or(DEFAULT_TAKE_VALUE)
+        .min(MAX_TAKE_VALUE) as usize;
+    tokens
+        .into_iter()
+        .filter(|token_id| prev.as_ref().is_none_or(|prev| token_id > prev))
+        .take(take)
+        .collect()
+}
+
+// Approvals in the order they were given, starting after the one of the
+// spender of `prev`
+fn approvals_page<'a>(
+    approvals: impl Iterator<Item = &'a ApprovalInfo>,
+    prev: Option<ApprovalInfo>,
+    take: Option<Nat>,
+) -> Vec<ApprovalInfo> {
+    let take = take
+        .as_ref()
+        .and_then(nat_to_u64)
+        .unwrap_or(DEFAULT_TAKE_VALUE)
+        .min(MAX_TAKE_VALUE) as usize;
+    let mut approvals = approvals;
+    if let Some(prev) = prev {
+        for approval in approvals.by_ref() {
+            if same_account(&approval.spender, &prev.spender) {
+                break;
+            }
+        }
+    }
+    approvals.take(take).cloned().collect()
+}
+
+fn to_value(value: GenericValue) -> Value {
+    match value {
+        GenericValue::BoolContent(v) => Value::Nat(Nat::from(u64::from(v))),
+        GenericValue::TextContent(v) => Value::Text(v),
+        GenericValue::BlobContent(v) => Value::Blob(v),
+        GenericValue::Principal(v) => Value::Blob(v.as_slice().to_vec()),
+        GenericValue::Nat8Content(v) => Value::Nat(Nat::from(v)),
+        GenericValue::Nat16Content(v) => Value::Nat(Nat::from(v)),
+        GenericValue::Nat32Content(v) => Value::Nat(Nat::from(v)),
+        GenericValue::Nat64Content(v) => Value::Nat(Nat::from(v)),
+        GenericValue::NatContent(v) => Value::Nat(v),
+        GenericValue::Int8Content(v) => Value::Int(Int::from(v)),
+        GenericValue::Int16Content(v) => Value::Int(Int::from(v)),
+        GenericValue::Int32Content(v) => Value::Int(Int::from(v)),
+        GenericValue::Int64Content(v) => Value::Int(Int::from(v)),
+        GenericValue::IntContent(v) => Value::Int(v),
+        GenericValue::FloatContent(v) => Value::Text(v.to_string()),
+        GenericValue::NestedContent(v) => {
+            Value::Map(v.into_iter().map(|(k, v)| (k, to_value(v))).collect())
+        }
+    }
+}
+
+fn token_metadata(ledger: &Ledger, token_id: &TokenIdentifier) -> Option<Vec<(String, Value)>> {
+    let token_metadata = ledger.token_metadata(token_id).ok()?;
+    if token_metadata.is_burned {
+        return None;
+    }
+
+    let mut metadata = Vec::new();
+    for (key, value) in token_metadata.properties.iter() {
+        // Surfacing the certificate fields under the keys wallets look for
+        match (key.as_str(), value) {
+            ("description", GenericValue::TextContent(text)) => {
+                metadata.push(("icrc7:description".to_string(), Value::Text(text.clone())))
+            }
+            ("certificate", GenericValue::TextContent(text)) => {
+                metadata.push(("icrc7:image".to_string(), Value::Text(text.clone())))
+            }
+            _ => {}
+        }
+        metadata.push((key.clone(), to_value(value.clone())));
+    }
+    Some(metadata)
+}
+
+fn total_supply(ledger: &Ledger) -> Nat {
+    Nat::from(
+        ledger
+            .tokens
+            .values()
+            .filter(|token_metadata| !token_metadata.is_burned)
+            .count(),
+    )
+}
+
+// Moving a token between accounts, clearing any approval on it
+fn move_token(
+    ledger: &mut Ledger,
+    caller: Principal,
+    operation: TxOperation,
+    from: &Account,
+    to: &Account,
+    spender_subaccount: Option<&Subaccount>,
+    token_id: &TokenIdentifier,
+) -> Nat {
+    let old_operator = ledger.operator_of(token_id).ok().flatten();
+    ledger.update_owner_cache(token_id, Some(from.owner), Some(to.owner));
+    ledger.update_operator_cache(token_id, old_operator, None);
+    ledger.transfer(caller, token_id, Some(to.owner));
+    ledger.set_owner_subaccount(token_id, to.subaccount.clone());
+    ledger.record_tx_with_details(
+        caller,
+        operation,
+        Some(from.owner),
+        Some(to.owner),
+        Some(token_id.clone()),
+        TxDetails {
+            from_subaccount: recorded_subaccount(from.subaccount.as_ref()),
+            to_subaccount: recorded_subaccount(to.subaccount.as_ref()),
+            spender_subaccount: recorded_subaccount(spender_subaccount),
+            expires_at: None,
+        },
+    )
+}
+
+fn set_spender(
+    ledger: &mut Ledger,
+    caller: Principal,
+    token_id: &TokenIdentifier,
+    spender: Option<Principal>,
+    expires_at: Option<u64>,
+) {
+    let old_operator = ledger.operator_of(token_id).ok().flatten();
+    ledger.update_operator_cache(token_id, old_operator, spender);
+    ledger.approve(caller, token_id, spender);
+    ledger.set_approval_expiry(token_id, expires_at);
+}
+
+fn transfer_token(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: TransferArg,
+) -> TransferResult {
+    check_created_at_time(arg.created_at_time, now)?;
+    let key = tx_key("icrc7_transfer", caller, &arg, arg.created_at_time);
+    if let Some(duplicate_of) = duplicate_of(ledger, key.as_ref()) {
+        return Err(TransferError::Duplicate { duplicate_of });
+    }
+    let from = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    };
+    if !is_live_token(ledger, &arg.token_id) {
+        return Err(TransferError::NonExistingTokenId);
+    }
+    if !holds_token(ledger, &arg.token_id, &from) {
+        return Err(TransferError::Unauthorized);
+    }
+    if arg.to.owner == Principal::anonymous() || same_account(&from, &arg.to) {
+        return Err(TransferError::InvalidRecipient);
+    }
+
+    let tx_id = move_token(
+        ledger,
+        caller,
+        TxOperation::Transfer,
+        &from,
+        &arg.to,
+        None,
+        &arg.token_id,
+    );
+    remember_tx(ledger, key, &tx_id, now);
+    Ok(tx_id)
+}
+
+fn transfer_token_from(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: TransferFromArg,
+) -> TransferFromResult {
+    check_created_at_time(arg.created_at_time, now)?;
+    let key = tx_key("icrc37_transfer_from", caller, &arg, arg.created_at_time);
+    if let Some(duplicate_of) = duplicate_of(ledger, key.as_ref()) {
+        return Err(TransferFromError::Duplicate { duplicate_of });
+    }
+    if !is_live_token(ledger, &arg.token_id) {
+        return Err(TransferFromError::NonExistingTokenId);
+    }
+    let spender = Account {
+        owner: caller,
+        subaccount: arg.spender_subaccount.clone(),
+    };
+    if !holds_token(ledger, &arg.token_id, &arg.from)
+        || !is_approved_spender(ledger, &arg.token_id, &spender, now)
+    {
+        return Err(TransferFromError::Unauthorized);
+    }
+    if arg.to.owner == Principal::anonymous() || same_account(&arg.from, &arg.to) {
+        return Err(TransferFromError::InvalidRecipient);
+    }
+
+    let tx_id = move_token(
+        ledger,
+        caller,
+        TxOperation::TransferFrom,
+        &arg.from,
+        &arg.to,
+        spender.subaccount.as_ref(),
+        &arg.token_id,
+    );
+    remember_tx(ledger, key, &tx_id, now);
+    Ok(tx_id)
+}
+
+fn approve_token(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: ApproveTokenArg,
+) -> ApproveTokenResult {
+    let created_at_time = arg.approval_info.created_at_time;
+    check_created_at_time(Some(created_at_time), now)?;
+    let key = tx_key("icrc37_approve_tokens", caller, &arg, Some(created_at_time));
+    if let Some(duplicate_of) = duplicate_of(ledger, key.as_ref()) {
+        return Err(ApproveTokenError::Duplicate { duplicate_of });
+    }
+    let approval_info = arg.approval_info;
+    if approval_info.spender.owner == caller {
+        return Err(ApproveTokenError::InvalidSpender);
+    }
+    if !is_live_token(ledger, &arg.token_id) {
+        return Err(ApproveTokenError::NonExistingTokenId);
+    }
+    let owner = Account {
+        owner: caller,
+        subaccount: approval_info.from_subaccount,
+    };
+    if !holds_token(ledger, &arg.token_id, &owner) {
+        return Err(ApproveTokenError::Unauthorized);
+    }
+
+    let spender = approval_info.spender.owner;
+    set_spender(
+        ledger,
+        caller,
+        &arg.token_id,
+        Some(spender),
+        approval_info.expires_at,
+    );
+    let details = TxDetails {
+        from_subaccount: recorded_subaccount(owner.subaccount.as_ref()),
+        to_subaccount: recorded_subaccount(approval_info.spender.subaccount.as_ref()),
+        spender_subaccount: None,
+        expires_at: approval_info.expires_at,
+    };
+    let tx_id = ledger.record_tx_with_details(
+        caller,
+        TxOperation::Approve,
+        Some(caller),
+        Some(spender),
+        Some(arg.token_id),
+        details,
+    );
+    remember_tx(ledger, key, &tx_id, now);
+    Ok(tx_id)
+}
+
+fn approve_collection(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: ApproveCollectionArg,
+) -> ApproveCollectionResult {
+    let created_at_time = arg.approval_info.created_at_time;
+    check_created_at_time(Some(created_at_time), now)?;
+    let key = tx_key(
+        "icrc37_approve_collection",
+        caller,
+        &arg,
+        Some(created_at_time),
+    );
+    if let Some(duplicate_of) = duplicate_of(ledger, key.as_ref()) {
+        return Err(ApproveCollectionError::Duplicate { duplicate_of });
+    }
+    let approval_info = arg.approval_info;
+    if approval_info.spender.owner == caller {
+        return Err(ApproveCollectionError::InvalidSpender);
+    }
+
+    // Approvals are kept per subaccount and spender, approving a spender again
+    // replaces its approval and leaves the other spenders as they are. The map
+    // is only touched once the approval is accepted
+    let spender = approval_info.spender.owner;
+    let kept = |approval: &ApprovalInfo| {
+        let replaced = same_subaccount(
+            approval.from_subaccount.as_ref(),
+            approval_info.from_subaccount.as_ref(),
+        ) && same_account(&approval.spender, &approval_info.spender);
+        !replaced && !is_expired(approval, now)
+    };
+    let given = ledger
+        .collection_approvals
+        .get(&caller)
+        .into_iter()
+        .flatten()
+        .filter(|approval| {
+            kept(approval)
+                && same_subaccount(
+                    approval.from_subaccount.as_ref(),
+                    approval_info.from_subaccount.as_ref(),
+                )
+        })
+        .count();
+    if given as u64 >= MAX_APPROVALS_PER_TOKEN_OR_COLLECTION {
+        return Err(ApproveCollectionError::GenericError {
+            error_code: Nat::from(0u64),
+            message: format!(
+                "an account can approve at most {} spenders",
+                MAX_APPROVALS_PER_TOKEN_OR_COLLECTION
+            ),
+        });
+    }
+    let details = TxDetails {
+        from_subaccount: recorded_subaccount(approval_info.from_subaccount.as_ref()),
+        to_subaccount: recorded_subaccount(approval_info.spender.subaccount.as_ref()),
+        spender_subaccount: None,
+        expires_at: approval_info.expires_at,
+    };
+    let approvals = ledger.collection_approvals.entry(caller).or_default();
+    approvals.retain(kept);
+    approvals.push(approval_info);
+    let tx_id = ledger.record_tx_with_details(
+        caller,
+        TxOperation::ApproveAll,
+        Some(caller),
+        Some(spender),
+        None,
+        details,
+    );
+    remember_tx(ledger, key, &tx_id, now);
+    Ok(tx_id)
+}
+
+fn revoke_token_approval(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: RevokeTokenApprovalArg,
+) -> RevokeTokenApprovalResult {
+    check_created_at_time(arg.created_at_time, now)?;
+    if !is_live_token(ledger, &arg.token_id) {
+        return Err(RevokeTokenApprovalError::NonExistingTokenId);
+    }
+    let owner = Account {
+        owner: caller,
+        subaccount: arg.from_subaccount,
+    };
+    if !holds_token(ledger, &arg.token_id, &owner) {
+        return Err(RevokeTokenApprovalError::Unauthorized);
+    }
+
+    let operator = ledger.operator_of(&arg.token_id).ok().flatten();
+    let matches = match (&operator, &arg.spender) {
+        (Some(operator), Some(spender)) => *operator == spender.owner,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    if !matches {
+        return Err(RevokeTokenApprovalError::ApprovalDoesNotExist);
+    }
+
+    let details = TxDetails {
+        from_subaccount: recorded_subaccount(owner.subaccount.as_ref()),
+        to_subaccount: arg
+            .spender
+            .as_ref()
+            .and_then(|spender| recorded_subaccount(spender.subaccount.as_ref())),
+        ..TxDetails::default()
+    };
+    set_spender(ledger, caller, &arg.token_id, None, None);
+    Ok(ledger.record_tx_with_details(
+        caller,
+        TxOperation::Revoke,
+        Some(caller),
+        operator,
+        Some(arg.token_id),
+        details,
+    ))
+}
+
+fn revoke_collection_approval(
+    ledger: &mut Ledger,
+    caller: Principal,
+    now: u64,
+    arg: RevokeCollectionApprovalArg,
+) -> RevokeCollectionApprovalResult {
+    check_created_at_time(arg.created_at_time, now)?;
+    let Some(approvals) = ledger.collection_approvals.get_mut(&caller) else {
+        return Err(RevokeCollectionApprovalError::ApprovalDoesNotExist);
+    };
+    let count = approvals.len();
+    approvals.retain(|approval| {
+        !(same_subaccount(
+            approval.from_subaccount.as_ref(),
+            arg.from_subaccount.as_ref(),
+        ) && arg
+            .spender
+            .as_ref()
+            .is_none_or(|spender| same_account(&approval.spender, spender)))
+    });
+    let revoked = approvals.len() < count;
+    if approvals.is_empty() {
+        ledger.collection_approvals.remove(&caller);
+    }
+    if !revoked {
+        return Err(RevokeCollectionApprovalError::ApprovalDoesNotExist);
+    }
+
+    let details = TxDetails {
+        from_subaccount: recorded_subaccount(arg.from_subaccount.as_ref()),
+        to_subaccount: arg
+            .spender
+            .as_ref()
+            .and_then(|spender| recorded_subaccount(spender.subaccount.as_ref())),
+        ..TxDetails::default()
+    };
+    let spender = arg.spender.map(|spender| spender.owner);
+    Ok(ledger.record_tx_with_details(
+        caller,
+        TxOperation::Revoke,
+        Some(caller),
+        spender,
+        None,
+        details,
+    ))
+}
+
+// ==================================================================================================
+// ICRC-10
+// ==================================================================================================
+
+#[query]
+fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    vec![
+        SupportedStandard {
+            name: "ICRC-3".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-7".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10".to_string(),
+        },
+        SupportedStandard {
+            name: "ICRC-37".to_string(),
+            url: "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37".to_string(),
+        },
+    ]
+}
+
+// ==================================================================================================
+// ICRC-7
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+#[query]
+fn icrc7_collection_metadata() -> Vec<(String, Value)> {
+    ledger::with(|ledger| {
+        let metadata = ledger.metadata();
+        let mut collection_metadata = Vec::new();
+        if let Some(name) = &metadata.name {
+            collection_metadata.push(("icrc7:name".to_string(), Value::Text(name.clone())));
+        }
+        if let Some(symbol) = &metadata.symbol {
+            collection_metadata.push(("icrc7:symbol".to_string(), Value::Text(symbol.clone())));
+        }
+        if let Some(logo) = &metadata.logo {
+            collection_metadata.push(("icrc7:logo".to_string(), Value::Text(logo.clone())));
+        }
+        collection_metadata.extend([
+            (
+                "icrc7:total_supply".to_string(),
+                Value::Nat(total_supply(ledger)),
+            ),
+            (
+                "icrc7:max_query_batch_size".to_string(),
+                Value::Nat(Nat::from(MAX_QUERY_BATCH_SIZE)),
+            ),
+            (
+                "icrc7:max_update_batch_size".to_string(),
+                Value::Nat(Nat::from(MAX_UPDATE_BATCH_SIZE)),
+            ),
+            (
+                "icrc7:default_take_value".to_string(),
+                Value::Nat(Nat::from(DEFAULT_TAKE_VALUE)),
+            ),
+            (
+                "icrc7:max_take_value".to_string(),
+                Value::Nat(Nat::from(MAX_TAKE_VALUE)),
+            ),
+            (
+                "icrc7:tx_window".to_string(),
+                Value::Nat(Nat::from(TX_WINDOW)),
+            ),
+            (
+                "icrc7:permitted_drift".to_string(),
+                Value::Nat(Nat::from(PERMITTED_DRIFT)),
+            ),
+        ]);
+        collection_metadata
+    })
+}
+
+#[query]
+fn icrc7_name() -> String {
+    ledger::with(|ledger| ledger.metadata().name.clone().unwrap_or_default())
+}
+
+#[query]
+fn icrc7_symbol() -> String {
+    ledger::with(|ledger| ledger.metadata().symbol.clone().unwrap_or_default())
+}
+
+#[query]
+fn icrc7_logo() -> Option<String> {
+    ledger::with(|ledger| ledger.metadata().logo.clone())
+}
+
+#[query]
+fn icrc7_total_supply() -> Nat {
+    ledger::with(total_supply)
+}
+
+#[query]
+fn icrc7_supply_cap() -> Option<Nat> {
+    None
+}
+
+#[query]
+fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_QUERY_BATCH_SIZE))
+}
+
+#[query]
+fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_UPDATE_BATCH_SIZE))
+}
+
+#[query]
+fn icrc7_default_take_value() -> Option<Nat> {
+    Some(Nat::from(DEFAULT_TAKE_VALUE))
+}
+
+#[query]
+fn icrc7_max_take_value() -> Option<Nat> {
+    Some(Nat::from(MAX_TAKE_VALUE))
+}
+
+#[query]
+fn icrc7_tx_window() -> Option<Nat> {
+    Some(Nat::from(TX_WINDOW))
+}
+
+#[query]
+fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(Nat::from(PERMITTED_DRIFT))
+}
+
+#[query]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, Value)>>> {
+    ledger::with(|ledger| {
+        token_ids
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|token_id| token_metadata(ledger, token_id))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    ledger::with(|ledger| {
+        token_ids
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|token_id| owner_account(ledger, token_id))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    ledger::with(|ledger| {
+        accounts
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|account| Nat::from(account_tokens(ledger, account).len()))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    ledger::with(|ledger| {
+        let mut tokens: Vec<TokenIdentifier> = ledger
+            .tokens
+            .values()
+            .filter(|token_metadata| !token_metadata.is_burned)
+            .map(|token_metadata| token_metadata.token_identifier.clone())
+            .collect();
+        tokens.sort();
+        paginate(tokens, prev, take)
+    })
+}
+
+#[query]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    ledger::with(|ledger| paginate(account_tokens(ledger, &account), prev, take))
+}
+
+// // ======================
+// //      UPDATE CALLS
+// // ======================
+
+#[update]
+fn icrc7_transfer(args: Vec<TransferArg>) -> Vec<Option<TransferResult>> {
+    if let Some(message) = batch_too_large(args.len()) {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message,
+        }))];
+    }
+    if let Some(message) = transfers_disabled() {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(1u64),
+            message,
+        }))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(transfer_token(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+// ==================================================================================================
+// ICRC-37
+// ==================================================================================================
+
+// // ======================
+// //      QUERY CALLS
+// // ======================
+
+#[query]
+fn icrc37_max_approvals_per_token_or_collection() -> Option<Nat> {
+    Some(Nat::from(MAX_APPROVALS_PER_TOKEN_OR_COLLECTION))
+}
+
+#[query]
+fn icrc37_max_revoke_approvals() -> Option<Nat> {
+    Some(Nat::from(MAX_REVOKE_APPROVALS))
+}
+
+#[query]
+fn icrc37_is_approved(args: Vec<IsApprovedArg>) -> Vec<bool> {
+    let now = time();
+    ledger::with(|ledger| {
+        args.iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|arg| {
+                let owner_subaccount = ledger.owner_subaccount(&arg.token_id);
+                same_subaccount(owner_subaccount, arg.from_subaccount.as_ref())
+                    && is_approved_spender(ledger, &arg.token_id, &arg.spender, now)
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn icrc37_get_token_approvals(
+    token_id: Nat,
+    prev: Option<TokenApproval>,
+    _take: Option<Nat>,
+) -> Vec<TokenApproval> {
+    // A token has at most one approval, so there is never a second page
+    if prev.is_some() {
+        return vec![];
+    }
+
+    let now = time();
+    ledger::with(|ledger| {
+        let Ok(token_metadata) = ledger.token_metadata(&token_id) else {
+            return vec![];
+        };
+        match token_metadata.operator {
+            Some(operator) if ledger.is_approved(&token_id, &operator, now) => {
+                vec![TokenApproval {
+                    token_id: token_id.clone(),
+                    approval_info: ApprovalInfo {
+                        spender: Account {
+                            owner: operator,
+                            subaccount: None,
+                        },
+                        from_subaccount: ledger.owner_subaccount(&token_id).cloned(),
+                        expires_at: ledger.approval_expiries.get(&token_id).copied(),
+                        memo: None,
+                        created_at_time: token_metadata.approved_at.unwrap_or_default(),
+                    },
+                }]
+            }
+            _ => vec![],
+        }
+    })
+}
+
+#[query]
+fn icrc37_get_collection_approvals(
+    owner: Account,
+    prev: Option<CollectionApproval>,
+    take: Option<Nat>,
+) -> Vec<CollectionApproval> {
+    let now = time();
+    ledger::with(|ledger| approvals_page(collection_approvals(ledger, &owner, now), prev, take))
+}
+
+// // ======================
+// //      UPDATE CALLS
+// // ======================
+
+#[update]
+fn icrc37_approve_tokens(args: Vec<ApproveTokenArg>) -> Vec<Option<ApproveTokenResult>> {
+    if let Some(message) = batch_too_large(args.len()) {
+        return vec![Some(Err(ApproveTokenError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message,
+        }))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(approve_token(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+#[update]
+fn icrc37_approve_collection(
+    args: Vec<ApproveCollectionArg>,
+) -> Vec<Option<ApproveCollectionResult>> {
+    if let Some(message) = batch_too_large(args.len()) {
+        return vec![Some(Err(ApproveCollectionError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message,
+        }))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(approve_collection(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+#[update]
+fn icrc37_revoke_token_approvals(
+    args: Vec<RevokeTokenApprovalArg>,
+) -> Vec<Option<RevokeTokenApprovalResult>> {
+    if args.len() as u64 > MAX_REVOKE_APPROVALS {
+        return vec![Some(Err(RevokeTokenApprovalError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message: format!("batch size exceeds the maximum of {}", MAX_REVOKE_APPROVALS),
+        }))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(revoke_token_approval(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+#[update]
+fn icrc37_revoke_collection_approvals(
+    args: Vec<RevokeCollectionApprovalArg>,
+) -> Vec<Option<RevokeCollectionApprovalResult>> {
+    if args.len() as u64 > MAX_REVOKE_APPROVALS {
+        return vec![Some(Err(
+            RevokeCollectionApprovalError::GenericBatchError {
+                error_code: Nat::from(0u64),
+                message: format!("batch size exceeds the maximum of {}", MAX_REVOKE_APPROVALS),
+            },
+        ))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(revoke_collection_approval(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+#[update]
+fn icrc37_transfer_from(args: Vec<TransferFromArg>) -> Vec<Option<TransferFromResult>> {
+    if let Some(message) = batch_too_large(args.len()) {
+        return vec![Some(Err(TransferFromError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message,
+        }))];
+    }
+    if let Some(message) = transfers_disabled() {
+        return vec![Some(Err(TransferFromError::GenericBatchError {
+            error_code: Nat::from(1u64),
+            message,
+        }))];
+    }
+
+    let caller = caller();
+    let now = time();
+    ledger::with_mut(|ledger| {
+        args.into_iter()
+            .map(|arg| Some(transfer_token_from(ledger, caller, now, arg)))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::testing::token;
+    use crate::domain::testing::{principal, NOW};
+
+    fn account(owner: Principal, subaccount: Option<u8>) -> Account {
+        Account {
+            owner,
+            subaccount: subaccount.map(|byte| vec![byte; 32]),
+        }
+    }
+
+    fn transfer(memo: &[u8], created_at_time: Option<u64>) -> TransferArg {
+        TransferArg {
+            from_subaccount: None,
+            to: account(principal(2), None),
+            token_id: Nat::from(0u64),
+            memo: Some(memo.to_vec()),
+            created_at_time,
+        }
+    }
+
+    fn approval_for(spender: Principal, expires_at: Option<u64>) -> ApprovalInfo {
+        ApprovalInfo {
+            spender: account(spender, None),
+            from_subaccount: Some(vec![0; 32]),
+            expires_at,
+            memo: None,
+            created_at_time: NOW,
+        }
+    }
+
+    // Alice (1) holds token 0 in her default subaccount and token 1 in subaccount 1
+    fn ledger() -> Ledger {
+        let alice = principal(1);
+        let mut ledger = Ledger::default();
+        for id in 0..2u64 {
+            ledger.tokens.insert(Nat::from(id), token(id, alice, alice));
+        }
+        ledger.set_owner_subaccount(&Nat::from(1u64), Some(vec![1; 32]));
+        ledger
+    }
+
+    #[test]
+    fn created_at_time_is_checked_against_the_window() {
+        let oldest = NOW - TX_WINDOW - PERMITTED_DRIFT;
+        assert!(check_created_at_time(None, NOW).is_ok());
+        assert!(check_created_at_time(Some(oldest), NOW).is_ok());
+        assert!(check_created_at_time(Some(NOW + PERMITTED_DRIFT), NOW).is_ok());
+        assert!(matches!(
+            check_created_at_time(Some(oldest - 1), NOW),
+            Err(TimeError::TooOld)
+        ));
+        assert!(matches!(
+            check_created_at_time(Some(NOW + PERMITTED_DRIFT + 1), NOW),
+            Err(TimeError::CreatedInFuture { ledger_time: NOW })
+        ));
+
+        // Times near the end of the range saturate instead of overflowing
+        assert!(matches!(
+            check_created_at_time(Some(u64::MAX), NOW),
+            Err(TimeError::CreatedInFuture { ledger_time: NOW })
+        ));
+        assert!(check_created_at_time(Some(u64::MAX), u64::MAX).is_ok());
+        assert!(!is_outside_window(u64::MAX, NOW));
+    }
+
+    #[test]
+    fn transactions_are_deduplicated_within_the_window() {
+        let (alice, bob) = (principal(1), principal(2));
+        let mut ledger = Ledger::default();
+        let arg = transfer(b"gift", Some(NOW));
+        let key = tx_key("icrc7_transfer", alice, &arg, arg.created_at_time);
+        assert_eq!(duplicate_of(&ledger, key.as_ref()), None);
+
+        remember_tx(&mut ledger, key, &Nat::from(7u64), NOW);
+        assert_eq!(duplicate_of(&ledger, key.as_ref()), Some(Nat::from(7u64)));
+
+        // Another caller, memo, method or time is a new transaction
+        let other_memo = transfer(b"other", Some(NOW));
+        let other_time = transfer(b"gift", Some(NOW + 1));
+        for other in [
+            tx_key("icrc7_transfer", bob, &arg, arg.created_at_time),
+            tx_key("icrc37_transfer_from", alice, &arg, arg.created_at_time),
+            tx_key(
+                "icrc7_transfer",
+                alice,
+                &other_memo,
+                other_memo.created_at_time,
+            ),
+            tx_key(
+                "icrc7_transfer",
+                alice,
+                &other_time,
+                other_time.created_at_time,
+            ),
+        ] {
+            assert_eq!(duplicate_of(&ledger, other.as_ref()), None);
+        }
+        // Without a created_at_time there is nothing to deduplicate on
+        let untimed = transfer(b"gift", None);
+        assert!(tx_key("icrc7_transfer", alice, &untimed, untimed.created_at_time).is_none());
+
+        // Entries are dropped once their created_at_time leaves the window
+        remember_tx(
+            &mut ledger,
+            None,
+            &Nat::from(8u64),
+            NOW + TX_WINDOW + PERMITTED_DRIFT,
+        );
+        assert_eq!(ledger.recent_txs.len(), 1);
+        remember_tx(
+            &mut ledger,
+            None,
+            &Nat::from(9u64),
+            NOW + TX_WINDOW + PERMITTED_DRIFT + 1,
+        );
+        assert!(ledger.recent_txs.is_empty());
+    }
+
+    #[test]
+    fn collection_approvals_cover_the_tokens_of_the_approving_account() {
+        let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+        let mut ledger = ledger();
+        ledger
+            .collection_approvals
+            .insert(alice, vec![approval_for(bob, Some(NOW + 10))]);
+        let (default_token, other_token) = (Nat::from(0u64), Nat::from(1u64));
+
+        // The zero subaccount of the approval is the default one
+        assert!(is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(bob, None),
+            NOW
+        ));
+        assert!(is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(bob, Some(0)),
+            NOW
+        ));
+        assert!(!is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(bob, Some(1)),
+            NOW
+        ));
+        assert!(!is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(carol, None),
+            NOW
+        ));
+        assert!(!is_approved_spender(
+            &ledger,
+            &other_token,
+            &account(bob, None),
+            NOW
+        ));
+        assert!(!is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(bob, None),
+            NOW + 10
+        ));
+        assert!(
+            collection_approvals(&ledger, &account(alice, None), NOW + 10)
+                .next()
+                .is_none()
+        );
+
+        // Token approvals are kept apart and still apply
+        ledger.tokens.get_mut(&other_token).unwrap().operator = Some(carol);
+        assert!(is_approved_spender(
+            &ledger,
+            &other_token,
+            &account(carol, None),
+            NOW
+        ));
+        assert!(!is_approved_spender(
+            &ledger,
+            &default_token,
+            &account(carol, None),
+            NOW
+        ));
+    }
+
+    #[test]
+    fn accounts_approve_several_spenders_for_the_collection() {
+        let (alice, bob, carol, dave) = (principal(1), principal(2), principal(3), principal(4));
+        let mut ledger = ledger();
+        ledger.collection_approvals.insert(
+            alice,
+            vec![
+                approval_for(bob, None),
+                approval_for(carol, None),
+                approval_for(dave, Some(NOW)),
+            ],
+        );
+        let token = Nat::from(0u64);
+        for spender in [bob, carol] {
+            assert!(is_approved_spender(
+                &ledger,
+                &token,
+                &account(spender, None),
+                NOW
+            ));
+        }
+        assert!(!is_approved_spender(
+            &ledger,
+            &token,
+            &account(dave, None),
+            NOW
+        ));
+
+        // Pages skip expired approvals and continue after the previous spender
+        let owner = account(alice, None);
+        let spenders = |prev: Option<ApprovalInfo>, take: Option<u64>| -> Vec<Principal> {
+            approvals_page(
+                collection_approvals(&ledger, &owner, NOW),
+                prev,
+                take.map(Nat::from),
+            )
+            .into_iter()
+            .map(|approval| approval.spender.owner)
+            .collect()
+        };
+        assert_eq!(spenders(None, None), vec![bob, carol]);
+        assert_eq!(spenders(None, Some(1)), vec![bob]);
+        assert_eq!(spenders(Some(approval_for(bob, None)), None), vec![carol]);
+        assert!(spenders(Some(approval_for(carol, None)), None).is_empty());
+    }
+
+    #[test]
+    fn rejected_collection_approvals_leave_the_approvals_as_they_were() {
+        let (alice, bob) = (principal(1), principal(2));
+        let mut ledger = ledger();
+        let approvals: Vec<ApprovalInfo> = (0..MAX_APPROVALS_PER_TOKEN_OR_COLLECTION as u8)
+            .map(|n| approval_for(principal(10 + n), None))
+            .collect();
+        ledger.collection_approvals.insert(alice, approvals);
+
+        let arg = ApproveCollectionArg {
+            approval_info: approval_for(bob, None),
+        };
+        assert!(matches!(
+            approve_collection(&mut ledger, alice, NOW, arg),
+            Err(ApproveCollectionError::GenericError { .. })
+        ));
+        assert_eq!(
+            ledger.collection_approvals[&alice].len() as u64,
+            MAX_APPROVALS_PER_TOKEN_OR_COLLECTION
+        );
+
+        // Revoking without any approval does not add an entry for the caller
+        let arg = RevokeCollectionApprovalArg {
+            spender: None,
+            from_subaccount: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert!(matches!(
+            revoke_collection_approval(&mut ledger, bob, NOW, arg),
+            Err(RevokeCollectionApprovalError::ApprovalDoesNotExist)
+        ));
+        assert!(!ledger.collection_approvals.contains_key(&bob));
+    }
+
+    #[test]
+    fn token_pages_start_after_the_previous_token() {
+        let tokens: Vec<Nat> = (0..20u64).map(Nat::from).collect();
+
+        assert_eq!(
+            paginate(tokens.clone(), None, None).len() as u64,
+            DEFAULT_TAKE_VALUE
+        );
+        assert_eq!(
+            paginate(tokens.clone(), Some(Nat::from(16u64)), None),
+            vec![Nat::from(17u64), Nat::from(18u64), Nat::from(19u64)]
+        );
+        assert_eq!(
+            paginate(tokens, Some(Nat::from(3u64)), Some(Nat::from(2u64))),
+            vec![Nat::from(4u64), Nat::from(5u64)]
+        );
+    }
+}
@@ -1,425 +1,658 @@
+pub mod assets;
+pub mod batch;
 pub mod certificate;
-
-use crate::certificate::mint;
-use candid::{CandidType, Nat, Principal};
+pub mod config;
+pub mod cv_export;
+pub mod domain;
+pub mod fees;
+pub mod history;
+pub mod http;
+pub mod icrc3;
+pub mod icrc7;
+pub mod memory;
+pub mod outbound;
+
+use crate::certificate::{is_canister_custodian, mint};
+use candid::{Nat, Principal};
 use certificate::types::{GenericValue, NftError};
-use ic_cdk::api::call::ManualReply;
-use ic_cdk::api::management_canister::main::raw_rand;
+use config::{is_admin, BackendArgs, BackendInitArgs, BackendUpgradeArgs};
+use domain::{
+    AccountError, AssetScope, CategoryRatings, Context, Course, CourseRatings, CourseReview, Cv,
+    CvDocument, CvError, EmailError, Engagement, JobError, Jobs, MemberRole, Message,
+    MessagingError, ModerationAction, ModerationCase, ModerationQueueEntry, NotificationError,
+    NotificationPage, NotificationPreferences, Organization, OrganizationDetails,
+    OrganizationError, Profile, PublicProfile, RegistrationError, ReviewError, Reviews, RoleError,
+    RoleProfile, Roles, Thread, ThreadScope, ThreadSummary, VerificationDecision,
+    VerificationError, VerificationRequest, VerificationSubject,
+};
 use ic_cdk_macros::*;
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use outbound::OutboundMessage;
 use std::collections::BTreeMap;
-use std::{
-    cell::RefCell,
-    sync::atomic::{AtomicU64, Ordering},
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Types of the endpoints declared in the other modules, `export_candid!` below
+// resolves them from the crate root
+#[allow(unused_imports)]
+use {
+    assets::{Asset, AssetError, AssetLink},
+    batch::{BatchMintError, BatchMintJob},
+    certificate::types::{Metadata, Stats, TokenIdentifier, TokenMetadata, TxEvent},
+    config::PlatformConfig,
+    http::{HttpRequest, HttpResponse},
+    ic_cdk::api::call::ManualReply,
+    ic_cdk::api::management_canister::http_request::{
+        HttpResponse as OutcallResponse, TransformArgs,
+    },
+    icrc3::{
+        ArchiveInfo, BlockType, DataCertificate, GetArchivesArgs, GetBlocksArgs, GetBlocksResult,
+    },
+    icrc7::{
+        Account, ApproveCollectionArg, ApproveCollectionResult, ApproveTokenArg,
+        ApproveTokenResult, CollectionApproval, IsApprovedArg, RevokeCollectionApprovalArg,
+        RevokeCollectionApprovalResult, RevokeTokenApprovalArg, RevokeTokenApprovalResult,
+        SupportedStandard, TokenApproval, TransferArg, TransferFromArg, TransferFromResult,
+        TransferResult, Value,
+    },
+    std::collections::HashSet,
 };
 
-type IdStore = BTreeMap<String, Principal>;
-type ProfileStore = BTreeMap<Principal, Profile>;
-type CourseStore = BTreeMap<String, Course>;
-type JobStore = BTreeMap<String, Jobs>;
-type ReviewStore = BTreeMap<String, Reviews>;
-
-thread_local! {
-    static CHECK_USER_STORE: RefCell<Vec<CheckUser>> = RefCell::default();
-    static PROFILE_STORE: RefCell<ProfileStore> = RefCell::default();
-    static ID_STORE: RefCell<IdStore> = RefCell::default();
-    static COURSE_STORE : RefCell<CourseStore> = RefCell::default();
-    static JOB_STORE : RefCell<JobStore> = RefCell::default();
-    static REVIEW_STORE: RefCell<ReviewStore> = RefCell::default();
-}
+type UpgradeState = (
+    Vec<u8>,
+    Vec<u8>,
+    Vec<u8>,
+    Vec<u8>,
+    Vec<u8>,
+    Vec<u8>,
+    Vec<u8>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+    Option<Vec<u8>>,
+);
 
 // ==================================================================================================
 // Stable Storage
 // ==================================================================================================
 
+// Installing the canister, the same arguments type is used for upgrades
+#[init]
+fn init(args: Option<BackendArgs>) {
+    let args = match args {
+        Some(BackendArgs::Init(args)) => args,
+        Some(BackendArgs::Upgrade(_)) => {
+            ic_cdk::trap("Upgrade arguments can't be used to install the canister")
+        }
+        None => BackendInitArgs::default(),
+    };
+    let installer = ic_cdk::api::caller();
+
+    config::CONFIG.with(|config| config.borrow_mut().apply_init(installer, &args));
+    certificate::ledger::with_mut(|ledger| ledger.init_metadata(installer, args.collection));
+    history::configure(certificate::ledger::with(|ledger| ledger.cap));
+}
+
 //  Making the stable storage upgradeable
 #[pre_upgrade]
 fn pre_upgrade() {
-    // Serializing and saving all the stores
-    let serialized_check_user_store =
-        serde_cbor::to_vec(&CHECK_USER_STORE.with(|store| store.borrow().clone()))
-            .expect("Failed to serialize check_user_store");
-    let serialized_profile_store =
-        serde_cbor::to_vec(&PROFILE_STORE.with(|store| store.borrow().clone()))
-            .expect("Failed to serialize profile_store");
-    let serialized_id_store = serde_cbor::to_vec(&ID_STORE.with(|store| store.borrow().clone()))
-        .expect("Failed to serialize id_store");
-    let serialized_course_store =
-        serde_cbor::to_vec(&COURSE_STORE.with(|store| store.borrow().clone()))
-            .expect("Failed to serialize course_store");
-    let serialized_job_store = serde_cbor::to_vec(&JOB_STORE.with(|store| store.borrow().clone()))
-        .expect("Failed to serialize job_store");
-    let serialized_review_store =
-        serde_cbor::to_vec(&REVIEW_STORE.with(|store| store.borrow().clone()))
-            .expect("Failed to serialize review_store");
-        let serialized_ledger =
+    // Serializing and saving all the stores, the domain state keeps one entry per store
+    let (
+        serialized_profile_store,
+        serialized_id_store,
+        serialized_course_store,
+        serialized_job_store,
+        serialized_review_store,
+        serialized_moderation_store,
+        serialized_course_review_store,
+        serialized_organization_store,
+        serialized_verification_store,
+        serialized_email_code_store,
+        serialized_notification_store,
+        serialized_thread_store,
+    ) = domain::with(|state| {
+        (
+            serde_cbor::to_vec(&state.profiles).expect("Failed to serialize profile_store"),
+            serde_cbor::to_vec(&state.ids).expect("Failed to serialize id_store"),
+            serde_cbor::to_vec(&state.courses).expect("Failed to serialize course_store"),
+            serde_cbor::to_vec(&state.jobs).expect("Failed to serialize job_store"),
+            serde_cbor::to_vec(&state.reviews).expect("Failed to serialize review_store"),
+            serde_cbor::to_vec(&state.moderation).expect("Failed to serialize moderation_store"),
+            serde_cbor::to_vec(&state.course_reviews)
+                .expect("Failed to serialize course_review_store"),
+            serde_cbor::to_vec(&state.organizations)
+                .expect("Failed to serialize organization_store"),
+            serde_cbor::to_vec(&state.verifications)
+                .expect("Failed to serialize verification_store"),
+            serde_cbor::to_vec(&state.email_codes).expect("Failed to serialize email_code_store"),
+            serde_cbor::to_vec(&state.notifications)
+                .expect("Failed to serialize notification_store"),
+            serde_cbor::to_vec(&state.threads).expect("Failed to serialize thread_store"),
+        )
+    });
+    let serialized_ledger =
         serde_cbor::to_vec(&certificate::ledger::LEDGER.with(|store| store.borrow().clone()))
-            .expect("Failed to serialize review_store");
-
-    // Saving the serialized data to stable storage
-    ic_cdk::storage::stable_save((
-        serialized_check_user_store,
+            .expect("Failed to serialize ledger");
+    let serialized_batch_store =
+        serde_cbor::to_vec(&batch::BATCH_STORE.with(|store| store.borrow().clone()))
+            .expect("Failed to serialize batch_store");
+    let serialized_config = serde_cbor::to_vec(&config::with(|config| config.clone()))
+        .expect("Failed to serialize config");
+
+    // Saving the serialized data to its own region of stable memory
+    // The first entry held the check_user store, registrations are now read
+    // from the profile store
+    let state: UpgradeState = (
+        vec![],
         serialized_profile_store,
         serialized_id_store,
         serialized_course_store,
         serialized_job_store,
         serialized_review_store,
-        serialized_ledger
-    ))
-    .expect("Failed to save to stable storage");
+        serialized_ledger,
+        Some(serialized_batch_store),
+        Some(serialized_config),
+        Some(serialized_moderation_store),
+        Some(serialized_course_review_store),
+        Some(serialized_organization_store),
+        Some(serialized_verification_store),
+        Some(serialized_email_code_store),
+        Some(serialized_notification_store),
+        Some(serialized_thread_store),
+    );
+    memory::save_upgrade_state(
+        &candid::encode_args(state).expect("Failed to save to stable storage"),
+    );
 }
 
 #[post_upgrade]
-fn post_upgrade() {
-    // Restoring the serializing data from stable storage
+fn post_upgrade(args: Option<BackendArgs>) {
+    let args = match args {
+        Some(BackendArgs::Upgrade(args)) => args.unwrap_or_default(),
+        Some(BackendArgs::Init(_)) => {
+            ic_cdk::trap("Init arguments can't be used to upgrade the canister")
+        }
+        None => BackendUpgradeArgs::default(),
+    };
+
+    // Restoring the serializing data from stable storage, canisters upgraded from
+    // a release without the memory manager still hold a `stable_save` snapshot
+    let restored: Result<UpgradeState, String> = if memory::is_legacy_layout() {
+        ic_cdk::storage::stable_restore()
+    } else {
+        candid::decode_args(&memory::read_upgrade_state()).map_err(|e| e.to_string())
+    };
     let (
-        serialized_check_user_store,
+        _,
         serialized_profile_store,
         serialized_id_store,
         serialized_course_store,
         serialized_job_store,
         serialized_review_store,
         serialized_ledger,
-    ): (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>) =
-        match ic_cdk::storage::stable_restore() {
-            Ok(data) => data,
-            Err(e) => {
-                ic_cdk::api::print(format!("Failed to restore from stable storage: {:?}", e));
-                return;
-            }
-        };
+        serialized_batch_store,
+        serialized_config,
+        serialized_moderation_store,
+        serialized_course_review_store,
+        serialized_organization_store,
+        serialized_verification_store,
+        serialized_email_code_store,
+        serialized_notification_store,
+        serialized_thread_store,
+    ) = match restored {
+        Ok(data) => data,
+        // Trapping rolls the upgrade back, carrying on would leave every store empty
+        Err(e) => ic_cdk::trap(&format!("Failed to restore from stable storage: {:?}", e)),
+    };
 
     // Deserializing the data and populating the stores
-    let check_user_store: Vec<CheckUser> =
-        serde_cbor::from_slice(&serialized_check_user_store).unwrap_or_else(|_| Vec::new());
-    let profile_store: ProfileStore =
-        serde_cbor::from_slice(&serialized_profile_store).unwrap_or_else(|_| BTreeMap::new());
-    let id_store: IdStore =
-        serde_cbor::from_slice(&serialized_id_store).unwrap_or_else(|_| BTreeMap::new());
-    let course_store: CourseStore =
-        serde_cbor::from_slice(&serialized_course_store).unwrap_or_else(|_| BTreeMap::new());
-    let job_store: JobStore =
-        serde_cbor::from_slice(&serialized_job_store).unwrap_or_else(|_| BTreeMap::new());
-    let review_store: ReviewStore =
-        serde_cbor::from_slice(&serialized_review_store).unwrap_or_else(|_| BTreeMap::new());
-    let ledger_store: certificate::ledger::Ledger =
-                serde_cbor::from_slice(&serialized_ledger).unwrap_or_else(|_| Default::default());
-
-    CHECK_USER_STORE.with(|store| *store.borrow_mut() = check_user_store);
-    PROFILE_STORE.with(|store| *store.borrow_mut() = profile_store);
-    ID_STORE.with(|store| *store.borrow_mut() = id_store);
-    COURSE_STORE.with(|store| *store.borrow_mut() = course_store);
-    JOB_STORE.with(|store| *store.borrow_mut() = job_store);
-    REVIEW_STORE.with(|store| *store.borrow_mut() = review_store);
+    let profiles: domain::ProfileStore =
+        serde_cbor::from_slice(&serialized_profile_store).unwrap_or_default();
+    let ids: domain::IdStore = serde_cbor::from_slice(&serialized_id_store).unwrap_or_default();
+    let courses: domain::CourseStore =
+        serde_cbor::from_slice(&serialized_course_store).unwrap_or_default();
+    let jobs: domain::JobStore = serde_cbor::from_slice(&serialized_job_store).unwrap_or_default();
+    let reviews: domain::ReviewStore =
+        serde_cbor::from_slice(&serialized_review_store).unwrap_or_default();
+    // Older releases also kept a copy of every review in the profile store
+    let legacy_reviews: BTreeMap<Principal, domain::LegacyProfileReviews> =
+        serde_cbor::from_slice(&serialized_profile_store).unwrap_or_default();
+    let legacy_resumes: BTreeMap<Principal, domain::LegacyProfileResume> =
+        serde_cbor::from_slice(&serialized_profile_store).unwrap_or_default();
+    let mut ledger_store: certificate::ledger::Ledger =
+        serde_cbor::from_slice(&serialized_ledger).unwrap_or_else(|_| Default::default());
+    ledger_store.rebuild_owner_cache();
+    // Older releases also kept a copy of every transaction in the ledger
+    let legacy_history: certificate::ledger::LegacyLedgerHistory =
+        serde_cbor::from_slice(&serialized_ledger).unwrap_or_default();
+    // Stores added after the first release are optional so older snapshots still restore
+    let moderation: domain::ModerationStore = serialized_moderation_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let course_reviews: domain::CourseReviewStore = serialized_course_review_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let organizations: domain::OrganizationStore = serialized_organization_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let verifications: domain::VerificationStore = serialized_verification_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let email_codes: domain::EmailCodeStore = serialized_email_code_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let notifications: domain::NotificationStore = serialized_notification_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let threads: domain::ThreadStore = serialized_thread_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let batch_store: batch::BatchStore = serialized_batch_store
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    let mut config_store: config::PlatformConfig = serialized_config
+        .and_then(|data| serde_cbor::from_slice(&data).ok())
+        .unwrap_or_default();
+    // Releases without a platform config had no admins, the custodians take over
+    if config_store.admins.is_empty() {
+        config_store.admins = ledger_store.metadata().custodians.iter().copied().collect();
+    }
+
+    // Applying the upgrade arguments on top of the restored state
+    config_store.apply_upgrade(ic_cdk::api::caller(), &args);
+    ledger_store.upgrade_metadata(args.collection);
+    history::configure(ledger_store.cap);
+    icrc3::post_upgrade(&legacy_history.tx_log, |token_identifier| {
+        ledger_store.minter(token_identifier)
+    });
+
+    // Token ids are handed out sequentially, continuing after the highest one minted so far
+    let next_token_id = ledger_store
+        .tokens
+        .keys()
+        .filter_map(|token_identifier| u64::try_from(&token_identifier.0).ok())
+        .max()
+        .map_or(0, |token_identifier| token_identifier + 1);
+    COUNTER.store(next_token_id, Ordering::SeqCst);
+
+    let mut state = domain::State {
+        profiles,
+        ids,
+        courses,
+        jobs,
+        reviews,
+        moderation,
+        course_reviews,
+        organizations,
+        verifications,
+        email_codes,
+        notifications,
+        threads,
+        ..Default::default()
+    };
+    state.remove_stale_ids();
+    state.migrate_single_roles();
+    state.migrate_profile_reviews(legacy_reviews);
+    assets::migrate_profile_resumes(legacy_resumes, &mut state.profiles, ic_cdk::api::time());
+    // Assets referenced before their records were noted on them
+    assets::with_mut(|store| {
+        for (id, scope) in state.asset_scopes() {
+            store.make_private(&id, scope);
+        }
+    });
+    // Review indexes and rating aggregates are derived from the review store
+    state.rebuild_review_indexes();
+    state.rebuild_ratings();
+
+    domain::STATE.with(|store| *store.borrow_mut() = state);
     certificate::ledger::LEDGER.with(|store| *store.borrow_mut() = ledger_store);
+    batch::BATCH_STORE.with(|store| *store.borrow_mut() = batch_store);
+    config::CONFIG.with(|store| *store.borrow_mut() = config_store);
 }
 
 // ==================================================================================================
-// Structs
+// User Functions
 // ==================================================================================================
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct Reviews {
-    pub ratings: f64,
-    pub title: String,
-    pub review: String,
-    pub image: Option<String>,
-    pub reviewer: String,
-    pub review_time: i64,
-    pub reviewer_principal: Option<Principal>,
-}
-
-impl Default for Reviews {
-    fn default() -> Self {
-        Self {
-            ratings: Default::default(),
-            title: Default::default(),
-            review: Default::default(),
-            image: Default::default(),
-            reviewer: Default::default(),
-            review_time: Default::default(),
-            reviewer_principal: Default::default(),
-        }
-    }
+// Check if the caller has registered a profile
+#[query]
+fn check_user() -> bool {
+    let ctx = Context::current();
+    domain::with(|state| state.is_registered(&ctx))
 }
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct Profile {
-    pub id: String,
-    pub principal_id: Principal,
-    pub fullname: String,
-    pub email: String,
-    pub occupation: String,
-    pub organization: String,
-    pub location: String,
-    pub resume: Vec<u8>,
-    pub role: Roles,
-    pub description: String,
-    pub keywords: Vec<String>,
-    pub skills: Vec<String>,
-    pub token_ids: Vec<(Nat, String)>,
-    pub reviews: Vec<Reviews>,
-}
-
-impl Default for Profile {
-    fn default() -> Self {
-        Self {
-            id: Default::default(),
-            principal_id: ic_cdk::api::caller(),
-            fullname: Default::default(),
-            email: Default::default(),
-            occupation: Default::default(),
-            organization: Default::default(),
-            location: Default::default(),
-            resume: Default::default(),
-            role: Roles::ADMIN,
-            description: Default::default(),
-            keywords: Default::default(),
-            skills: Default::default(),
-            token_ids: Default::default(),
-            reviews: Default::default(),
-        }
-    }
+// Create a new profile, once per principal
+#[update]
+async fn create_user(
+    fullname: String,
+    email: String,
+    role: String,
+) -> Result<Profile, RegistrationError> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.create_user(&ctx, fullname, email, role))
 }
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct Course {
-    pub id: String,
-    pub title: String,
-    pub creator: Principal,
-    pub creator_fullname: String,
-    pub applicants: Vec<Principal>,
-}
-impl Default for Course {
-    fn default() -> Self {
-        Self {
-            id: Default::default(),
-            title: Default::default(),
-            creator: ic_cdk::api::caller(),
-            creator_fullname: Default::default(),
-            applicants: Default::default(),
-        }
-    }
+// Get full name of the user
+#[query]
+fn get_full_name() -> String {
+    let caller = ic_cdk::api::caller();
+    domain::with(|state| {
+        state
+            .profiles
+            .get(&caller)
+            .map(|profile| profile.fullname.clone())
+            .unwrap_or_default()
+    })
 }
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct Jobs {
-    pub id: String,
-    pub title: String,
-    pub creator: Principal,
-    pub creator_fullname: String,
-    pub applicants: Vec<Principal>,
-}
-impl Default for Jobs {
-    fn default() -> Self {
-        Self {
-            id: Default::default(),
-            title: Default::default(),
-            creator: ic_cdk::api::caller(),
-            creator_fullname: Default::default(),
-            applicants: Default::default(),
-        }
-    }
+// Get the active role of the user
+#[query]
+fn get_role() -> String {
+    let caller = ic_cdk::api::caller();
+    domain::with(|state| {
+        state
+            .profiles
+            .get(&caller)
+            .map(|profile| format!("{:?}", profile.role))
+            .unwrap_or_default()
+    })
+}
+
+// Adding a role to the caller, admins are appointed through the platform config
+#[update]
+fn add_role(role: Roles) -> Result<Profile, RoleError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.add_role(&ctx, role))
 }
 
-#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
-struct CheckUser {
-    pub user: Principal,
+#[update]
+fn remove_role(role: Roles) -> Result<Profile, RoleError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.remove_role(&ctx, role))
 }
-impl Default for CheckUser {
-    fn default() -> Self {
-        Self {
-            user: ic_cdk::api::caller(),
+
+// Choosing which of its roles the caller acts in
+#[update]
+fn switch_role(role: Roles) -> Result<Profile, RoleError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.switch_role(&ctx, role))
+}
+
+// Freelancer rates, employer company details or trainer bio, for a role the caller holds
+#[update]
+fn update_role_profile(role_profile: RoleProfile) -> Result<Profile, RoleError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.update_role_profile(&ctx, role_profile))
+}
+
+// Getting user profile from caller
+#[query]
+fn get_self() -> Profile {
+    let ctx = Context::current();
+    domain::with(|state| state.own_profile(&ctx))
+}
+
+// Getting user profile from user id, as far as its privacy settings let the caller see it
+#[query]
+fn get(uid: String) -> PublicProfile {
+    let ctx = Context::current();
+    domain::with(|state| state.public_profile(&ctx.caller, &state.profile_by_id(&ctx, &uid)))
+}
+
+// The whole profile, for its owner and the employers the owner applied to,
+// without the fields it keeps private
+#[query]
+fn get_full_profile(uid: String) -> Option<Profile> {
+    let ctx = Context::current();
+    domain::with(|state| state.full_profile(&ctx, &uid))
+}
+
+// updating, the resume has to be an asset uploaded by the caller
+#[update]
+fn update(profile: Profile) {
+    let ctx = Context::current();
+    if let Some(resume_id) = &profile.resume_id {
+        if !assets::with(|store| store.is_owned_by(resume_id, &ctx.caller)) {
+            ic_cdk::trap("The resume is not an asset of the caller");
         }
     }
+    let resume_id = profile.resume_id.clone();
+    domain::with_mut(|state| state.update_profile(&ctx, profile));
+    if let Some(resume_id) = resume_id {
+        assets::with_mut(|store| store.make_private(&resume_id, AssetScope::Resume(ctx.caller)));
+    }
 }
 
-// Role enum
-#[derive(CandidType, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
-pub enum Roles {
-    FREELANCER,
-    #[default]
-    EMPLOYER,
-    TRAINER,
-    ADMIN,
-}
-impl Roles {
-    pub fn from_str(el: &str) -> Roles {
-        match el.to_lowercase().as_str() {
-            "trainee" => Roles::FREELANCER,
-            "employer" => Roles::EMPLOYER,
-            "trainer" => Roles::TRAINER,
-            _ => Roles::ADMIN,
-        }
+// Replacing the structured CV of the caller
+#[update]
+fn update_cv(cv: Cv) -> Result<Cv, CvError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.update_cv(&ctx, cv))
+}
+
+// CV of a profile with the courses and jobs done on the platform, also
+// downloadable from `/cv/<uid>.html` and `/cv/<uid>.pdf`
+#[query]
+fn get_cv(uid: String) -> Option<CvDocument> {
+    let ctx = Context::current();
+    domain::with(|state| state.cv_document(&ctx.caller, &uid))
+}
+
+// Searching
+#[query]
+fn search(text: String) -> Option<PublicProfile> {
+    let ctx = Context::current();
+    domain::with(|state| {
+        let profile = state.search(&text)?;
+        Some(state.public_profile(&ctx.caller, profile))
+    })
+}
+
+// Getting all freelancers
+#[query]
+fn get_all_freelancers() -> Vec<PublicProfile> {
+    let ctx = Context::current();
+    domain::with(|state| {
+        state
+            .freelancers(&ctx)
+            .map(|freelancers| state.public_profiles(&ctx, freelancers))
+            .unwrap_or_default()
+    })
+}
+
+// Freelancers sorted by their smoothed rating
+#[query]
+fn get_top_rated_freelancers(limit: u32) -> Vec<PublicProfile> {
+    let ctx = Context::current();
+    domain::with(|state| state.public_profiles(&ctx, state.top_rated_freelancers(limit as usize)))
+}
+
+// ==================================================================================================
+// Email verification
+// ==================================================================================================
+
+// Sending a one-time code to the email of the caller, returns when the code expires
+#[update]
+async fn request_email_verification() -> Result<u64, EmailError> {
+    let ctx = Context::with_randomness().await;
+    let code = domain::with_mut(|state| state.issue_email_code(&ctx))?;
+    let message = OutboundMessage {
+        id: ctx.rng.next_id(),
+        to: code.email,
+        subject: "Your verification code".to_string(),
+        body: format!(
+            "Your verification code is {}. It expires in {} minutes.",
+            code.code,
+            domain::EMAIL_CODE_TTL / 60_000_000_000
+        ),
+    };
+    if let Err(error) = outbound::send(message).await {
+        // A code that never arrived can't be confirmed either
+        domain::with_mut(|state| state.discard_email_code(&ctx.caller, &code.code));
+        return Err(EmailError::DeliveryFailed(error));
     }
+    Ok(code.expires_at)
+}
+
+#[update]
+fn confirm_email(code: String) -> Result<Profile, EmailError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.confirm_email(&ctx, &code))
 }
 
 // ==================================================================================================
-// User Functions
+// Account lifecycle
 // ==================================================================================================
 
-// Check if the principal exists in the vector
+// Hiding the profile of the caller from searches and listings
 #[update]
-async fn check_user() -> bool {
-    let user = ic_cdk::api::caller();
-    let exists = CHECK_USER_STORE.with(|check_user_store| {
-        check_user_store
-            .borrow()
-            .iter()
-            .any(|check_user| check_user.user == user)
-    });
+fn deactivate_account() -> Result<(), AccountError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.deactivate_account(&ctx))
+}
 
-    // If the principal does not exist, store it in the vector
-    if !exists {
-        CHECK_USER_STORE.with(|check_user_store| {
-            check_user_store.borrow_mut().push(CheckUser { user });
-        });
-        false
-    } else {
-        true
-    }
+#[update]
+fn reactivate_account() -> Result<(), AccountError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.reactivate_account(&ctx))
 }
 
-// Create a new profile
-#[update]
-async fn create_user(fullname: String, email: String, role: String) -> Profile {
-    let principal_id = ic_cdk::api::caller();
-
-    let uid = raw_rand().await.unwrap().0;
-    let uid = format!("{:x}", Sha256::digest(&uid));
-    let f = uid.clone();
-    let id = uid.clone().to_string();
-    let m = fullname.clone();
-    let e = email.clone();
-    ID_STORE.with(|el| el.borrow_mut().insert(uid.to_string(), principal_id));
-    PROFILE_STORE.with(|el| {
-        el.borrow_mut().insert(
-            principal_id,
-            Profile {
-                id: f.to_string(),
-                principal_id: principal_id,
-                fullname,
-                email,
-                role: Roles::from_str(&role),
-                ..Default::default()
-            },
-        )
+// Removing the caller and the assets they uploaded. Reviews they wrote stay
+// without their author and the certificates issued to them stay valid, with
+// the artwork they show. The ids of the assets kept as artwork are returned,
+// they stay with the caller.
+#[update]
+async fn delete_account() -> Result<Vec<String>, AccountError> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.delete_account(&ctx))?;
+    let owned = assets::with(|store| store.owned_by(&ctx.caller));
+    let kept = assets::with_mut(|store| {
+        store.delete_owned_by(&ctx, |asset| {
+            certificate::ledger::with(|ledger| ledger.references_asset(&asset.id))
+        })
     });
-
-    Profile {
-        id,
-        principal_id,
-        fullname: m,
-        email: e,
-        role: Roles::from_str(&role),
-        ..Default::default()
-    }
+    let deleted = owned
+        .into_iter()
+        .map(|asset| asset.id)
+        .filter(|id| !kept.contains(id))
+        .collect();
+    domain::with_mut(|state| state.forget_assets(&deleted));
+    Ok(kept)
 }
 
-// Get full name of the user
+// Everything held about the caller, with the assets they uploaded, as one JSON document
 #[query]
-fn get_full_name() -> String {
-    let principal_id = ic_cdk::api::caller();
-    PROFILE_STORE.with(|profile_store| {
-        profile_store
-            .borrow()
-            .get(&principal_id)
-            .map(|profile| profile.fullname.clone())
-            .unwrap_or_default()
-    })
+fn export_my_data() -> String {
+    let ctx = Context::current();
+    let account = domain::with(|state| state.export_account(&ctx));
+    let assets = assets::with(|store| store.owned_by(&ctx.caller));
+    serde_json::json!({ "account": account, "assets": assets }).to_string()
 }
 
-// Get role of the user
+// ==================================================================================================
+// Notifications
+// ==================================================================================================
+
+// A page of the caller's notifications, newest first
 #[query]
-fn get_role() -> String {
-    let principal_id = ic_cdk::api::caller();
-    PROFILE_STORE.with(|profile_store| {
-        profile_store
-            .borrow()
-            .get(&principal_id)
-            .map(|profile| format!("{:?}", profile.role))
-            .unwrap_or_default()
-    })
+fn get_notifications(offset: u64, limit: u64) -> NotificationPage {
+    let ctx = Context::current();
+    domain::with(|state| state.notifications_page(&ctx, offset, limit))
 }
 
-// Getting user profile from caller
 #[query]
-fn get_self() -> Profile {
-    let id = ic_cdk::api::caller();
-    PROFILE_STORE.with(|profile_store| profile_store.borrow().get(&id).cloned().unwrap_or_default())
+fn get_unread_notification_count() -> u64 {
+    let ctx = Context::current();
+    domain::with(|state| state.unread_notifications(&ctx))
+}
+
+// Returns how many notifications are left unread
+#[update]
+fn mark_notifications_read(ids: Vec<u64>) -> u64 {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.mark_notifications_read(&ctx, &ids))
+}
+
+#[update]
+fn mark_all_notifications_read() {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.mark_all_notifications_read(&ctx))
 }
 
-// Getting user profile from user id
 #[query]
-fn get(uid: String) -> Profile {
-    ID_STORE.with(|id_store| {
-        PROFILE_STORE.with(|profile_store| {
-            id_store
-                .borrow()
-                .get(&uid)
-                .and_then(|id| profile_store.borrow().get(id).cloned())
-                .unwrap_or_default()
-        })
-    })
+fn get_notification_preferences() -> NotificationPreferences {
+    let ctx = Context::current();
+    domain::with(|state| state.notification_preferences(&ctx))
 }
 
-// updating
+// Choosing which kinds of notifications the caller receives
 #[update]
-fn update(profile: Profile) {
-    let principal_id = ic_cdk::api::caller();
-
-    PROFILE_STORE.with(|profile_store| {
-        profile_store
-            .borrow_mut()
-            .entry(principal_id)
-            .and_modify(|el| {
-                *el = Profile { ..profile };
-            });
-    });
+fn set_notification_preferences(
+    preferences: NotificationPreferences,
+) -> Result<NotificationPreferences, NotificationError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.set_notification_preferences(&ctx, preferences))
 }
 
-// Searching
-#[query(manual_reply = true)]
-fn search(text: String) -> ManualReply<Option<Profile>> {
-    let text = text.to_lowercase();
-    PROFILE_STORE.with(|profile_store| {
-        for (_, p) in profile_store.borrow().iter() {
-            if p.fullname.to_lowercase().contains(&text)
-                || p.description.to_lowercase().contains(&text)
-            {
-                return ManualReply::one(Some(p));
-            }
+// ==================================================================================================
+// Messaging
+// ==================================================================================================
 
-            for x in p.keywords.iter() {
-                if x.to_lowercase() == text {
-                    return ManualReply::one(Some(p));
-                }
-            }
-        }
-        ManualReply::one(None::<Profile>)
-    })
+// The conversation about a job application or course enrollment, between the
+// applicant or learner and the people managing the job or course
+#[update]
+async fn open_thread(scope: ThreadScope) -> Result<Thread, MessagingError> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.open_thread(&ctx, scope))
 }
 
-// Getting all freelancers
-#[query]
-fn get_all_freelancers() -> Vec<Profile> {
-    let mut freelancers = Vec::new();
-    let principal_id = ic_cdk::api::caller();
-    let mut m: Profile = Default::default();
-    PROFILE_STORE.with(|el| m = el.borrow().get(&principal_id).unwrap().clone());
-    assert!(m.role == Roles::EMPLOYER);
-
-    PROFILE_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for profile in store_borrowed.values() {
-            if profile.role == Roles::ADMIN {
-                freelancers.push(profile.clone());
-            }
+// Attachments are assets uploaded by the sender
+#[update]
+fn send_message(
+    thread_id: String,
+    body: String,
+    attachments: Vec<String>,
+) -> Result<Message, MessagingError> {
+    let ctx = Context::current();
+    if !attachments
+        .iter()
+        .all(|id| assets::with(|store| store.is_owned_by(id, &ctx.caller)))
+    {
+        return Err(MessagingError::InvalidAsset);
+    }
+    let message =
+        domain::with_mut(|state| state.send_message(&ctx, &thread_id, body, attachments))?;
+    assets::with_mut(|store| {
+        for id in &message.attachments {
+            store.make_private(id, AssetScope::Thread(thread_id.clone()));
         }
     });
+    Ok(message)
+}
+
+// Threads of the caller, with their latest message and unread count
+#[query]
+fn get_my_threads() -> Vec<ThreadSummary> {
+    let ctx = Context::current();
+    domain::with(|state| state.threads_of(&ctx.caller))
+}
+
+// Participants read their threads, admins any thread to moderate it
+#[query]
+fn get_thread(thread_id: String) -> Result<Thread, MessagingError> {
+    let ctx = Context::current();
+    let admin = is_admin().is_ok();
+    domain::with(|state| state.thread(&ctx, &thread_id, admin))
+}
 
-    freelancers
+// Recording that the caller read the thread up to its latest message
+#[update]
+fn mark_thread_read(thread_id: String) -> Result<Thread, MessagingError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.mark_thread_read(&ctx, &thread_id))
 }
 
 // ==================================================================================================
@@ -428,154 +661,77 @@ fn get_all_freelancers() -> Vec<Profile> {
 
 // Create a new Course
 #[update]
-async fn create_course(title: String) -> Course {
-    let principal_id = ic_cdk::api::caller();
-    let mut m: Profile = Default::default();
-    PROFILE_STORE.with(|el| m = el.borrow().get(&principal_id).unwrap().clone());
-    assert!(m.role == Roles::TRAINER);
-    let uid = raw_rand().await.unwrap().0;
-    let uid = format!("{:x}", Sha256::digest(&uid));
-
-    // let c = title.clone();
-    let d = uid.clone();
-    let e = uid.clone();
-
-    COURSE_STORE.with(|el| {
-        el.borrow_mut().insert(
-            uid,
-            Course {
-                id: d,
-                title: title.to_string(),
-                creator: principal_id,
-                creator_fullname: m.fullname.to_string(),
-                applicants: vec![],
-            },
-        )
-    });
-
-    Course {
-        id: e,
-        title: title.to_string(),
-        creator: principal_id,
-        creator_fullname: m.fullname.to_string(),
-        applicants: vec![],
-    }
+async fn create_course(title: String) -> Option<Course> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.create_course(&ctx, title))
 }
 
 // Get all the courses by the creator
 #[update]
 fn get_courses_by_creator() -> Vec<Course> {
-    let principal_id = ic_cdk::api::caller();
-    let mut courses_by_creator = Vec::new();
-
-    COURSE_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for course in store_borrowed.values() {
-            if course.creator == principal_id {
-                courses_by_creator.push(course.clone());
-            }
-        }
-    });
-
-    courses_by_creator.reverse();
-
-    courses_by_creator
+    let ctx = Context::current();
+    domain::with(|state| state.courses_by_creator(&ctx))
 }
 
 // Get a specific course by id
 #[query]
-fn get_course(id: String) -> Course {
-    COURSE_STORE.with(|el| el.borrow().get(&id).cloned().unwrap())
+fn get_course(id: String) -> Option<Course> {
+    domain::with(|state| state.course(&id))
 }
 
 // Get all courses
 #[query]
 fn get_all_courses() -> Vec<Course> {
-    let mut courses = Vec::new();
-    COURSE_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for course in store_borrowed.values() {
-            courses.push(course.clone());
-        }
-    });
+    domain::with(|state| state.all_courses())
+}
 
-    courses.reverse();
+// Courses sorted by their smoothed rating
+#[query]
+fn get_top_rated_courses(limit: u32) -> Vec<Course> {
+    domain::with(|state| state.top_rated_courses(limit as usize))
+}
 
-    courses
+// Setting the media of a course, every asset has to be uploaded by the creator
+#[update]
+fn set_course_media(id: String, media: Vec<String>) -> Option<Course> {
+    let ctx = Context::current();
+    let owned = assets::with(|store| {
+        media
+            .iter()
+            .all(|asset_id| store.is_owned_by(asset_id, &ctx.caller))
+    });
+    if !owned {
+        return None;
+    }
+    domain::with_mut(|state| state.set_course_media(&ctx, &id, media))
 }
 
 // Applying for a new course
 #[update]
 fn apply_course(id: String) -> Option<Course> {
-    let principal_id = ic_cdk::api::caller();
-    let mut course_opt = None;
-
-    COURSE_STORE.with(|el| {
-        let mut store = el.borrow_mut();
-        if let Some(course) = store.get_mut(&id) {
-            if !course.applicants.contains(&principal_id) {
-                course.applicants.push(principal_id);
-            }
-            course_opt = Some(course.clone());
-        }
-    });
-
-    course_opt
+    let ctx = Context::current();
+    domain::with_mut(|state| state.apply_course(&ctx, &id))
 }
 
 // Check if the user has applied for the course
 #[query]
 fn check_applied_course(course_id: String) -> bool {
-    let principal_id = ic_cdk::api::caller();
-
-    let result = COURSE_STORE.with(|store| {
-        store
-            .borrow()
-            .get(&course_id)
-            .map_or(false, |course| course.applicants.contains(&principal_id))
-    });
-
-    result
+    let ctx = Context::current();
+    domain::with(|state| state.has_applied_course(&ctx, &course_id))
 }
 
 // Get registered courses for the user
 #[update]
 fn get_courses_registered_by_user() -> Vec<Course> {
-    let principal_id = ic_cdk::api::caller();
-    let mut registered_courses = Vec::new();
-
-    COURSE_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for course in store_borrowed.values() {
-            if course.applicants.contains(&principal_id) {
-                registered_courses.push(course.clone());
-            }
-        }
-    });
-
-    registered_courses.reverse();
-
-    registered_courses
+    let ctx = Context::current();
+    domain::with(|state| state.courses_registered_by(&ctx))
 }
 
 // Getting course registered students
 #[query]
-fn get_course_applicants(course_id: String) -> Vec<Profile> {
-    let mut applicants = Vec::new();
-    COURSE_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        if let Some(course) = store_borrowed.get(&course_id) {
-            for applicant in &course.applicants {
-                if let Some(profile) = PROFILE_STORE
-                    .with(|profile_store| profile_store.borrow().get(applicant).cloned())
-                {
-                    applicants.push(profile);
-                }
-            }
-        }
-    });
-
-    applicants
+fn get_course_applicants(course_id: String) -> Vec<PublicProfile> {
+    let ctx = Context::current();
+    domain::with(|state| state.public_profiles(&ctx, state.course_applicants(&course_id)))
 }
 
 // ==================================================================================================
@@ -584,145 +740,213 @@ fn get_course_applicants(course_id: String) -> Vec<Profile> {
 
 #[update]
 fn apply_jobs(id: String) -> Option<Jobs> {
-    let principal_id = ic_cdk::api::caller();
-    let mut job_opt = None;
-
-    JOB_STORE.with(|el| {
-        let mut store = el.borrow_mut();
-        if let Some(job) = store.get_mut(&id) {
-            if !job.applicants.contains(&principal_id) {
-                job.applicants.push(principal_id);
-            }
-            job_opt = Some(job.clone());
-        }
-    });
+    let ctx = Context::current();
+    domain::with_mut(|state| state.apply_job(&ctx, &id))
+}
 
-    job_opt
+// Hiring an applicant, which lets both sides review each other
+#[update]
+fn hire_applicant(job_id: String, applicant: Principal) -> Result<Jobs, JobError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.hire(&ctx, &job_id, applicant))
 }
 
 // Create a new Job
 #[update]
-async fn create_job(title: String) -> Jobs {
-    let principal_id = ic_cdk::api::caller();
-    let mut m: Profile = Default::default();
-    PROFILE_STORE.with(|el| m = el.borrow().get(&principal_id).unwrap().clone());
-    assert!(m.role == Roles::EMPLOYER);
-    let uid = raw_rand().await.unwrap().0;
-    let uid = format!("{:x}", Sha256::digest(&uid));
-
-    // let c = title.clone();
-    let d = uid.clone();
-    let e = uid.clone();
-
-    JOB_STORE.with(|el| {
-        el.borrow_mut().insert(
-            uid,
-            Jobs {
-                id: d,
-                title: title.to_string(),
-                creator: principal_id,
-                creator_fullname: m.fullname.to_string(),
-                applicants: vec![],
-            },
-        )
-    });
-
-    Jobs {
-        id: e,
-        title: title.to_string(),
-        creator: principal_id,
-        creator_fullname: m.fullname.to_string(),
-        applicants: vec![],
-    }
+async fn create_job(title: String) -> Option<Jobs> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.create_job(&ctx, title))
 }
 
 // Get all jobs
 #[query]
 fn get_all_jobs() -> Vec<Jobs> {
-    let mut jobs = Vec::new();
-    JOB_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for job in store_borrowed.values() {
-            jobs.push(job.clone());
-        }
-    });
-
-    jobs.reverse();
-
-    jobs
+    domain::with(|state| state.all_jobs())
 }
 
 // Get all jobs by the creator
 #[update]
 fn get_jobs_by_creator() -> Vec<Jobs> {
-    let principal_id = ic_cdk::api::caller();
-    let mut jobs_by_creator = Vec::new();
-
-    JOB_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for jobs in store_borrowed.values() {
-            if jobs.creator == principal_id {
-                jobs_by_creator.push(jobs.clone());
-            }
-        }
-    });
-
-    jobs_by_creator.reverse();
-
-    jobs_by_creator
+    let ctx = Context::current();
+    domain::with(|state| state.jobs_by_creator(&ctx))
 }
 
 // Check if the user has applied for the job
 #[query]
 fn check_applied_job(job_id: String) -> bool {
-    let principal_id = ic_cdk::api::caller();
-
-    let result = JOB_STORE.with(|store| {
-        store
-            .borrow()
-            .get(&job_id)
-            .map_or(false, |job| job.applicants.contains(&principal_id))
-    });
-
-    result
+    let ctx = Context::current();
+    domain::with(|state| state.has_applied_job(&ctx, &job_id))
 }
 
 // Get job count for the user
 #[query]
 fn get_jobs_applied_count() -> u32 {
-    let principal_id = ic_cdk::api::caller();
-    let mut jobs_applied_count = 0;
-
-    JOB_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        for job in store_borrowed.values() {
-            if job.applicants.contains(&principal_id) {
-                jobs_applied_count += 1;
-            }
+    let ctx = Context::current();
+    domain::with(|state| state.jobs_applied_count(&ctx))
+}
+
+// Getting job applicants
+#[query]
+fn get_job_applicants(job_id: String) -> Vec<PublicProfile> {
+    let ctx = Context::current();
+    domain::with(|state| state.public_profiles(&ctx, state.job_applicants(&job_id)))
+}
+
+// ==================================================================================================
+// Organizations
+// ==================================================================================================
+
+// The logo has to be an asset uploaded by the caller
+fn check_logo(ctx: &Context, details: &OrganizationDetails) -> Result<(), OrganizationError> {
+    match &details.logo_id {
+        Some(logo_id) if !assets::with(|store| store.is_owned_by(logo_id, &ctx.caller)) => {
+            Err(OrganizationError::InvalidAsset)
         }
-    });
+        _ => Ok(()),
+    }
+}
 
-    jobs_applied_count
+// Creating a company or training provider owned by the caller
+#[update]
+async fn create_organization(
+    details: OrganizationDetails,
+) -> Result<Organization, OrganizationError> {
+    let ctx = Context::with_randomness().await;
+    check_logo(&ctx, &details)?;
+    domain::with_mut(|state| state.create_organization(&ctx, details))
+}
+
+#[update]
+fn update_organization(
+    id: String,
+    details: OrganizationDetails,
+) -> Result<Organization, OrganizationError> {
+    let ctx = Context::current();
+    check_logo(&ctx, &details)?;
+    domain::with_mut(|state| state.update_organization(&ctx, &id, details))
+}
+
+// Adding a member or changing its role, owners only
+#[update]
+fn set_organization_member(
+    id: String,
+    member: Principal,
+    role: MemberRole,
+) -> Result<Organization, OrganizationError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.set_organization_member(&ctx, &id, member, role))
+}
+
+#[update]
+fn remove_organization_member(
+    id: String,
+    member: Principal,
+) -> Result<Organization, OrganizationError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.remove_organization_member(&ctx, &id, member))
 }
 
-// Getting job applicants
 #[query]
-fn get_job_applicants(job_id: String) -> Vec<Profile> {
-    let mut applicants = Vec::new();
-    JOB_STORE.with(|store| {
-        let store_borrowed = store.borrow();
-        if let Some(job) = store_borrowed.get(&job_id) {
-            for applicant in &job.applicants {
-                if let Some(profile) = PROFILE_STORE
-                    .with(|profile_store| profile_store.borrow().get(applicant).cloned())
-                {
-                    applicants.push(profile);
-                }
-            }
+fn get_organization(id: String) -> Option<Organization> {
+    domain::with(|state| state.organization(&id))
+}
+
+// Organizations the caller is a member of
+#[query]
+fn get_my_organizations() -> Vec<Organization> {
+    let ctx = Context::current();
+    domain::with(|state| state.organizations_of(&ctx.caller))
+}
+
+// Posting a job owned by an organization, for its owners and recruiters
+#[update]
+async fn create_organization_job(
+    organization_id: String,
+    title: String,
+) -> Result<Jobs, OrganizationError> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.create_organization_job(&ctx, &organization_id, title))
+}
+
+// Creating a course owned by an organization, for its owners and instructors
+#[update]
+async fn create_organization_course(
+    organization_id: String,
+    title: String,
+) -> Result<Course, OrganizationError> {
+    let ctx = Context::with_randomness().await;
+    domain::with_mut(|state| state.create_organization_course(&ctx, &organization_id, title))
+}
+
+#[query]
+fn get_organization_jobs(organization_id: String) -> Vec<Jobs> {
+    domain::with(|state| state.organization_jobs(&organization_id))
+}
+
+#[query]
+fn get_organization_courses(organization_id: String) -> Vec<Course> {
+    domain::with(|state| state.organization_courses(&organization_id))
+}
+
+// ==================================================================================================
+// Verification
+// ==================================================================================================
+
+// Submitting documents for an organization owned by the caller, or for the
+// caller as a trainer. Documents are assets uploaded by the caller.
+#[update]
+async fn submit_verification(
+    subject: VerificationSubject,
+    documents: Vec<String>,
+) -> Result<VerificationRequest, VerificationError> {
+    let ctx = Context::with_randomness().await;
+    if !documents
+        .iter()
+        .all(|id| assets::with(|store| store.is_owned_by(id, &ctx.caller)))
+    {
+        return Err(VerificationError::InvalidAsset);
+    }
+    let request = domain::with_mut(|state| state.submit_verification(&ctx, subject, documents))?;
+    assets::with_mut(|store| {
+        for id in &request.documents {
+            store.make_private(id, AssetScope::Verification(request.id.clone()));
         }
     });
+    Ok(request)
+}
+
+#[query]
+fn get_my_verification_requests() -> Vec<VerificationRequest> {
+    let ctx = Context::current();
+    domain::with(|state| state.verification_requests_of(&ctx.caller))
+}
+
+// Requests waiting for a decision
+#[query(guard = "is_admin")]
+fn get_verification_queue() -> Vec<VerificationRequest> {
+    domain::with(|state| state.verification_queue())
+}
+
+#[update(guard = "is_admin")]
+fn approve_verification(
+    request_id: String,
+    note: String,
+) -> Result<VerificationRequest, VerificationError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| {
+        state.decide_verification(&ctx, &request_id, VerificationDecision::Approve, note)
+    })
+}
 
-    applicants
+#[update(guard = "is_admin")]
+fn reject_verification(
+    request_id: String,
+    note: String,
+) -> Result<VerificationRequest, VerificationError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| {
+        state.decide_verification(&ctx, &request_id, VerificationDecision::Reject, note)
+    })
 }
 
 // ==================================================================================================
@@ -736,54 +960,39 @@ fn get_job_applicants(job_id: String) -> Vec<Profile> {
 // Confirming reviewer account
 #[query]
 fn confirm_reviewer(reviewee: String) -> bool {
-    let reviewer = ic_cdk::api::caller();
-    let reviewer_principal_string = Principal::to_text(&reviewer);
-
-    if reviewee == reviewer_principal_string {
-        return true;
-    } else {
-        return false;
-    }
+    let ctx = Context::current();
+    domain::with(|state| state.confirm_reviewer(&ctx, &reviewee))
 }
 
 // Confirming if user has already reviewed or not
 #[query]
-fn confirm_reviewed(reviewee_principal : Principal) -> bool {
-
-    let reviewer_principal = ic_cdk::api::caller();
-
-    let profile = PROFILE_STORE.with(|store| {
-        store.borrow().get(&reviewee_principal).cloned()
-    });
-
-    if let Some(profile) = profile {
-        for review in profile.reviews.iter() {
-            if let Some(reviewer) = &review.reviewer_principal {
-                if reviewer == &reviewer_principal {
-                    return true;
-                }
-            }
-        }
-    }
-    false
+fn confirm_reviewed(reviewee_principal: Principal) -> bool {
+    let ctx = Context::current();
+    domain::with(|state| state.confirm_reviewed(&ctx, &reviewee_principal))
 }
 
-
 // // ======================
 // //      UPDATE CALLS
 // // ======================
 
 // fetching all reviews
 #[query]
-fn get_all_reviews(principal : Principal) -> Vec<Reviews> {
+fn get_all_reviews(principal: Principal) -> Vec<Reviews> {
+    domain::with(|state| state.reviews_of(&principal))
+}
 
-    let mut n: Profile = Default::default();
-    PROFILE_STORE.with(|el| n = el.borrow().get(&principal).unwrap().clone());
+// fetching the reviews written by a user
+#[query]
+fn get_reviews_by_reviewer(principal: Principal) -> Vec<Reviews> {
+    domain::with(|state| state.reviews_written_by(&principal))
+}
 
-    n.reviews
+#[query]
+fn get_review(review_id: String) -> Option<Reviews> {
+    domain::with(|state| state.review(&review_id))
 }
 
-// creating a new review
+// creating a new review of the other side of an engagement, timestamped by the canister
 #[update]
 async fn add_reviews(
     new_ratings: f64,
@@ -791,53 +1000,140 @@ async fn add_reviews(
     new_review: String,
     new_image: String,
     profile_reviewed: String,
-    new_review_time: i64,
-) -> Reviews {
-
-    // Getting the reviewer principal and fetching the full name
-    let reviewer_principal = ic_cdk::api::caller();
-    let mut n: Profile = Default::default();
-    PROFILE_STORE.with(|el| n = el.borrow().get(&reviewer_principal).unwrap().clone());
-    let reviewer_full_name = n.fullname;
-
-    // Handling image option
-    let image_option = if new_image.is_empty() {
-        None
+    engagement: Engagement,
+    categories: Option<CategoryRatings>,
+) -> Result<Reviews, ReviewError> {
+    let ctx = Context::with_randomness().await;
+    check_review_image(&ctx, &new_image)?;
+    domain::with_mut(|state| {
+        state.add_review(
+            &ctx,
+            new_ratings,
+            new_title,
+            new_review,
+            new_image,
+            &profile_reviewed,
+            engagement,
+            categories,
+        )
+    })
+}
+
+// editing a review, only its author can within the edit window
+#[update]
+fn edit_review(
+    review_id: String,
+    new_ratings: f64,
+    new_title: String,
+    new_review: String,
+    new_image: String,
+    categories: Option<CategoryRatings>,
+) -> Result<Reviews, ReviewError> {
+    let ctx = Context::current();
+    check_review_image(&ctx, &new_image)?;
+    domain::with_mut(|state| {
+        state.edit_review(
+            &ctx,
+            &review_id,
+            new_ratings,
+            new_title,
+            new_review,
+            new_image,
+            categories,
+        )
+    })
+}
+
+// Review images are assets uploaded by the reviewer
+fn check_review_image(ctx: &Context, image: &str) -> Result<(), ReviewError> {
+    if image.is_empty() || assets::with(|store| store.is_owned_by(image, &ctx.caller)) {
+        Ok(())
     } else {
-        Some(new_image)
-    };
+        Err(ReviewError::InvalidAsset)
+    }
+}
 
-    // Creating unique id for a review
-    let uid = raw_rand().await.unwrap().0;
-    let uid = format!("{:x}", Sha256::digest(&uid));
-
-    // Creating the new review object
-    let new_review = Reviews {
-        ratings: new_ratings,
-        title: new_title.to_string(),
-        review: new_review.to_string(),
-        image: image_option.clone(),
-        reviewer: reviewer_full_name.clone(),
-        review_time: new_review_time,
-        reviewer_principal: Some(reviewer_principal),
-    };
+#[update]
+fn delete_review(review_id: String) -> Result<(), ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.delete_review(&ctx, &review_id))
+}
 
-    // Updating the review store with the new review
-    REVIEW_STORE.with(|el| {
-        el.borrow_mut().insert(uid.clone(), new_review.clone());
-    });
+// answering a review of the caller's profile
+#[update]
+fn reply_to_review(review_id: String, text: String) -> Result<Reviews, ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.reply_to_review(&ctx, &review_id, text))
+}
 
-    // Retrieving the reviewee profile and updating the profile store with the new review
-    let mut reviewee_profile = get(profile_reviewed.clone());
-    reviewee_profile.reviews.push(new_review.clone());
-    PROFILE_STORE.with(|profile_store| {
-        let id_store = ID_STORE.with(|id_store| id_store.borrow().clone());
-        if let Some(reviewee_principal) = id_store.get(&profile_reviewed) {
-            profile_store.borrow_mut().insert(reviewee_principal.clone(), reviewee_profile);
-        }
-    });
+// ==================================================================================================
+// Review moderation
+// ==================================================================================================
+
+// Reporting an abusive review, it is hidden once enough users reported it
+#[update]
+fn report_review(review_id: String, reason: String) -> Result<(), ReviewError> {
+    let ctx = Context::current();
+    let threshold = config::settings().review_report_threshold;
+    domain::with_mut(|state| state.report_review(&ctx, &review_id, reason, threshold))
+}
+
+// Reported reviews waiting for a decision
+#[query(guard = "is_admin")]
+fn get_moderation_queue() -> Vec<ModerationQueueEntry> {
+    domain::with(|state| state.moderation_queue())
+}
+
+#[update(guard = "is_admin")]
+fn hide_review(review_id: String, note: String) -> Result<ModerationCase, ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.moderate_review(&ctx, &review_id, ModerationAction::Hide, note))
+}
 
-    new_review
+#[update(guard = "is_admin")]
+fn restore_review(review_id: String, note: String) -> Result<ModerationCase, ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| {
+        state.moderate_review(&ctx, &review_id, ModerationAction::Restore, note)
+    })
+}
+
+// ==================================================================================================
+// Course reviews
+// ==================================================================================================
+
+// Rating a course the caller is enrolled in, rating it again replaces the review
+#[update]
+fn rate_course(
+    course_id: String,
+    new_ratings: f64,
+    course_ratings: CourseRatings,
+    new_title: String,
+    new_review: String,
+) -> Result<CourseReview, ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| {
+        state.rate_course(
+            &ctx,
+            &course_id,
+            new_ratings,
+            course_ratings,
+            new_title,
+            new_review,
+        )
+    })
+}
+
+#[update]
+fn delete_course_review(course_id: String) -> Result<(), ReviewError> {
+    let ctx = Context::current();
+    domain::with_mut(|state| state.delete_course_review(&ctx, &course_id))
+}
+
+// Reviews of a course, newest first
+#[query]
+fn get_course_reviews(course_id: String) -> Vec<CourseReview> {
+    domain::with(|state| state.course_reviews(&course_id))
 }
 
 // ==================================================================================================
@@ -850,14 +1146,18 @@ async fn add_reviews(
 
 static COUNTER: AtomicU64 = AtomicU64::new(0);
 
-fn generate_unique_nat() -> Nat {
-    // Increment the counter safely
-    let counter_value = COUNTER.fetch_add(1, Ordering::SeqCst);
-    // Convert the counter value directly to Nat
-    Nat::from(counter_value)
+// The next free token id, only taken once a certificate is minted with it
+fn next_token_identifier() -> Nat {
+    loop {
+        let token_identifier = Nat::from(COUNTER.load(Ordering::SeqCst));
+        if !certificate::ledger::with(|ledger| ledger.is_token_existed(&token_identifier)) {
+            return token_identifier;
+        }
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+    }
 }
 
-// Minting certificate to the students
+// Minting certificate to the students of the course, by its managers or a custodian
 #[update]
 async fn mint_certificate(
     to: String,
@@ -866,22 +1166,53 @@ async fn mint_certificate(
     course_id: String,
     certificate: String,
 ) -> Result<Nat, NftError> {
-    let to = Principal::from_text(to).unwrap();
-
-    let token_identifier = generate_unique_nat();
+    let caller = ic_cdk::api::caller();
+    let course = domain::with(|state| state.courses.get(&course_id).cloned())
+        .ok_or(NftError::CourseNotFound)?;
+    let manager = domain::with(|state| state.manages_course(&course, &caller));
+    if !manager && is_canister_custodian().is_err() {
+        return Err(NftError::Unauthorized);
+    }
+    let to = Principal::from_text(to).map_err(|_| NftError::InvalidPrincipal)?;
+    if !course.applicants.contains(&to) {
+        return Err(NftError::NotEnrolled);
+    }
+    // Checked before the fee is taken as well, custodians mint for free
+    if config::features().verified_issuers_only
+        && !domain::with(|state| state.is_verified_issuer(&course_id))
+    {
+        return Err(NftError::UnverifiedIssuer);
+    }
+    if is_canister_custodian().is_err() {
+        fees::charge_certificates(caller, 1)
+            .await
+            .map_err(NftError::FeeNotPaid)?;
+    }
 
-    let token_id_tuple = (token_identifier.clone(), course_id.clone());
+    mint_course_certificate(to, description, tag, course_id, certificate, None)
+}
 
-    PROFILE_STORE.with(|store| {
-        if let Some(profile) = store.borrow_mut().get_mut(&to) {
-            if !profile.token_ids.contains(&token_id_tuple) {
-                profile.token_ids.push(token_id_tuple);
-            }
-        }
-    });
+// Minting a course certificate and recording the token on the recipient's profile
+fn mint_course_certificate(
+    to: Principal,
+    description: String,
+    tag: String,
+    course_id: String,
+    certificate: String,
+    grade: Option<String>,
+) -> Result<Nat, NftError> {
+    if !domain::with(|state| state.courses.contains_key(&course_id)) {
+        return Err(NftError::CourseNotFound);
+    }
+    // Platforms can require the organization or trainer behind the course to be verified
+    let verified_issuer = domain::with(|state| state.is_verified_issuer(&course_id));
+    if config::features().verified_issuers_only && !verified_issuer {
+        return Err(NftError::UnverifiedIssuer);
+    }
+    let token_identifier = next_token_identifier();
 
     // Assigning metadata values
-    let properties = vec![
+    let mut properties = vec![
         (
             "description".to_string(),
             GenericValue::TextContent(description.to_string()),
@@ -894,13 +1225,69 @@ async fn mint_certificate(
             "course_id".to_string(),
             GenericValue::TextContent(course_id.to_string()),
         ),
-        (
-            "certificate".to_string(),
-            GenericValue::TextContent(certificate.to_string()),
-        ),
     ];
-
-    mint(to, token_identifier, properties)
+    // Artwork uploaded to the asset store is referenced by id instead of inlined
+    let artwork = if assets::with(|store| store.is_committed(&certificate)) {
+        "certificate_asset"
+    } else {
+        "certificate"
+    };
+    properties.push((artwork.to_string(), GenericValue::TextContent(certificate)));
+    if let Some(grade) = grade {
+        properties.push(("grade".to_string(), GenericValue::TextContent(grade)));
+    }
+    if let Some(issuer) = domain::with(|state| state.certificate_issuer(&course_id)) {
+        properties.push(("issuer".to_string(), GenericValue::TextContent(issuer)));
+    }
+    properties.push((
+        "verified_issuer".to_string(),
+        GenericValue::BoolContent(verified_issuer),
+    ));
+
+    // `mint` returns the id of the mint transaction, the profile and the
+    // notification refer to the token
+    mint(to, token_identifier.clone(), properties)?;
+    COUNTER.fetch_add(1, Ordering::SeqCst);
+    let token_id_tuple = (token_identifier.clone(), course_id.clone());
+    domain::with_mut(|state| {
+        if let Some(profile) = state.profiles.get_mut(&to) {
+            if !profile.token_ids.contains(&token_id_tuple) {
+                profile.token_ids.push(token_id_tuple);
+            }
+        }
+        state.notify_certificate(
+            to,
+            token_identifier.0.to_string(),
+            &course_id,
+            ic_cdk::api::time(),
+        )
+    });
+    Ok(token_identifier)
 }
 
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use candid::utils::{service_compatible, CandidSource};
+    use std::path::Path;
+
+    // `ea_backend.did` is generated with `scripts/generate_did.sh`, this keeps
+    // the committed interface and the one exported by the code in sync
+    #[test]
+    fn candid_interface_matches_the_code() {
+        let generated = super::__export_service();
+        let committed = Path::new(env!("CARGO_MANIFEST_DIR")).join("ea_backend.did");
+
+        service_compatible(
+            CandidSource::Text(&generated),
+            CandidSource::File(&committed),
+        )
+        .expect("the canister is not compatible with ea_backend.did");
+        service_compatible(
+            CandidSource::File(&committed),
+            CandidSource::Text(&generated),
+        )
+        .expect("ea_backend.did is out of date, run scripts/generate_did.sh");
+    }
+}
@@ -0,0 +1,63 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _};
+use std::cell::RefCell;
+
+// Stable memory is split into virtual memories so the upgrade snapshot and the
+// structures living directly in stable memory never overwrite each other.
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const UPGRADES: MemoryId = MemoryId::new(0);
+pub const BLOCK_LOG_INDEX: MemoryId = MemoryId::new(1);
+pub const BLOCK_LOG_DATA: MemoryId = MemoryId::new(2);
+pub const BLOCK_INDEX: MemoryId = MemoryId::new(3);
+pub const ASSETS: MemoryId = MemoryId::new(4);
+pub const ASSET_CHUNKS: MemoryId = MemoryId::new(5);
+pub const ASSET_USAGE: MemoryId = MemoryId::new(6);
+pub const ASSET_UPLOADS: MemoryId = MemoryId::new(7);
+pub const ASSET_LINK_KEY: MemoryId = MemoryId::new(8);
+
+const MEMORY_MANAGER_MAGIC: &[u8; 3] = b"MGR";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|memory_manager| memory_manager.borrow().get(id))
+}
+
+// Stable memory written by `ic_cdk::storage::stable_save` before the memory
+// manager was introduced. Has to be checked before the memory manager is first used.
+pub fn is_legacy_layout() -> bool {
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return false;
+    }
+    let mut magic = [0u8; 3];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    &magic != MEMORY_MANAGER_MAGIC
+}
+
+// Saving the serialized heap state, prefixed with its length
+pub fn save_upgrade_state(state: &[u8]) {
+    let mut memory = get(UPGRADES);
+    let mut writer = Writer::new(&mut memory, 0);
+    writer
+        .write(&(state.len() as u64).to_le_bytes())
+        .expect("Failed to write upgrade state length");
+    writer.write(state).expect("Failed to write upgrade state");
+}
+
+pub fn read_upgrade_state() -> Vec<u8> {
+    let memory = get(UPGRADES);
+    if memory.size() == 0 {
+        return Vec::new();
+    }
+    let mut len = [0u8; 8];
+    memory.read(0, &mut len);
+    let mut state = vec![0; u64::from_le_bytes(len) as usize];
+    memory.read(8, &mut state);
+    state
+}
@@ -0,0 +1,1148 @@
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{encode_one, CandidType, Deserialize, Nat, Principal};
+use pocket_ic::{query_candid_as, update_candid_as, CallError, PocketIc};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+// Harness for driving the compiled backend canister inside PocketIC. The wasm is
+// built with `scripts/test_backend_local.sh`, or pointed to with `EA_BACKEND_WASM`.
+
+const INITIAL_CYCLES: u128 = 2_000_000_000_000;
+
+// ==================================================================================================
+// Structs
+// ==================================================================================================
+
+// Mirrors of the canister types, only the fields the tests look at are declared
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Roles {
+    FREELANCER,
+    EMPLOYER,
+    TRAINER,
+    ADMIN,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EmployerProfile {
+    pub company_name: String,
+    pub website: Option<String>,
+    pub industry: String,
+    pub company_size: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RoleProfile {
+    Employer(EmployerProfile),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RoleError {
+    ProfileNotFound,
+    RoleNotAllowed,
+    RoleNotHeld,
+    LastRole,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AssetStatus {
+    Uploading,
+    Committed,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Asset {
+    pub id: String,
+    pub owner: Principal,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub sha256: Option<String>,
+    pub status: AssetStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AssetError {
+    AssetNotFound,
+    Unauthorized,
+    UnsupportedContentType,
+    EmptyAsset,
+    TooLarge(u64),
+    InvalidChunk,
+    IncompleteUpload,
+    HashMismatch,
+    AlreadyCommitted,
+    ProfileNotFound,
+    QuotaExceeded(u64),
+    CertificateArtwork,
+    RandomnessUnavailable(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AssetLink {
+    pub path: String,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CvDate {
+    pub year: u16,
+    pub month: u8,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct WorkExperience {
+    pub employer: String,
+    pub title: String,
+    pub start: CvDate,
+    pub end: Option<CvDate>,
+    pub description: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Education {
+    pub institution: String,
+    pub degree: String,
+    pub field_of_study: String,
+    pub start: CvDate,
+    pub end: Option<CvDate>,
+    pub description: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum LanguageProficiency {
+    Elementary,
+    Intermediate,
+    Professional,
+    Fluent,
+    Native,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Language {
+    pub language: String,
+    pub proficiency: LanguageProficiency,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PortfolioLink {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Project {
+    pub name: String,
+    pub description: String,
+    pub url: Option<String>,
+    pub skills: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Cv {
+    pub experience: Vec<WorkExperience>,
+    pub education: Vec<Education>,
+    pub languages: Vec<Language>,
+    pub links: Vec<PortfolioLink>,
+    pub projects: Vec<Project>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum CvError {
+    ProfileNotFound,
+    InvalidDate,
+    EmptyField(String),
+    InvalidUrl(String),
+    TooManyEntries(u64),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CompletedCourse {
+    pub course_id: String,
+    pub title: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlatformJob {
+    pub job_id: String,
+    pub title: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CvDocument {
+    pub fullname: String,
+    pub cv: Cv,
+    pub completed_courses: Vec<CompletedCourse>,
+    pub platform_jobs: Vec<PlatformJob>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Reviews {
+    pub id: String,
+    pub reviewee: Principal,
+    pub ratings: f64,
+    pub title: String,
+    pub review: String,
+    pub reviewer: String,
+    pub reviewer_principal: Option<Principal>,
+    pub engagement: Option<Engagement>,
+    pub edited_at: Option<i64>,
+    pub reply: Option<ReviewReply>,
+    pub hidden: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReviewReply {
+    pub text: String,
+    pub replied_at: i64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CategoryRatings {
+    pub communication: f64,
+    pub quality: f64,
+    pub timeliness: f64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RatingSummary {
+    pub count: u64,
+    pub mean: f64,
+    pub score: f64,
+    pub stars: Vec<u64>,
+    pub categories: CategoryRatings,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CourseRatings {
+    pub content_quality: f64,
+    pub pacing: f64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CourseRatingSummary {
+    pub overall: RatingSummary,
+    pub means: CourseRatings,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CourseReview {
+    pub course_id: String,
+    pub reviewer: Principal,
+    pub ratings: f64,
+    pub course_ratings: CourseRatings,
+    pub edited_at: Option<i64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Engagement {
+    Job(String),
+    Course(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReviewError {
+    ReviewerNotFound,
+    RevieweeNotFound,
+    SelfReview,
+    InvalidRating,
+    EngagementNotFound,
+    NotEngaged,
+    AlreadyReviewed,
+    ReviewNotFound,
+    Unauthorized,
+    EditWindowClosed,
+    AlreadyReplied,
+    AlreadyReported,
+    CourseNotFound,
+    InvalidAsset,
+    UnderModeration,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ModerationNote {
+    pub moderator: Option<Principal>,
+    pub note: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ModerationCase {
+    pub review_id: String,
+    pub audit: Vec<ModerationNote>,
+    pub open: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ModerationQueueEntry {
+    pub case: ModerationCase,
+    pub review: Reviews,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Profile {
+    pub id: String,
+    pub principal_id: Principal,
+    pub fullname: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub role: Roles,
+    pub roles: Vec<Roles>,
+    pub employer: Option<EmployerProfile>,
+    pub token_ids: Vec<(Nat, String)>,
+    pub rating: RatingSummary,
+    pub course_rating: CourseRatingSummary,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum EmailError {
+    ProfileNotFound,
+    InvalidEmail,
+    AlreadyVerified,
+    RequestedTooSoon,
+    NoPendingCode,
+    CodeExpired,
+    TooManyAttempts,
+    InvalidCode,
+    DeliveryFailed(String),
+    LockedOut(u64),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OutboundMessage {
+    pub to: String,
+    pub body: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum NotificationKind {
+    JobApplication,
+    Hired,
+    CourseEnrollment,
+    CertificateMinted,
+    ReviewReceived,
+    NewMessage,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Notification {
+    pub id: u64,
+    pub kind: NotificationKind,
+    pub actor: Option<Principal>,
+    pub reference: String,
+    pub message: String,
+    pub read: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>,
+    pub total: u64,
+    pub unread: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct NotificationPreferences {
+    pub job_applications: bool,
+    pub hires: bool,
+    pub course_enrollments: bool,
+    pub certificates: bool,
+    pub reviews: bool,
+    pub messages: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum NotificationError {
+    ProfileNotFound,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ThreadScope {
+    JobApplication {
+        job_id: String,
+        applicant: Principal,
+    },
+    CourseEnrollment {
+        course_id: String,
+        learner: Principal,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Message {
+    pub id: u64,
+    pub sender: Principal,
+    pub body: String,
+    pub attachments: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReadReceipt {
+    pub reader: Principal,
+    pub last_read: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Thread {
+    pub id: String,
+    pub scope: ThreadScope,
+    pub messages: Vec<Message>,
+    pub receipts: Vec<ReadReceipt>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ThreadSummary {
+    pub id: String,
+    pub unread: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum MessagingError {
+    JobNotFound,
+    CourseNotFound,
+    NotAnApplicant,
+    NotEnrolled,
+    ThreadNotFound,
+    Unauthorized,
+    EmptyMessage,
+    MessageTooLong,
+    TooManyAttachments,
+    InvalidAsset,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RegistrationError {
+    AnonymousCaller,
+    AlreadyRegistered,
+    InvalidRole,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AccountError {
+    ProfileNotFound,
+    AlreadyDeactivated,
+    NotDeactivated,
+    LastOwner,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PublicProfile {
+    pub id: String,
+    pub principal_id: Principal,
+    pub fullname: String,
+    pub email: Option<String>,
+    pub resume_id: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Course {
+    pub id: String,
+    pub title: String,
+    pub creator: Principal,
+    pub applicants: Vec<Principal>,
+    pub media: Vec<String>,
+    pub rating: CourseRatingSummary,
+    pub organization_id: Option<String>,
+    pub verified: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Jobs {
+    pub id: String,
+    pub title: String,
+    pub creator: Principal,
+    pub applicants: Vec<Principal>,
+    pub hired: Vec<Principal>,
+    pub organization_id: Option<String>,
+    pub verified: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum OrganizationKind {
+    Company,
+    TrainingProvider,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct OrganizationDetails {
+    pub name: String,
+    pub kind: OrganizationKind,
+    pub description: String,
+    pub website: Option<String>,
+    pub location: String,
+    pub logo_id: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum MemberRole {
+    Owner,
+    Recruiter,
+    Instructor,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Member {
+    pub principal: Principal,
+    pub role: MemberRole,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Organization {
+    pub id: String,
+    pub details: OrganizationDetails,
+    pub verification: VerificationStatus,
+    pub members: Vec<Member>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum OrganizationError {
+    ProfileNotFound,
+    OrganizationNotFound,
+    Unauthorized,
+    EmptyName,
+    InvalidAsset,
+    MemberNotFound,
+    LastOwner,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum VerificationStatus {
+    Unverified,
+    Pending,
+    Verified,
+    Rejected,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum VerificationSubject {
+    Organization(String),
+    Trainer(Principal),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VerificationNote {
+    pub admin: Principal,
+    pub note: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VerificationRequest {
+    pub id: String,
+    pub subject: VerificationSubject,
+    pub status: VerificationStatus,
+    pub decision: Option<VerificationNote>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum VerificationError {
+    ProfileNotFound,
+    OrganizationNotFound,
+    Unauthorized,
+    NoDocuments,
+    InvalidAsset,
+    AlreadyPending,
+    AlreadyVerified,
+    RequestNotFound,
+    AlreadyDecided,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum JobError {
+    JobNotFound,
+    Unauthorized,
+    NotAnApplicant,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum NftError {
+    UnauthorizedOwner,
+    UnauthorizedOperator,
+    OwnerNotFound,
+    OperatorNotFound,
+    TokenNotFound,
+    ExistedNFT,
+    SelfApprove,
+    SelfTransfer,
+    TxNotFound,
+    TransferDisabled,
+    UnverifiedIssuer,
+    Unauthorized,
+    CourseNotFound,
+    InvalidPrincipal,
+    NotEnrolled,
+    FeeNotPaid(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TxOperation {
+    Mint,
+    Transfer,
+    TransferFrom,
+    Approve,
+    ApproveAll,
+    Burn,
+    Revoke,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct TxEvent {
+    pub tx_id: Nat,
+    pub operation: TxOperation,
+    pub caller: Principal,
+    pub from: Option<Principal>,
+    pub to: Option<Principal>,
+    pub token_identifier: Option<Nat>,
+    pub timestamp: u64,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to_subaccount: Option<Vec<u8>>,
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApprovalInfo {
+    pub spender: Account,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub expires_at: Option<u64>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveTokenArg {
+    pub token_id: Nat,
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ApproveTokenError {
+    InvalidSpender,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveCollectionArg {
+    pub approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ApproveCollectionError {
+    InvalidSpender,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RevokeCollectionApprovalArg {
+    pub spender: Option<Account>,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RevokeCollectionApprovalError {
+    ApprovalDoesNotExist,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct IsApprovedArg {
+    pub spender: Account,
+    pub from_subaccount: Option<Vec<u8>>,
+    pub token_id: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArg {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferFromError {
+    InvalidRecipient,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(candid::Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    // Field of a map value
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DataCertificate {
+    pub certificate: Vec<u8>,
+    pub hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum BatchStatus {
+    InProgress,
+    Completed,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecipientStatus {
+    Pending,
+    Minted(Nat),
+    AlreadyCertified,
+    Failed(NftError),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BatchRecipient {
+    pub to: Principal,
+    pub grade: String,
+    pub status: RecipientStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BatchMintJob {
+    pub id: String,
+    pub course_id: String,
+    pub recipients: Vec<BatchRecipient>,
+    pub next_index: u64,
+    pub status: BatchStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum BatchMintError {
+    CourseNotFound,
+    Unauthorized,
+    EmptyBatch,
+    InvalidRecipients(Vec<Principal>),
+    JobNotFound,
+    BatchMintingDisabled,
+    TooManyRecipients(u64),
+    UnverifiedIssuer,
+    FeeNotPaid(String),
+    RandomnessUnavailable(String),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct InitArgs {
+    pub name: Option<String>,
+    pub logo: Option<String>,
+    pub symbol: Option<String>,
+    pub custodians: Option<Vec<Principal>>,
+    pub cap: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct UpgradeArgs {
+    pub name: Option<String>,
+    pub logo: Option<String>,
+    pub symbol: Option<String>,
+    pub cap: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlatformSettings {
+    pub certificate_fee_e8s: u64,
+    pub fee_ledger: Principal,
+    pub max_batch_recipients: u64,
+    pub batch_chunk_size: u64,
+    pub review_report_threshold: u64,
+    pub max_asset_size: u64,
+    pub max_owner_assets_size: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeatureFlags {
+    pub batch_minting: bool,
+    pub certificate_transfers: bool,
+    pub verified_issuers_only: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PlatformConfig {
+    pub admins: Vec<Principal>,
+    pub settings: PlatformSettings,
+    pub features: FeatureFlags,
+    pub outbound: OutboundChannel,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum OutboundChannel {
+    Disabled,
+    Mock,
+    Https { url: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct BackendInitArgs {
+    pub collection: Option<InitArgs>,
+    pub admins: Option<Vec<Principal>>,
+    pub settings: Option<PlatformSettings>,
+    pub features: Option<FeatureFlags>,
+    pub outbound: Option<OutboundChannel>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct BackendUpgradeArgs {
+    pub collection: Option<UpgradeArgs>,
+    pub admins: Option<Vec<Principal>>,
+    pub settings: Option<PlatformSettings>,
+    pub features: Option<FeatureFlags>,
+    pub outbound: Option<OutboundChannel>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum BackendArgs {
+    Init(BackendInitArgs),
+    Upgrade(Option<BackendUpgradeArgs>),
+}
+
+// ==================================================================================================
+// Harness
+// ==================================================================================================
+
+// Deterministic test principals, distinct from the controller and from each other
+pub fn user(seed: u8) -> Principal {
+    Principal::self_authenticating([seed])
+}
+
+// The default account of a principal
+pub fn account(owner: Principal) -> Account {
+    Account {
+        owner,
+        subaccount: None,
+    }
+}
+
+pub fn backend_wasm() -> Vec<u8> {
+    let path = std::env::var_os("EA_BACKEND_WASM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("../../target/wasm32-unknown-unknown/release/ea_backend.wasm")
+        });
+    std::fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read the backend wasm at {}: {}. Build it with scripts/test_backend_local.sh",
+            path.display(),
+            e
+        )
+    })
+}
+
+pub struct Backend {
+    pub pic: PocketIc,
+    pub canister_id: Principal,
+    pub controller: Principal,
+    wasm: Vec<u8>,
+}
+
+impl Backend {
+    // Installing a fresh canister, the controller becomes custodian and admin
+    pub fn install(args: Option<BackendArgs>) -> Self {
+        let pic = PocketIc::new();
+        let controller = user(0);
+        let canister_id = pic.create_canister_with_settings(Some(controller), None);
+        pic.add_cycles(canister_id, INITIAL_CYCLES);
+
+        let wasm = backend_wasm();
+        pic.install_canister(
+            canister_id,
+            wasm.clone(),
+            encode_one(args).expect("Failed to encode the init arguments"),
+            Some(controller),
+        );
+
+        Backend {
+            pic,
+            canister_id,
+            controller,
+            wasm,
+        }
+    }
+
+    // Current time of the replica, in nanoseconds since the epoch
+    pub fn now(&self) -> u64 {
+        self.pic
+            .get_time()
+            .duration_since(UNIX_EPOCH)
+            .expect("Replica time is before the epoch")
+            .as_nanos() as u64
+    }
+
+    pub fn upgrade(&self, args: Option<BackendArgs>) {
+        self.pic
+            .upgrade_canister(
+                self.canister_id,
+                self.wasm.clone(),
+                encode_one(args).expect("Failed to encode the upgrade arguments"),
+                Some(self.controller),
+            )
+            .expect("Failed to upgrade the backend");
+    }
+
+    pub fn update<I, O>(&self, sender: Principal, method: &str, args: I) -> O
+    where
+        I: ArgumentEncoder,
+        O: for<'a> ArgumentDecoder<'a>,
+    {
+        self.try_update(sender, method, args)
+            .unwrap_or_else(|e| panic!("Update call {} failed: {:?}", method, e))
+    }
+
+    pub fn try_update<I, O>(&self, sender: Principal, method: &str, args: I) -> Result<O, CallError>
+    where
+        I: ArgumentEncoder,
+        O: for<'a> ArgumentDecoder<'a>,
+    {
+        update_candid_as(&self.pic, self.canister_id, sender, method, args)
+    }
+
+    pub fn query<I, O>(&self, sender: Principal, method: &str, args: I) -> O
+    where
+        I: ArgumentEncoder,
+        O: for<'a> ArgumentDecoder<'a>,
+    {
+        query_candid_as(&self.pic, self.canister_id, sender, method, args)
+            .unwrap_or_else(|e| panic!("Query call {} failed: {:?}", method, e))
+    }
+
+    // // ======================
+    // //      WORKFLOWS
+    // // ======================
+
+    pub fn create_user(&self, sender: Principal, fullname: &str, role: &str) -> Profile {
+        self.register(sender, fullname, role)
+            .expect("Failed to register the user")
+    }
+
+    pub fn register(
+        &self,
+        sender: Principal,
+        fullname: &str,
+        role: &str,
+    ) -> Result<Profile, RegistrationError> {
+        let email = format!("{}@example.com", fullname.to_lowercase());
+        let (result,): (Result<Profile, RegistrationError>,) = self.update(
+            sender,
+            "create_user",
+            (fullname.to_string(), email, role.to_string()),
+        );
+        result
+    }
+
+    pub fn get_self(&self, sender: Principal) -> Profile {
+        let (profile,): (Profile,) = self.query(sender, "get_self", ());
+        profile
+    }
+
+    pub fn get_course(&self, course_id: &str) -> Course {
+        let (course,): (Option<Course>,) =
+            self.query(self.controller, "get_course", (course_id.to_string(),));
+        course.expect("course not found")
+    }
+
+    pub fn create_course(&self, sender: Principal, title: &str) -> Course {
+        let (course,): (Option<Course>,) =
+            self.update(sender, "create_course", (title.to_string(),));
+        course.expect("only trainers create courses")
+    }
+
+    pub fn create_job(&self, sender: Principal, title: &str) -> Jobs {
+        let (job,): (Option<Jobs>,) = self.update(sender, "create_job", (title.to_string(),));
+        job.expect("only employers create jobs")
+    }
+
+    pub fn add_review(
+        &self,
+        sender: Principal,
+        reviewee: &Profile,
+        ratings: f64,
+        engagement: Engagement,
+        categories: Option<CategoryRatings>,
+    ) -> Result<Reviews, ReviewError> {
+        let (result,): (Result<Reviews, ReviewError>,) = self.update(
+            sender,
+            "add_reviews",
+            (
+                ratings,
+                "Great work".to_string(),
+                "Delivered on time".to_string(),
+                String::new(),
+                reviewee.id.clone(),
+                engagement,
+                categories,
+            ),
+        );
+        result
+    }
+
+    // Uploading `content` in as many chunks as the canister asks for
+    pub fn upload_asset(&self, sender: Principal, content_type: &str, content: &[u8]) -> Asset {
+        let (begun,): (Result<Asset, AssetError>,) = self.update(
+            sender,
+            "begin_asset_upload",
+            (content_type.to_string(), content.len() as u64),
+        );
+        let asset = begun.unwrap();
+        for (index, chunk) in content.chunks(asset.chunk_size as usize).enumerate() {
+            let (put,): (Result<(), AssetError>,) = self.update(
+                sender,
+                "put_asset_chunk",
+                (asset.id.clone(), index as u32, chunk.to_vec()),
+            );
+            put.unwrap();
+        }
+        let (committed,): (Result<Asset, AssetError>,) = self.update(
+            sender,
+            "commit_asset_upload",
+            (asset.id.clone(), None::<String>),
+        );
+        committed.unwrap()
+    }
+
+    pub fn http_get(&self, path: &str, range: Option<&str>) -> HttpResponse {
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            url: path.to_string(),
+            headers: range
+                .map(|range| vec![("Range".to_string(), range.to_string())])
+                .unwrap_or_default(),
+            body: vec![],
+        };
+        let (response,): (HttpResponse,) =
+            self.query(Principal::anonymous(), "http_request", (request,));
+        response
+    }
+
+    pub fn rate_course(
+        &self,
+        sender: Principal,
+        course_id: &str,
+        ratings: f64,
+        course_ratings: CourseRatings,
+    ) -> Result<CourseReview, ReviewError> {
+        let (result,): (Result<CourseReview, ReviewError>,) = self.update(
+            sender,
+            "rate_course",
+            (
+                course_id.to_string(),
+                ratings,
+                course_ratings,
+                "Great course".to_string(),
+                "Clear and well paced".to_string(),
+            ),
+        );
+        result
+    }
+
+    pub fn mint_certificate(&self, recipient: Principal, course_id: &str) -> Result<Nat, NftError> {
+        let (result,): (Result<Nat, NftError>,) = self.update(
+            self.controller,
+            "mint_certificate",
+            (
+                recipient.to_text(),
+                "Completed the course".to_string(),
+                "certificate".to_string(),
+                course_id.to_string(),
+                "https://example.com/certificate.png".to_string(),
+            ),
+        );
+        result
+    }
+}
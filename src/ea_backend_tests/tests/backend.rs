@@ -0,0 +1,1799 @@
+use candid::{Nat, Principal};
+use ea_backend_tests::*;
+use std::time::Duration;
+
+// Every workflow is checked before and after an upgrade, so state that is not
+// carried through `pre_upgrade`/`post_upgrade` shows up as a failure here.
+
+struct Cast {
+    trainer: Principal,
+    student: Principal,
+    employer: Principal,
+}
+
+fn setup() -> (Backend, Cast) {
+    let backend = Backend::install(None);
+    let cast = Cast {
+        trainer: user(1),
+        student: user(2),
+        employer: user(3),
+    };
+    backend.create_user(cast.trainer, "Alice", "trainer");
+    backend.create_user(cast.student, "Bob", "trainee");
+    backend.create_user(cast.employer, "Carol", "employer");
+    (backend, cast)
+}
+
+// ==================================================================================================
+// Profiles
+// ==================================================================================================
+
+#[test]
+fn profiles_survive_upgrades() {
+    let backend = Backend::install(None);
+    let student = user(2);
+
+    let (known,): (bool,) = backend.query(student, "check_user", ());
+    assert!(!known);
+    assert_eq!(
+        backend.register(student, "Bob", "admin").unwrap_err(),
+        RegistrationError::InvalidRole
+    );
+
+    let created = backend.create_user(student, "Bob", "trainee");
+    assert_eq!(created.principal_id, student);
+    assert_eq!(created.role, Roles::FREELANCER);
+
+    for _ in 0..2 {
+        let profile = backend.get_self(student);
+        assert_eq!(profile.id, created.id);
+        assert_eq!(profile.fullname, "Bob");
+        assert_eq!(profile.email, "bob@example.com");
+
+        let (by_id,): (PublicProfile,) = backend.query(user(9), "get", (created.id.clone(),));
+        assert_eq!(by_id.principal_id, student);
+
+        let (known,): (bool,) = backend.query(student, "check_user", ());
+        assert!(known);
+        assert_eq!(
+            backend
+                .register(student, "Mallory", "employer")
+                .unwrap_err(),
+            RegistrationError::AlreadyRegistered
+        );
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn profiles_are_shown_according_to_privacy_settings() {
+    let (backend, cast) = setup();
+    let student = backend.get_self(cast.student);
+    let job = backend.create_job(cast.employer, "Canister developer");
+    let _: (Option<Jobs>,) = backend.update(cast.student, "apply_jobs", (job.id.clone(),));
+
+    for _ in 0..2 {
+        let (public,): (PublicProfile,) = backend.query(cast.trainer, "get", (student.id.clone(),));
+        assert_eq!(public.fullname, "Bob");
+        assert_eq!(public.email, None);
+
+        let (applicants,): (Vec<PublicProfile>,) =
+            backend.query(cast.employer, "get_job_applicants", (job.id.clone(),));
+        assert_eq!(applicants[0].email.as_deref(), Some("bob@example.com"));
+
+        let (full,): (Option<Profile>,) =
+            backend.query(cast.trainer, "get_full_profile", (student.id.clone(),));
+        assert!(full.is_none());
+        let (full,): (Option<Profile>,) =
+            backend.query(cast.employer, "get_full_profile", (student.id.clone(),));
+        assert_eq!(full.unwrap().email, "bob@example.com");
+
+        let (freelancers,): (Vec<PublicProfile>,) =
+            backend.query(cast.employer, "get_all_freelancers", ());
+        assert_eq!(freelancers.len(), 1);
+        assert_eq!(freelancers[0].principal_id, cast.student);
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn emails_are_verified_with_a_code_sent_to_them() {
+    let (backend, cast) = setup();
+    let (config,): (PlatformConfig,) = backend.query(backend.controller, "get_platform_config", ());
+    assert_eq!(config.outbound, OutboundChannel::Disabled);
+
+    // Nothing is sent until a channel is configured
+    let (requested,): (Result<u64, EmailError>,) =
+        backend.update(cast.employer, "request_email_verification", ());
+    assert!(matches!(
+        requested.unwrap_err(),
+        EmailError::DeliveryFailed(_)
+    ));
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        outbound: Some(OutboundChannel::Mock),
+        ..Default::default()
+    }))));
+
+    let (requested,): (Result<u64, EmailError>,) =
+        backend.update(cast.student, "request_email_verification", ());
+    assert!(requested.is_ok());
+    let (again,): (Result<u64, EmailError>,) =
+        backend.update(cast.student, "request_email_verification", ());
+    assert_eq!(again.unwrap_err(), EmailError::RequestedTooSoon);
+
+    // The mock channel keeps the message for admins to read
+    let (outbox,): (Vec<OutboundMessage>,) = backend.query(backend.controller, "get_outbox", ());
+    let message = outbox.last().unwrap();
+    assert_eq!(message.to, "bob@example.com");
+    let code = message
+        .body
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|word| word.len() == 6)
+        .unwrap()
+        .to_string();
+
+    let (wrong,): (Result<Profile, EmailError>,) =
+        backend.update(cast.student, "confirm_email", ("not the code".to_string(),));
+    assert_eq!(wrong.unwrap_err(), EmailError::InvalidCode);
+    assert!(!backend.get_self(cast.student).email_verified);
+
+    // Pending codes survive upgrades
+    backend.upgrade(None);
+    let (confirmed,): (Result<Profile, EmailError>,) =
+        backend.update(cast.student, "confirm_email", (code,));
+    assert!(confirmed.unwrap().email_verified);
+
+    for _ in 0..2 {
+        assert!(backend.get_self(cast.student).email_verified);
+        assert!(!backend.get_self(cast.trainer).email_verified);
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn users_hold_several_roles() {
+    let (backend, cast) = setup();
+
+    let (added,): (Result<Profile, RoleError>,) =
+        backend.update(cast.student, "add_role", (Roles::EMPLOYER,));
+    assert_eq!(
+        added.unwrap().roles,
+        vec![Roles::FREELANCER, Roles::EMPLOYER]
+    );
+    let (admin,): (Result<Profile, RoleError>,) =
+        backend.update(cast.student, "add_role", (Roles::ADMIN,));
+    assert_eq!(admin.unwrap_err(), RoleError::RoleNotAllowed);
+
+    // Hiring is gated on holding the role, whichever role is active
+    let job = backend.create_job(cast.student, "Tutor");
+    assert_eq!(job.creator, cast.student);
+
+    let company = EmployerProfile {
+        company_name: "Bob & Co".to_string(),
+        website: None,
+        industry: "Education".to_string(),
+        company_size: Some(3),
+    };
+    let (updated,): (Result<Profile, RoleError>,) = backend.update(
+        cast.student,
+        "update_role_profile",
+        (RoleProfile::Employer(company.clone()),),
+    );
+    assert!(updated.is_ok());
+    let (switched,): (Result<Profile, RoleError>,) =
+        backend.update(cast.student, "switch_role", (Roles::EMPLOYER,));
+    assert!(switched.is_ok());
+
+    for _ in 0..2 {
+        let profile = backend.get_self(cast.student);
+        assert_eq!(profile.role, Roles::EMPLOYER);
+        assert_eq!(profile.roles, vec![Roles::FREELANCER, Roles::EMPLOYER]);
+        assert_eq!(profile.employer, Some(company.clone()));
+
+        let (role,): (String,) = backend.query(cast.student, "get_role", ());
+        assert_eq!(role, "EMPLOYER");
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn accounts_are_deactivated_exported_and_deleted() {
+    let (backend, cast) = setup();
+    let engagement = hire(&backend, &cast);
+    let student = backend.get_self(cast.student);
+    let employer = backend.get_self(cast.employer);
+    let review = backend
+        .add_review(cast.student, &employer, 4.0, engagement, None)
+        .unwrap();
+
+    let (deactivated,): (Result<(), AccountError>,) =
+        backend.update(cast.student, "deactivate_account", ());
+    assert!(deactivated.is_ok());
+    let (export,): (String,) = backend.query(cast.student, "export_my_data", ());
+    assert!(export.contains("bob@example.com"));
+    assert!(export.contains(&review.id));
+
+    for _ in 0..2 {
+        let (hidden,): (PublicProfile,) = backend.query(cast.trainer, "get", (student.id.clone(),));
+        assert_eq!(hidden.id, "");
+        backend.upgrade(None);
+    }
+
+    let (deleted,): (Result<Vec<String>, AccountError>,) =
+        backend.update(cast.student, "delete_account", ());
+    assert_eq!(deleted, Ok(vec![]));
+
+    for _ in 0..2 {
+        assert_eq!(backend.get_self(cast.student).id, "");
+        let (reviews,): (Vec<Reviews>,) =
+            backend.query(cast.trainer, "get_all_reviews", (cast.employer,));
+        assert_eq!(reviews[0].reviewer, "Deleted user");
+        assert_eq!(reviews[0].reviewer_principal, None);
+
+        let (deleted,): (Result<Vec<String>, AccountError>,) =
+            backend.update(cast.student, "delete_account", ());
+        assert_eq!(deleted, Err(AccountError::ProfileNotFound));
+        backend.upgrade(None);
+    }
+}
+
+// ==================================================================================================
+// Courses and jobs
+// ==================================================================================================
+
+#[test]
+fn courses_and_jobs_survive_upgrades() {
+    let (backend, cast) = setup();
+
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let (applied,): (Option<Course>,) =
+        backend.update(cast.student, "apply_course", (course.id.clone(),));
+    assert_eq!(applied.unwrap().applicants, vec![cast.student]);
+
+    let job = backend.create_job(cast.employer, "Canister developer");
+    let (applied,): (Option<Jobs>,) = backend.update(cast.student, "apply_jobs", (job.id.clone(),));
+    assert_eq!(applied.unwrap().applicants, vec![cast.student]);
+
+    for _ in 0..2 {
+        let (stored,): (Option<Course>,) =
+            backend.query(cast.student, "get_course", (course.id.clone(),));
+        let stored = stored.unwrap();
+        assert_eq!(stored.title, "Rust on the IC");
+        assert_eq!(stored.creator, cast.trainer);
+
+        let (applicants,): (Vec<PublicProfile>,) =
+            backend.query(cast.trainer, "get_course_applicants", (course.id.clone(),));
+        assert_eq!(applicants.len(), 1);
+        assert_eq!(applicants[0].principal_id, cast.student);
+
+        let (registered,): (Vec<Course>,) =
+            backend.update(cast.student, "get_courses_registered_by_user", ());
+        assert_eq!(registered.len(), 1);
+
+        let (jobs,): (Vec<Jobs>,) = backend.update(cast.employer, "get_jobs_by_creator", ());
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, job.id);
+
+        let (count,): (u32,) = backend.query(cast.student, "get_jobs_applied_count", ());
+        assert_eq!(count, 1);
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn only_trainers_create_courses_and_only_employers_create_jobs() {
+    let (backend, cast) = setup();
+
+    let (course,): (Option<Course>,) =
+        backend.update(cast.student, "create_course", ("Not mine".to_string(),));
+    assert!(course.is_none());
+
+    let (job,): (Option<Jobs>,) =
+        backend.update(cast.trainer, "create_job", ("Not mine".to_string(),));
+    assert!(job.is_none());
+
+    let (course,): (Option<Course>,) =
+        backend.query(cast.student, "get_course", ("missing".to_string(),));
+    assert!(course.is_none());
+    let (freelancers,): (Vec<PublicProfile>,) =
+        backend.query(cast.student, "get_all_freelancers", ());
+    assert!(freelancers.is_empty());
+
+    let (courses,): (Vec<Course>,) = backend.query(cast.student, "get_all_courses", ());
+    let (jobs,): (Vec<Jobs>,) = backend.query(cast.student, "get_all_jobs", ());
+    assert!(courses.is_empty());
+    assert!(jobs.is_empty());
+}
+
+fn acme_details() -> OrganizationDetails {
+    OrganizationDetails {
+        name: "Acme".to_string(),
+        kind: OrganizationKind::Company,
+        description: "Builds canisters".to_string(),
+        website: None,
+        location: "Nairobi".to_string(),
+        logo_id: None,
+    }
+}
+
+// Carol creates Acme, returns its id
+fn create_acme(backend: &Backend, cast: &Cast) -> String {
+    let (created,): (Result<Organization, OrganizationError>,) =
+        backend.update(cast.employer, "create_organization", (acme_details(),));
+    created.unwrap().id
+}
+
+#[test]
+fn organizations_own_jobs_and_courses() {
+    let (backend, cast) = setup();
+    let acme = create_acme(&backend, &cast);
+
+    // Bob is a trainee, being a recruiter of Acme is what lets him post its jobs
+    let (denied,): (Result<Jobs, OrganizationError>,) = backend.update(
+        cast.student,
+        "create_organization_job",
+        (acme.clone(), "Designer".to_string()),
+    );
+    assert_eq!(denied.unwrap_err(), OrganizationError::Unauthorized);
+    for (member, role) in [
+        (cast.student, MemberRole::Recruiter),
+        (cast.trainer, MemberRole::Instructor),
+    ] {
+        let (added,): (Result<Organization, OrganizationError>,) = backend.update(
+            cast.employer,
+            "set_organization_member",
+            (acme.clone(), member, role),
+        );
+        assert!(added.is_ok());
+    }
+    let (job,): (Result<Jobs, OrganizationError>,) = backend.update(
+        cast.student,
+        "create_organization_job",
+        (acme.clone(), "Designer".to_string()),
+    );
+    let job = job.unwrap();
+    let (course,): (Result<Course, OrganizationError>,) = backend.update(
+        cast.trainer,
+        "create_organization_course",
+        (acme.clone(), "Rust on the IC".to_string()),
+    );
+    let course = course.unwrap();
+
+    for _ in 0..2 {
+        let (organization,): (Option<Organization>,) =
+            backend.query(cast.student, "get_organization", (acme.clone(),));
+        let organization = organization.unwrap();
+        assert_eq!(organization.details, acme_details());
+        assert_eq!(organization.members.len(), 3);
+        assert_eq!(organization.members[0].role, MemberRole::Owner);
+
+        let (mine,): (Vec<Organization>,) = backend.query(cast.trainer, "get_my_organizations", ());
+        assert_eq!(mine[0].id, acme);
+
+        let (jobs,): (Vec<Jobs>,) =
+            backend.query(cast.student, "get_organization_jobs", (acme.clone(),));
+        assert_eq!(jobs[0].id, job.id);
+        assert_eq!(jobs[0].organization_id.as_deref(), Some(acme.as_str()));
+
+        let (courses,): (Vec<Course>,) =
+            backend.query(cast.student, "get_organization_courses", (acme.clone(),));
+        assert_eq!(courses[0].id, course.id);
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn admins_verify_organizations_before_they_certify() {
+    let (backend, cast) = setup();
+    let acme = create_acme(&backend, &cast);
+    let (job,): (Result<Jobs, OrganizationError>,) = backend.update(
+        cast.employer,
+        "create_organization_job",
+        (acme.clone(), "Designer".to_string()),
+    );
+    let job = job.unwrap();
+    let (course,): (Result<Course, OrganizationError>,) = backend.update(
+        cast.employer,
+        "create_organization_course",
+        (acme.clone(), "Rust on the IC".to_string()),
+    );
+    let course = course.unwrap();
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        features: Some(FeatureFlags {
+            batch_minting: true,
+            certificate_transfers: true,
+            verified_issuers_only: true,
+        }),
+        ..Default::default()
+    }))));
+    assert_eq!(
+        backend.mint_certificate(cast.student, &course.id),
+        Err(NftError::UnverifiedIssuer)
+    );
+
+    // Documents have to be assets of the submitter
+    let licence = backend.upload_asset(cast.employer, "application/pdf", b"%PDF-1.4 licence");
+    let subject = VerificationSubject::Organization(acme.clone());
+    let (stolen,): (Result<VerificationRequest, VerificationError>,) = backend.update(
+        cast.trainer,
+        "submit_verification",
+        (
+            VerificationSubject::Trainer(cast.trainer),
+            vec![licence.id.clone()],
+        ),
+    );
+    assert_eq!(stolen.unwrap_err(), VerificationError::InvalidAsset);
+    let (submitted,): (Result<VerificationRequest, VerificationError>,) = backend.update(
+        cast.employer,
+        "submit_verification",
+        (subject.clone(), vec![licence.id]),
+    );
+    let request = submitted.unwrap();
+    assert_eq!(request.status, VerificationStatus::Pending);
+
+    let denied: Result<(Result<VerificationRequest, VerificationError>,), _> = backend.try_update(
+        cast.employer,
+        "approve_verification",
+        (request.id.clone(), String::new()),
+    );
+    assert!(denied.is_err());
+    let (queue,): (Vec<VerificationRequest>,) =
+        backend.query(backend.controller, "get_verification_queue", ());
+    assert_eq!(queue[0].subject, subject);
+    let (approved,): (Result<VerificationRequest, VerificationError>,) = backend.update(
+        backend.controller,
+        "approve_verification",
+        (request.id.clone(), "Registry entry checked".to_string()),
+    );
+    assert_eq!(approved.unwrap().status, VerificationStatus::Verified);
+    assert!(backend.mint_certificate(cast.student, &course.id).is_ok());
+
+    for _ in 0..2 {
+        let (organization,): (Option<Organization>,) =
+            backend.query(cast.student, "get_organization", (acme.clone(),));
+        assert_eq!(
+            organization.unwrap().verification,
+            VerificationStatus::Verified
+        );
+        assert!(backend.get_course(&course.id).verified);
+        let (jobs,): (Vec<Jobs>,) =
+            backend.query(cast.student, "get_organization_jobs", (acme.clone(),));
+        assert_eq!(jobs[0].id, job.id);
+        assert!(jobs[0].verified);
+
+        let (requests,): (Vec<VerificationRequest>,) =
+            backend.query(cast.employer, "get_my_verification_requests", ());
+        let decision = requests[0].decision.clone().unwrap();
+        assert_eq!(decision.admin, backend.controller);
+        assert_eq!(decision.note, "Registry entry checked");
+
+        backend.upgrade(None);
+    }
+}
+
+// ==================================================================================================
+// Reviews
+// ==================================================================================================
+
+// Carol hires Bob on a job, so they can review each other
+fn hire(backend: &Backend, cast: &Cast) -> Engagement {
+    let job = backend.create_job(cast.employer, "Canister developer");
+    let _: (Option<Jobs>,) = backend.update(cast.student, "apply_jobs", (job.id.clone(),));
+    let (hired,): (Result<Jobs, JobError>,) = backend.update(
+        cast.employer,
+        "hire_applicant",
+        (job.id.clone(), cast.student),
+    );
+    assert_eq!(hired.unwrap().hired, vec![cast.student]);
+    Engagement::Job(job.id)
+}
+
+#[test]
+fn reviews_survive_upgrades() {
+    let (backend, cast) = setup();
+    let engagement = hire(&backend, &cast);
+    let student = backend.get_self(cast.student);
+
+    let review = backend
+        .add_review(cast.employer, &student, 5.0, engagement.clone(), None)
+        .unwrap();
+    assert_eq!(review.reviewer, "Carol");
+    assert_eq!(review.reviewer_principal, Some(cast.employer));
+    assert_eq!(review.engagement, Some(engagement));
+
+    let (replied,): (Result<Reviews, ReviewError>,) = backend.update(
+        cast.student,
+        "reply_to_review",
+        (review.id.clone(), "Thank you".to_string()),
+    );
+    assert!(replied.is_ok());
+
+    for _ in 0..2 {
+        let (reviews,): (Vec<Reviews>,) =
+            backend.query(cast.trainer, "get_all_reviews", (cast.student,));
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].id, review.id);
+        assert_eq!(reviews[0].title, "Great work");
+        assert_eq!(reviews[0].reply.as_ref().unwrap().text, "Thank you");
+
+        let (written,): (Vec<Reviews>,) =
+            backend.query(cast.trainer, "get_reviews_by_reviewer", (cast.employer,));
+        assert_eq!(written.len(), 1);
+
+        let (reviewed,): (bool,) =
+            backend.query(cast.employer, "confirm_reviewed", (cast.student,));
+        assert!(reviewed);
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn reviews_are_limited_to_engagements() {
+    let (backend, cast) = setup();
+    let engagement = hire(&backend, &cast);
+    let student = backend.get_self(cast.student);
+    let employer = backend.get_self(cast.employer);
+
+    let result = backend.add_review(cast.trainer, &student, 5.0, engagement.clone(), None);
+    assert_eq!(result.unwrap_err(), ReviewError::NotEngaged);
+    let result = backend.add_review(cast.employer, &student, 6.0, engagement.clone(), None);
+    assert_eq!(result.unwrap_err(), ReviewError::InvalidRating);
+    let result = backend.add_review(cast.student, &student, 5.0, engagement.clone(), None);
+    assert_eq!(result.unwrap_err(), ReviewError::SelfReview);
+
+    backend
+        .add_review(cast.student, &employer, 4.0, engagement.clone(), None)
+        .unwrap();
+    let result = backend.add_review(cast.student, &employer, 5.0, engagement, None);
+    assert_eq!(result.unwrap_err(), ReviewError::AlreadyReviewed);
+}
+
+#[test]
+fn authors_edit_and_delete_their_reviews() {
+    let (backend, cast) = setup();
+    let engagement = hire(&backend, &cast);
+    let student = backend.get_self(cast.student);
+    let review = backend
+        .add_review(cast.employer, &student, 2.0, engagement, None)
+        .unwrap();
+
+    let (edited,): (Result<Reviews, ReviewError>,) = backend.update(
+        cast.employer,
+        "edit_review",
+        (
+            review.id.clone(),
+            4.0f64,
+            "Second thoughts".to_string(),
+            "It all worked out".to_string(),
+            String::new(),
+            None::<CategoryRatings>,
+        ),
+    );
+    let edited = edited.unwrap();
+    assert_eq!(edited.title, "Second thoughts");
+    assert!(edited.edited_at.is_some());
+    assert_eq!(backend.get_self(cast.student).rating.mean, 4.0);
+
+    let (deleted,): (Result<(), ReviewError>,) =
+        backend.update(cast.student, "delete_review", (review.id.clone(),));
+    assert_eq!(deleted, Err(ReviewError::Unauthorized));
+    let (deleted,): (Result<(), ReviewError>,) =
+        backend.update(cast.employer, "delete_review", (review.id.clone(),));
+    assert_eq!(deleted, Ok(()));
+
+    backend.upgrade(None);
+
+    let (stored,): (Option<Reviews>,) = backend.query(cast.student, "get_review", (review.id,));
+    assert!(stored.is_none());
+    assert_eq!(backend.get_self(cast.student).rating.count, 0);
+}
+
+#[test]
+fn reported_reviews_are_hidden_until_an_admin_restores_them() {
+    let (backend, cast) = setup();
+    let engagement = hire(&backend, &cast);
+    let student = backend.get_self(cast.student);
+    let review = backend
+        .add_review(cast.employer, &student, 1.0, engagement, None)
+        .unwrap();
+
+    backend.create_user(user(4), "Dan", "trainee");
+    for reporter in [cast.student, cast.trainer, user(4)] {
+        let (reported,): (Result<(), ReviewError>,) = backend.update(
+            reporter,
+            "report_review",
+            (review.id.clone(), "Abusive".to_string()),
+        );
+        assert_eq!(reported, Ok(()));
+    }
+
+    let (reviews,): (Vec<Reviews>,) =
+        backend.query(cast.trainer, "get_all_reviews", (cast.student,));
+    assert!(reviews.is_empty());
+    assert_eq!(backend.get_self(cast.student).rating.count, 0);
+
+    let result: Result<(Vec<ModerationQueueEntry>,), _> =
+        backend.try_update(cast.student, "get_moderation_queue", ());
+    assert!(result.is_err());
+    let (queue,): (Vec<ModerationQueueEntry>,) =
+        backend.query(backend.controller, "get_moderation_queue", ());
+    assert_eq!(queue.len(), 1);
+    assert!(queue[0].review.hidden);
+
+    backend.upgrade(None);
+
+    let (restored,): (Result<ModerationCase, ReviewError>,) = backend.update(
+        backend.controller,
+        "restore_review",
+        (review.id.clone(), "Harsh but fair".to_string()),
+    );
+    let restored = restored.unwrap();
+    assert!(!restored.open);
+    assert_eq!(
+        restored.audit.last().unwrap().moderator,
+        Some(backend.controller)
+    );
+
+    let (reviews,): (Vec<Reviews>,) =
+        backend.query(cast.trainer, "get_all_reviews", (cast.student,));
+    assert_eq!(reviews.len(), 1);
+    assert_eq!(backend.get_self(cast.student).rating.count, 1);
+}
+
+#[test]
+fn ratings_are_aggregated_per_profile() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    let trainer = backend.get_self(cast.trainer);
+
+    let categories = CategoryRatings {
+        communication: 5.0,
+        quality: 4.0,
+        timeliness: 3.0,
+    };
+    backend
+        .add_review(
+            cast.student,
+            &trainer,
+            4.0,
+            Engagement::Course(course.id.clone()),
+            Some(categories.clone()),
+        )
+        .unwrap();
+
+    for _ in 0..2 {
+        let rating = backend.get_self(cast.trainer).rating;
+        assert_eq!(rating.count, 1);
+        assert_eq!(rating.mean, 4.0);
+        assert_eq!(rating.stars, vec![0, 0, 0, 1, 0]);
+        assert_eq!(rating.categories, categories);
+        // Reviews of the trainer don't rate the course itself
+        assert_eq!(backend.get_course(&course.id).rating.overall.count, 0);
+
+        backend.upgrade(None);
+    }
+}
+
+#[test]
+fn enrolled_learners_rate_courses() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let other = backend.create_course(cast.trainer, "Advanced canisters");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    let course_ratings = CourseRatings {
+        content_quality: 5.0,
+        pacing: 3.0,
+    };
+
+    let not_enrolled = backend.rate_course(cast.employer, &course.id, 5.0, course_ratings.clone());
+    assert_eq!(not_enrolled.unwrap_err(), ReviewError::NotEngaged);
+    let own_course = backend.rate_course(cast.trainer, &course.id, 5.0, course_ratings.clone());
+    assert_eq!(own_course.unwrap_err(), ReviewError::SelfReview);
+
+    backend
+        .rate_course(cast.student, &course.id, 2.0, course_ratings.clone())
+        .unwrap();
+    let edited = backend
+        .rate_course(cast.student, &course.id, 4.0, course_ratings.clone())
+        .unwrap();
+    assert!(edited.edited_at.is_some());
+
+    for _ in 0..2 {
+        let rating = backend.get_course(&course.id).rating;
+        assert_eq!(rating.overall.count, 1);
+        assert_eq!(rating.overall.mean, 4.0);
+        assert_eq!(rating.means, course_ratings);
+
+        let (reviews,): (Vec<CourseReview>,) =
+            backend.query(cast.employer, "get_course_reviews", (course.id.clone(),));
+        assert_eq!(reviews.len(), 1);
+        assert_eq!(reviews[0].reviewer, cast.student);
+
+        let trainer = backend.get_self(cast.trainer);
+        assert_eq!(trainer.course_rating.overall.count, 1);
+        assert_eq!(trainer.rating.count, 0);
+
+        let (courses,): (Vec<Course>,) =
+            backend.query(cast.student, "get_top_rated_courses", (10u32,));
+        assert_eq!(courses[0].id, course.id);
+        assert_eq!(courses[1].id, other.id);
+
+        backend.upgrade(None);
+    }
+
+    let (deleted,): (Result<(), ReviewError>,) =
+        backend.update(cast.student, "delete_course_review", (course.id.clone(),));
+    assert_eq!(deleted, Ok(()));
+    assert_eq!(
+        backend.get_self(cast.trainer).course_rating.overall.count,
+        0
+    );
+}
+
+#[test]
+fn cvs_list_platform_work_and_render_for_download() {
+    let (backend, cast) = setup();
+    hire(&backend, &cast);
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    backend.mint_certificate(cast.student, &course.id).unwrap();
+
+    let cv = Cv {
+        experience: vec![WorkExperience {
+            employer: "Acme".to_string(),
+            title: "Engineer".to_string(),
+            start: CvDate {
+                year: 2021,
+                month: 3,
+            },
+            end: None,
+            description: "Backend work".to_string(),
+        }],
+        languages: vec![Language {
+            language: "Swahili".to_string(),
+            proficiency: LanguageProficiency::Native,
+        }],
+        ..Default::default()
+    };
+    let (updated,): (Result<Cv, CvError>,) =
+        backend.update(cast.student, "update_cv", (cv.clone(),));
+    assert_eq!(updated, Ok(cv.clone()));
+
+    let student = backend.get_self(cast.student);
+    for _ in 0..2 {
+        let (document,): (Option<CvDocument>,) =
+            backend.query(cast.employer, "get_cv", (student.id.clone(),));
+        let document = document.unwrap();
+        assert_eq!(document.cv, cv);
+        assert_eq!(document.completed_courses[0].course_id, course.id);
+        assert_eq!(document.platform_jobs[0].title, "Canister developer");
+
+        let html = backend.http_get(&format!("/cv/{}.html", student.id), None);
+        assert_eq!(html.status_code, 200);
+        assert!(String::from_utf8(html.body)
+            .unwrap()
+            .contains("Rust on the IC"));
+        let pdf = backend.http_get(&format!("/cv/{}.pdf", student.id), None);
+        assert!(pdf.body.starts_with(b"%PDF"));
+
+        backend.upgrade(None);
+    }
+}
+
+// ==================================================================================================
+// Notifications
+// ==================================================================================================
+
+fn notifications(backend: &Backend, sender: Principal) -> NotificationPage {
+    let (page,): (NotificationPage,) = backend.query(sender, "get_notifications", (0u64, 10u64));
+    page
+}
+
+#[test]
+fn existing_flows_fill_the_notification_inbox() {
+    let (backend, cast) = setup();
+    let preferences = NotificationPreferences {
+        job_applications: true,
+        hires: true,
+        course_enrollments: true,
+        certificates: true,
+        reviews: false,
+        messages: true,
+    };
+    let (set,): (Result<NotificationPreferences, NotificationError>,) = backend.update(
+        cast.student,
+        "set_notification_preferences",
+        (preferences.clone(),),
+    );
+    assert_eq!(set, Ok(preferences.clone()));
+
+    // A burned certificate moves the transaction ids away from the token ids
+    let dan = user(4);
+    backend.create_user(dan, "Dan", "trainee");
+    let intro = backend.create_course(cast.trainer, "Intro to the IC");
+    let _: (Option<Course>,) = backend.update(dan, "apply_course", (intro.id.clone(),));
+    let burned = backend.mint_certificate(dan, &intro.id).unwrap();
+    let (burn,): (Result<Nat, NftError>,) = backend.update(dan, "dip721_burn", (burned,));
+    assert!(burn.is_ok());
+
+    let engagement = hire(&backend, &cast);
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    let token_id = backend.mint_certificate(cast.student, &course.id).unwrap();
+    let student = backend.get_self(cast.student);
+    backend
+        .add_review(cast.employer, &student, 5.0, engagement, None)
+        .unwrap();
+
+    let employer = notifications(&backend, cast.employer);
+    assert_eq!(
+        employer.notifications[0].kind,
+        NotificationKind::JobApplication
+    );
+    assert_eq!(employer.notifications[0].actor, Some(cast.student));
+    let trainer = notifications(&backend, cast.trainer);
+    assert_eq!(
+        trainer.notifications[0].message,
+        "Bob enrolled in Rust on the IC"
+    );
+
+    // Reviews were turned off
+    let page = notifications(&backend, cast.student);
+    let kinds: Vec<NotificationKind> = page
+        .notifications
+        .iter()
+        .map(|notification| notification.kind.clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![NotificationKind::CertificateMinted, NotificationKind::Hired]
+    );
+    assert_eq!(page.notifications[0].reference, token_id.0.to_string());
+    let (unread,): (u64,) = backend.update(
+        cast.student,
+        "mark_notifications_read",
+        (vec![page.notifications[0].id],),
+    );
+    assert_eq!(unread, 1);
+
+    for _ in 0..2 {
+        let page = notifications(&backend, cast.student);
+        assert_eq!(page.total, 2);
+        assert_eq!(page.unread, 1);
+        assert!(page.notifications[0].read);
+        let (stored,): (NotificationPreferences,) =
+            backend.query(cast.student, "get_notification_preferences", ());
+        assert_eq!(stored, preferences);
+
+        backend.upgrade(None);
+    }
+
+    let _: () = backend.update(cast.student, "mark_all_notifications_read", ());
+    let (unread,): (u64,) = backend.query(cast.student, "get_unread_notification_count", ());
+    assert_eq!(unread, 0);
+}
+
+// ==================================================================================================
+// Messaging
+// ==================================================================================================
+
+#[test]
+fn applicants_and_employers_message_each_other() {
+    let (backend, cast) = setup();
+    let job = backend.create_job(cast.employer, "Canister developer");
+    let _: (Option<Jobs>,) = backend.update(cast.student, "apply_jobs", (job.id.clone(),));
+    let scope = ThreadScope::JobApplication {
+        job_id: job.id.clone(),
+        applicant: cast.student,
+    };
+
+    let (outsider,): (Result<Thread, MessagingError>,) =
+        backend.update(cast.trainer, "open_thread", (scope.clone(),));
+    assert_eq!(outsider.unwrap_err(), MessagingError::Unauthorized);
+    let (thread,): (Result<Thread, MessagingError>,) =
+        backend.update(cast.employer, "open_thread", (scope.clone(),));
+    let thread = thread.unwrap();
+    assert_eq!(thread.scope, scope);
+
+    let (sent,): (Result<Message, MessagingError>,) = backend.update(
+        cast.employer,
+        "send_message",
+        (
+            thread.id.clone(),
+            "When can you start?",
+            Vec::<String>::new(),
+        ),
+    );
+    assert_eq!(sent.unwrap().id, 0);
+    // Attachments have to be assets of the sender
+    let (sent,): (Result<Message, MessagingError>,) = backend.update(
+        cast.student,
+        "send_message",
+        (thread.id.clone(), "My CV", vec!["missing".to_string()]),
+    );
+    assert_eq!(sent.unwrap_err(), MessagingError::InvalidAsset);
+    let cv = backend.upload_asset(cast.student, "application/pdf", b"%PDF-1.4");
+    let (sent,): (Result<Message, MessagingError>,) = backend.update(
+        cast.student,
+        "send_message",
+        (
+            thread.id.clone(),
+            "Monday, here is my CV",
+            vec![cv.id.clone()],
+        ),
+    );
+    assert_eq!(sent.unwrap().attachments, vec![cv.id.clone()]);
+
+    // Attachments are private, participants get a short-lived link to them
+    let path = format!("/assets/{}", cv.id);
+    assert_eq!(backend.http_get(&path, None).status_code, 403);
+    let (link,): (Result<AssetLink, AssetError>,) =
+        backend.update(cast.trainer, "get_asset_link", (cv.id.clone(),));
+    assert_eq!(link.unwrap_err(), AssetError::Unauthorized);
+    let (asset,): (Option<Asset>,) = backend.query(cast.trainer, "get_asset", (cv.id.clone(),));
+    assert!(asset.is_none());
+    let (asset,): (Option<Asset>,) = backend.query(cast.employer, "get_asset", (cv.id.clone(),));
+    assert_eq!(asset.unwrap().size, 8);
+    let (link,): (Result<AssetLink, AssetError>,) =
+        backend.update(cast.employer, "get_asset_link", (cv.id.clone(),));
+    let link = link.unwrap();
+    let response = backend.http_get(&link.path, None);
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, b"%PDF-1.4".to_vec());
+    assert_eq!(
+        notifications(&backend, cast.employer).notifications[0].kind,
+        NotificationKind::NewMessage
+    );
+
+    let (read,): (Result<Thread, MessagingError>,) =
+        backend.update(cast.employer, "mark_thread_read", (thread.id.clone(),));
+    assert!(read
+        .unwrap()
+        .receipts
+        .iter()
+        .any(|receipt| receipt.reader == cast.employer && receipt.last_read == 1));
+
+    for _ in 0..2 {
+        let (threads,): (Vec<ThreadSummary>,) = backend.query(cast.employer, "get_my_threads", ());
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].unread, 0);
+        let (threads,): (Vec<ThreadSummary>,) = backend.query(cast.student, "get_my_threads", ());
+        assert_eq!(threads[0].unread, 0);
+
+        let (hidden,): (Result<Thread, MessagingError>,) =
+            backend.query(cast.trainer, "get_thread", (thread.id.clone(),));
+        assert_eq!(hidden.unwrap_err(), MessagingError::Unauthorized);
+        // Admins read every thread to moderate it
+        let (moderated,): (Result<Thread, MessagingError>,) =
+            backend.query(backend.controller, "get_thread", (thread.id.clone(),));
+        assert_eq!(moderated.unwrap().messages.len(), 2);
+        // Links stay valid across upgrades until they expire
+        assert_eq!(backend.http_get(&link.path, None).status_code, 200);
+
+        backend.upgrade(None);
+    }
+}
+
+// ==================================================================================================
+// Assets
+// ==================================================================================================
+
+#[test]
+fn uploaded_assets_are_served_over_http() {
+    let (backend, cast) = setup();
+    let (begun,): (Result<Asset, AssetError>,) = backend.update(
+        user(9),
+        "begin_asset_upload",
+        ("image/png".to_string(), 10u64),
+    );
+    assert_eq!(begun.unwrap_err(), AssetError::ProfileNotFound);
+    let (begun,): (Result<Asset, AssetError>,) = backend.update(
+        cast.trainer,
+        "begin_asset_upload",
+        ("image/svg+xml".to_string(), 10u64),
+    );
+    assert_eq!(begun.unwrap_err(), AssetError::UnsupportedContentType);
+
+    // Three chunks, the last one partial
+    let content: Vec<u8> = (0..2_500_000u32).map(|byte| byte as u8).collect();
+    let asset = backend.upload_asset(cast.trainer, "video/mp4", &content);
+    assert_eq!(asset.status, AssetStatus::Committed);
+    assert_eq!(asset.size, content.len() as u64);
+
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let (media,): (Option<Course>,) = backend.update(
+        cast.student,
+        "set_course_media",
+        (course.id.clone(), vec![asset.id.clone()]),
+    );
+    assert!(media.is_none());
+    let (media,): (Option<Course>,) = backend.update(
+        cast.trainer,
+        "set_course_media",
+        (course.id.clone(), vec![asset.id.clone()]),
+    );
+    assert_eq!(media.unwrap().media, vec![asset.id.clone()]);
+
+    for _ in 0..2 {
+        let path = format!("/assets/{}", asset.id);
+        let range = "bytes=1048570-1048589";
+        let response = backend.http_get(&path, Some(range));
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body, content[1_048_570..1_048_590].to_vec());
+
+        let response = backend.http_get(&path, Some("bytes=2000000-"));
+        assert_eq!(response.body, content[2_000_000..].to_vec());
+        assert!(response
+            .headers
+            .contains(&("X-Content-Type-Options".to_string(), "nosniff".to_string())));
+        assert_eq!(backend.http_get("/assets/missing", None).status_code, 404);
+
+        assert_eq!(backend.get_course(&course.id).media, vec![asset.id.clone()]);
+        backend.upgrade(None);
+    }
+
+    let (deleted,): (Result<(), AssetError>,) =
+        backend.update(cast.student, "delete_asset", (asset.id.clone(),));
+    assert_eq!(deleted, Err(AssetError::Unauthorized));
+}
+
+// ==================================================================================================
+// Certificates
+// ==================================================================================================
+
+#[test]
+fn certificates_survive_upgrades() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+
+    // Rejected mints leave the token ids alone
+    assert_eq!(
+        backend.mint_certificate(cast.employer, &course.id),
+        Err(NftError::NotEnrolled)
+    );
+    let token_id = backend.mint_certificate(cast.student, &course.id).unwrap();
+    assert_eq!(token_id, Nat::from(0u64));
+
+    backend.upgrade(None);
+
+    let (owner,): (Result<Option<Principal>, NftError>,) =
+        backend.query(cast.trainer, "dip721_owner_of", (token_id.clone(),));
+    assert_eq!(owner, Ok(Some(cast.student)));
+
+    let (balance,): (Result<Nat, NftError>,) =
+        backend.query(cast.trainer, "dip721_balance_of", (cast.student,));
+    assert_eq!(balance, Ok(Nat::from(1u64)));
+
+    let profile = backend.get_self(cast.student);
+    assert_eq!(profile.token_ids, vec![(token_id, course.id.clone())]);
+
+    // Token ids keep counting after an upgrade instead of colliding with minted ones
+    let employer_course = backend.create_course(cast.trainer, "Advanced canisters");
+    let _: (Option<Course>,) =
+        backend.update(cast.employer, "apply_course", (employer_course.id.clone(),));
+    let next = backend.mint_certificate(cast.employer, &employer_course.id);
+    assert_eq!(next, Ok(Nat::from(1u64)));
+
+    let (transactions,): (Nat,) = backend.query(cast.trainer, "dip721_total_transactions", ());
+    assert_eq!(transactions, Nat::from(2u64));
+}
+
+#[test]
+fn certificate_history_survives_upgrades() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    for applicant in [cast.student, cast.employer, cast.trainer] {
+        let _: (Option<Course>,) = backend.update(applicant, "apply_course", (course.id.clone(),));
+    }
+    let first = backend.mint_certificate(cast.student, &course.id).unwrap();
+    let (transfer,): (Result<Nat, NftError>,) = backend.update(
+        cast.student,
+        "dip721_transfer",
+        (cast.employer, first.clone()),
+    );
+    assert_eq!(transfer, Ok(Nat::from(1u64)));
+    let second = backend.mint_certificate(cast.trainer, &course.id).unwrap();
+
+    backend.upgrade(None);
+
+    let (count,): (Nat,) = backend.query(cast.trainer, "dip721_total_transactions", ());
+    assert_eq!(count, Nat::from(3u64));
+    let (transaction,): (Result<TxEvent, NftError>,) =
+        backend.query(cast.trainer, "dip721_transaction", (Nat::from(1u64),));
+    let transaction = transaction.unwrap();
+    assert_eq!(transaction.operation, TxOperation::Transfer);
+    assert_eq!(transaction.caller, cast.student);
+    assert_eq!(transaction.from, Some(cast.student));
+    assert_eq!(transaction.to, Some(cast.employer));
+    assert_eq!(transaction.token_identifier, Some(first.clone()));
+
+    let (missing,): (Result<TxEvent, NftError>,) =
+        backend.query(cast.trainer, "dip721_transaction", (Nat::from(3u64),));
+    assert_eq!(missing.unwrap_err(), NftError::TxNotFound);
+
+    let principal_ids = |principal: Principal, offset: u64, limit: u64| -> Vec<Nat> {
+        let (txs,): (Vec<TxEvent>,) = backend.query(
+            cast.trainer,
+            "dip721_principal_transactions",
+            (principal, offset, limit),
+        );
+        txs.into_iter().map(|tx| tx.tx_id).collect()
+    };
+    let token_ids = |token: &Nat, offset: u64, limit: u64| -> Vec<Nat> {
+        let (txs,): (Vec<TxEvent>,) = backend.query(
+            cast.trainer,
+            "dip721_token_transactions",
+            (token.clone(), offset, limit),
+        );
+        txs.into_iter().map(|tx| tx.tx_id).collect()
+    };
+    assert_eq!(
+        token_ids(&first, 0, 10),
+        vec![Nat::from(0u64), Nat::from(1u64)]
+    );
+    assert_eq!(token_ids(&first, 1, 10), vec![Nat::from(1u64)]);
+    assert_eq!(token_ids(&second, 0, 10), vec![Nat::from(2u64)]);
+    assert_eq!(
+        principal_ids(cast.student, 0, 10),
+        vec![Nat::from(0u64), Nat::from(1u64)]
+    );
+    assert_eq!(principal_ids(cast.student, 0, 1), vec![Nat::from(0u64)]);
+    assert_eq!(
+        principal_ids(backend.controller, 0, 10),
+        vec![Nat::from(0u64), Nat::from(2u64)]
+    );
+}
+
+#[test]
+fn certificates_are_minted_by_the_course_managers() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let mint = |sender: Principal, to: &str, course_id: &str| {
+        let (result,): (Result<Nat, NftError>,) = backend.update(
+            sender,
+            "mint_certificate",
+            (
+                to.to_string(),
+                "Completed the course".to_string(),
+                "certificate".to_string(),
+                course_id.to_string(),
+                "https://example.com/certificate.png".to_string(),
+            ),
+        );
+        result
+    };
+    let student = cast.student.to_text();
+
+    assert_eq!(
+        mint(cast.student, &student, &course.id),
+        Err(NftError::Unauthorized)
+    );
+    assert_eq!(
+        mint(cast.trainer, &student, "missing"),
+        Err(NftError::CourseNotFound)
+    );
+    assert_eq!(
+        mint(cast.trainer, "not a principal", &course.id),
+        Err(NftError::InvalidPrincipal)
+    );
+    assert_eq!(
+        mint(cast.trainer, &student, &course.id),
+        Err(NftError::NotEnrolled)
+    );
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    assert_eq!(
+        mint(cast.trainer, &student, &course.id),
+        Ok(Nat::from(0u64))
+    );
+    assert_eq!(
+        backend.get_self(cast.student).token_ids,
+        vec![(Nat::from(0u64), course.id.clone())]
+    );
+}
+
+#[test]
+fn course_managers_pay_the_certificate_fee() {
+    let (backend, cast) = setup();
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(cast.student, "apply_course", (course.id.clone(),));
+    // The fee ledger is not installed, so no fee can be taken
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        settings: Some(PlatformSettings {
+            certificate_fee_e8s: 10_000,
+            fee_ledger: user(99),
+            max_batch_recipients: 10,
+            batch_chunk_size: 10,
+            review_report_threshold: 3,
+            max_asset_size: 1024,
+            max_owner_assets_size: 4096,
+        }),
+        ..Default::default()
+    }))));
+
+    let (minted,): (Result<Nat, NftError>,) = backend.update(
+        cast.trainer,
+        "mint_certificate",
+        (
+            cast.student.to_text(),
+            "Completed the course".to_string(),
+            "certificate".to_string(),
+            course.id.clone(),
+            "https://example.com/certificate.png".to_string(),
+        ),
+    );
+    assert!(matches!(minted, Err(NftError::FeeNotPaid(_))));
+    let (batch,): (Result<BatchMintJob, BatchMintError>,) = backend.update(
+        cast.trainer,
+        "mint_certificates_batch",
+        (
+            course.id.clone(),
+            vec![(cast.student, "A".to_string())],
+            "Completed the course".to_string(),
+            "certificate".to_string(),
+            "https://example.com/certificate.png".to_string(),
+        ),
+    );
+    assert!(matches!(batch, Err(BatchMintError::FeeNotPaid(_))));
+    assert!(backend.get_self(cast.student).token_ids.is_empty());
+
+    // Custodians mint for free
+    assert_eq!(
+        backend.mint_certificate(cast.student, &course.id),
+        Ok(Nat::from(0u64))
+    );
+}
+
+#[test]
+fn batch_minting_certifies_every_applicant() {
+    let (backend, cast) = setup();
+    // A burned certificate moves the transaction ids away from the token ids
+    let intro = backend.create_course(cast.trainer, "Intro to the IC");
+    let _: (Option<Course>,) = backend.update(cast.employer, "apply_course", (intro.id.clone(),));
+    let burned = backend.mint_certificate(cast.employer, &intro.id).unwrap();
+    let (burn,): (Result<Nat, NftError>,) = backend.update(cast.employer, "dip721_burn", (burned,));
+    assert_eq!(burn, Ok(Nat::from(1u64)));
+
+    let course = backend.create_course(cast.trainer, "Rust on the IC");
+    for applicant in [cast.student, cast.employer] {
+        let _: (Option<Course>,) = backend.update(applicant, "apply_course", (course.id.clone(),));
+    }
+
+    let (job,): (Result<BatchMintJob, BatchMintError>,) = backend.update(
+        cast.trainer,
+        "mint_certificates_batch",
+        (
+            course.id.clone(),
+            vec![
+                (cast.student, "A".to_string()),
+                (cast.employer, "B".to_string()),
+            ],
+            "Completed the course".to_string(),
+            "certificate".to_string(),
+            "https://example.com/certificate.png".to_string(),
+        ),
+    );
+    let job = job.expect("batch mint was rejected");
+    assert_eq!(job.status, BatchStatus::Completed);
+    assert_eq!(job.next_index, 2);
+
+    backend.upgrade(None);
+
+    let (stored,): (Option<BatchMintJob>,) =
+        backend.query(cast.trainer, "get_certificates_batch", (job.id.clone(),));
+    assert_eq!(stored.unwrap().status, BatchStatus::Completed);
+    // Recipients and grades are only shown to the creator and the course managers
+    let (stored,): (Option<BatchMintJob>,) =
+        backend.query(cast.student, "get_certificates_batch", (job.id.clone(),));
+    assert!(stored.is_none());
+    for (n, recipient) in job.recipients.iter().enumerate() {
+        let token_id = Nat::from(n as u64 + 1);
+        assert_eq!(recipient.status, RecipientStatus::Minted(token_id.clone()));
+        assert!(backend
+            .get_self(recipient.to)
+            .token_ids
+            .contains(&(token_id, course.id.clone())));
+    }
+}
+
+#[test]
+fn batch_minting_commits_every_chunk() {
+    let backend = Backend::install(Some(BackendArgs::Init(BackendInitArgs {
+        settings: Some(PlatformSettings {
+            certificate_fee_e8s: 0,
+            fee_ledger: Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap(),
+            max_batch_recipients: 10,
+            batch_chunk_size: 1,
+            review_report_threshold: 3,
+            max_asset_size: 1024,
+            max_owner_assets_size: 4096,
+        }),
+        ..Default::default()
+    })));
+    let trainer = user(1);
+    let learners = [user(2), user(3), user(4)];
+    backend.create_user(trainer, "Alice", "trainer");
+    let course = backend.create_course(trainer, "Rust on the IC");
+    for (n, learner) in learners.iter().enumerate() {
+        backend.create_user(*learner, &format!("Learner {}", n), "trainee");
+        let _: (Option<Course>,) = backend.update(*learner, "apply_course", (course.id.clone(),));
+    }
+
+    let (job,): (Result<BatchMintJob, BatchMintError>,) = backend.update(
+        trainer,
+        "mint_certificates_batch",
+        (
+            course.id.clone(),
+            learners
+                .iter()
+                .map(|learner| (*learner, "A".to_string()))
+                .collect::<Vec<_>>(),
+            "Completed the course".to_string(),
+            "certificate".to_string(),
+            "https://example.com/certificate.png".to_string(),
+        ),
+    );
+    // Every recipient is a chunk of its own, the job still completes in one call
+    let job = job.expect("batch mint was rejected");
+    assert_eq!(job.status, BatchStatus::Completed);
+    assert_eq!(job.next_index, 3);
+    for (n, recipient) in job.recipients.iter().enumerate() {
+        assert_eq!(
+            recipient.status,
+            RecipientStatus::Minted(Nat::from(n as u64))
+        );
+    }
+
+    let (resumed,): (Result<BatchMintJob, BatchMintError>,) =
+        backend.update(learners[0], "resume_certificates_batch", (job.id.clone(),));
+    assert_eq!(resumed.unwrap_err(), BatchMintError::Unauthorized);
+    // Only the canister yields between chunks
+    let yielded: Result<(), _> = backend.try_update(trainer, "yield_batch", ());
+    assert!(yielded.is_err());
+
+    // Resuming a completed job mints nothing again
+    backend.upgrade(None);
+    let (resumed,): (Result<BatchMintJob, BatchMintError>,) =
+        backend.update(trainer, "resume_certificates_batch", (job.id.clone(),));
+    assert_eq!(resumed.unwrap().next_index, 3);
+    let (transactions,): (Nat,) = backend.query(trainer, "dip721_total_transactions", ());
+    assert_eq!(transactions, Nat::from(3u64));
+
+    // Batches can't be resumed once batch minting is turned off
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        features: Some(FeatureFlags {
+            batch_minting: false,
+            certificate_transfers: true,
+            verified_issuers_only: false,
+        }),
+        ..Default::default()
+    }))));
+    let (resumed,): (Result<BatchMintJob, BatchMintError>,) =
+        backend.update(trainer, "resume_certificates_batch", (job.id.clone(),));
+    assert_eq!(resumed.unwrap_err(), BatchMintError::BatchMintingDisabled);
+}
+
+// ==================================================================================================
+// ICRC-7 and ICRC-37
+// ==================================================================================================
+
+// Bob holds one certificate for each of two courses, returns their token ids
+fn certify_twice(backend: &Backend, cast: &Cast) -> (Nat, Nat) {
+    let mut tokens = vec![];
+    for title in ["Rust on the IC", "Advanced canisters"] {
+        let course = backend.create_course(cast.trainer, title);
+        let _: (Option<Course>,) =
+            backend.update(cast.student, "apply_course", (course.id.clone(),));
+        tokens.push(backend.mint_certificate(cast.student, &course.id).unwrap());
+    }
+    (tokens[0].clone(), tokens[1].clone())
+}
+
+fn icrc7_transfer(
+    backend: &Backend,
+    sender: Principal,
+    arg: TransferArg,
+) -> Result<Nat, TransferError> {
+    let (results,): (Vec<Option<Result<Nat, TransferError>>>,) =
+        backend.update(sender, "icrc7_transfer", (vec![arg],));
+    results[0].clone().expect("transfer was not processed")
+}
+
+fn icrc37_transfer_from(
+    backend: &Backend,
+    sender: Principal,
+    arg: TransferFromArg,
+) -> Result<Nat, TransferFromError> {
+    let (results,): (Vec<Option<Result<Nat, TransferFromError>>>,) =
+        backend.update(sender, "icrc37_transfer_from", (vec![arg],));
+    results[0].clone().expect("transfer was not processed")
+}
+
+fn icrc37_approve_token(
+    backend: &Backend,
+    sender: Principal,
+    arg: ApproveTokenArg,
+) -> Result<Nat, ApproveTokenError> {
+    let (results,): (Vec<Option<Result<Nat, ApproveTokenError>>>,) =
+        backend.update(sender, "icrc37_approve_tokens", (vec![arg],));
+    results[0].clone().expect("approval was not processed")
+}
+
+fn icrc37_is_approved(backend: &Backend, spender: Principal, token_id: &Nat) -> bool {
+    let (approved,): (Vec<bool>,) = backend.query(
+        spender,
+        "icrc37_is_approved",
+        (vec![IsApprovedArg {
+            spender: account(spender),
+            from_subaccount: None,
+            token_id: token_id.clone(),
+        }],),
+    );
+    approved[0]
+}
+
+fn owner_of(backend: &Backend, token_id: &Nat) -> Option<Account> {
+    let (owners,): (Vec<Option<Account>>,) =
+        backend.query(user(0), "icrc7_owner_of", (vec![token_id.clone()],));
+    owners[0].clone()
+}
+
+#[test]
+fn certificates_are_transferred_through_icrc7() {
+    let (backend, cast) = setup();
+    let (first, second) = certify_twice(&backend, &cast);
+
+    let (balances,): (Vec<Nat>,) = backend.query(
+        cast.trainer,
+        "icrc7_balance_of",
+        (vec![account(cast.student), account(cast.employer)],),
+    );
+    assert_eq!(balances, vec![Nat::from(2u64), Nat::from(0u64)]);
+    let (tokens,): (Vec<Nat>,) = backend.query(
+        cast.trainer,
+        "icrc7_tokens_of",
+        (account(cast.student), None::<Nat>, None::<Nat>),
+    );
+    assert_eq!(tokens, vec![first.clone(), second.clone()]);
+
+    let now = backend.now();
+    let transfer = |token_id: &Nat, created_at_time: Option<u64>| TransferArg {
+        from_subaccount: None,
+        to: account(cast.employer),
+        token_id: token_id.clone(),
+        memo: Some(b"hired".to_vec()),
+        created_at_time,
+    };
+    assert_eq!(
+        icrc7_transfer(&backend, cast.employer, transfer(&first, Some(now))),
+        Err(TransferError::Unauthorized)
+    );
+    assert_eq!(
+        icrc7_transfer(
+            &backend,
+            cast.student,
+            transfer(&first, Some(now - 25 * 3_600_000_000_000))
+        ),
+        Err(TransferError::TooOld)
+    );
+
+    let tx_id = icrc7_transfer(&backend, cast.student, transfer(&first, Some(now))).unwrap();
+    assert_eq!(owner_of(&backend, &first), Some(account(cast.employer)));
+    // The same transfer sent twice within the window is only applied once
+    assert_eq!(
+        icrc7_transfer(&backend, cast.student, transfer(&first, Some(now))),
+        Err(TransferError::Duplicate {
+            duplicate_of: tx_id
+        })
+    );
+
+    backend.upgrade(None);
+
+    assert_eq!(owner_of(&backend, &first), Some(account(cast.employer)));
+    assert_eq!(owner_of(&backend, &second), Some(account(cast.student)));
+    let (supply,): (Nat,) = backend.query(cast.trainer, "icrc7_total_supply", ());
+    assert_eq!(supply, Nat::from(2u64));
+}
+
+#[test]
+fn token_approvals_expire() {
+    let (backend, cast) = setup();
+    let (first, _) = certify_twice(&backend, &cast);
+
+    let now = backend.now();
+    let approval = ApproveTokenArg {
+        token_id: first.clone(),
+        approval_info: ApprovalInfo {
+            spender: account(cast.trainer),
+            from_subaccount: None,
+            expires_at: Some(now + 60_000_000_000),
+            memo: None,
+            created_at_time: now,
+        },
+    };
+    assert_eq!(
+        icrc37_approve_token(&backend, cast.employer, approval.clone()),
+        Err(ApproveTokenError::Unauthorized)
+    );
+    let tx_id = icrc37_approve_token(&backend, cast.student, approval.clone()).unwrap();
+    assert_eq!(
+        icrc37_approve_token(&backend, cast.student, approval),
+        Err(ApproveTokenError::Duplicate {
+            duplicate_of: tx_id
+        })
+    );
+    assert!(icrc37_is_approved(&backend, cast.trainer, &first));
+    assert!(!icrc37_is_approved(&backend, cast.employer, &first));
+
+    backend.pic.advance_time(Duration::from_secs(120));
+    backend.pic.tick();
+
+    assert!(!icrc37_is_approved(&backend, cast.trainer, &first));
+    let transfer_from = TransferFromArg {
+        spender_subaccount: None,
+        from: account(cast.student),
+        to: account(cast.employer),
+        token_id: first.clone(),
+        memo: None,
+        created_at_time: None,
+    };
+    assert_eq!(
+        icrc37_transfer_from(&backend, cast.trainer, transfer_from),
+        Err(TransferFromError::Unauthorized)
+    );
+    assert_eq!(owner_of(&backend, &first), Some(account(cast.student)));
+}
+
+#[test]
+fn collection_approvals_cover_every_certificate_of_the_owner() {
+    let (backend, cast) = setup();
+    let (first, second) = certify_twice(&backend, &cast);
+
+    let now = backend.now();
+    let approval = ApprovalInfo {
+        spender: account(cast.trainer),
+        from_subaccount: None,
+        expires_at: None,
+        memo: None,
+        created_at_time: now,
+    };
+    let (results,): (Vec<Option<Result<Nat, ApproveCollectionError>>>,) = backend.update(
+        cast.student,
+        "icrc37_approve_collection",
+        (vec![ApproveCollectionArg {
+            approval_info: approval.clone(),
+        }],),
+    );
+    assert!(matches!(results[0], Some(Ok(_))));
+
+    // The same approval sent twice is only recorded once, another spender is
+    // approved next to the first one
+    let approve = |approval_info: ApprovalInfo| {
+        let (results,): (Vec<Option<Result<Nat, ApproveCollectionError>>>,) = backend.update(
+            cast.student,
+            "icrc37_approve_collection",
+            (vec![ApproveCollectionArg { approval_info }],),
+        );
+        results[0].clone().unwrap()
+    };
+    assert!(matches!(
+        approve(approval.clone()),
+        Err(ApproveCollectionError::Duplicate { .. })
+    ));
+    let other = ApprovalInfo {
+        spender: account(user(9)),
+        ..approval.clone()
+    };
+    assert!(approve(other.clone()).is_ok());
+
+    backend.upgrade(None);
+
+    let (approvals,): (Vec<ApprovalInfo>,) = backend.query(
+        cast.student,
+        "icrc37_get_collection_approvals",
+        (account(cast.student), None::<ApprovalInfo>, None::<Nat>),
+    );
+    assert_eq!(approvals, vec![approval.clone(), other]);
+    assert!(icrc37_is_approved(&backend, cast.trainer, &first));
+    assert!(icrc37_is_approved(&backend, cast.trainer, &second));
+
+    let transfer_from = |token_id: &Nat| TransferFromArg {
+        spender_subaccount: None,
+        from: account(cast.student),
+        to: account(cast.employer),
+        token_id: token_id.clone(),
+        memo: None,
+        created_at_time: None,
+    };
+    assert_eq!(
+        icrc37_transfer_from(&backend, cast.employer, transfer_from(&first)),
+        Err(TransferFromError::Unauthorized)
+    );
+    assert!(icrc37_transfer_from(&backend, cast.trainer, transfer_from(&first)).is_ok());
+    assert_eq!(owner_of(&backend, &first), Some(account(cast.employer)));
+
+    // Revoking the collection approval stops the spender from moving the rest
+    let revoke = RevokeCollectionApprovalArg {
+        spender: None,
+        from_subaccount: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (results,): (Vec<Option<Result<Nat, RevokeCollectionApprovalError>>>,) = backend.update(
+        cast.student,
+        "icrc37_revoke_collection_approvals",
+        (vec![revoke.clone()],),
+    );
+    assert!(matches!(results[0], Some(Ok(_))));
+    let (results,): (Vec<Option<Result<Nat, RevokeCollectionApprovalError>>>,) = backend.update(
+        cast.student,
+        "icrc37_revoke_collection_approvals",
+        (vec![revoke],),
+    );
+    assert_eq!(
+        results[0],
+        Some(Err(RevokeCollectionApprovalError::ApprovalDoesNotExist))
+    );
+    assert_eq!(
+        icrc37_transfer_from(&backend, cast.trainer, transfer_from(&second)),
+        Err(TransferFromError::Unauthorized)
+    );
+}
+
+// ==================================================================================================
+// ICRC-3
+// ==================================================================================================
+
+fn blocks(backend: &Backend, start: u64, length: u64) -> GetBlocksResult {
+    let (result,): (GetBlocksResult,) = backend.query(
+        user(0),
+        "icrc3_get_blocks",
+        (vec![GetBlocksArgs {
+            start: Nat::from(start),
+            length: Nat::from(length),
+        }],),
+    );
+    result
+}
+
+// Leaf under `label` in the certified tip tree
+fn certified_leaf(tree: &serde_cbor::Value, label: &[u8]) -> Option<Vec<u8>> {
+    use serde_cbor::Value as Cbor;
+
+    let Cbor::Array(node) = tree else {
+        return None;
+    };
+    match &node[..] {
+        [Cbor::Integer(1), left, right] => {
+            certified_leaf(left, label).or_else(|| certified_leaf(right, label))
+        }
+        [Cbor::Integer(2), Cbor::Bytes(name), Cbor::Array(leaf)] if name.as_slice() == label => {
+            match &leaf[..] {
+                [Cbor::Integer(3), Cbor::Bytes(value)] => Some(value.clone()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn the_certificate_ledger_keeps_a_certified_block_log() {
+    let (backend, cast) = setup();
+    let (first, _) = certify_twice(&backend, &cast);
+    let (transfer,): (Result<Nat, NftError>,) =
+        backend.update(cast.student, "dip721_transfer", (cast.employer, first));
+    assert_eq!(transfer, Ok(Nat::from(2u64)));
+
+    let log = blocks(&backend, 0, 10);
+    assert_eq!(log.log_length, Nat::from(3u64));
+    let types: Vec<Value> = log
+        .blocks
+        .iter()
+        .map(|block| block.block.get("btype").cloned().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        ["7mint", "7mint", "7xfer"].map(|btype| Value::Text(btype.to_string()))
+    );
+    // Every block but the first points at its parent
+    for (n, block) in log.blocks.iter().enumerate() {
+        assert_eq!(block.id, Nat::from(n as u64));
+        assert_eq!(block.block.get("phash").is_some(), n > 0);
+    }
+    let page = blocks(&backend, 1, 1);
+    assert_eq!(page.blocks.len(), 1);
+    assert_eq!(page.blocks[0].id, Nat::from(1u64));
+
+    backend.upgrade(None);
+
+    // The tip is certified again after the upgrade
+    let (certificate,): (Option<DataCertificate>,) =
+        backend.query(user(0), "icrc3_get_tip_certificate", ());
+    let certificate = certificate.expect("the tip is not certified");
+    assert!(!certificate.certificate.is_empty());
+    let tree: serde_cbor::Value = serde_cbor::from_slice(&certificate.hash_tree[3..]).unwrap();
+    assert_eq!(certified_leaf(&tree, b"last_block_index"), Some(vec![2]));
+    let tip = certified_leaf(&tree, b"last_block_hash").unwrap();
+
+    // The log carries on from the certified tip
+    let burned = Nat::from(1u64);
+    let (burn,): (Result<Nat, NftError>,) = backend.update(cast.student, "dip721_burn", (burned,));
+    assert_eq!(burn, Ok(Nat::from(3u64)));
+    let log = blocks(&backend, 3, 1);
+    assert_eq!(log.log_length, Nat::from(4u64));
+    assert_eq!(log.blocks[0].block.get("phash"), Some(&Value::Blob(tip)));
+}
+
+// ==================================================================================================
+// Configuration
+// ==================================================================================================
+
+#[test]
+fn init_and_upgrade_arguments_are_applied() {
+    let admin = user(7);
+    let backend = Backend::install(Some(BackendArgs::Init(BackendInitArgs {
+        collection: Some(InitArgs {
+            name: Some("EA Certificates".to_string()),
+            symbol: Some("EAC".to_string()),
+            ..Default::default()
+        }),
+        admins: Some(vec![admin]),
+        ..Default::default()
+    })));
+
+    let (name,): (Option<String>,) = backend.query(admin, "dip721_name", ());
+    assert_eq!(name.as_deref(), Some("EA Certificates"));
+    let (config,): (PlatformConfig,) = backend.query(admin, "get_platform_config", ());
+    assert!(config.admins.contains(&admin));
+    assert!(config.admins.contains(&backend.controller));
+    assert!(config.features.certificate_transfers);
+
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        collection: Some(UpgradeArgs {
+            name: Some("Renamed".to_string()),
+            ..Default::default()
+        }),
+        features: Some(FeatureFlags {
+            batch_minting: true,
+            certificate_transfers: false,
+            verified_issuers_only: false,
+        }),
+        ..Default::default()
+    }))));
+
+    let (name,): (Option<String>,) = backend.query(admin, "dip721_name", ());
+    let (symbol,): (Option<String>,) = backend.query(admin, "dip721_symbol", ());
+    assert_eq!(name.as_deref(), Some("Renamed"));
+    assert_eq!(symbol.as_deref(), Some("EAC"));
+
+    let (config,): (PlatformConfig,) = backend.query(admin, "get_platform_config", ());
+    assert!(config.admins.contains(&admin));
+    assert!(!config.features.certificate_transfers);
+
+    // An empty list of admins leaves the controller upgrading the canister
+    backend.upgrade(Some(BackendArgs::Upgrade(Some(BackendUpgradeArgs {
+        admins: Some(vec![]),
+        ..Default::default()
+    }))));
+    let (config,): (PlatformConfig,) = backend.query(admin, "get_platform_config", ());
+    assert_eq!(config.admins, vec![backend.controller]);
+
+    let student = user(2);
+    backend.create_user(user(1), "Alice", "trainer");
+    let course = backend.create_course(user(1), "Rust on the IC");
+    let _: (Option<Course>,) = backend.update(student, "apply_course", (course.id.clone(),));
+    let token_id = backend.mint_certificate(student, &course.id).unwrap();
+    let (result,): (Result<Nat, NftError>,) =
+        backend.update(student, "dip721_transfer", (user(3), token_id));
+    assert_eq!(result, Err(NftError::TransferDisabled));
+}